@@ -0,0 +1,230 @@
+//! A bump allocator for MemTables that are flushed as a whole. Allocations are carved out of large blocks
+//! one after the other, so entries written together sit next to each other in memory, and nothing is
+//! freed on its own: every block goes at once when the arena is reset or dropped.
+//!
+//! Allocating is a single `fetch_add` on the current block. Only the thread that finds the block full
+//! takes a lock, to put in the next one.
+
+use std::mem::MaybeUninit;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// The size of a block, allocations above a quarter of it get a block of their own so they never waste
+// the rest of the current one
+const BLOCK_SIZE: usize = 64 * 1024;
+
+// Every allocation is rounded up to this, which keeps every offset within a block aligned to it
+const ALIGN: usize = 8;
+
+/// An Arena hands out memory through a shared reference, so it can back a structure taking writes from
+/// many threads. It never runs destructors, whatever is placed in it that owns memory elsewhere has to be
+/// dropped in place by its owner first.
+pub(crate) struct Arena {
+    // The block allocations are bumped out of, null until the first one
+    current: AtomicPtr<Block>,
+    // Every block, including the current one. Boxed, so `current` stays valid as the list grows
+    #[allow(clippy::vec_box)]
+    blocks: Mutex<Vec<Box<Block>>>,
+    // The bytes handed out, rounded up to `ALIGN`
+    allocated: AtomicUsize,
+}
+
+struct Block {
+    // Owned, allocated as a boxed slice of `u64` for its alignment
+    data: NonNull<[MaybeUninit<u64>]>,
+    // Bytes taken from the start of `data`, which may run past its end once the block is full
+    used: AtomicUsize,
+}
+
+// SAFETY: the arena only hands out raw memory, and blocks are only ever freed through `&mut self`
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    pub(crate) fn new() -> Self {
+        Self {
+            current: AtomicPtr::new(ptr::null_mut()),
+            blocks: Mutex::new(Vec::new()),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// `len` bytes aligned to at least 8, uninitialized. They stay valid until the arena is reset or
+    /// dropped.
+    pub(crate) fn alloc(&self, len: usize) -> NonNull<u8> {
+        let len = len.max(1).next_multiple_of(ALIGN);
+        self.allocated.fetch_add(len, Ordering::Relaxed);
+
+        if len > BLOCK_SIZE / 4 {
+            let block = Block::new(len);
+            let at = block.at(0);
+            self.lock().push(block);
+            return at;
+        }
+
+        loop {
+            // SAFETY: a non-null `current` is a block in `blocks`, which outlive every `&self`
+            if let Some(block) = unsafe { self.current.load(Ordering::Acquire).as_ref() } {
+                let offset = block.used.fetch_add(len, Ordering::Relaxed);
+                if offset + len <= block.capacity() {
+                    return block.at(offset);
+                }
+            }
+
+            let full = self.current.load(Ordering::Acquire);
+            let mut blocks = self.lock();
+            // Another thread may have put in a new block while this one was waiting
+            if self.current.load(Ordering::Acquire) == full {
+                let block = Block::new(BLOCK_SIZE);
+                block.used.store(len, Ordering::Relaxed);
+                let at = block.at(0);
+                self.current
+                    .store(ptr::from_ref(&*block).cast_mut(), Ordering::Release);
+                blocks.push(block);
+                return at;
+            }
+        }
+    }
+
+    /// Copies `bytes` into the arena.
+    pub(crate) fn alloc_bytes(&self, bytes: &[u8]) -> NonNull<u8> {
+        let dst = self.alloc(bytes.len());
+        // SAFETY: `dst` is fresh and at least `bytes.len()` long
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), dst.as_ptr(), bytes.len()) };
+        dst
+    }
+
+    /// Places `val` in the arena. It is never dropped, see `Arena`.
+    pub(crate) fn alloc_val<T>(&self, val: T) -> NonNull<T> {
+        assert!(
+            align_of::<T>() <= ALIGN,
+            "arena values are aligned to {ALIGN}"
+        );
+        let dst = self.alloc(size_of::<T>()).cast::<T>();
+        // SAFETY: `dst` is fresh, aligned for `T` and large enough for one
+        unsafe { dst.write(val) };
+        dst
+    }
+
+    /// The bytes handed out so far.
+    pub(crate) fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// Frees every block at once, invalidating everything allocated.
+    pub(crate) fn reset(&mut self) {
+        self.current.store(ptr::null_mut(), Ordering::Relaxed);
+        self.lock().clear();
+        self.allocated.store(0, Ordering::Relaxed);
+    }
+
+    #[allow(clippy::vec_box)]
+    fn lock(&self) -> MutexGuard<'_, Vec<Box<Block>>> {
+        self.blocks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Block {
+    fn new(capacity: usize) -> Box<Self> {
+        let data = Box::<[u64]>::new_uninit_slice(capacity.div_ceil(size_of::<u64>()));
+        Box::new(Self {
+            // SAFETY: from a box, so never null
+            data: unsafe { NonNull::new_unchecked(Box::into_raw(data)) },
+            used: AtomicUsize::new(0),
+        })
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len() * size_of::<u64>()
+    }
+
+    fn at(&self, offset: usize) -> NonNull<u8> {
+        // SAFETY: callers only pass offsets within the block
+        unsafe { self.data.cast::<u8>().add(offset) }
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        // SAFETY: `data` came out of `Box::into_raw` in `new`, and nothing points into it any more
+        drop(unsafe { Box::from_raw(self.data.as_ptr()) });
+    }
+}
+
+#[cfg(test)]
+mod arena_test {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned_and_never_overlap() {
+        let arena = Arena::new();
+        let arena = &arena;
+        let mut allocations: Vec<(usize, usize)> = std::thread::scope(|scope| {
+            let writers: Vec<_> = (0..4usize)
+                .map(|writer| {
+                    scope.spawn(move || {
+                        (0..2000usize)
+                            .map(|i| {
+                                // Every so often one too large for a shared block
+                                let len = if i % 500 == 0 {
+                                    BLOCK_SIZE
+                                } else {
+                                    (i + writer) % 100
+                                };
+                                let fill = vec![writer as u8; len];
+                                let at = arena.alloc_bytes(&fill);
+                                (at.as_ptr() as usize, len, writer as u8)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            writers
+                .into_iter()
+                .flat_map(|writer| writer.join().unwrap())
+                .map(|(at, len, writer)| {
+                    // SAFETY: each allocation was filled by its writer and is still alive
+                    let bytes = unsafe { std::slice::from_raw_parts(at as *const u8, len) };
+                    assert!(bytes.iter().all(|&byte| byte == writer));
+                    (at, len)
+                })
+                .collect()
+        });
+
+        assert!(allocations.iter().all(|&(at, _)| at % ALIGN == 0));
+        allocations.sort_unstable();
+        assert!(
+            allocations
+                .windows(2)
+                .all(|pair| pair[0].0 + pair[0].1 <= pair[1].0)
+        );
+        let allocated: usize = allocations
+            .iter()
+            .map(|&(_, len)| len.max(1).next_multiple_of(ALIGN))
+            .sum();
+        assert_eq!(arena.allocated(), allocated);
+    }
+
+    #[test]
+    fn reset_frees_everything() {
+        let mut arena = Arena::new();
+        for _ in 0..100 {
+            arena.alloc_val([0u64; 1000]);
+        }
+        assert!(arena.lock().len() > 1);
+
+        arena.reset();
+        assert_eq!(arena.allocated(), 0);
+        assert!(arena.lock().is_empty());
+        let val = arena.alloc_val(7u64);
+        // SAFETY: just written
+        assert_eq!(unsafe { *val.as_ptr() }, 7);
+    }
+}
@@ -0,0 +1,328 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+
+use crate::compaction::{self, CompactionOptions};
+use crate::flush::{self, FlushOptions};
+use crate::sstable::SSTableConfig;
+use crate::table_cache::TableCache;
+use crate::types::DBError;
+use crate::version::VersionSet;
+
+/// Work handed to the background worker. Jobs run one at a time in the order they were scheduled, except
+/// for flushes which have a thread of their own so they never wait behind a compaction.
+pub(crate) enum Job {
+    /// Flushes the frozen MemTable, see `flush::flush`, then schedules a `Job::Compact`.
+    Flush,
+    /// Runs compactions until nothing is left to compact, see `compaction::compact`.
+    Compact,
+    /// Pushes the inclusive key range down to the last level, see `compaction::compact_range`. The result
+    /// is sent back once done.
+    CompactRange {
+        smallest: Vec<u8>,
+        largest: Vec<u8>,
+        done: Sender<Result<(), DBError>>,
+    },
+    /// Signals the sender once every job scheduled before it has run.
+    Barrier(Sender<()>),
+}
+
+/// The BackgroundWorker owns the threads that run flushes and compactions off the write path, so `put` only
+/// ever pays for scheduling them. The first error a compaction fails with is kept until taken with
+/// `take_error`, later jobs still run. A failed flush is reported through `flush::PendingFlush` instead.
+///
+/// Compactions are held back while the worker is paused, see `pause`. Flushes never are.
+///
+/// Dropping the worker runs the jobs already scheduled and joins the threads.
+pub(crate) struct BackgroundWorker {
+    sender: Option<Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+    flush_sender: Option<Sender<Job>>,
+    flush_handle: Option<JoinHandle<()>>,
+    error: Arc<Mutex<Option<DBError>>>,
+    pause: Arc<Pause>,
+}
+
+/// Lets the DB hold the worker back between jobs.
+#[derive(Default)]
+struct Pause {
+    state: Mutex<PauseState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct PauseState {
+    // Every `pause` has to be matched by a `resume` before jobs run again
+    pauses: u32,
+    running: bool,
+}
+
+impl Pause {
+    fn lock(&self) -> MutexGuard<'_, PauseState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait_while<'a>(
+        &self,
+        state: MutexGuard<'a, PauseState>,
+        condition: impl FnMut(&mut PauseState) -> bool,
+    ) -> MutexGuard<'a, PauseState> {
+        self.changed
+            .wait_while(state, condition)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What a job needs to get at the live tables.
+struct Context {
+    versions: Arc<Mutex<VersionSet>>,
+    table_cache: Arc<TableCache>,
+    compaction_options: CompactionOptions,
+    ss_table_config: SSTableConfig,
+}
+
+impl BackgroundWorker {
+    pub(crate) fn spawn(
+        versions: Arc<Mutex<VersionSet>>,
+        table_cache: Arc<TableCache>,
+        compaction_options: CompactionOptions,
+        ss_table_config: SSTableConfig,
+        flush_options: FlushOptions,
+    ) -> Result<Self, DBError> {
+        let (sender, receiver) = mpsc::channel();
+        let (flush_sender, flush_receiver) = mpsc::channel();
+        let error = Arc::new(Mutex::new(None));
+
+        let (flush_versions, flush_config, compact) =
+            (versions.clone(), ss_table_config.clone(), sender.clone());
+        let flush_handle = std::thread::Builder::new()
+            .name(String::from("lsmdb-flush"))
+            .spawn(move || {
+                run_flushes(
+                    flush_versions,
+                    flush_options,
+                    flush_config,
+                    flush_receiver,
+                    compact,
+                )
+            })
+            .map_err(|e| DBError::Io {
+                op: "failed to spawn flush worker",
+                path: Default::default(),
+                source: e,
+            })?;
+
+        let context = Context {
+            versions,
+            table_cache,
+            compaction_options,
+            ss_table_config,
+        };
+
+        let pause = Arc::new(Pause::default());
+        let (worker_error, worker_pause) = (error.clone(), pause.clone());
+        let handle = std::thread::Builder::new()
+            .name(String::from("lsmdb-background"))
+            .spawn(move || run(context, receiver, worker_error, worker_pause))
+            .map_err(|e| DBError::Io {
+                op: "failed to spawn background worker",
+                path: Default::default(),
+                source: e,
+            })?;
+
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            flush_sender: Some(flush_sender),
+            flush_handle: Some(flush_handle),
+            error,
+            pause,
+        })
+    }
+
+    pub(crate) fn schedule(&self, job: Job) {
+        let sender = match job {
+            Job::Flush => &self.flush_sender,
+            _ => &self.sender,
+        };
+        if let Some(sender) = sender {
+            // The worker only hangs up once shut down, at which point there is nothing left to do
+            let _ = sender.send(job);
+        }
+    }
+
+    /// Runs `compaction::compact_range` on the worker, so it never races a background compaction for the
+    /// same tables, and waits for it to finish.
+    pub(crate) fn compact_range(&self, smallest: Vec<u8>, largest: Vec<u8>) -> Result<(), DBError> {
+        // Waiting on a paused worker would block until someone else resumes it
+        if self.is_paused() {
+            return Err(DBError::Busy {
+                what: "compactions are paused",
+            });
+        }
+
+        let (done, result) = mpsc::channel();
+        self.schedule(Job::CompactRange {
+            smallest,
+            largest,
+            done,
+        });
+        // The worker only goes away early if a job panicked
+        result.recv().unwrap_or_else(|_| {
+            Err(DBError::Io {
+                op: "background worker exited",
+                path: Default::default(),
+                source: std::io::ErrorKind::BrokenPipe.into(),
+            })
+        })
+    }
+
+    /// Blocks until every job scheduled so far has run, along with the compactions the flushes among them
+    /// scheduled. Only waits for the flushes while paused, compactions won't run until resumed.
+    pub(crate) fn wait(&self) {
+        // A flush schedules its compaction before picking up the next job, so once the flush thread got to
+        // its barrier the second one is queued behind that compaction
+        Self::barrier(&self.flush_sender);
+        if !self.is_paused() {
+            Self::barrier(&self.sender);
+        }
+    }
+
+    fn barrier(sender: &Option<Sender<Job>>) {
+        if let Some(sender) = sender {
+            let (done, receiver) = mpsc::channel();
+            let _ = sender.send(Job::Barrier(done));
+            // An error means the worker is gone, in which case there is nothing to wait for
+            let _ = receiver.recv();
+        }
+    }
+
+    pub(crate) fn take_error(&self) -> Option<DBError> {
+        self.error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Holds back the compactions scheduled from now on, and blocks until the one running right now, if
+    /// any, has finished. Once this returns the worker does no compaction I/O until `resume` is called as
+    /// many times. Flushes keep running.
+    pub(crate) fn pause(&self) {
+        let mut state = self.pause.lock();
+        state.pauses += 1;
+        let _state = self.pause.wait_while(state, |state| state.running);
+    }
+
+    /// Undoes one `pause`, the worker picks up where it left off once every pause is undone. Does nothing
+    /// when not paused.
+    pub(crate) fn resume(&self) {
+        let mut state = self.pause.lock();
+        state.pauses = state.pauses.saturating_sub(1);
+        self.pause.changed.notify_all();
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.pause.lock().pauses > 0
+    }
+
+    /// Stops the worker once the jobs already scheduled have run, resuming it if need be.
+    pub(crate) fn shutdown(&mut self) {
+        self.pause.lock().pauses = 0;
+        self.pause.changed.notify_all();
+
+        // Hanging up ends the worker's loops once they have drained their queues. The flush thread goes
+        // first, it still schedules compactions on its way out
+        self.flush_sender = None;
+        if let Some(handle) = self.flush_handle.take() {
+            let _ = handle.join();
+        }
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for BackgroundWorker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run(
+    context: Context,
+    receiver: Receiver<Job>,
+    error: Arc<Mutex<Option<DBError>>>,
+    pause: Arc<Pause>,
+) {
+    while let Ok(job) = receiver.recv() {
+        if !matches!(job, Job::Barrier(_)) {
+            let mut state = pause.wait_while(pause.lock(), |state| state.pauses > 0);
+            state.running = true;
+        }
+
+        let result = match job {
+            // Never sent here, see `schedule`
+            Job::Flush => Ok(()),
+            Job::Compact => compaction::compact(
+                &context.versions,
+                &context.table_cache,
+                &context.compaction_options,
+                &context.ss_table_config,
+            ),
+            Job::CompactRange {
+                smallest,
+                largest,
+                done,
+            } => {
+                let result = compaction::compact_range(
+                    &context.versions,
+                    &context.table_cache,
+                    &smallest,
+                    &largest,
+                    &context.compaction_options,
+                    &context.ss_table_config,
+                );
+                // The caller gets the error, it is not a background failure
+                let _ = done.send(result);
+                Ok(())
+            }
+            Job::Barrier(done) => {
+                let _ = done.send(());
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get_or_insert(e);
+        }
+
+        pause.lock().running = false;
+        pause.changed.notify_all();
+    }
+}
+
+fn run_flushes(
+    versions: Arc<Mutex<VersionSet>>,
+    options: FlushOptions,
+    ss_table_config: SSTableConfig,
+    receiver: Receiver<Job>,
+    compact: Sender<Job>,
+) {
+    while let Ok(job) = receiver.recv() {
+        match job {
+            Job::Flush => {
+                flush::flush(&versions, &options, &ss_table_config);
+                let _ = compact.send(Job::Compact);
+            }
+            Job::Barrier(done) => {
+                let _ = done.send(());
+            }
+            // Never sent here, see `schedule`
+            Job::Compact | Job::CompactRange { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Atomic multi-key writes. A `WriteBatch` gathers puts and deletes which `DB::write` then commits as
+//! one: they are logged as a single WAL batch record and applied to the MemTable together, so a crash or
+//! a failed write leaves either all of them or none. A batch may write to column families too, when they
+//! share the WAL of their DB.
+
+use crate::column_family::ColumnFamilyHandle;
+use crate::types::Encode;
+use crate::wal::Op;
+
+/// A WriteBatch is a list of writes to commit at once with `DB::write`. The writes are applied in the
+/// order they were added, so a later write to a key wins over an earlier one in the same batch.
+///
+/// Nothing is checked until the batch is written, a batch holding an invalid write, e.g. one with an
+/// empty key, fails as a whole.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+    // The writes to column families, by column family in the order first written to
+    column_families: Vec<(ColumnFamilyHandle, WriteBatch)>,
+}
+
+/// A single write of a `WriteBatch`, laid out as its `WALRecord` minus the seq_no.
+#[derive(Debug, Clone)]
+pub(crate) struct BatchOp {
+    pub(crate) op: Op,
+    pub(crate) key: Vec<u8>,
+    pub(crate) val: Vec<u8>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put<K: Encode, V: Encode>(&mut self, key: &K, val: &V) -> &mut Self {
+        self.push(Op::Put, key.encode(), val.encode())
+    }
+
+    pub fn delete<K: Encode>(&mut self, key: &K) -> &mut Self {
+        self.push(Op::Delete, key.encode(), Vec::new())
+    }
+
+    /// Merges `operand` into the value of `key`, see `DB::merge`. A batch holding a merge fails as a whole
+    /// on a DB without a merge operator.
+    pub fn merge<K: Encode, V: Encode>(&mut self, key: &K, operand: &V) -> &mut Self {
+        self.push(Op::Merge, key.encode(), operand.encode())
+    }
+
+    /// Deletes every key in the half-open range `[start, end)` written before the batch, see
+    /// `DB::delete_range`.
+    pub fn delete_range<K: Encode>(&mut self, start: &K, end: &K) -> &mut Self {
+        self.push(Op::DeleteRange, start.encode(), end.encode())
+    }
+
+    /// `put` to the column family `cf`. Writing to column families takes `ColumnFamilyWal::Shared`, see
+    /// `DB::write`.
+    pub fn put_cf<K: Encode, V: Encode>(
+        &mut self,
+        cf: &ColumnFamilyHandle,
+        key: &K,
+        val: &V,
+    ) -> &mut Self {
+        self.cf_batch(cf).put(key, val);
+        self
+    }
+
+    pub fn delete_cf<K: Encode>(&mut self, cf: &ColumnFamilyHandle, key: &K) -> &mut Self {
+        self.cf_batch(cf).delete(key);
+        self
+    }
+
+    /// The number of writes in the batch, those to column families included.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+            + self
+                .column_families
+                .iter()
+                .map(|(_, batch)| batch.len())
+                .sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+        self.column_families.clear();
+    }
+
+    pub(crate) fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+
+    /// Splits the batch into its own writes and those it has for every column family.
+    pub(crate) fn into_parts(self) -> (WriteBatch, Vec<(ColumnFamilyHandle, WriteBatch)>) {
+        let own = WriteBatch {
+            ops: self.ops,
+            column_families: Vec::new(),
+        };
+        (own, self.column_families)
+    }
+
+    /// `put` of a value expiring at `expires_at`, see `DB::put_with_ttl`.
+    pub(crate) fn put_expiring(&mut self, key: Vec<u8>, val: &[u8], expires_at: u64) -> &mut Self {
+        self.push(
+            Op::ExpiringPut,
+            key,
+            [&expires_at.to_le_bytes(), val].concat(),
+        )
+    }
+
+    pub(crate) fn push_op(&mut self, op: BatchOp) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    fn push(&mut self, op: Op, key: Vec<u8>, val: Vec<u8>) -> &mut Self {
+        self.push_op(BatchOp { op, key, val })
+    }
+
+    fn cf_batch(&mut self, cf: &ColumnFamilyHandle) -> &mut WriteBatch {
+        let position = self
+            .column_families
+            .iter()
+            .position(|(handle, _)| handle == cf);
+        let index = position.unwrap_or_else(|| {
+            self.column_families.push((cf.clone(), WriteBatch::new()));
+            self.column_families.len() - 1
+        });
+        &mut self.column_families[index].1
+    }
+}
+
+#[cfg(test)]
+mod batch_test {
+    use super::*;
+
+    #[test]
+    fn writes_are_kept_in_the_order_added() {
+        let mut batch = WriteBatch::new();
+        batch
+            .put(&"a".to_string(), &"1".to_string())
+            .delete(&"b".to_string())
+            .delete_range(&"c".to_string(), &"d".to_string());
+        assert_eq!(batch.len(), 3);
+
+        let ops: Vec<_> = batch
+            .clone()
+            .into_ops()
+            .into_iter()
+            .map(|op| (op.op, op.key, op.val))
+            .collect();
+        assert_eq!(
+            ops,
+            [
+                (Op::Put, b"a".to_vec(), b"1".to_vec()),
+                (Op::Delete, b"b".to_vec(), Vec::new()),
+                (Op::DeleteRange, b"c".to_vec(), b"d".to_vec()),
+            ]
+        );
+
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+}
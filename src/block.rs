@@ -0,0 +1,727 @@
+use std::borrow::Cow;
+
+use crate::comparator::Comparator;
+use crate::entry::Entry;
+use crate::types::{read_u32_le, read_u64_le};
+
+pub const DEFAULT_RESTART_INTERVAL: usize = 16;
+
+const ENTRY_KIND_VALUE: u8 = 1;
+const ENTRY_KIND_TOMBSTONE: u8 = 2;
+const ENTRY_KIND_OVERFLOW: u8 = 3;
+// A value or overflow pointer that starts with the `u64` timestamp it was written at
+const ENTRY_KIND_TIMED_VALUE: u8 = 4;
+const ENTRY_KIND_TIMED_OVERFLOW: u8 = 5;
+// A merge operand, see `Entry::Merge`, laid out as a value
+const ENTRY_KIND_MERGE: u8 = 6;
+const ENTRY_KIND_TIMED_MERGE: u8 = 7;
+// A value or overflow pointer with a TTL, see `Entry::Value`. Its `u64` expiry comes after the timestamp,
+// if there is one, and before the val bytes
+const ENTRY_KIND_EXPIRING_VALUE: u8 = 8;
+const ENTRY_KIND_EXPIRING_OVERFLOW: u8 = 9;
+const ENTRY_KIND_TIMED_EXPIRING_VALUE: u8 = 10;
+const ENTRY_KIND_TIMED_EXPIRING_OVERFLOW: u8 = 11;
+
+/// [shared u32][unshared u32][kind u8][seq u64][val_len u32]
+const ENTRY_HEADER_LEN: usize = 4 + 4 + 1 + 8 + 4;
+
+/// The BlockBuilder encodes sorted entries into a single data block using shared-prefix compression.
+/// Each key only stores the bytes that differ from the previous key, except at every `restart_interval`-th
+/// entry (a restart point) where the full key is stored. Below is a map of the encoding:
+///
+/// [entry 0]...[entry n][restart_offset u32 * num_restarts][num_restarts u32]
+///
+/// where every entry is
+///
+/// [shared u32][unshared u32][kind u8][seq u64][val_len u32][unshared key bytes][val bytes]
+///
+/// Tombstones are written with a `val_len` of 0 so every entry shares the same header, merge operands
+/// like values. A value written with a timestamp is of its own kind, its val bytes start with the `u64`
+/// timestamp, and so is one written with a TTL, whose expiry comes next. Because restart
+/// points hold full keys, readers can binary-search over them before scanning a handful of entries.
+///
+/// Values too large for a data block are stored outside of it, the entry then only holds an opaque pointer
+/// to them (see `BlockEntry::Overflow`).
+pub struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            restarts: vec![0],
+            restart_interval: restart_interval.max(1),
+            counter: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Appends `entry` for `key`. Callers must add keys in increasing order, the versions of a key newest
+    /// first.
+    pub fn add(&mut self, key: &[u8], entry: &Entry) {
+        match entry {
+            Entry::Value {
+                seq_no,
+                val,
+                timestamp,
+                expires_at,
+            } => {
+                let kind = value_kind(*timestamp, *expires_at, false);
+                self.append(key, kind, *seq_no, [*timestamp, *expires_at], val)
+            }
+            Entry::Tombstone { seq_no } => {
+                self.append(key, ENTRY_KIND_TOMBSTONE, *seq_no, [None, None], &[])
+            }
+            Entry::Merge {
+                seq_no,
+                operand,
+                timestamp: None,
+            } => self.append(key, ENTRY_KIND_MERGE, *seq_no, [None, None], operand),
+            Entry::Merge {
+                seq_no,
+                operand,
+                timestamp,
+            } => self.append(
+                key,
+                ENTRY_KIND_TIMED_MERGE,
+                *seq_no,
+                [*timestamp, None],
+                operand,
+            ),
+        }
+    }
+
+    /// Appends a value for `key` that lives outside the block, `pointer` tells the reader where.
+    pub fn add_overflow(
+        &mut self,
+        key: &[u8],
+        seq_no: u64,
+        timestamp: Option<u64>,
+        expires_at: Option<u64>,
+        pointer: &[u8],
+    ) {
+        let kind = value_kind(timestamp, expires_at, true);
+        self.append(key, kind, seq_no, [timestamp, expires_at], pointer);
+    }
+
+    /// Appends an entry whose val bytes start with the `u64`s of `prefix` that are set, in order.
+    fn append(&mut self, key: &[u8], kind: u8, seq_no: u64, prefix: [Option<u64>; 2], val: &[u8]) {
+        let shared = if self.counter < self.restart_interval {
+            shared_prefix_len(&self.last_key, key)
+        } else {
+            self.restarts
+                .push(self.buf.len().try_into().expect("block too large"));
+            self.counter = 0;
+            0
+        };
+
+        let unshared = &key[shared..];
+        let shared_u32: u32 = shared.try_into().expect("key is too large");
+        let unshared_u32: u32 = unshared.len().try_into().expect("key is too large");
+        let prefix_len = prefix.iter().flatten().count() * 8;
+        let val_len: u32 = (prefix_len + val.len()).try_into().expect("val too large");
+
+        self.buf.extend_from_slice(&shared_u32.to_le_bytes());
+        self.buf.extend_from_slice(&unshared_u32.to_le_bytes());
+        self.buf.push(kind);
+        self.buf.extend_from_slice(&seq_no.to_le_bytes());
+        self.buf.extend_from_slice(&val_len.to_le_bytes());
+        self.buf.extend_from_slice(unshared);
+        for n in prefix.into_iter().flatten() {
+            self.buf.extend_from_slice(&n.to_le_bytes());
+        }
+        self.buf.extend_from_slice(val);
+
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(unshared);
+        self.counter += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The size of the block were it to be finished now.
+    pub fn estimated_size(&self) -> usize {
+        self.buf.len() + self.restarts.len() * 4 + 4
+    }
+
+    /// Appends the restart array and returns the encoded block, leaving the builder empty for reuse.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buf);
+        for restart in &self.restarts {
+            block.extend_from_slice(&restart.to_le_bytes());
+        }
+        let num_restarts: u32 = self.restarts.len().try_into().expect("block too large");
+        block.extend_from_slice(&num_restarts.to_le_bytes());
+
+        self.restarts = vec![0];
+        self.counter = 0;
+        self.last_key.clear();
+
+        block
+    }
+}
+
+/// An entry as it is stored in a data block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockEntry {
+    Entry(Entry),
+    /// A value stored outside the block, `pointer` is whatever was passed to `BlockBuilder::add_overflow`.
+    Overflow {
+        seq_no: u64,
+        pointer: Vec<u8>,
+        timestamp: Option<u64>,
+        expires_at: Option<u64>,
+    },
+}
+
+impl BlockEntry {
+    pub fn seq_no(&self) -> u64 {
+        match self {
+            BlockEntry::Entry(entry) => entry.seq_no(),
+            BlockEntry::Overflow { seq_no, .. } => *seq_no,
+        }
+    }
+}
+
+/// A decoded data block, see `BlockBuilder` for the layout. The block either owns its bytes or borrows
+/// them straight out of a memory-mapped table.
+pub struct Block<'a> {
+    data: Cow<'a, [u8]>,
+    // Where the restart array starts, which is also where the entries end
+    restarts_offset: usize,
+    num_restarts: usize,
+}
+
+impl<'a> Block<'a> {
+    pub fn decode(data: Cow<'a, [u8]>) -> Option<Self> {
+        let num_restarts = read_u32_le(data.get(data.len().checked_sub(4)?..)?)? as usize;
+        let restarts_len = num_restarts.checked_mul(4)?;
+        let restarts_offset = data.len().checked_sub(4 + restarts_len)?;
+
+        if num_restarts == 0 {
+            return None;
+        }
+
+        Some(Self {
+            data,
+            restarts_offset,
+            num_restarts,
+        })
+    }
+
+    /// Looks up `key` in the block, whose keys are sorted by `comparator`. Returns `None` when the block is
+    /// malformed.
+    pub fn get(&self, key: &[u8], comparator: &dyn Comparator) -> Option<Option<BlockEntry>> {
+        self.get_before(key, u64::MAX, comparator)
+    }
+
+    /// Looks up the newest version of `key` written before `read_seq_no`. The versions of a key follow
+    /// each other newest first, see `SSTableWriter::add`. Returns `None` when the block is malformed.
+    pub fn get_before(
+        &self,
+        key: &[u8],
+        read_seq_no: u64,
+        comparator: &dyn Comparator,
+    ) -> Option<Option<BlockEntry>> {
+        let mut offset = self.restart_point(self.seek_restart(key, comparator)?)?;
+        let mut entry_key = Vec::new();
+        while offset < self.restarts_offset {
+            let (entry, next) = self.decode_at(offset, &mut entry_key)?;
+            match comparator.compare(&entry_key, key) {
+                std::cmp::Ordering::Less => offset = next,
+                std::cmp::Ordering::Equal if entry.seq_no() >= read_seq_no => offset = next,
+                std::cmp::Ordering::Equal => return Some(Some(entry)),
+                std::cmp::Ordering::Greater => return Some(None),
+            }
+        }
+
+        Some(None)
+    }
+
+    /// Returns a cursor over the entries of the block, positioned nowhere until one of its seek methods is
+    /// called.
+    pub fn iter(self) -> BlockIter<'a> {
+        BlockIter {
+            block: self,
+            offset: 0,
+            next: 0,
+            key: Vec::new(),
+            entry: None,
+        }
+    }
+
+    /// Finds the last restart point whose key is < `key`, or the first one, the key can only live after
+    /// it. A restart point holding `key` itself may come after newer versions of the key.
+    fn seek_restart(&self, key: &[u8], comparator: &dyn Comparator) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.num_restarts - 1);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            let mut restart_key = Vec::new();
+            self.decode_at(self.restart_point(mid)?, &mut restart_key)?;
+            if comparator.compare(&restart_key, key).is_lt() {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+
+        Some(lo)
+    }
+
+    fn restart_point(&self, idx: usize) -> Option<usize> {
+        let offset = read_u32_le(self.data.get(self.restarts_offset + idx * 4..)?)? as usize;
+        (offset < self.restarts_offset).then_some(offset)
+    }
+
+    /// Decodes the entry starting at `offset`. `key` must hold the previous entry's key (or anything, at a
+    /// restart point) and is rewritten in place to the decoded entry's key. Returns the `Entry` and the
+    /// offset of the next entry.
+    fn decode_at(&self, offset: usize, key: &mut Vec<u8>) -> Option<(BlockEntry, usize)> {
+        let entries = &self.data[..self.restarts_offset];
+        let header = entries.get(offset..offset + ENTRY_HEADER_LEN)?;
+
+        let shared = read_u32_le(header)? as usize;
+        let unshared = read_u32_le(&header[4..])? as usize;
+        let kind = header[8];
+        let seq_no = read_u64_le(&header[9..])?;
+        let val_len = read_u32_le(&header[17..])? as usize;
+
+        if shared > key.len() {
+            return None;
+        }
+
+        let key_start = offset + ENTRY_HEADER_LEN;
+        let val_start = key_start + unshared;
+        let next = val_start + val_len;
+
+        key.truncate(shared);
+        key.extend_from_slice(entries.get(key_start..val_start)?);
+        let val = entries.get(val_start..next)?;
+
+        let (timestamp, val) = match kind {
+            ENTRY_KIND_TIMED_VALUE
+            | ENTRY_KIND_TIMED_OVERFLOW
+            | ENTRY_KIND_TIMED_MERGE
+            | ENTRY_KIND_TIMED_EXPIRING_VALUE
+            | ENTRY_KIND_TIMED_EXPIRING_OVERFLOW => (Some(read_u64_le(val)?), val.get(8..)?),
+            _ => (None, val),
+        };
+        let (expires_at, val) = match kind {
+            ENTRY_KIND_EXPIRING_VALUE
+            | ENTRY_KIND_EXPIRING_OVERFLOW
+            | ENTRY_KIND_TIMED_EXPIRING_VALUE
+            | ENTRY_KIND_TIMED_EXPIRING_OVERFLOW => (Some(read_u64_le(val)?), val.get(8..)?),
+            _ => (None, val),
+        };
+        let entry = match kind {
+            ENTRY_KIND_VALUE
+            | ENTRY_KIND_TIMED_VALUE
+            | ENTRY_KIND_EXPIRING_VALUE
+            | ENTRY_KIND_TIMED_EXPIRING_VALUE => BlockEntry::Entry(Entry::Value {
+                seq_no,
+                val: val.to_vec(),
+                timestamp,
+                expires_at,
+            }),
+            ENTRY_KIND_TOMBSTONE => BlockEntry::Entry(Entry::Tombstone { seq_no }),
+            ENTRY_KIND_MERGE | ENTRY_KIND_TIMED_MERGE => BlockEntry::Entry(Entry::Merge {
+                seq_no,
+                operand: val.to_vec(),
+                timestamp,
+            }),
+            ENTRY_KIND_OVERFLOW
+            | ENTRY_KIND_TIMED_OVERFLOW
+            | ENTRY_KIND_EXPIRING_OVERFLOW
+            | ENTRY_KIND_TIMED_EXPIRING_OVERFLOW => BlockEntry::Overflow {
+                seq_no,
+                pointer: val.to_vec(),
+                timestamp,
+                expires_at,
+            },
+            _ => return None,
+        };
+
+        Some((entry, next))
+    }
+}
+
+/// A cursor over the entries of a `Block` in key order. Every positioning method returns `None` when the
+/// block turns out to be malformed, after which the cursor is no longer valid.
+pub struct BlockIter<'a> {
+    block: Block<'a>,
+    // Where the current entry starts and where the one after it starts
+    offset: usize,
+    next: usize,
+    key: Vec<u8>,
+    entry: Option<BlockEntry>,
+}
+
+impl BlockIter<'_> {
+    /// Whether the cursor is positioned at an entry.
+    pub fn valid(&self) -> bool {
+        self.entry.is_some()
+    }
+
+    /// The key of the current entry. Only meaningful while `valid()`.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The current entry, or `None` when the cursor is not `valid()`.
+    pub fn entry(&self) -> Option<&BlockEntry> {
+        self.entry.as_ref()
+    }
+
+    pub fn seek_to_first(&mut self) -> Option<()> {
+        let offset = self.block.restart_point(0)?;
+        self.decode_at(offset)
+    }
+
+    pub fn seek_to_last(&mut self) -> Option<()> {
+        let offset = self.block.restart_point(self.block.num_restarts - 1)?;
+        self.decode_at(offset)?;
+        while self.next < self.block.restarts_offset {
+            self.decode_at(self.next)?;
+        }
+        Some(())
+    }
+
+    /// Positions the cursor at the first entry whose key is >= `key` by `comparator`, leaving it invalid if
+    /// there is none.
+    pub fn seek(&mut self, key: &[u8], comparator: &dyn Comparator) -> Option<()> {
+        let restart = self.block.seek_restart(key, comparator)?;
+        self.decode_at(self.block.restart_point(restart)?)?;
+        while comparator.compare(&self.key, key).is_lt() {
+            if self.next >= self.block.restarts_offset {
+                self.invalidate();
+                return Some(());
+            }
+            self.decode_at(self.next)?;
+        }
+        Some(())
+    }
+
+    // A cursor step rather than `Iterator::next`, the cursor also has to move backwards
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<()> {
+        if !self.valid() {
+            return Some(());
+        }
+        if self.next >= self.block.restarts_offset {
+            self.invalidate();
+            return Some(());
+        }
+        self.decode_at(self.next)
+    }
+
+    pub fn prev(&mut self) -> Option<()> {
+        if !self.valid() {
+            return Some(());
+        }
+
+        // Entries only decode forwards, so scan up from the last restart point before the current entry
+        let target = self.offset;
+        let mut restart = self.block.num_restarts;
+        loop {
+            if restart == 0 {
+                self.invalidate();
+                return Some(());
+            }
+            restart -= 1;
+            if self.block.restart_point(restart)? < target {
+                break;
+            }
+        }
+
+        self.decode_at(self.block.restart_point(restart)?)?;
+        while self.next < target {
+            self.decode_at(self.next)?;
+        }
+        Some(())
+    }
+
+    fn decode_at(&mut self, offset: usize) -> Option<()> {
+        match self.block.decode_at(offset, &mut self.key) {
+            Some((entry, next)) => {
+                self.offset = offset;
+                self.next = next;
+                self.entry = Some(entry);
+                Some(())
+            }
+            None => {
+                self.invalidate();
+                None
+            }
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.entry = None;
+        self.key.clear();
+    }
+}
+
+/// The kind of a value, or of an `overflow` pointer to one, written at `timestamp` and expiring at
+/// `expires_at`.
+fn value_kind(timestamp: Option<u64>, expires_at: Option<u64>, overflow: bool) -> u8 {
+    match (timestamp.is_some(), expires_at.is_some(), overflow) {
+        (false, false, false) => ENTRY_KIND_VALUE,
+        (false, false, true) => ENTRY_KIND_OVERFLOW,
+        (true, false, false) => ENTRY_KIND_TIMED_VALUE,
+        (true, false, true) => ENTRY_KIND_TIMED_OVERFLOW,
+        (false, true, false) => ENTRY_KIND_EXPIRING_VALUE,
+        (false, true, true) => ENTRY_KIND_EXPIRING_OVERFLOW,
+        (true, true, false) => ENTRY_KIND_TIMED_EXPIRING_VALUE,
+        (true, true, true) => ENTRY_KIND_TIMED_EXPIRING_OVERFLOW,
+    }
+}
+
+fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+#[cfg(test)]
+mod block_test {
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+
+    #[test]
+    fn prefix_compression_and_restart_search() {
+        let mut builder = BlockBuilder::new(4);
+        let keys: Vec<Vec<u8>> = (0..50)
+            .map(|i| format!("a/very/long/common/prefix/{i:03}").into_bytes())
+            .collect();
+
+        // What the entries would cost with full keys
+        let mut uncompressed_len = 0;
+        for (i, key) in keys.iter().enumerate() {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: vec![i as u8],
+                timestamp: None,
+                expires_at: None,
+            };
+            uncompressed_len += ENTRY_HEADER_LEN + key.len() + 1;
+            builder.add(key, &entry);
+        }
+        let encoded = builder.finish();
+        assert!(encoded.len() + 500 < uncompressed_len);
+        assert!(builder.is_empty());
+
+        let block = Block::decode(encoded.into()).unwrap();
+        assert_eq!(block.num_restarts, 13);
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                block.get(key, &BytewiseComparator),
+                Some(Some(BlockEntry::Entry(Entry::Value {
+                    seq_no: i as u64,
+                    val: vec![i as u8],
+                    timestamp: None,
+                    expires_at: None,
+                })))
+            );
+        }
+
+        assert_eq!(block.get(b"a", &BytewiseComparator), Some(None));
+        assert_eq!(
+            block.get(b"a/very/long/common/prefix/0005", &BytewiseComparator),
+            Some(None)
+        );
+        assert_eq!(block.get(b"z", &BytewiseComparator), Some(None));
+    }
+
+    #[test]
+    fn timestamps_are_kept_with_values_overflows_and_merge_operands() {
+        let timed = Entry::Value {
+            seq_no: 1,
+            val: b"val".to_vec(),
+            timestamp: Some(1_700_000_000_000),
+            expires_at: None,
+        };
+        let untimed = Entry::Value {
+            seq_no: 2,
+            val: b"val".to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+        let mut builder = BlockBuilder::new(DEFAULT_RESTART_INTERVAL);
+        builder.add(b"a", &timed);
+        builder.add(b"b", &untimed);
+        builder.add_overflow(b"c", 3, Some(1_700_000_000_003), None, b"pointer");
+        let merges = [
+            Entry::Merge {
+                seq_no: 4,
+                operand: b"+1".to_vec(),
+                timestamp: Some(1_700_000_000_004),
+            },
+            Entry::Merge {
+                seq_no: 5,
+                operand: b"+2".to_vec(),
+                timestamp: None,
+            },
+        ];
+        builder.add(b"d", &merges[0]);
+        builder.add(b"e", &merges[1]);
+        let block = Block::decode(builder.finish().into()).unwrap();
+
+        assert_eq!(
+            block.get(b"a", &BytewiseComparator),
+            Some(Some(BlockEntry::Entry(timed)))
+        );
+        assert_eq!(
+            block.get(b"b", &BytewiseComparator),
+            Some(Some(BlockEntry::Entry(untimed)))
+        );
+        assert_eq!(
+            block.get(b"c", &BytewiseComparator),
+            Some(Some(BlockEntry::Overflow {
+                seq_no: 3,
+                pointer: b"pointer".to_vec(),
+                timestamp: Some(1_700_000_000_003),
+                expires_at: None,
+            }))
+        );
+        let [timed_merge, untimed_merge] = merges;
+        assert_eq!(
+            block.get(b"d", &BytewiseComparator),
+            Some(Some(BlockEntry::Entry(timed_merge)))
+        );
+        assert_eq!(
+            block.get(b"e", &BytewiseComparator),
+            Some(Some(BlockEntry::Entry(untimed_merge)))
+        );
+    }
+
+    #[test]
+    fn expiries_are_kept_with_values_and_overflows_timed_or_not() {
+        let value = |timestamp, expires_at| Entry::Value {
+            seq_no: 1,
+            val: b"val".to_vec(),
+            timestamp,
+            expires_at,
+        };
+        let overflow = |timestamp, expires_at| BlockEntry::Overflow {
+            seq_no: 2,
+            pointer: b"pointer".to_vec(),
+            timestamp,
+            expires_at,
+        };
+        let mut builder = BlockBuilder::new(DEFAULT_RESTART_INTERVAL);
+        builder.add(b"a", &value(None, Some(2_000)));
+        builder.add(b"b", &value(Some(1_000), Some(2_000)));
+        builder.add_overflow(b"c", 2, None, Some(3_000), b"pointer");
+        builder.add_overflow(b"d", 2, Some(1_000), Some(3_000), b"pointer");
+        let block = Block::decode(builder.finish().into()).unwrap();
+
+        let entry = |entry| Some(Some(BlockEntry::Entry(entry)));
+        assert_eq!(
+            block.get(b"a", &BytewiseComparator),
+            entry(value(None, Some(2_000)))
+        );
+        assert_eq!(
+            block.get(b"b", &BytewiseComparator),
+            entry(value(Some(1_000), Some(2_000)))
+        );
+        assert_eq!(
+            block.get(b"c", &BytewiseComparator),
+            Some(Some(overflow(None, Some(3_000))))
+        );
+        assert_eq!(
+            block.get(b"d", &BytewiseComparator),
+            Some(Some(overflow(Some(1_000), Some(3_000))))
+        );
+    }
+
+    #[test]
+    fn iterate_forwards_backwards_and_seek() {
+        let mut builder = BlockBuilder::new(3);
+        let keys: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!("key-{i:02}").into_bytes())
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            builder.add(key, &Entry::Tombstone { seq_no: i as u64 });
+        }
+        builder.add_overflow(b"key-20", 20, None, None, b"pointer");
+        let mut iter = Block::decode(builder.finish().into()).unwrap().iter();
+        assert!(!iter.valid());
+
+        let mut forwards = Vec::new();
+        iter.seek_to_first().unwrap();
+        while iter.valid() {
+            forwards.push(iter.key().to_vec());
+            iter.next().unwrap();
+        }
+        assert_eq!(forwards[..20], keys);
+        assert_eq!(forwards[20], b"key-20");
+
+        let mut backwards = Vec::new();
+        iter.seek_to_last().unwrap();
+        while iter.valid() {
+            backwards.push(iter.key().to_vec());
+            iter.prev().unwrap();
+        }
+        backwards.reverse();
+        assert_eq!(backwards, forwards);
+
+        iter.seek(b"key-20", &BytewiseComparator).unwrap();
+        assert_eq!(
+            iter.entry(),
+            Some(&BlockEntry::Overflow {
+                seq_no: 20,
+                pointer: b"pointer".to_vec(),
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+        iter.seek(b"key-07", &BytewiseComparator).unwrap();
+        assert_eq!(iter.key(), b"key-07");
+        assert_eq!(
+            iter.entry(),
+            Some(&BlockEntry::Entry(Entry::Tombstone { seq_no: 7 }))
+        );
+        iter.seek(b"key-07a", &BytewiseComparator).unwrap();
+        assert_eq!(iter.key(), b"key-08");
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"key-07");
+        iter.seek(b"a", &BytewiseComparator).unwrap();
+        assert_eq!(iter.key(), b"key-00");
+        iter.seek(b"z", &BytewiseComparator).unwrap();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn versions_of_a_key_are_found_across_restart_points() {
+        // "b" has six versions, newest first, with restart points falling among them
+        let mut builder = BlockBuilder::new(2);
+        builder.add(b"a", &Entry::Tombstone { seq_no: 1 });
+        for seq_no in (10..16).rev() {
+            builder.add(b"b", &Entry::Tombstone { seq_no });
+        }
+        builder.add(b"c", &Entry::Tombstone { seq_no: 2 });
+        let block = Block::decode(builder.finish().into()).unwrap();
+
+        let get = |key: &[u8], read_seq_no| {
+            block
+                .get_before(key, read_seq_no, &BytewiseComparator)
+                .unwrap()
+                .map(|entry| entry.seq_no())
+        };
+        assert_eq!(get(b"b", u64::MAX), Some(15));
+        assert_eq!(get(b"b", 15), Some(14));
+        assert_eq!(get(b"b", 12), Some(11));
+        assert_eq!(get(b"b", 10), None);
+        assert_eq!(get(b"c", 10), Some(2));
+
+        let mut iter = block.iter();
+        iter.seek(b"b", &BytewiseComparator).unwrap();
+        assert_eq!(iter.entry().map(BlockEntry::seq_no), Some(15));
+    }
+}
@@ -0,0 +1,282 @@
+/// The BloomFilter is a probabilistic set used to answer "is this key definitely absent?" without touching
+/// the underlying data. `may_contain` never returns `false` for a key that was inserted, but may return
+/// `true` for keys that were not (a false positive), at roughly the rate the filter was sized for.
+///
+/// Probing uses double hashing i.e. `h_i = h1 + i * h2`, deriving both halves from a single 64-bit hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    num_probes: u32,
+}
+
+const MAX_PROBES: u32 = 30;
+
+impl BloomFilter {
+    /// Sizes a filter for `expected_items` keys at the given `false_positive_rate` (e.g. `0.01` for 1%).
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        // m = -n * ln(p) / ln(2)^2, k = m / n * ln(2)
+        let num_bits = ((-n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let num_probes = ((num_bits as f64 / n) * ln2).round() as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(8)],
+            num_probes: num_probes.clamp(1, MAX_PROBES),
+        }
+    }
+
+    /// Builds a filter holding every key in `keys`.
+    pub fn from_keys<'a>(
+        keys: impl ExactSizeIterator<Item = &'a [u8]>,
+        false_positive_rate: f64,
+    ) -> Self {
+        let mut filter = Self::new(keys.len(), false_positive_rate);
+        for key in keys {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        self.insert_hash(hash(key));
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.may_contain_hash(hash(key))
+    }
+
+    /// The bytes taken by the filter's bits.
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub(crate) fn insert_hash(&mut self, hash: u64) {
+        let num_bits = self.num_bits();
+        for bit in probes(hash, self.num_probes, num_bits) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub(crate) fn may_contain_hash(&self, hash: u64) -> bool {
+        may_contain_bits(&self.bits, self.num_probes, hash)
+    }
+
+    /// Probes an encoded filter in place, without copying its bits out first. A malformed filter can't
+    /// rule anything out, so it answers `true`.
+    pub fn encoded_may_contain(buf: &[u8], hash: u64) -> bool {
+        match split_encoded(buf) {
+            Some((bits, num_probes)) => may_contain_bits(bits, num_probes, hash),
+            None => true,
+        }
+    }
+
+    /// Encodes the filter as [bits bytes][num_probes u8].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.bits.len() + 1);
+        out.extend_from_slice(&self.bits);
+        out.push(self.num_probes as u8);
+        out
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (bits, num_probes) = split_encoded(buf)?;
+        Some(Self {
+            bits: bits.to_vec(),
+            num_probes,
+        })
+    }
+
+    fn num_bits(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+fn probes(hash: u64, num_probes: u32, num_bits: usize) -> impl Iterator<Item = usize> {
+    let h1 = hash as u32;
+    let h2 = (hash >> 32) as u32;
+    (0..num_probes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) as usize) % num_bits)
+}
+
+fn may_contain_bits(bits: &[u8], num_probes: u32, hash: u64) -> bool {
+    probes(hash, num_probes, bits.len() * 8).all(|bit| bits[bit / 8] & (1 << (bit % 8)) != 0)
+}
+
+/// The number of bits in a `BlockedBloomFilter` block, one 64 byte cache line.
+const BLOCK_BITS: usize = 512;
+const BLOCK_BYTES: usize = BLOCK_BITS / 8;
+
+/// The BlockedBloomFilter is a bloom filter split into cache line sized blocks. Every key maps to a single
+/// block and all of its probes land within it, so a lookup touches one cache line rather than
+/// `num_probes` random ones. The price is a somewhat higher false-positive rate than a `BloomFilter` of the
+/// same size, since keys are not spread evenly across blocks.
+///
+/// The upper half of the hash picks the block and the lower half drives the probes within it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedBloomFilter {
+    blocks: Vec<u8>,
+    num_probes: u32,
+}
+
+impl BlockedBloomFilter {
+    /// Sizes a filter for `expected_items` keys at roughly the given `false_positive_rate`.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        // Sized like a `BloomFilter`, plus 1/8th to make up for the uneven load across blocks
+        let num_bits = ((-n * p.ln()) / (ln2 * ln2) * 1.125).ceil() as usize;
+        let num_blocks = num_bits.div_ceil(BLOCK_BITS).max(1);
+        let num_probes = ((num_bits as f64 / n) * ln2).round() as u32;
+
+        Self {
+            blocks: vec![0; num_blocks * BLOCK_BYTES],
+            num_probes: num_probes.clamp(1, MAX_PROBES),
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        self.insert_hash(hash(key));
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.may_contain_hash(hash(key))
+    }
+
+    pub(crate) fn insert_hash(&mut self, hash: u64) {
+        let num_blocks = self.blocks.len() / BLOCK_BYTES;
+        for bit in blocked_probes(hash, self.num_probes, num_blocks) {
+            self.blocks[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub(crate) fn may_contain_hash(&self, hash: u64) -> bool {
+        may_contain_blocks(&self.blocks, self.num_probes, hash)
+    }
+
+    /// Probes an encoded filter in place, see `BloomFilter::encoded_may_contain`.
+    pub fn encoded_may_contain(buf: &[u8], hash: u64) -> bool {
+        match split_encoded(buf) {
+            Some((blocks, num_probes)) if blocks.len() % BLOCK_BYTES == 0 => {
+                may_contain_blocks(blocks, num_probes, hash)
+            }
+            _ => true,
+        }
+    }
+
+    /// Encodes the filter as [blocks bytes][num_probes u8].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.blocks.len() + 1);
+        out.extend_from_slice(&self.blocks);
+        out.push(self.num_probes as u8);
+        out
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (blocks, num_probes) = split_encoded(buf)?;
+        if blocks.len() % BLOCK_BYTES != 0 {
+            return None;
+        }
+
+        Some(Self {
+            blocks: blocks.to_vec(),
+            num_probes,
+        })
+    }
+}
+
+/// Yields the absolute bit positions probed for `hash`, all within the same block.
+fn blocked_probes(hash: u64, num_probes: u32, num_blocks: usize) -> impl Iterator<Item = usize> {
+    let block_start = ((hash >> 32) as usize % num_blocks) * BLOCK_BITS;
+    let mut h = hash as u32;
+    let delta = h.rotate_left(15) | 1;
+    (0..num_probes).map(move |_| {
+        let bit = block_start + h as usize % BLOCK_BITS;
+        h = h.wrapping_add(delta);
+        bit
+    })
+}
+
+fn may_contain_blocks(blocks: &[u8], num_probes: u32, hash: u64) -> bool {
+    blocked_probes(hash, num_probes, blocks.len() / BLOCK_BYTES)
+        .all(|bit| blocks[bit / 8] & (1 << (bit % 8)) != 0)
+}
+
+/// Splits an encoded filter into its bits and probe count.
+fn split_encoded(buf: &[u8]) -> Option<(&[u8], u32)> {
+    let (num_probes, bits) = buf.split_last()?;
+    if bits.is_empty() || *num_probes == 0 || *num_probes as u32 > MAX_PROBES {
+        return None;
+    }
+
+    Some((bits, *num_probes as u32))
+}
+
+/// 64-bit FNV-1a with a final avalanche step, so that both 32-bit halves are usable as independent hashes.
+/// This is the hash `FilterPolicy` implementations are handed for every key.
+pub fn hash(key: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key {
+        h ^= *byte as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    // splitmix64 finalizer
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+#[cfg(test)]
+mod bloom_test {
+    use super::*;
+
+    #[test]
+    fn no_false_negatives_and_bounded_false_positives() {
+        let keys: Vec<Vec<u8>> = (0..10_000)
+            .map(|i| format!("key-{i}").into_bytes())
+            .collect();
+        let filter = BloomFilter::from_keys(keys.iter().map(|k| k.as_slice()), 0.01);
+
+        assert!(keys.iter().all(|k| filter.may_contain(k)));
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.may_contain(format!("missing-{i}").as_bytes()))
+            .count();
+        // 1% target, allow some slack
+        assert!(false_positives < 300, "false_positives: {false_positives}");
+
+        let decoded = BloomFilter::decode(&filter.encode()).unwrap();
+        assert_eq!(decoded, filter);
+    }
+
+    #[test]
+    fn blocked_filter_has_no_false_negatives() {
+        let mut filter = BlockedBloomFilter::new(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(format!("key-{i}").as_bytes());
+        }
+
+        let encoded = filter.encode();
+        for i in 0..10_000 {
+            let h = hash(format!("key-{i}").as_bytes());
+            assert!(filter.may_contain_hash(h));
+            assert!(BlockedBloomFilter::encoded_may_contain(&encoded, h));
+        }
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.may_contain(format!("missing-{i}").as_bytes()))
+            .count();
+        // Blocking costs some accuracy, allow more slack than the plain filter
+        assert!(false_positives < 400, "false_positives: {false_positives}");
+
+        assert_eq!(BlockedBloomFilter::decode(&encoded).unwrap(), filter);
+        // Not a whole number of blocks
+        assert_eq!(BlockedBloomFilter::decode(&encoded[1..]), None);
+    }
+}
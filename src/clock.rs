@@ -0,0 +1,51 @@
+//! The time as the DB tells it. Write times and TTL expiries, see `DB::put_with_ttl`, are read off the
+//! `Clock` of `DBConfig::clock`, which tests swap for a `ManualClock` to move time along themselves.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The time in milliseconds since the UNIX epoch.
+    fn now_millis(&self) -> u64;
+}
+
+/// The wall clock, 0 for a clock set before the UNIX epoch.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now_millis: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now_millis),
+        }
+    }
+
+    pub fn set(&self, now_millis: u64) {
+        self.now.store(now_millis, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,144 @@
+//! Column families, separate keyspaces within one DB. Each column family is a DB of its own, with its own
+//! MemTable, levels and `DBConfig`, kept in a directory under the ss_table_dir and wal_dir of the DB it
+//! belongs to, whose manifest records it. Datasets with different shapes, e.g. data and an index over it,
+//! are then tuned apart and never mix keys. Whether they also keep WALs apart is up to
+//! `DBConfig::column_family_wal`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::types::DBError;
+use crate::wal::WAL;
+use crate::{DB, DBConfig};
+
+/// The directory under the ss_table_dir and wal_dir holding a directory for every column family.
+const COLUMN_FAMILIES_DIR: &str = "column_families";
+
+/// A handle to a column family, from `DB::create_cf` or `DB::cf_handle`. It must only be used with the DB
+/// it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamilyHandle {
+    id: usize,
+    name: String,
+}
+
+impl ColumnFamilyHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The id the column family's records are tagged with in a shared WAL, see `ColumnFamilyWal::Shared`.
+    pub(crate) fn wal_id(&self) -> u32 {
+        wal_id(self.id)
+    }
+}
+
+/// Where the column families of a DB log their writes, see `DBConfig::column_family_wal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColumnFamilyWal {
+    /// Every column family has a WAL of its own, so a column family written to a lot neither holds back
+    /// the truncation of the other WALs nor has its writes replayed along with theirs on open. A
+    /// `WriteBatch` can't span column families.
+    #[default]
+    Separate,
+    /// The column families log to the WAL of their DB, their records tagged with the column family they
+    /// belong to, see `wal::column_family_record`. A `WriteBatch` spanning column families is then logged as
+    /// a single record and commits atomically.
+    ///
+    /// A segment is only truncated once every column family has flushed its writes in it, so one written
+    /// to rarely holds on to the WAL until its MemTable fills up. The WAL options of the column families'
+    /// configs go unused. Tagged records can't be told from those a recycled segment is left with, so
+    /// `wal_recycle_files` must be 0.
+    Shared,
+}
+
+pub(crate) struct ColumnFamily {
+    pub(crate) name: String,
+    pub(crate) db: DB,
+}
+
+impl ColumnFamily {
+    /// Opens the column family `name` of the DB configured with `parent`, the `id`th it has. The
+    /// directories of `opts` are replaced by the column family's own. Under `ColumnFamilyWal::Shared` it
+    /// logs to `wal`, that of the DB.
+    pub(crate) fn open(
+        parent: &DBConfig,
+        wal: &Arc<WAL>,
+        id: usize,
+        name: &str,
+        mut opts: DBConfig,
+    ) -> Result<Self, DBError> {
+        validate_name(name)?;
+        opts.ss_table_dir = dir(&parent.ss_table_dir, name);
+        opts.wal_dir = dir(&parent.wal_dir, name);
+        let shared_wal = match parent.column_family_wal {
+            ColumnFamilyWal::Separate => None,
+            ColumnFamilyWal::Shared => Some((wal.clone(), wal_id(id))),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            db: DB::open(opts, shared_wal)?,
+        })
+    }
+}
+
+/// The id the records of the `id`th column family are tagged with in a shared WAL, those of the DB itself
+/// taking 0.
+fn wal_id(id: usize) -> u32 {
+    u32::try_from(id + 1).expect("too many column families")
+}
+
+/// The column family whose records are tagged with `wal_id` among `families`, see `wal_id`.
+pub(crate) fn get_by_wal_id(
+    families: &mut [ColumnFamily],
+    wal_id: u32,
+) -> Option<&mut ColumnFamily> {
+    let id = (wal_id as usize).checked_sub(1)?;
+    families.get_mut(id)
+}
+
+/// Looks up the column family `cf` is a handle to among `families`.
+///
+/// # Panics
+///
+/// If `cf` comes from another DB.
+pub(crate) fn get<'a>(families: &'a [ColumnFamily], cf: &ColumnFamilyHandle) -> &'a ColumnFamily {
+    families
+        .get(cf.id)
+        .filter(|family| family.name == cf.name)
+        .expect("column family handle from another DB")
+}
+
+pub(crate) fn get_mut<'a>(
+    families: &'a mut [ColumnFamily],
+    cf: &ColumnFamilyHandle,
+) -> &'a mut ColumnFamily {
+    families
+        .get_mut(cf.id)
+        .filter(|family| family.name == cf.name)
+        .expect("column family handle from another DB")
+}
+
+/// The handle to the column family `name` among `families`, if there is one.
+pub(crate) fn handle(families: &[ColumnFamily], name: &str) -> Option<ColumnFamilyHandle> {
+    let id = families.iter().position(|family| family.name == name)?;
+    Some(ColumnFamilyHandle {
+        id,
+        name: name.to_string(),
+    })
+}
+
+/// Column family names name directories, so they are kept to ASCII letters, digits, `_` and `-`.
+fn validate_name(name: &str) -> Result<(), DBError> {
+    let valid = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-';
+    if name.is_empty() || !name.bytes().all(valid) {
+        return Err(DBError::InvalidConfig {
+            what: "column family names must be non-empty ASCII letters, digits, '_' or '-'",
+        });
+    }
+    Ok(())
+}
+
+fn dir(parent: &Path, name: &str) -> PathBuf {
+    parent.join(COLUMN_FAMILIES_DIR).join(name)
+}
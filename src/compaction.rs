@@ -0,0 +1,1668 @@
+//! Compaction merges SSTables into fewer, non-overlapping tables further down the tree. Every flush adds
+//! an L0 table whose key range may overlap any other L0 table, so a read may have to consult all of them.
+//! Once there are more than `ss_l0_compact_threshold` L0 tables they are merged, together with the L1
+//! tables they overlap, into a single L1 table. Only the newest version of every key survives the merge,
+//! along with the older versions live snapshots still read.
+//!
+//! Below L0 the tables of a level never overlap, and every level has a target size `level_base_size *
+//! level_multiplier^(level - 1)`. A level that outgrows its target has one of its tables merged into the
+//! tables it overlaps in the next level. The last level has no target, it is where data finally settles.
+//! That is the default `LeveledCompactionPicker`, a different `CompactionPicker` can be plugged in to
+//! decide otherwise which tables go first.
+//!
+//! A tombstone only has to be kept as long as there may be an older version of its key to shadow. Once a
+//! compaction's output is the bottommost data for its key range, i.e. no table further down overlaps it,
+//! the tombstone and every version it shadows are dropped, unless a live snapshot still needs them.
+//!
+//! A version of a key is read by the snapshots taken after it was written but before the next version was,
+//! or a range tombstone deleted it, see `Snapshot`. Each of those versions is written out along with the
+//! newest one, and dropped by the first compaction after its last snapshot is released.
+//!
+//! Range tombstones from `DB::delete_range` are carried along the same way: the entries an input's range
+//! tombstone deletes are dropped by every compaction, while the range tombstone itself, cut down to the
+//! output's key range, is kept until it reaches the bottommost data too.
+//!
+//! A `CompactionFilter` sees every value a compaction writes, and may drop or rewrite it on the way.
+//!
+//! `CompactionStyle::Fifo` skips all of the above and never merges, it only drops the oldest tables once
+//! they exceed a size or age budget.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::comparator::Comparator;
+use crate::entry::{self, Entry, RangeTombstone};
+use crate::iterator::{EntryIterator, MergingIterator};
+use crate::listener::{CompactionJobInfo, EventListener};
+use crate::manifest::VersionEdit;
+use crate::merge::{self, MergeOperator};
+use crate::sstable::{SSTableConfig, SSTableMeta, SSTableWriter, TableProperties, table_file_name};
+use crate::table_cache::TableCache;
+use crate::types::DBError;
+use crate::version::{self, VersionSet};
+
+/// The number of levels, L0 included.
+pub(crate) const NUM_LEVELS: u32 = 7;
+
+/// How SSTables are compacted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompactionStyle {
+    /// Tables are merged down a tree of levels, see the module docs.
+    #[default]
+    Leveled,
+    /// Tables stay in L0 and are never merged. The oldest tables are deleted, data and all, while the
+    /// tables together take up more than `max_total_size` bytes, and once a table is older than `max_age`.
+    /// Suited to bounded event or metrics stores where old data is worthless anyway.
+    Fifo {
+        max_total_size: Option<u64>,
+        max_age: Option<Duration>,
+    },
+}
+
+/// What a `CompactionFilter` wants done with a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    /// Deletes the key, as if by `DB::delete`.
+    Remove,
+    /// Writes the given value in place of the old one, keeping its `seq_no`.
+    ChangeValue(Vec<u8>),
+}
+
+/// A CompactionFilter is handed every value a compaction writes, i.e. the newest version of every key in
+/// its inputs, and decides whether it stays. This is how data gets expired or migrated by the application
+/// without rewriting it all through `put`.
+///
+/// Values are only filtered once compaction gets to them, so a value the filter would drop is readable
+/// until then, and values still in the MemTable or in tables yet to be compacted are not filtered at all.
+/// Tombstones are never passed to the filter.
+pub trait CompactionFilter: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Decides on the `value` of `key`, which is being compacted into `level`.
+    fn filter(&self, level: u32, key: &[u8], value: &[u8]) -> CompactionDecision;
+}
+
+/// Which tables to compact next, as chosen by a `CompactionPicker`: `file_nos` of `level` are merged into
+/// `output_level`, together with every table of `output_level` they overlap. `output_level` has to be
+/// `level + 1`, or 0 for an intra-L0 compaction merging L0 tables into a single L0 table.
+///
+/// Picking any L0 table compacts all of L0, see `pick_compaction`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionPick {
+    pub level: u32,
+    pub output_level: u32,
+    pub file_nos: Vec<u64>,
+}
+
+/// A CompactionPicker decides when a `CompactionStyle::Leveled` tree needs compacting and which tables go
+/// first. It is asked again after every compaction until it picks nothing, so it must eventually run out,
+/// e.g. by only picking levels over some limit.
+///
+/// The picker only chooses, the tables it picks are then extended as needed to keep the levels below L0
+/// free of overlaps. A pick of the last level, or of tables that are not in the level, picks nothing.
+pub trait CompactionPicker: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Picks from the live `tables`, ordered by level, then newest first within a level.
+    fn pick(&self, tables: &[SSTableMeta]) -> Option<CompactionPick>;
+}
+
+/// The default `CompactionPicker`, see the module docs. An over-full L0 goes first since it slows down
+/// every read, after that the level furthest over its target.
+///
+/// With `l0_intra_compact_threshold` set, L0 is merged into a single L0 table as soon as it holds that many
+/// tables, which cuts the number of tables a read has to consult without rewriting any of L1. L0 then
+/// moves on into L1 once it holds more than `l0_compact_threshold` tables or, since intra-L0 compactions
+/// keep the table count down, more than `level_base_size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeveledCompactionPicker {
+    pub l0_compact_threshold: u32,
+    pub l0_intra_compact_threshold: Option<u32>,
+    pub level_base_size: u64,
+    pub level_multiplier: u64,
+}
+
+impl LeveledCompactionPicker {
+    /// The target size in bytes of `level`, or `None` for L0 and the last level which have none.
+    pub fn level_target_size(&self, level: u32) -> Option<u64> {
+        if level == 0 || level >= NUM_LEVELS - 1 {
+            return None;
+        }
+        Some(
+            self.level_multiplier
+                .saturating_pow(level - 1)
+                .saturating_mul(self.level_base_size),
+        )
+    }
+}
+
+impl CompactionPicker for LeveledCompactionPicker {
+    fn name(&self) -> &'static str {
+        "lsmdb.LeveledCompactionPicker"
+    }
+
+    fn pick(&self, tables: &[SSTableMeta]) -> Option<CompactionPick> {
+        let level_sizes = level_sizes(tables);
+        let l0: Vec<u64> = tables
+            .iter()
+            .filter(|meta| meta.level() == 0)
+            .map(|meta| meta.file_no())
+            .collect();
+        let l0_over_size =
+            self.l0_intra_compact_threshold.is_some() && level_sizes[0] > self.level_base_size;
+        if l0.len() > self.l0_compact_threshold as usize || l0_over_size {
+            return Some(CompactionPick {
+                level: 0,
+                output_level: 1,
+                file_nos: l0,
+            });
+        }
+        if let Some(intra_threshold) = self.l0_intra_compact_threshold
+            && l0.len() >= intra_threshold.max(2) as usize
+        {
+            return Some(CompactionPick {
+                level: 0,
+                output_level: 0,
+                file_nos: l0,
+            });
+        }
+
+        let (level, _) = (1..NUM_LEVELS)
+            .filter_map(|level| {
+                let target = self.level_target_size(level)?;
+                let size = level_sizes[level as usize];
+                (size > target).then_some((level, size as f64 / target.max(1) as f64))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        // The oldest table of the level has gone the longest without being pushed down
+        let input = tables
+            .iter()
+            .filter(|meta| meta.level() == level)
+            .min_by_key(|meta| meta.file_no())?;
+
+        Some(CompactionPick {
+            level,
+            output_level: level + 1,
+            file_nos: vec![input.file_no()],
+        })
+    }
+}
+
+/// The total size in bytes of the tables of every level.
+fn level_sizes(tables: &[SSTableMeta]) -> [u64; NUM_LEVELS as usize] {
+    let mut level_sizes = [0u64; NUM_LEVELS as usize];
+    for meta in tables {
+        level_sizes[(meta.level() as usize).min(NUM_LEVELS as usize - 1)] += meta.file_size();
+    }
+    level_sizes
+}
+
+/// Estimates how many bytes compaction is behind by as `picker` sees it: all of L0 once it holds more than
+/// `l0_compact_threshold` tables (or, with intra-L0 compactions, more than `level_base_size` bytes), plus
+/// whatever every other level holds over its target.
+pub(crate) fn pending_compaction_bytes(
+    tables: &[SSTableMeta],
+    picker: &LeveledCompactionPicker,
+) -> u64 {
+    let level_sizes = level_sizes(tables);
+    let l0_tables = tables.iter().filter(|meta| meta.level() == 0).count();
+
+    let mut pending = 0u64;
+    let l0_over_size =
+        picker.l0_intra_compact_threshold.is_some() && level_sizes[0] > picker.level_base_size;
+    if l0_tables > picker.l0_compact_threshold as usize || l0_over_size {
+        pending = level_sizes[0];
+    }
+    for level in 1..NUM_LEVELS {
+        if let Some(target) = picker.level_target_size(level) {
+            pending = pending.saturating_add(level_sizes[level as usize].saturating_sub(target));
+        }
+    }
+    pending
+}
+
+/// What compaction has done to a single level since the DB was opened, see `DB::compaction_stats`. The
+/// compaction counters are those of compactions into the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LevelStats {
+    pub level: u32,
+    // The tables currently in the level and their total size in bytes
+    pub files: usize,
+    pub bytes: u64,
+    pub compactions: u64,
+    // Read from the level above, and from this level itself
+    pub bytes_read_from_upper_level: u64,
+    pub bytes_read_from_level: u64,
+    pub bytes_written: u64,
+    // The size of the tables trivially moved into the level, which no compaction had to read or write
+    pub bytes_moved: u64,
+    pub compaction_time: Duration,
+}
+
+impl LevelStats {
+    /// Bytes written into the level for every byte brought down from the level above. A level merging
+    /// small inputs into large overlapping tables rewrites a lot of data for little.
+    pub fn write_amplification(&self) -> f64 {
+        if self.bytes_read_from_upper_level == 0 {
+            return 0.0;
+        }
+        self.bytes_written as f64 / self.bytes_read_from_upper_level as f64
+    }
+}
+
+/// A summary of how the tree is laid out and what compaction has cost so far, see `DB::compaction_stats`.
+/// Counters start at zero whenever the DB is opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionStats {
+    // One entry per level, L0 first
+    pub levels: Vec<LevelStats>,
+    pub flushes: u64,
+    pub flush_bytes_written: u64,
+}
+
+impl Default for CompactionStats {
+    fn default() -> Self {
+        Self {
+            levels: (0..NUM_LEVELS)
+                .map(|level| LevelStats {
+                    level,
+                    ..Default::default()
+                })
+                .collect(),
+            flushes: 0,
+            flush_bytes_written: 0,
+        }
+    }
+}
+
+impl CompactionStats {
+    /// Bytes written to tables, by flushes and compactions alike, for every byte flushed.
+    pub fn write_amplification(&self) -> f64 {
+        if self.flush_bytes_written == 0 {
+            return 0.0;
+        }
+        let compaction_bytes: u64 = self.levels.iter().map(|level| level.bytes_written).sum();
+        (self.flush_bytes_written + compaction_bytes) as f64 / self.flush_bytes_written as f64
+    }
+
+    /// The number of tables a point lookup may have to consult in the worst case: every L0 table, plus
+    /// one table in every other non-empty level.
+    pub fn read_amplification(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| match level.level {
+                0 => level.files,
+                _ => usize::from(level.files > 0),
+            })
+            .sum()
+    }
+
+    pub(crate) fn record_flush(&mut self, output: &SSTableMeta) {
+        self.flushes += 1;
+        self.flush_bytes_written += output.file_size();
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        compaction: &Compaction,
+        outputs: &[SSTableMeta],
+        elapsed: Duration,
+    ) {
+        let level = &mut self.levels[compaction.output_level as usize];
+        level.compactions += 1;
+        for input in &compaction.inputs {
+            if input.level() == compaction.output_level {
+                level.bytes_read_from_level += input.file_size();
+            } else {
+                level.bytes_read_from_upper_level += input.file_size();
+            }
+        }
+        level.bytes_written += outputs.iter().map(|meta| meta.file_size()).sum::<u64>();
+        level.compaction_time += elapsed;
+    }
+
+    /// Fills in the current `files` and `bytes` of every level from the live `tables`.
+    pub(crate) fn with_tables(mut self, tables: &[SSTableMeta]) -> Self {
+        for meta in tables {
+            let level = &mut self.levels[(meta.level() as usize).min(NUM_LEVELS as usize - 1)];
+            level.files += 1;
+            level.bytes += meta.file_size();
+        }
+        self
+    }
+}
+
+/// The knobs driving when and what to compact, see `DBConfig`.
+#[derive(Debug, Clone)]
+pub(crate) struct CompactionOptions {
+    pub(crate) style: CompactionStyle,
+    pub(crate) picker: Arc<dyn CompactionPicker>,
+    pub(crate) filter: Option<Arc<dyn CompactionFilter>>,
+    pub(crate) merge_operator: Option<Arc<dyn MergeOperator>>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) max_subcompactions: usize,
+    pub(crate) target_file_size: Option<u64>,
+    pub(crate) periodic_compaction_age: Option<Duration>,
+    pub(crate) listeners: Vec<Arc<dyn EventListener>>,
+    // The order of the keys, which decides what overlaps what
+    pub(crate) comparator: Arc<dyn Comparator>,
+}
+
+/// A set of tables to merge into `output_level`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Compaction {
+    pub(crate) output_level: u32,
+    // Newest first, see `MergingIterator` for which version of a key wins
+    pub(crate) inputs: Vec<SSTableMeta>,
+    // No table below `output_level` overlaps the inputs, so nothing is left for a tombstone to shadow
+    pub(crate) bottommost: bool,
+    pub(crate) reason: CompactionReason,
+}
+
+/// Why a compaction runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionReason {
+    /// Picked by the `CompactionPicker`.
+    Picker,
+    /// Requested through `DB::compact_range`.
+    Manual,
+    /// Its inputs are older than `periodic_compaction_age`. The inputs are always rewritten, a trivial move
+    /// would leave them just as old.
+    Periodic,
+}
+
+/// Asks the picker of `options` for the next compaction to run, if any, and failing that looks for a
+/// table older than `periodic_compaction_age` as of `now` (seconds since the unix epoch). `tables` must be
+/// ordered newest-to-oldest as in `DB::ss_meta`.
+///
+/// Nothing is ever merged with `CompactionStyle::Fifo`, see `pick_fifo_expired` instead.
+pub(crate) fn pick_compaction(
+    tables: &[SSTableMeta],
+    options: &CompactionOptions,
+    now: u64,
+) -> Option<Compaction> {
+    if options.style != CompactionStyle::Leveled {
+        return None;
+    }
+
+    options
+        .picker
+        .pick(tables)
+        .and_then(|pick| pick_inputs(tables, &pick, options.comparator.as_ref()))
+        .or_else(|| {
+            let age = options.periodic_compaction_age?;
+            pick_periodic_compaction(tables, age, now, options.comparator.as_ref())
+        })
+}
+
+/// The most compactions `plan` lays out, a custom picker may otherwise keep picking forever.
+pub(crate) const MAX_PLANNED_COMPACTIONS: usize = 100;
+
+/// A compaction `DB::compaction_plan` expects to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCompaction {
+    pub reason: CompactionReason,
+    pub output_level: u32,
+    // Newest first. Inputs written by an earlier step of the plan don't exist yet, they carry the
+    // `file_no` they are expected to get
+    pub inputs: Vec<SSTableMeta>,
+    // The size of the inputs, an upper bound since shadowed versions and tombstones are dropped on the way
+    pub estimated_output_size: u64,
+}
+
+/// Lays out the compactions `compact` would run on `tables` right now, in order, without running any.
+/// Each step assumes the one before it produced a single table the size of its inputs, numbered from
+/// `next_file_no` on. Stops after `MAX_PLANNED_COMPACTIONS` steps.
+///
+/// FIFO expiry is not part of the plan, `CompactionStyle::Fifo` plans nothing.
+pub(crate) fn plan(
+    tables: &[SSTableMeta],
+    options: &CompactionOptions,
+    next_file_no: u64,
+    now: u64,
+) -> Vec<PlannedCompaction> {
+    let mut tables = tables.to_vec();
+    let mut planned = Vec::new();
+    while planned.len() < MAX_PLANNED_COMPACTIONS {
+        let Some(compaction) = pick_compaction(&tables, options, now) else {
+            break;
+        };
+
+        let estimated_output_size = compaction.inputs.iter().map(|meta| meta.file_size()).sum();
+        let file_no = next_file_no + planned.len() as u64;
+        let props = TableProperties {
+            level: compaction.output_level,
+            min_seq_no: compaction
+                .inputs
+                .iter()
+                .map(|meta| meta.min_seq_no())
+                .min()
+                .unwrap_or(0),
+            max_seq_no: compaction
+                .inputs
+                .iter()
+                .map(|meta| meta.max_seq_no())
+                .max()
+                .unwrap_or(0),
+            creation_time: now,
+            smallest_key: compaction
+                .inputs
+                .iter()
+                .map(|meta| meta.smallest_key())
+                .min_by(|a, b| options.comparator.compare(a, b))
+                .unwrap_or_default()
+                .to_vec(),
+            largest_key: compaction
+                .inputs
+                .iter()
+                .map(|meta| meta.largest_key())
+                .max_by(|a, b| options.comparator.compare(a, b))
+                .unwrap_or_default()
+                .to_vec(),
+            ..Default::default()
+        };
+        let output = SSTableMeta::from_properties(
+            file_no,
+            table_file_name(file_no),
+            &props,
+            estimated_output_size,
+            0,
+        );
+
+        tables.retain(|meta| !compaction.inputs.contains(meta));
+        tables.push(output);
+        tables.sort_by_key(version::newest_first);
+
+        planned.push(PlannedCompaction {
+            reason: compaction.reason,
+            output_level: compaction.output_level,
+            inputs: compaction.inputs,
+            estimated_output_size,
+        });
+    }
+
+    planned
+}
+
+/// Resolves a `CompactionPick` into the compaction it stands for.
+fn pick_inputs(
+    tables: &[SSTableMeta],
+    pick: &CompactionPick,
+    comparator: &dyn Comparator,
+) -> Option<Compaction> {
+    let intra_l0 = pick.level == 0 && pick.output_level == 0;
+    if pick.level >= NUM_LEVELS - 1 || (pick.output_level != pick.level + 1 && !intra_l0) {
+        return None;
+    }
+
+    // L0 tables can overlap each other arbitrarily, so all of them are compacted at once. Compacting only
+    // some could leave an older version of a key in L0, shadowing the newer one moved to L1
+    let inputs: Vec<&SSTableMeta> = tables
+        .iter()
+        .filter(|meta| {
+            meta.level() == pick.level
+                && (pick.level == 0 || pick.file_nos.contains(&meta.file_no()))
+        })
+        .collect();
+    with_overlapping(
+        tables,
+        inputs,
+        pick.output_level,
+        CompactionReason::Picker,
+        comparator,
+    )
+}
+
+/// Picks the table written longest ago, provided it is older than `age` as of `now`. It is compacted into
+/// the next level as usual, or rewritten in place once in the last level, so filters and tombstone garbage
+/// collection get to see data that would otherwise sit there untouched.
+fn pick_periodic_compaction(
+    tables: &[SSTableMeta],
+    age: Duration,
+    now: u64,
+    comparator: &dyn Comparator,
+) -> Option<Compaction> {
+    let oldest = tables
+        .iter()
+        .filter(|meta| now.saturating_sub(meta.creation_time()) > age.as_secs())
+        .min_by_key(|meta| meta.creation_time())?;
+
+    let level = oldest.level();
+    let inputs = if level == 0 {
+        tables.iter().filter(|meta| meta.level() == 0).collect()
+    } else {
+        vec![oldest]
+    };
+    let output_level = (level + 1).min(NUM_LEVELS - 1);
+    with_overlapping(
+        tables,
+        inputs,
+        output_level,
+        CompactionReason::Periodic,
+        comparator,
+    )
+}
+
+/// Returns the tables `CompactionStyle::Fifo` deletes as of `now` (seconds since the unix epoch): every
+/// table older than `max_age`, then the oldest of the rest until they fit in `max_total_size`.
+pub(crate) fn pick_fifo_expired(
+    tables: &[SSTableMeta],
+    options: &CompactionOptions,
+    now: u64,
+) -> Vec<SSTableMeta> {
+    let CompactionStyle::Fifo {
+        max_total_size,
+        max_age,
+    } = options.style
+    else {
+        return Vec::new();
+    };
+
+    let mut oldest_first: Vec<&SSTableMeta> = tables.iter().collect();
+    oldest_first.sort_by_key(|meta| meta.file_no());
+
+    let mut total_size: u64 = tables.iter().map(|meta| meta.file_size()).sum();
+    let mut expired = Vec::new();
+    for meta in oldest_first {
+        let too_old = max_age
+            .is_some_and(|max_age| now.saturating_sub(meta.creation_time()) > max_age.as_secs());
+        let too_large = max_total_size.is_some_and(|max_total_size| total_size > max_total_size);
+        if !too_old && !too_large {
+            // Anything newer can't be older than `max_age` either
+            break;
+        }
+
+        total_size -= meta.file_size();
+        expired.push(meta.clone());
+    }
+
+    expired
+}
+
+/// Picks the tables of `level` that overlap `[smallest, largest]` for a move into the next level, see
+/// `compact_range`. All of L0 is picked as soon as any L0 table overlaps, for the reason given in
+/// `pick_compaction`.
+pub(crate) fn pick_range_compaction(
+    tables: &[SSTableMeta],
+    level: u32,
+    smallest: &[u8],
+    largest: &[u8],
+    comparator: &dyn Comparator,
+) -> Option<Compaction> {
+    let in_level = || tables.iter().filter(move |meta| meta.level() == level);
+    if !in_level().any(|meta| meta.overlaps(comparator, smallest, largest)) {
+        return None;
+    }
+
+    let inputs = if level == 0 {
+        in_level().collect()
+    } else {
+        in_level()
+            .filter(|meta| meta.overlaps(comparator, smallest, largest))
+            .collect()
+    };
+    with_overlapping(
+        tables,
+        inputs,
+        level + 1,
+        CompactionReason::Manual,
+        comparator,
+    )
+}
+
+/// The smallest and largest key of `tables` combined, in the order of `comparator`.
+fn key_range<'t>(
+    tables: impl Iterator<Item = &'t SSTableMeta> + Clone,
+    comparator: &dyn Comparator,
+) -> Option<(&'t [u8], &'t [u8])> {
+    let smallest = tables
+        .clone()
+        .map(|meta| meta.smallest_key())
+        .min_by(|a, b| comparator.compare(a, b))?;
+    let largest = tables
+        .map(|meta| meta.largest_key())
+        .max_by(|a, b| comparator.compare(a, b))?;
+    Some((smallest, largest))
+}
+
+/// Builds the compaction of `inputs` into `output_level`, pulling in every other table of `output_level`
+/// that overlaps the inputs' combined key range.
+fn with_overlapping(
+    tables: &[SSTableMeta],
+    inputs: Vec<&SSTableMeta>,
+    output_level: u32,
+    reason: CompactionReason,
+    comparator: &dyn Comparator,
+) -> Option<Compaction> {
+    let (smallest, largest) = key_range(inputs.iter().copied(), comparator)?;
+    let overlapping: Vec<&SSTableMeta> = tables
+        .iter()
+        .filter(|meta| {
+            meta.level() == output_level
+                && meta.overlaps(comparator, smallest, largest)
+                && !inputs.iter().any(|input| input.file_no() == meta.file_no())
+        })
+        .collect();
+    let inputs: Vec<SSTableMeta> = inputs.into_iter().chain(overlapping).cloned().collect();
+
+    // The tables pulled in from `output_level` may widen the range
+    let (smallest, largest) = key_range(inputs.iter(), comparator)?;
+    let bottommost = !tables
+        .iter()
+        .any(|meta| meta.level() > output_level && meta.overlaps(comparator, smallest, largest));
+
+    Some(Compaction {
+        output_level,
+        inputs,
+        bottommost,
+        reason,
+    })
+}
+
+/// Runs compactions until L0 is back under `l0_compact_threshold` tables and every level is within its
+/// target size and no table is older than `periodic_compaction_age`, then with `CompactionStyle::Fifo`
+/// drops the tables over its budget. Every compaction moves data one level down or leaves a freshly
+/// written table behind, so this always terminates.
+///
+/// `versions` is only locked to pick a compaction and to install its result, never while merging, so reads
+/// and flushes carry on in the meantime. A flush running alongside only ever adds an L0 table holding writes
+/// newer than any input, though it may have taken its `file_no` before the compaction's outputs did, which
+/// is why L0 is ordered by `seq_no`, see `version::newest_first`. Nothing but compaction removes tables, so
+/// the picked inputs stay valid throughout.
+pub(crate) fn compact(
+    versions: &Mutex<VersionSet>,
+    table_cache: &TableCache,
+    options: &CompactionOptions,
+    config: &SSTableConfig,
+) -> Result<(), DBError> {
+    let now = || options.clock.now_millis() / 1000;
+    loop {
+        let compaction = {
+            let versions = version::lock(versions);
+            match pick_compaction(&versions.ss_meta, options, now()) {
+                Some(compaction) => compaction,
+                None => break,
+            }
+        };
+        run_and_install(versions, table_cache, &compaction, options, config)?;
+    }
+
+    let now = now();
+    let expired = {
+        let mut versions = version::lock(versions);
+        let expired = pick_fifo_expired(&versions.ss_meta, options, now);
+        if expired.is_empty() {
+            return Ok(());
+        }
+        versions.apply(VersionEdit {
+            added: Vec::new(),
+            removed: expired.iter().map(|meta| meta.file_no()).collect(),
+        })?;
+        expired
+    };
+    version::remove_ss_table_files(&expired, table_cache)
+}
+
+/// Pushes every table overlapping the inclusive range `[smallest, largest]` down to the last level, level by
+/// level. Each level is taken care of with a single compaction, whose output then overlaps the range in
+/// the next level down.
+pub(crate) fn compact_range(
+    versions: &Mutex<VersionSet>,
+    table_cache: &TableCache,
+    smallest: &[u8],
+    largest: &[u8],
+    options: &CompactionOptions,
+    config: &SSTableConfig,
+) -> Result<(), DBError> {
+    for level in 0..NUM_LEVELS - 1 {
+        let compaction = pick_range_compaction(
+            &version::lock(versions).ss_meta,
+            level,
+            smallest,
+            largest,
+            options.comparator.as_ref(),
+        );
+        if let Some(compaction) = compaction {
+            run_and_install(versions, table_cache, &compaction, options, config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `compaction` and swaps its output in for its inputs, see `compact` for the locking. A trivial move
+/// is installed without running anything, see `is_trivial_move`. The `listeners` are told about it either
+/// way.
+fn run_and_install(
+    versions: &Mutex<VersionSet>,
+    table_cache: &TableCache,
+    compaction: &Compaction,
+    options: &CompactionOptions,
+    config: &SSTableConfig,
+) -> Result<(), DBError> {
+    let mut info = CompactionJobInfo {
+        reason: compaction.reason,
+        output_level: compaction.output_level,
+        inputs: compaction.inputs.clone(),
+        outputs: Vec::new(),
+        trivial_move: false,
+        elapsed: Duration::ZERO,
+    };
+    for listener in &options.listeners {
+        listener.on_compaction_start(&info);
+    }
+
+    let started = Instant::now();
+    let result = install(
+        versions,
+        table_cache,
+        compaction,
+        options,
+        config,
+        &mut info,
+    );
+    info.elapsed = started.elapsed();
+    for listener in &options.listeners {
+        match &result {
+            Ok(()) => listener.on_compaction_finish(&info),
+            Err(e) => listener.on_compaction_error(&info, e),
+        }
+    }
+    result
+}
+
+/// The body of `run_and_install`, filling in the `outputs` of `info` along the way.
+fn install(
+    versions: &Mutex<VersionSet>,
+    table_cache: &TableCache,
+    compaction: &Compaction,
+    options: &CompactionOptions,
+    config: &SSTableConfig,
+    info: &mut CompactionJobInfo,
+) -> Result<(), DBError> {
+    if is_trivial_move(compaction, options, table_cache)? {
+        let input = &compaction.inputs[0];
+        let moved = input.clone().with_level(compaction.output_level);
+        let mut versions = version::lock(versions);
+        versions.apply(VersionEdit {
+            added: vec![moved.clone()],
+            removed: vec![input.file_no()],
+        })?;
+        versions.stats.levels[compaction.output_level as usize].bytes_moved += input.file_size();
+        info.outputs = vec![moved];
+        info.trivial_move = true;
+        return Ok(());
+    }
+
+    let started = Instant::now();
+
+    let bounds = subcompaction_bounds(
+        compaction,
+        options.max_subcompactions,
+        options.comparator.as_ref(),
+    );
+    let now = options.clock.now_millis();
+    let (subcompactions, snapshots) = {
+        let mut locked = version::lock(versions);
+        let mut subcompactions = Vec::with_capacity(bounds.len() + 1);
+        for i in 0..=bounds.len() {
+            let file_no = locked.manifest.new_file_no();
+            subcompactions.push(Subcompaction {
+                compaction,
+                start: i.checked_sub(1).map(|i| bounds[i].as_slice()),
+                end: bounds.get(i).map(Vec::as_slice),
+                versions,
+                first_output: Some((file_no, locked.manifest.table_path(file_no))),
+                target_file_size: options.target_file_size,
+                now,
+            });
+        }
+        // Any snapshot taken after this is newer than every entry of the inputs
+        (subcompactions, locked.live_snapshots())
+    };
+    let snapshots = snapshots.as_slice();
+
+    let filter = options.filter.as_deref();
+    let merge_operator = options.merge_operator.as_deref();
+    let results: Vec<Result<Vec<SSTableMeta>, DBError>> = if subcompactions.len() == 1 {
+        subcompactions
+            .into_iter()
+            .map(|sub| run(sub, table_cache, config, snapshots, filter, merge_operator))
+            .collect()
+    } else {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = subcompactions
+                .into_iter()
+                .map(|sub| {
+                    scope.spawn(move || {
+                        run(sub, table_cache, config, snapshots, filter, merge_operator)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    };
+
+    let (outputs, errors): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+    let outputs: Vec<SSTableMeta> = outputs.into_iter().flat_map(Result::unwrap).collect();
+    if let Some(Err(e)) = errors.into_iter().next() {
+        // The other outputs were never installed, nothing refers to them
+        let _ = version::remove_ss_table_files(&outputs, table_cache);
+        return Err(e);
+    }
+
+    {
+        let mut versions = version::lock(versions);
+        versions
+            .stats
+            .record(compaction, &outputs, started.elapsed());
+        info.outputs = outputs.clone();
+        // All outputs are installed by the one edit, a crash can't leave the key range half compacted
+        versions.apply(VersionEdit {
+            added: outputs,
+            removed: compaction
+                .inputs
+                .iter()
+                .map(|meta| meta.file_no())
+                .collect(),
+        })?;
+    }
+    version::remove_ss_table_files(&compaction.inputs, table_cache)
+}
+
+/// Whether `compaction` can just move its only input down to `output_level`, by a manifest edit alone.
+/// With nothing in `output_level` to merge with, rewriting the table would produce the very same entries,
+/// which is what a sequential insert workload would otherwise pay for on every level.
+///
+/// The table still has to be rewritten when its entries may change on the way: with a `CompactionFilter`,
+/// and with tombstones or range tombstones a `bottommost` compaction would drop.
+fn is_trivial_move(
+    compaction: &Compaction,
+    options: &CompactionOptions,
+    table_cache: &TableCache,
+) -> Result<bool, DBError> {
+    let [input] = compaction.inputs.as_slice() else {
+        return Ok(false);
+    };
+    if input.level() == compaction.output_level {
+        return Ok(false);
+    }
+    if options.filter.is_some() || compaction.reason == CompactionReason::Periodic {
+        return Ok(false);
+    }
+    if compaction.bottommost {
+        let reader = table_cache.get(input)?;
+        let props = reader.properties();
+        if props.tombstone_count > 0 || props.range_deletion_count > 0 {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Splits the key range of `compaction` into at most `max_subcompactions` ranges to be merged in
+/// parallel, returning the keys between them. Each range starts at the key it is split off at.
+///
+/// The split points are picked from the key ranges of the inputs, spread evenly across them, so the
+/// ranges hold a comparable number of tables. A compaction of a single table is never split.
+pub(crate) fn subcompaction_bounds(
+    compaction: &Compaction,
+    max_subcompactions: usize,
+    comparator: &dyn Comparator,
+) -> Vec<Vec<u8>> {
+    if max_subcompactions <= 1 || compaction.inputs.len() <= 1 {
+        return Vec::new();
+    }
+
+    let mut keys: Vec<&[u8]> = compaction
+        .inputs
+        .iter()
+        .flat_map(|meta| [meta.smallest_key(), meta.largest_key()])
+        .collect();
+    keys.sort_unstable_by(|a, b| comparator.compare(a, b));
+    keys.dedup_by(|a, b| comparator.compare(a, b).is_eq());
+    // Nothing comes before the smallest key, splitting there would leave an empty range
+    keys.remove(0);
+
+    let count = max_subcompactions.min(keys.len() + 1);
+    let mut bounds: Vec<Vec<u8>> = (1..count)
+        .map(|i| keys[i * keys.len() / count].to_vec())
+        .collect();
+    bounds.dedup();
+    bounds
+}
+
+/// One key range of a compaction, merged into tables of its own.
+pub(crate) struct Subcompaction<'a> {
+    compaction: &'a Compaction,
+    // Inclusive, `None` is unbounded
+    start: Option<&'a [u8]>,
+    // Exclusive, `None` is unbounded
+    end: Option<&'a [u8]>,
+    versions: &'a Mutex<VersionSet>,
+    // Allocated along with the compaction, before any table flushed while it runs. Tables after the first
+    // get a `file_no` once needed
+    first_output: Option<(u64, PathBuf)>,
+    // Outputs are cut once they reach this size, see `DBConfig::target_file_size`
+    target_file_size: Option<u64>,
+    // Values expired by then are dropped, see `Entry::is_expired`
+    now: u64,
+}
+
+/// Merges the entries of `sub`'s key range in the inputs into new tables, cutting a new table whenever the
+/// current one reaches the `target_file_size`. Returns no tables when nothing survived the merge.
+///
+/// Every version of a key in the inputs is looked at, newest first. The newest survives unless a range
+/// tombstone deletes it, an older one only if one of the `snapshots` reads it, see the module docs. A
+/// table holds every version of the keys it holds, so outputs are only cut between keys.
+///
+/// With a `bottommost` compaction, tombstones older than every snapshot are dropped along with the
+/// versions they shadow: every snapshot sees the key as deleted, and a missing key reads the same. Newer
+/// tombstones are kept, the snapshots before them read the versions they shadow. The same goes for range
+/// tombstones, while the entries they delete for every snapshot are dropped by any compaction.
+///
+/// The surviving values are run through `filter` first. A value it removes becomes a tombstone, which only
+/// a bottommost compaction can drop, or an older version of the key further down would show through. An
+/// expired value goes the same way.
+///
+/// Merge operands are folded with `merge_operator` into the older versions of their key in the inputs,
+/// see `MergeOperator`. They make a value once one is found, or once nothing is left below for them to
+/// apply to: in a bottommost compaction, or beneath a tombstone. Otherwise they are folded into a single
+/// operand.
+pub(crate) fn run(
+    mut sub: Subcompaction<'_>,
+    table_cache: &TableCache,
+    config: &SSTableConfig,
+    snapshots: &[u64],
+    filter: Option<&dyn CompactionFilter>,
+    merge_operator: Option<&dyn MergeOperator>,
+) -> Result<Vec<SSTableMeta>, DBError> {
+    let mut outputs = Vec::new();
+    let merged = sub.merge(
+        table_cache,
+        config,
+        snapshots,
+        (filter, merge_operator),
+        &mut outputs,
+    );
+    if let Err(e) = merged {
+        // Nothing refers to the tables finished so far
+        let _ = version::remove_ss_table_files(&outputs, table_cache);
+        return Err(e);
+    }
+    Ok(outputs)
+}
+
+impl Subcompaction<'_> {
+    fn merge(
+        &mut self,
+        table_cache: &TableCache,
+        config: &SSTableConfig,
+        snapshots: &[u64],
+        (filter, merge_operator): (Option<&dyn CompactionFilter>, Option<&dyn MergeOperator>),
+        outputs: &mut Vec<SSTableMeta>,
+    ) -> Result<(), DBError> {
+        let compaction = self.compaction;
+        let comparator = config.comparator.as_ref();
+        let readers = compaction
+            .inputs
+            .iter()
+            .map(|meta| table_cache.get(meta))
+            .collect::<Result<Vec<_>, _>>()?;
+        let droppable = |seq_no: u64| {
+            compaction.bottommost
+                && snapshots
+                    .first()
+                    .is_none_or(|&oldest_snapshot| seq_no < oldest_snapshot)
+        };
+
+        let range_tombstones: Vec<RangeTombstone> = readers
+            .iter()
+            .flat_map(|reader| reader.range_tombstones())
+            .filter_map(|tombstone| {
+                clip_range_tombstone(comparator, tombstone, self.start, self.end)
+            })
+            .collect();
+        // Every range tombstone masks the inputs, only those still needed are written out
+        let kept_range_tombstones: Vec<RangeTombstone> = range_tombstones
+            .iter()
+            .filter(|tombstone| !droppable(tombstone.seq_no))
+            .cloned()
+            .collect();
+        let mut iter = MergingIterator::all_versions(
+            readers
+                .iter()
+                .map(|reader| Box::new(reader.iter()) as Box<dyn EntryIterator>)
+                .collect(),
+            comparator,
+        );
+        match self.start {
+            Some(start) => iter.seek(start)?,
+            None => iter.seek_to_first()?,
+        }
+
+        // L0 tables are ordered by `file_no`, which only the first output's is guaranteed to fit into
+        let target_file_size = self
+            .target_file_size
+            .filter(|_| compaction.output_level > 0);
+        let mut writer = None;
+        // The smallest key the table being written may hold
+        let mut output_start = self.start.map(<[u8]>::to_vec);
+        let mut versions: Vec<Entry> = Vec::new();
+        while iter.valid() {
+            let key = iter.key().to_vec();
+            if self
+                .end
+                .is_some_and(|end| comparator.compare(&key, end).is_ge())
+            {
+                break;
+            }
+            versions.clear();
+            while let Some(entry) = iter.entry()
+                && iter.key() == key.as_slice()
+            {
+                versions.push(entry.clone());
+                iter.next()?;
+            }
+
+            let mut survivors = Vec::new();
+            for (i, version) in versions.iter().enumerate() {
+                // Read by the snapshots after it up to the newer version, or the range tombstone deleting it
+                let newer = i.checked_sub(1).map(|i| versions[i].seq_no());
+                let deleted_at = range_tombstones
+                    .iter()
+                    .filter(|tombstone| tombstone.deletes(comparator, &key, version.seq_no()))
+                    .map(|tombstone| tombstone.seq_no)
+                    .min();
+                let read = match newer.into_iter().chain(deleted_at).min() {
+                    None => true,
+                    Some(until) => read_by_snapshot(snapshots, version.seq_no(), until),
+                };
+                if !read {
+                    continue;
+                }
+
+                let mut entry = version.clone();
+                if let Entry::Merge { .. } = entry
+                    && let Some(merge_operator) = merge_operator
+                {
+                    entry = merge_versions(
+                        comparator,
+                        (merge_operator, self.now),
+                        &key,
+                        entry,
+                        &versions[i + 1..],
+                        &range_tombstones,
+                        compaction.bottommost,
+                    );
+                }
+                if let (
+                    Some(filter),
+                    Entry::Value {
+                        seq_no,
+                        val,
+                        timestamp,
+                        expires_at,
+                    },
+                ) = (filter, &entry)
+                {
+                    match filter.filter(compaction.output_level, &key, val) {
+                        CompactionDecision::Keep => {}
+                        CompactionDecision::Remove => entry = Entry::Tombstone { seq_no: *seq_no },
+                        CompactionDecision::ChangeValue(val) => {
+                            entry = Entry::Value {
+                                seq_no: *seq_no,
+                                val,
+                                timestamp: *timestamp,
+                                expires_at: *expires_at,
+                            }
+                        }
+                    }
+                }
+                if entry.is_expired(self.now) {
+                    entry = Entry::Tombstone {
+                        seq_no: entry.seq_no(),
+                    };
+                }
+
+                let drop = match entry {
+                    Entry::Tombstone { seq_no } => droppable(seq_no),
+                    Entry::Value { .. } | Entry::Merge { .. } => false,
+                };
+                if !drop {
+                    survivors.push(entry);
+                }
+            }
+            if survivors.is_empty() {
+                continue;
+            }
+
+            if let Some(target_file_size) = target_file_size
+                && let Some(full) = writer.take_if(|writer: &mut SSTableWriter| {
+                    writer.estimated_file_size() >= target_file_size
+                })
+            {
+                outputs.push(finish_output(
+                    comparator,
+                    full,
+                    &kept_range_tombstones,
+                    output_start.as_deref(),
+                    Some(&key),
+                )?);
+                output_start = Some(key.clone());
+            }
+            let writer = self.writer(&mut writer, config)?;
+            for entry in &survivors {
+                writer.add(&key, entry)?;
+            }
+        }
+
+        // Whatever is left of the range tombstones goes to the last table, even if it holds nothing else
+        let tail: Vec<RangeTombstone> = kept_range_tombstones
+            .iter()
+            .filter_map(|tombstone| {
+                clip_range_tombstone(comparator, tombstone, output_start.as_deref(), self.end)
+            })
+            .collect();
+        if !tail.is_empty() {
+            self.writer(&mut writer, config)?;
+        }
+        if let Some(writer) = writer {
+            outputs.push(finish_output(comparator, writer, &tail, None, None)?);
+        }
+
+        Ok(())
+    }
+
+    /// The writer of the output table being written. It is only created once something survives, so a
+    /// compaction dropping everything writes no table.
+    fn writer<'w>(
+        &mut self,
+        writer: &'w mut Option<SSTableWriter>,
+        config: &SSTableConfig,
+    ) -> Result<&'w mut SSTableWriter, DBError> {
+        if let Some(writer) = writer {
+            return Ok(writer);
+        }
+
+        let (file_no, path) = match self.first_output.take() {
+            Some(first_output) => first_output,
+            None => {
+                let mut versions = version::lock(self.versions);
+                let file_no = versions.manifest.new_file_no();
+                (file_no, versions.manifest.table_path(file_no))
+            }
+        };
+        Ok(writer.insert(SSTableWriter::with_config(
+            path,
+            file_no,
+            self.compaction.output_level,
+            config.clone(),
+        )?))
+    }
+}
+
+/// Whether one of the `snapshots`, oldest first, is taken after `seq_no` and at or before `until`, i.e.
+/// reads the version written at `seq_no` when the next one is written at `until`.
+fn read_by_snapshot(snapshots: &[u64], seq_no: u64, until: u64) -> bool {
+    let after = snapshots.partition_point(|&snapshot| snapshot <= seq_no);
+    snapshots
+        .get(after)
+        .is_some_and(|&snapshot| snapshot <= until)
+}
+
+/// Folds `newest`, a merge operand of `key` in the inputs, into `older`, the versions of the key in the
+/// inputs written before it, newest first, see `run`. `range_tombstones` delete versions just as they do on
+/// reads at `newest`, and values expired by `now` are gone just as they are on reads.
+fn merge_versions(
+    comparator: &dyn Comparator,
+    (merge_operator, now): (&dyn MergeOperator, u64),
+    key: &[u8],
+    newest: Entry,
+    older: &[Entry],
+    range_tombstones: &[RangeTombstone],
+    bottommost: bool,
+) -> Entry {
+    let deleted_at =
+        entry::covering_seq_no_before(comparator, range_tombstones, key, newest.seq_no());
+    let mut merges = vec![newest];
+    let mut beneath = None;
+    for version in older {
+        if deleted_at.is_some_and(|deleted_at| version.seq_no() < deleted_at) {
+            break;
+        }
+        match version {
+            Entry::Merge { .. } => merges.push(version.clone()),
+            version => {
+                beneath = Some(version);
+                break;
+            }
+        }
+    }
+
+    let beneath = match beneath {
+        Some(version) => Some(Some(version)),
+        // Deleted, or with no older version left anywhere below
+        None if deleted_at.is_some() || bottommost => Some(None),
+        None => None,
+    };
+    merge::fold(merge_operator, key, &merges, beneath, now)
+}
+
+/// Adds the parts of `range_tombstones` within `[start, end)` to `writer` and finishes it.
+fn finish_output(
+    comparator: &dyn Comparator,
+    mut writer: SSTableWriter,
+    range_tombstones: &[RangeTombstone],
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+) -> Result<SSTableMeta, DBError> {
+    for tombstone in range_tombstones {
+        if let Some(tombstone) = clip_range_tombstone(comparator, tombstone, start, end) {
+            writer.add_range_tombstone(&tombstone)?;
+        }
+    }
+    writer.finish()
+}
+
+/// Cuts `tombstone` down to the part of it within `[start, end)`, if any, so that an output table's range
+/// tombstones stay within its key range. `None` bounds are unbounded.
+fn clip_range_tombstone(
+    comparator: &dyn Comparator,
+    tombstone: &RangeTombstone,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+) -> Option<RangeTombstone> {
+    let clipped_start = start.map_or(tombstone.start.as_slice(), |start| {
+        std::cmp::max_by(start, tombstone.start.as_slice(), |a, b| {
+            comparator.compare(a, b)
+        })
+    });
+    let clipped_end = end.map_or(tombstone.end.as_slice(), |end| {
+        std::cmp::min_by(end, tombstone.end.as_slice(), |a, b| {
+            comparator.compare(a, b)
+        })
+    });
+    comparator
+        .compare(clipped_start, clipped_end)
+        .is_lt()
+        .then(|| RangeTombstone {
+            start: clipped_start.to_vec(),
+            end: clipped_end.to_vec(),
+            seq_no: tombstone.seq_no,
+        })
+}
+
+#[cfg(test)]
+mod compaction_test {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::comparator::BytewiseComparator;
+    use crate::sstable::TableProperties;
+
+    fn meta(
+        file_no: u64,
+        level: u32,
+        smallest: &str,
+        largest: &str,
+        file_size: u64,
+    ) -> SSTableMeta {
+        meta_created_at(file_no, level, smallest, largest, file_size, 0)
+    }
+
+    fn meta_created_at(
+        file_no: u64,
+        level: u32,
+        smallest: &str,
+        largest: &str,
+        file_size: u64,
+        creation_time: u64,
+    ) -> SSTableMeta {
+        let props = TableProperties {
+            level,
+            creation_time,
+            smallest_key: smallest.as_bytes().to_vec(),
+            largest_key: largest.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        SSTableMeta::from_properties(file_no, format!("{file_no:06}.sst"), &props, file_size, 0)
+    }
+
+    fn leveled(
+        l0_compact_threshold: u32,
+        level_base_size: u64,
+        level_multiplier: u64,
+    ) -> CompactionOptions {
+        CompactionOptions {
+            style: CompactionStyle::Leveled,
+            picker: Arc::new(LeveledCompactionPicker {
+                l0_compact_threshold,
+                l0_intra_compact_threshold: None,
+                level_base_size,
+                level_multiplier,
+            }),
+            filter: None,
+            merge_operator: None,
+            clock: Arc::new(SystemClock),
+            max_subcompactions: 1,
+            target_file_size: None,
+            listeners: Vec::new(),
+            periodic_compaction_age: None,
+            comparator: Arc::new(BytewiseComparator),
+        }
+    }
+
+    #[test]
+    fn picks_all_of_l0_and_the_overlapping_l1_tables() {
+        let tables = vec![
+            meta(5, 0, "c", "f", 0),
+            meta(4, 0, "b", "d", 0),
+            meta(3, 1, "a", "a", 0),
+            meta(2, 1, "a", "c", 0),
+            meta(1, 1, "g", "z", 0),
+        ];
+
+        assert_eq!(pick_compaction(&tables, &leveled(2, u64::MAX, 1), 0), None);
+
+        let compaction = pick_compaction(&tables, &leveled(1, u64::MAX, 1), 0).unwrap();
+        assert_eq!(compaction.output_level, 1);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![5, 4, 2]);
+        assert!(compaction.bottommost);
+
+        // L1 table 2 widens the range to "a", which a deeper table overlaps
+        let mut tables = tables;
+        tables.push(meta(0, 2, "a", "a", 0));
+        let compaction = pick_compaction(&tables, &leveled(1, u64::MAX, 1), 0).unwrap();
+        assert!(!compaction.bottommost);
+    }
+
+    #[test]
+    fn picks_the_level_furthest_over_its_target() {
+        let picker = LeveledCompactionPicker {
+            l0_compact_threshold: 4,
+            l0_intra_compact_threshold: None,
+            level_base_size: 100,
+            level_multiplier: 10,
+        };
+        assert_eq!(picker.level_target_size(0), None);
+        assert_eq!(picker.level_target_size(1), Some(100));
+        assert_eq!(picker.level_target_size(3), Some(10_000));
+        assert_eq!(picker.level_target_size(NUM_LEVELS - 1), None);
+        let options = leveled(4, 100, 10);
+
+        let mut tables = vec![
+            meta(9, 0, "a", "z", 1_000_000),
+            // L1 at 1.5x its target
+            meta(8, 1, "a", "f", 100),
+            meta(4, 1, "g", "m", 50),
+            // L2 at 2x its target
+            meta(7, 2, "a", "c", 1_000),
+            meta(3, 2, "d", "k", 1_000),
+            meta(6, 3, "a", "b", 10),
+            meta(2, 3, "c", "e", 10),
+            meta(1, 3, "l", "z", 10),
+            meta(5, 6, "a", "z", u64::MAX),
+        ];
+
+        let compaction = pick_compaction(&tables, &options, 0).unwrap();
+        assert_eq!(compaction.output_level, 3);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![3, 2]);
+
+        tables.retain(|meta| meta.level() != 2);
+        let compaction = pick_compaction(&tables, &options, 0).unwrap();
+        assert_eq!(compaction.output_level, 2);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![4]);
+
+        tables.retain(|meta| meta.level() != 1);
+        assert_eq!(pick_compaction(&tables, &options, 0), None);
+    }
+
+    #[test]
+    fn intra_l0_compactions_go_ahead_of_l0_to_l1() {
+        let picker = LeveledCompactionPicker {
+            l0_compact_threshold: 8,
+            l0_intra_compact_threshold: Some(3),
+            level_base_size: 100,
+            level_multiplier: 10,
+        };
+        let options = CompactionOptions {
+            picker: Arc::new(picker),
+            ..leveled(8, 100, 10)
+        };
+        let mut tables = vec![
+            meta(4, 0, "c", "f", 10),
+            meta(3, 0, "a", "d", 10),
+            meta(2, 1, "a", "b", 10),
+            meta(1, 1, "c", "z", 10),
+        ];
+        assert_eq!(pick_compaction(&tables, &options, 0), None);
+
+        // L0 is merged into itself, leaving L1 alone
+        tables.insert(0, meta(5, 0, "x", "y", 10));
+        let compaction = pick_compaction(&tables, &options, 0).unwrap();
+        assert_eq!(compaction.output_level, 0);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![5, 4, 3]);
+        assert!(!compaction.bottommost);
+
+        // Once L0 outgrows L1's target it moves on into L1, however few tables it holds
+        let mut tables = vec![meta(6, 0, "a", "z", 101), meta(1, 1, "c", "z", 10)];
+        let compaction = pick_compaction(&tables, &options, 0).unwrap();
+        assert_eq!(compaction.output_level, 1);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![6, 1]);
+        assert_eq!(pending_compaction_bytes(&tables, &picker), 101);
+
+        tables[0] = meta(6, 0, "a", "z", 100);
+        assert_eq!(pick_compaction(&tables, &options, 0), None);
+        assert_eq!(pending_compaction_bytes(&tables, &picker), 0);
+    }
+
+    #[test]
+    fn pending_bytes_count_l0_over_its_threshold_and_levels_over_their_target() {
+        let picker = LeveledCompactionPicker {
+            l0_compact_threshold: 1,
+            l0_intra_compact_threshold: None,
+            level_base_size: 100,
+            level_multiplier: 10,
+        };
+        let mut tables = vec![
+            meta(5, 0, "a", "z", 30),
+            // L1 is 50 bytes over its target, L2 well within it
+            meta(4, 1, "a", "f", 100),
+            meta(3, 1, "g", "m", 50),
+            meta(2, 2, "a", "z", 500),
+            meta(1, 6, "a", "z", 1_000_000),
+        ];
+        assert_eq!(pending_compaction_bytes(&tables, &picker), 50);
+
+        tables.push(meta(6, 0, "a", "z", 40));
+        assert_eq!(pending_compaction_bytes(&tables, &picker), 50 + 70);
+    }
+
+    #[test]
+    fn periodic_compaction_picks_the_oldest_table_past_the_age() {
+        let options = CompactionOptions {
+            periodic_compaction_age: Some(Duration::from_secs(100)),
+            ..leveled(10, u64::MAX, 1)
+        };
+        let tables = vec![
+            meta_created_at(5, 0, "a", "c", 0, 950),
+            meta_created_at(4, 1, "a", "b", 0, 890),
+            meta_created_at(3, 1, "c", "d", 0, 850),
+            meta_created_at(2, 2, "a", "z", 0, 880),
+            meta_created_at(1, 6, "a", "z", 0, 800),
+        ];
+
+        assert_eq!(pick_compaction(&tables, &options, 900), None);
+
+        // The last level is rewritten in place
+        let compaction = pick_compaction(&tables, &options, 1_000).unwrap();
+        assert_eq!(compaction.reason, CompactionReason::Periodic);
+        assert_eq!(compaction.output_level, 6);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![1]);
+
+        let tables = &tables[..4];
+        let compaction = pick_compaction(tables, &options, 1_000).unwrap();
+        assert_eq!(compaction.output_level, 2);
+        let inputs: Vec<u64> = compaction.inputs.iter().map(|m| m.file_no()).collect();
+        assert_eq!(inputs, vec![3, 2]);
+    }
+
+    #[test]
+    fn range_compaction_picks_the_overlapping_tables() {
+        let tables = vec![
+            meta(7, 0, "x", "z", 0),
+            meta(6, 0, "a", "b", 0),
+            meta(5, 1, "a", "c", 0),
+            meta(4, 1, "d", "f", 0),
+            meta(3, 1, "g", "k", 0),
+            meta(2, 2, "a", "e", 0),
+            meta(1, 2, "f", "z", 0),
+        ];
+        let file_nos = |compaction: Compaction| {
+            compaction
+                .inputs
+                .iter()
+                .map(|m| m.file_no())
+                .collect::<Vec<_>>()
+        };
+
+        // Any overlap pulls in all of L0, and L1 for the combined range
+        let compaction =
+            pick_range_compaction(&tables, 0, b"a", b"a", &BytewiseComparator).unwrap();
+        assert_eq!(compaction.output_level, 1);
+        assert_eq!(file_nos(compaction), vec![7, 6, 5, 4, 3]);
+        assert_eq!(
+            pick_range_compaction(&tables, 0, b"m", b"n", &BytewiseComparator),
+            None
+        );
+
+        let compaction =
+            pick_range_compaction(&tables, 1, b"e", b"g", &BytewiseComparator).unwrap();
+        assert_eq!(compaction.output_level, 2);
+        assert_eq!(file_nos(compaction), vec![4, 3, 2, 1]);
+        assert_eq!(
+            pick_range_compaction(&tables, 1, b"l", b"z", &BytewiseComparator),
+            None
+        );
+    }
+
+    #[test]
+    fn range_tombstones_are_clipped_to_the_subcompaction() {
+        let tombstone = RangeTombstone {
+            start: b"c".to_vec(),
+            end: b"m".to_vec(),
+            seq_no: 4,
+        };
+        let clipped = |start: Option<&[u8]>, end: Option<&[u8]>| {
+            clip_range_tombstone(&BytewiseComparator, &tombstone, start, end)
+                .map(|clipped| (clipped.start, clipped.end, clipped.seq_no))
+        };
+
+        assert_eq!(clipped(None, None), Some((b"c".to_vec(), b"m".to_vec(), 4)));
+        assert_eq!(
+            clipped(Some(b"e"), Some(b"g")),
+            Some((b"e".to_vec(), b"g".to_vec(), 4))
+        );
+        assert_eq!(
+            clipped(Some(b"a"), Some(b"f")),
+            Some((b"c".to_vec(), b"f".to_vec(), 4))
+        );
+        assert_eq!(clipped(Some(b"m"), None), None);
+        assert_eq!(clipped(None, Some(b"c")), None);
+    }
+
+    #[test]
+    fn subcompactions_split_at_input_boundaries() {
+        let compaction = Compaction {
+            output_level: 1,
+            inputs: vec![
+                meta(4, 0, "a", "d", 0),
+                meta(3, 0, "e", "h", 0),
+                meta(2, 1, "a", "c", 0),
+                meta(1, 1, "d", "z", 0),
+            ],
+            bottommost: true,
+            reason: CompactionReason::Picker,
+        };
+
+        assert!(subcompaction_bounds(&compaction, 1, &BytewiseComparator).is_empty());
+        assert_eq!(
+            subcompaction_bounds(&compaction, 2, &BytewiseComparator),
+            vec![b"e".to_vec()]
+        );
+        assert_eq!(
+            subcompaction_bounds(&compaction, 3, &BytewiseComparator),
+            vec![b"d".to_vec(), b"h".to_vec()]
+        );
+        // At most one split point per distinct boundary past the smallest key
+        assert_eq!(
+            subcompaction_bounds(&compaction, 100, &BytewiseComparator).len(),
+            5
+        );
+
+        let single = Compaction {
+            inputs: vec![meta(1, 0, "a", "z", 0)],
+            ..compaction
+        };
+        assert!(subcompaction_bounds(&single, 4, &BytewiseComparator).is_empty());
+    }
+
+    #[test]
+    fn fifo_drops_the_oldest_tables_over_budget() {
+        let mut options = CompactionOptions {
+            style: CompactionStyle::Fifo {
+                max_total_size: Some(250),
+                max_age: None,
+            },
+            ..leveled(1, 1, 1)
+        };
+        let tables = vec![
+            meta_created_at(4, 0, "a", "z", 100, 1_000),
+            meta_created_at(3, 0, "a", "z", 100, 900),
+            meta_created_at(2, 0, "a", "z", 100, 800),
+            meta_created_at(1, 0, "a", "z", 100, 700),
+        ];
+        let file_nos =
+            |expired: Vec<SSTableMeta>| expired.iter().map(|m| m.file_no()).collect::<Vec<_>>();
+
+        // Never merges, however far over the L0 threshold
+        assert_eq!(pick_compaction(&tables, &options, 0), None);
+        assert_eq!(
+            file_nos(pick_fifo_expired(&tables, &options, 1_000)),
+            vec![1, 2]
+        );
+
+        options.style = CompactionStyle::Fifo {
+            max_total_size: None,
+            max_age: Some(Duration::from_secs(150)),
+        };
+        assert_eq!(
+            file_nos(pick_fifo_expired(&tables, &options, 1_000)),
+            vec![1, 2]
+        );
+        assert_eq!(
+            file_nos(pick_fifo_expired(&tables, &options, 1_100)),
+            vec![1, 2, 3]
+        );
+        assert!(pick_fifo_expired(&tables, &options, 850).is_empty());
+    }
+}
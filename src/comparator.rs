@@ -0,0 +1,78 @@
+//! The order keys are kept in. Every MemTable, table, merge and compaction sorts keys with the
+//! `Comparator` of `DBConfig::comparator`, plain byte order unless told otherwise. The order is baked into
+//! every table written, so its name is recorded in the manifest and in the properties of every table, and
+//! a DB written under one comparator can't be opened under another.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Bound;
+
+/// A Comparator orders keys. The order has to be total and stay the same for as long as the DB exists,
+/// keys that compare equal are the same key. `name` identifies the order on disk, a comparator that
+/// changes how it orders keys must change its name too.
+pub trait Comparator: fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+/// Orders keys by their bytes, the way `[u8]` compares. The default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BytewiseComparator;
+
+/// The name of `BytewiseComparator`, also what a manifest or table that records no comparator was written
+/// with.
+pub const BYTEWISE_COMPARATOR_NAME: &str = "lsmdb.BytewiseComparator";
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        BYTEWISE_COMPARATOR_NAME
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Orders keys by their bytes, largest first, e.g. for big-endian timestamps read newest first.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "lsmdb.ReverseBytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Whether `comparator` orders keys by their bytes, which some structures rely on, see `MemTableKind::BTree`.
+pub fn is_bytewise(comparator: &dyn Comparator) -> bool {
+    comparator.name() == BYTEWISE_COMPARATOR_NAME
+}
+
+/// Whether `key` is within the bounds `from` and `until` in the order of `comparator`.
+pub(crate) fn in_bounds(
+    comparator: &dyn Comparator,
+    key: &[u8],
+    from: Bound<&[u8]>,
+    until: Bound<&[u8]>,
+) -> bool {
+    let after_from = match from {
+        Bound::Included(from) => comparator.compare(key, from).is_ge(),
+        Bound::Excluded(from) => comparator.compare(key, from).is_gt(),
+        Bound::Unbounded => true,
+    };
+    after_from && before(comparator, key, until)
+}
+
+/// Whether `key` comes before `until`, or is it if the bound is inclusive.
+pub(crate) fn before(comparator: &dyn Comparator, key: &[u8], until: Bound<&[u8]>) -> bool {
+    match until {
+        Bound::Included(until) => comparator.compare(key, until).is_le(),
+        Bound::Excluded(until) => comparator.compare(key, until).is_lt(),
+        Bound::Unbounded => true,
+    }
+}
@@ -0,0 +1,39 @@
+//! Encryption of data at rest. The DB ships no cipher of its own: an `Encryptor` is implemented by the
+//! user on top of the cipher of their choice, and is also where the keys come from, so they never have
+//! to be handed to the DB. Registered through `DBConfig::wal_encryptor`.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The size of the nonce every encrypted file starts with
+pub const NONCE_LEN: usize = 16;
+
+/// An Encryptor encrypts the bytes of a file in place as they are written, and decrypts them as they are
+/// read back. Every file gets a nonce of its own, and every call is told where in the file its `data`
+/// starts, so that a stream cipher such as AES-CTR can keep its key stream from ever repeating: the
+/// offset picks the position in the stream of the file's nonce.
+///
+/// Both directions must preserve the length of `data`, and `decrypt` must undo `encrypt` for the same
+/// nonce and offset. Data is not necessarily decrypted in the same pieces it was encrypted in.
+pub trait Encryptor: fmt::Debug + Send + Sync {
+    fn encrypt(&self, nonce: &[u8; NONCE_LEN], offset: u64, data: &mut [u8]);
+
+    fn decrypt(&self, nonce: &[u8; NONCE_LEN], offset: u64, data: &mut [u8]);
+}
+
+/// A nonce for a new file numbered `file_no`. It only has to be unique, not secret, hence the file
+/// number followed by a timestamp made unique within the process.
+pub(crate) fn new_nonce(file_no: u64) -> [u8; NONCE_LEN] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    let unique = now.wrapping_add(COUNTER.fetch_add(1, Ordering::Relaxed));
+
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..8].copy_from_slice(&file_no.to_le_bytes());
+    nonce[8..].copy_from_slice(&unique.to_le_bytes());
+    nonce
+}
@@ -1,7 +1,26 @@
-#[derive(Debug, PartialEq, Eq)]
+use crate::comparator::Comparator;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Entry {
-    Value { seq_no: u64, val: Vec<u8> },
-    Tombstone { seq_no: u64 },
+    /// `timestamp` is when the value was written, in milliseconds since the UNIX epoch, if the DB recorded
+    /// it, see `DBConfig::record_write_time`. `expires_at` is when it expires, by the same measure, for a
+    /// value written with `DB::put_with_ttl`.
+    Value {
+        seq_no: u64,
+        val: Vec<u8>,
+        timestamp: Option<u64>,
+        expires_at: Option<u64>,
+    },
+    Tombstone {
+        seq_no: u64,
+    },
+    /// An operand written with `DB::merge`, to be folded into the value beneath it by the merge operator.
+    /// It may stand for several operands already folded into one, see `MergeOperator`.
+    Merge {
+        seq_no: u64,
+        operand: Vec<u8>,
+        timestamp: Option<u64>,
+    },
 }
 
 impl Entry {
@@ -9,6 +28,75 @@ impl Entry {
         match self {
             Entry::Value { seq_no, .. } => *seq_no,
             Entry::Tombstone { seq_no } => *seq_no,
+            Entry::Merge { seq_no, .. } => *seq_no,
+        }
+    }
+
+    /// When the value or operand was written, `None` for a tombstone or one written without it.
+    pub fn timestamp(&self) -> Option<u64> {
+        match self {
+            Entry::Value { timestamp, .. } | Entry::Merge { timestamp, .. } => *timestamp,
+            Entry::Tombstone { .. } => None,
+        }
+    }
+
+    /// When the value expires, `None` for anything but a value written with a TTL.
+    pub fn expires_at(&self) -> Option<u64> {
+        match self {
+            Entry::Value { expires_at, .. } => *expires_at,
+            Entry::Tombstone { .. } | Entry::Merge { .. } => None,
         }
     }
-}
\ No newline at end of file
+
+    /// Whether the value has expired by `now`, in milliseconds since the UNIX epoch. An expired value
+    /// reads as missing.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// A RangeTombstone deletes every key in the half-open range `[start, end)` written before it, i.e. every
+/// version with a lower `seq_no`. Versions written after it are left alone. The range is in the order of
+/// the DB's `Comparator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub seq_no: u64,
+}
+
+impl RangeTombstone {
+    pub fn contains(&self, comparator: &dyn Comparator, key: &[u8]) -> bool {
+        comparator.compare(&self.start, key).is_le() && comparator.compare(key, &self.end).is_lt()
+    }
+
+    /// Whether the version of `key` written at `seq_no` is deleted by this tombstone.
+    pub fn deletes(&self, comparator: &dyn Comparator, key: &[u8], seq_no: u64) -> bool {
+        seq_no < self.seq_no && self.contains(comparator, key)
+    }
+}
+
+/// The highest `seq_no` among the `tombstones` whose range holds `key`, every version of `key` below it is
+/// deleted.
+pub fn covering_seq_no(
+    comparator: &dyn Comparator,
+    tombstones: &[RangeTombstone],
+    key: &[u8],
+) -> Option<u64> {
+    covering_seq_no_before(comparator, tombstones, key, u64::MAX)
+}
+
+/// `covering_seq_no` among the tombstones written before `seq_no`, the ones a snapshot at `seq_no` sees.
+pub fn covering_seq_no_before(
+    comparator: &dyn Comparator,
+    tombstones: &[RangeTombstone],
+    key: &[u8],
+    seq_no: u64,
+) -> Option<u64> {
+    tombstones
+        .iter()
+        .filter(|tombstone| tombstone.seq_no < seq_no && tombstone.contains(comparator, key))
+        .map(|tombstone| tombstone.seq_no)
+        .max()
+}
@@ -0,0 +1,208 @@
+//! Flushes of the MemTable into L0 tables. A full MemTable is frozen into an `ImmutableMemTable` and
+//! swapped for an empty one right away, so writes go on while the background worker flushes the frozen
+//! one. Reads look through the frozen MemTable until its table is installed.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::FlushProgressCallback;
+use crate::entry::RangeTombstone;
+use crate::iterator::{EntryIterator, MemTableIterator, MergingIterator};
+use crate::listener::{EventListener, FlushJobInfo};
+use crate::manifest::VersionEdit;
+use crate::memtable::{self, MemTableRep, SupersededEntries};
+use crate::sstable::{SSTableConfig, SSTableMeta, SSTableWriter};
+use crate::types::DBError;
+use crate::version::{self, VersionSet};
+use crate::wal::WAL;
+
+/// A MemTable frozen for flushing, along with the range tombstones written next to it and the entries it
+/// overwrote that live snapshots still read.
+pub(crate) struct ImmutableMemTable {
+    pub(crate) mem_table: Box<dyn MemTableRep>,
+    pub(crate) superseded: SupersededEntries,
+    pub(crate) range_tombstones: Vec<RangeTombstone>,
+    // Every write in the MemTable is in the WAL segments numbered below this one
+    pub(crate) wal_segment_no: u64,
+}
+
+/// The frozen MemTable, while it is being flushed. Only one MemTable is flushed at a time, a writer that
+/// fills the next one first waits for this one, see `wait`.
+#[derive(Default)]
+pub(crate) struct PendingFlush {
+    state: Mutex<PendingState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct PendingState {
+    mem_table: Option<Arc<ImmutableMemTable>>,
+    // Why the last flush of `mem_table` failed. It stays frozen until the flush is retried
+    error: Option<DBError>,
+}
+
+impl PendingFlush {
+    pub(crate) fn get(&self) -> Option<Arc<ImmutableMemTable>> {
+        self.lock().mem_table.clone()
+    }
+
+    /// Freezes `mem_table`, which is only to be done once `wait` returned.
+    pub(crate) fn set(&self, mem_table: ImmutableMemTable) {
+        let mut state = self.lock();
+        state.mem_table = Some(Arc::new(mem_table));
+        state.error = None;
+    }
+
+    /// Blocks until no MemTable is frozen any more. If its flush failed it stays frozen and the error is
+    /// returned, it is up to the caller to have the flush retried.
+    pub(crate) fn wait(&self) -> Result<(), DBError> {
+        let mut state = self
+            .changed
+            .wait_while(self.lock(), |state| {
+                state.mem_table.is_some() && state.error.is_none()
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        match state.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&self, result: Result<(), DBError>) {
+        let mut state = self.lock();
+        match result {
+            Ok(()) => state.mem_table = None,
+            Err(e) => state.error = Some(e),
+        }
+        self.changed.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PendingState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What a flush needs besides the tables.
+pub(crate) struct FlushOptions {
+    pub(crate) pending: Arc<PendingFlush>,
+    pub(crate) wal: Arc<WAL>,
+    // Who the flushed writes are held in the WAL by, see `WAL::hold`
+    pub(crate) wal_owner: u32,
+    pub(crate) listeners: Vec<Arc<dyn EventListener>>,
+    pub(crate) on_progress: Option<FlushProgressCallback>,
+}
+
+/// Flushes the frozen MemTable, if any, into a new L0 SSTable, records it in the manifest and registers
+/// it. The WAL segments holding its writes are truncated after, and the MemTable unfrozen. A failed flush
+/// leaves the MemTable frozen with the error, see `PendingFlush::wait`.
+pub(crate) fn flush(versions: &Mutex<VersionSet>, options: &FlushOptions, config: &SSTableConfig) {
+    let Some(mem_table) = options.pending.get() else {
+        return;
+    };
+
+    let (file_no, path) = {
+        let mut versions = version::lock(versions);
+        let file_no = versions.manifest.new_file_no();
+        (file_no, versions.manifest.table_path(file_no))
+    };
+
+    let mut info = FlushJobInfo {
+        file_no,
+        entries: (mem_table.mem_table.len()
+            + mem_table.superseded.len()
+            + mem_table.range_tombstones.len()) as u64,
+        table: None,
+        elapsed: Duration::ZERO,
+    };
+    for listener in &options.listeners {
+        listener.on_flush_start(&info);
+    }
+
+    let started = Instant::now();
+    let result = write_mem_table(versions, options, config, &mem_table, file_no, path);
+    info.elapsed = started.elapsed();
+    match result {
+        Ok(meta) => {
+            info.table = Some(meta);
+            options.pending.finish(Ok(()));
+            for listener in &options.listeners {
+                listener.on_flush_finish(&info);
+            }
+        }
+        Err(e) => {
+            for listener in &options.listeners {
+                listener.on_flush_error(&info, &e);
+            }
+            options.pending.finish(Err(e));
+        }
+    }
+}
+
+/// Writes `mem_table` to the table `file_no` at `path` and installs it, returning the new table. The WAL
+/// segments before `wal_segment_no` are truncated after, everything in them is now in the table.
+fn write_mem_table(
+    versions: &Mutex<VersionSet>,
+    options: &FlushOptions,
+    config: &SSTableConfig,
+    mem_table: &ImmutableMemTable,
+    file_no: u64,
+    path: PathBuf,
+) -> Result<SSTableMeta, DBError> {
+    let mut writer = SSTableWriter::with_config(path, file_no, 0, config.clone())?;
+    if let Some(on_progress) = &options.on_progress {
+        let on_progress = on_progress.clone();
+        writer.set_progress_callback(Box::new(move |progress| on_progress(progress)));
+    }
+    writer.preallocate(mem_table_size_hint(mem_table))?;
+    // A MemTable split into several runs, e.g. a `ShardedMemTable`, is merged back into key order
+    let runs = mem_table.mem_table.runs();
+    let mut entries = MergingIterator::new(
+        runs.into_iter()
+            .map(|run| Box::new(MemTableIterator::new(run)) as Box<dyn EntryIterator>)
+            .collect(),
+        mem_table.mem_table.comparator(),
+    );
+    entries.seek_to_first()?;
+    while let Some(entry) = entries.entry() {
+        writer.add(entries.key(), entry)?;
+        for version in mem_table.superseded.versions(entries.key()) {
+            writer.add(entries.key(), version)?;
+        }
+        entries.next()?;
+    }
+    for tombstone in &mem_table.range_tombstones {
+        writer.add_range_tombstone(tombstone)?;
+    }
+    let meta = writer.finish()?;
+
+    {
+        let mut versions = version::lock(versions);
+        versions.stats.record_flush(&meta);
+        versions.apply(VersionEdit {
+            added: vec![meta.clone()],
+            removed: Vec::new(),
+        })?;
+    }
+    options.wal.release_frozen(options.wal_owner);
+    options.wal.truncate_before(mem_table.wal_segment_no)?;
+
+    Ok(meta)
+}
+
+/// A rough upper bound on the size of the SSTable `mem_table` flushes to, ignoring compression and
+/// prefix compression which only ever shrink it.
+fn mem_table_size_hint(mem_table: &ImmutableMemTable) -> u64 {
+    // Roughly the per-entry header of a data block
+    const ENTRY_OVERHEAD: usize = 21;
+
+    mem_table
+        .mem_table
+        .iter()
+        .flat_map(|(key, entry)| {
+            std::iter::once(entry)
+                .chain(mem_table.superseded.versions(key))
+                .map(move |entry| (key.len() + memtable::val_len(entry) + ENTRY_OVERHEAD) as u64)
+        })
+        .sum()
+}
@@ -0,0 +1,269 @@
+//! Secondary indexes. An `Index` of `DBConfig::indexes` maps a value to the key it is indexed under, and
+//! every put and delete keeps its entries up to date in the same `WriteBatch` as the write itself, so an
+//! index never misses a committed write nor sees one that failed. `DB::get_by_index` and
+//! `DB::index_range` then look values up by their index key rather than their own.
+//!
+//! Index entries live in a keyspace of their own at the end of the DB's, every key starting with
+//! `INDEX_KEY_PREFIX`. The key of an entry is
+//!
+//! [INDEX_KEY_PREFIX][index name][0x00][escaped index key][0x00 0x01][primary key]
+//!
+//! and its value the primary key. Every 0x00 byte of the index key is escaped to 0x00 0xff, so entries
+//! sort by index key first and by primary key among those sharing one.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::batch::{BatchOp, WriteBatch};
+use crate::iterator::KeyValue;
+use crate::types::DBError;
+use crate::wal::Op;
+use crate::{DB, RawValue, prefix_successor};
+
+/// The keys of every index entry start with this, see above. Iterating the DB never yields them once an
+/// index is configured, keys starting with it are best left alone.
+pub const INDEX_KEY_PREFIX: &[u8] = b"\xff\xffindex\x00";
+
+const ESCAPED_ZERO: [u8; 2] = [0x00, 0xff];
+const INDEX_KEY_END: [u8; 2] = [0x00, 0x01];
+
+/// An Index derives the key a value is indexed under from the value's bytes. Merges and range deletions
+/// aren't reflected in indexes, see `DB::rebuild_index`.
+pub trait Index: fmt::Debug + Send + Sync {
+    /// Names the index for `DB::get_by_index`. It must not be empty or hold a 0x00 byte.
+    fn name(&self) -> &str;
+
+    /// The key `val` is indexed under, `None` to leave it out of the index. Several values may share one.
+    fn index_key(&self, val: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// An `Index` deriving index keys with a function, see `from_fn`.
+pub struct FnIndex<F> {
+    name: String,
+    f: F,
+}
+
+/// The index `name`, indexing a value under what `f` returns for it.
+pub fn from_fn<F>(name: &str, f: F) -> Arc<dyn Index>
+where
+    F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    Arc::new(FnIndex {
+        name: name.to_string(),
+        f,
+    })
+}
+
+impl<F> fmt::Debug for FnIndex<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FnIndex")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync> Index for FnIndex<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn index_key(&self, val: &[u8]) -> Option<Vec<u8>> {
+        (self.f)(val)
+    }
+}
+
+/// Fails with `DBError::InvalidConfig` unless every index has a valid name of its own.
+pub(crate) fn validate(indexes: &[Arc<dyn Index>]) -> Result<(), DBError> {
+    for (i, index) in indexes.iter().enumerate() {
+        let name = index.name();
+        if name.is_empty() || name.as_bytes().contains(&0) {
+            return Err(DBError::InvalidConfig {
+                what: "index names must be non-empty and hold no 0x00 byte",
+            });
+        }
+        if indexes[..i].iter().any(|other| other.name() == name) {
+            return Err(DBError::InvalidConfig {
+                what: "index names must be unique",
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether `key` is the key of an index entry rather than of a value.
+pub(crate) fn is_index_key(key: &[u8]) -> bool {
+    key.starts_with(INDEX_KEY_PREFIX)
+}
+
+/// `batch` along with the index entry updates its puts and deletes call for, each right after its write.
+/// A write's old value is the one it overwrites, either in the DB or earlier in the batch.
+pub(crate) fn with_index_updates(db: &DB, batch: WriteBatch) -> Result<WriteBatch, DBError> {
+    let indexes = &db.opts.indexes;
+    let mut updated = WriteBatch::new();
+    // What the batch has written so far, as its later writes see it
+    let mut written: HashMap<Vec<u8>, Option<Vec<u8>>> = HashMap::new();
+    for op in batch.into_ops() {
+        let new_val = match op.op {
+            Op::Put => Some(Some(op.val.clone())),
+            Op::ExpiringPut => Some(op.val.get(8..).map(<[u8]>::to_vec)),
+            Op::Delete => Some(None),
+            _ => None,
+        };
+        let key = op.key.clone();
+        updated.push_op(op);
+        let Some(new_val) = new_val.filter(|_| !is_index_key(&key)) else {
+            continue;
+        };
+
+        let old_val = match written.get(&key) {
+            Some(old_val) => old_val.clone(),
+            None => db.get_raw(&RawValue(&key))?,
+        };
+        for index in indexes {
+            let old_index_key = old_val.as_deref().and_then(|val| index.index_key(val));
+            let new_index_key = new_val.as_deref().and_then(|val| index.index_key(val));
+            if old_index_key == new_index_key {
+                continue;
+            }
+            if let Some(old_index_key) = old_index_key {
+                updated.push_op(BatchOp {
+                    op: Op::Delete,
+                    key: entry_key(index.name(), &old_index_key, &key),
+                    val: Vec::new(),
+                });
+            }
+            if let Some(new_index_key) = new_index_key {
+                updated.push_op(BatchOp {
+                    op: Op::Put,
+                    key: entry_key(index.name(), &new_index_key, &key),
+                    val: key.clone(),
+                });
+            }
+        }
+        written.insert(key, new_val);
+    }
+    Ok(updated)
+}
+
+/// The index entry of `primary_key` under `index_key` in the index `name`.
+pub(crate) fn entry_key(name: &str, index_key: &[u8], primary_key: &[u8]) -> Vec<u8> {
+    let mut key = escaped_index_key(name, index_key);
+    key.extend_from_slice(&INDEX_KEY_END);
+    key.extend_from_slice(primary_key);
+    key
+}
+
+/// Where the entries of the index `name` start and end.
+pub(crate) fn index_bounds(name: &str) -> (Vec<u8>, Vec<u8>) {
+    let start = index_prefix(name);
+    let mut end = start.clone();
+    // The 0x00 ending the name
+    *end.last_mut().expect("index prefixes end with 0x00") = 0x01;
+    (start, end)
+}
+
+/// The bounds of the entries whose index key is within `from` and `until`, inside those of the index.
+pub(crate) fn entry_bounds(
+    name: &str,
+    from: Bound<&[u8]>,
+    until: Bound<&[u8]>,
+) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let (start, end) = index_bounds(name);
+    // The entries of an index key all start with it, followed by `INDEX_KEY_END`
+    let past = |index_key: &[u8]| {
+        let mut key = escaped_index_key(name, index_key);
+        key.extend_from_slice(&INDEX_KEY_END);
+        prefix_successor(&key).expect("entry keys end in 0x01")
+    };
+    let from = match from {
+        Bound::Included(index_key) => Bound::Included(escaped_index_key(name, index_key)),
+        Bound::Excluded(index_key) => Bound::Included(past(index_key)),
+        Bound::Unbounded => Bound::Included(start),
+    };
+    let until = match until {
+        Bound::Included(index_key) => Bound::Excluded(past(index_key)),
+        Bound::Excluded(index_key) => Bound::Excluded(escaped_index_key(name, index_key)),
+        Bound::Unbounded => Bound::Excluded(end),
+    };
+    (from, until)
+}
+
+/// The values the `entries` of `index` point to, as `(primary key, value)` pairs, skipping the entries
+/// the values no longer match: those a merge or a range deletion left behind, or an expired value.
+pub(crate) fn resolve(
+    db: &DB,
+    index: &dyn Index,
+    entries: Vec<KeyValue>,
+) -> Result<Vec<KeyValue>, DBError> {
+    let mut found = Vec::with_capacity(entries.len());
+    for (key, primary_key) in entries {
+        let Some(val) = db.get_raw(&RawValue(&primary_key))? else {
+            continue;
+        };
+        if index
+            .index_key(&val)
+            .is_some_and(|index_key| entry_key(index.name(), &index_key, &primary_key) == key)
+        {
+            found.push((primary_key, val));
+        }
+    }
+    Ok(found)
+}
+
+fn index_prefix(name: &str) -> Vec<u8> {
+    let mut key = INDEX_KEY_PREFIX.to_vec();
+    key.extend_from_slice(name.as_bytes());
+    key.push(0x00);
+    key
+}
+
+fn escaped_index_key(name: &str, index_key: &[u8]) -> Vec<u8> {
+    let mut key = index_prefix(name);
+    for &byte in index_key {
+        match byte {
+            0x00 => key.extend_from_slice(&ESCAPED_ZERO),
+            byte => key.push(byte),
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod index_test {
+    use std::ops::RangeBounds;
+
+    use super::*;
+
+    #[test]
+    fn entries_sort_by_index_key_then_primary_key() {
+        let mut keys = vec![
+            entry_key("age", b"ab", b"2"),
+            entry_key("age", b"a\x00", b"1"),
+            entry_key("age", b"a", b"9"),
+            entry_key("age", b"ab", b"1"),
+            entry_key("age", b"a", b"1"),
+        ];
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                entry_key("age", b"a", b"1"),
+                entry_key("age", b"a", b"9"),
+                entry_key("age", b"a\x00", b"1"),
+                entry_key("age", b"ab", b"1"),
+                entry_key("age", b"ab", b"2"),
+            ]
+        );
+
+        let (start, end) = index_bounds("age");
+        assert!(keys.iter().all(|key| start < *key && *key < end));
+        let (other_start, _) = index_bounds("agee");
+        assert!(end <= other_start);
+
+        let bounds = entry_bounds("age", Bound::Excluded(b"a"), Bound::Included(b"ab"));
+        let within: Vec<_> = keys.iter().filter(|key| bounds.contains(*key)).collect();
+        assert_eq!(within, [&keys[2], &keys[3], &keys[4]]);
+    }
+}
@@ -0,0 +1,1226 @@
+//! Cursors over sorted runs of entries, and the `MergingIterator` combining several of them into a single
+//! sorted run. Compaction merges its input tables with it, and scans over the whole DB merge the MemTable
+//! with every live table the same way. The `RangeDeletionIterator` hides whatever range tombstones delete.
+//!
+//! The `DBIterator` handed out by `DB::iter` and friends is built on top, see `DB::range`, and so is the
+//! `DBCursor` of `DB::cursor`, and the pages of `DB::scan_page` resumed with a `ResumeToken`.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::iter::FusedIterator;
+use std::ops::Bound;
+
+use crate::comparator::Comparator;
+use crate::entry::{Entry, RangeTombstone};
+use crate::memtable::MemTableRep;
+use crate::sstable::SSTableIterator;
+use crate::types::{DBError, Decode, Encode, read_u64_le};
+use crate::{DB, ReadOptions};
+
+/// A cursor over entries sorted by key. A table may hold several versions of a key, newest first, a
+/// `SnapshotIterator` over it surfaces one of them. It starts out invalid and has to be positioned with one
+/// of the seeks before `key`/`entry` mean anything, and running off either end leaves it invalid.
+pub trait EntryIterator {
+    fn valid(&self) -> bool;
+
+    /// The key of the current entry. Only meaningful while `valid()`.
+    fn key(&self) -> &[u8];
+
+    /// The current entry, or `None` when the cursor is not `valid()`.
+    fn entry(&self) -> Option<&Entry>;
+
+    fn seek_to_first(&mut self) -> Result<(), DBError>;
+
+    fn seek_to_last(&mut self) -> Result<(), DBError>;
+
+    /// Positions the cursor at the first entry whose key is >= `key`.
+    fn seek(&mut self, key: &[u8]) -> Result<(), DBError>;
+
+    /// Positions the cursor at the last entry whose key is <= `key`.
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError>;
+
+    fn next(&mut self) -> Result<(), DBError>;
+
+    fn prev(&mut self) -> Result<(), DBError>;
+}
+
+impl EntryIterator for SSTableIterator<'_> {
+    fn valid(&self) -> bool {
+        SSTableIterator::valid(self)
+    }
+
+    fn key(&self) -> &[u8] {
+        SSTableIterator::key(self)
+    }
+
+    fn entry(&self) -> Option<&Entry> {
+        SSTableIterator::entry(self)
+    }
+
+    fn seek_to_first(&mut self) -> Result<(), DBError> {
+        SSTableIterator::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) -> Result<(), DBError> {
+        SSTableIterator::seek_to_last(self)
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        SSTableIterator::seek(self, key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        SSTableIterator::seek_for_prev(self, key)
+    }
+
+    fn next(&mut self) -> Result<(), DBError> {
+        SSTableIterator::next(self)
+    }
+
+    fn prev(&mut self) -> Result<(), DBError> {
+        SSTableIterator::prev(self)
+    }
+}
+
+/// A cursor over a `MemTable`. Every step is a lookup in the map, so the MemTable can't change under it.
+pub struct MemTableIterator<'a> {
+    mem_table: &'a dyn MemTableRep,
+    current: Option<(&'a [u8], &'a Entry)>,
+}
+
+impl<'a> MemTableIterator<'a> {
+    pub fn new(mem_table: &'a dyn MemTableRep) -> Self {
+        Self {
+            mem_table,
+            current: None,
+        }
+    }
+
+    fn position(&mut self, from: Bound<&[u8]>) {
+        self.current = self.mem_table.iter_from(from).next();
+    }
+
+    fn position_before(&mut self, until: Bound<&[u8]>) {
+        self.current = self.mem_table.iter_before(until).next();
+    }
+}
+
+impl EntryIterator for MemTableIterator<'_> {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.current.map_or(&[], |(key, _)| key)
+    }
+
+    fn entry(&self) -> Option<&Entry> {
+        self.current.map(|(_, entry)| entry)
+    }
+
+    fn seek_to_first(&mut self) -> Result<(), DBError> {
+        self.position(Bound::Unbounded);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<(), DBError> {
+        self.position_before(Bound::Unbounded);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.position(Bound::Included(key));
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.position_before(Bound::Included(key));
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<(), DBError> {
+        if let Some((key, _)) = self.current {
+            self.position(Bound::Excluded(key));
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<(), DBError> {
+        if let Some((key, _)) = self.current {
+            self.position_before(Bound::Excluded(key));
+        }
+        Ok(())
+    }
+}
+
+/// The MergingIterator merges any number of `EntryIterator`s into one, in the order of its `Comparator`,
+/// which has to be the one the sources are sorted by. A key held by more
+/// than one source shows up once, with the entry of the highest `seq_no`, as long as every source holds
+/// one entry per key, see `SnapshotIterator`. Sources are given newest first, which only decides between
+/// entries with the same `seq_no`. `all_versions` surfaces every entry instead.
+///
+/// Tombstones are passed through like any other entry, it is up to the caller to skip or keep them.
+///
+/// The sources sit in a binary heap keyed by their current key, so a step costs O(log n) for n sources
+/// plus one step of every source that held the key just passed. Turning around, i.e. a `prev` after a
+/// `next` or the other way round, first repositions every source around the current key.
+pub struct MergingIterator<'a> {
+    sources: Vec<Box<dyn EntryIterator + 'a>>,
+    heap: BinaryHeap<HeapEntry<'a>>,
+    comparator: &'a dyn Comparator,
+    direction: Direction,
+    all_versions: bool,
+}
+
+/// Which way a `MergingIterator` moves, every source is positioned at or past its current key that way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Where a source stands, ordered so the heap's top is the entry to surface next.
+struct HeapEntry<'a> {
+    key: Vec<u8>,
+    seq_no: u64,
+    source: usize,
+    comparator: &'a dyn Comparator,
+    direction: Direction,
+}
+
+impl PartialEq for HeapEntry<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for HeapEntry<'_> {}
+
+impl Ord for HeapEntry<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap: the smallest key comes out first, or the largest going backwards,
+        // then the highest seq_no, then the newest source
+        let keys = match self.direction {
+            Direction::Forward => self.comparator.compare(&other.key, &self.key),
+            Direction::Backward => self.comparator.compare(&self.key, &other.key),
+        };
+        keys.then(self.seq_no.cmp(&other.seq_no))
+            .then(other.source.cmp(&self.source))
+    }
+}
+
+impl PartialOrd for HeapEntry<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> MergingIterator<'a> {
+    /// Merges `sources`, newest first, each sorted by `comparator`.
+    pub fn new(sources: Vec<Box<dyn EntryIterator + 'a>>, comparator: &'a dyn Comparator) -> Self {
+        let heap = BinaryHeap::with_capacity(sources.len());
+        Self {
+            sources,
+            heap,
+            comparator,
+            direction: Direction::Forward,
+            all_versions: false,
+        }
+    }
+
+    /// Merges `sources` like `new`, but surfaces every entry of every source, the versions of a key newest
+    /// first. Compaction goes through its input tables this way, to keep the versions snapshots still read.
+    /// Only meant to be moved forwards.
+    pub fn all_versions(
+        sources: Vec<Box<dyn EntryIterator + 'a>>,
+        comparator: &'a dyn Comparator,
+    ) -> Self {
+        Self {
+            all_versions: true,
+            ..Self::new(sources, comparator)
+        }
+    }
+
+    fn push(&mut self, source: usize) {
+        let iter = &self.sources[source];
+        if let Some(entry) = iter.entry() {
+            self.heap.push(HeapEntry {
+                key: iter.key().to_vec(),
+                seq_no: entry.seq_no(),
+                source,
+                comparator: self.comparator,
+                direction: self.direction,
+            });
+        }
+    }
+
+    fn rebuild_heap(&mut self, direction: Direction) {
+        self.direction = direction;
+        self.heap.clear();
+        for source in 0..self.sources.len() {
+            self.push(source);
+        }
+    }
+
+    /// Makes the merge move towards `direction` from the current key. Going forward every source is put
+    /// at its first key >= the current one, going backward at its last key <= it.
+    fn turn(&mut self, direction: Direction) -> Result<(), DBError> {
+        if self.direction == direction {
+            return Ok(());
+        }
+        let Some(top) = self.heap.peek() else {
+            return Ok(());
+        };
+
+        let key = top.key.clone();
+        for source in &mut self.sources {
+            match direction {
+                Direction::Forward => source.seek(&key)?,
+                Direction::Backward => source.seek_for_prev(&key)?,
+            }
+        }
+        self.rebuild_heap(direction);
+        Ok(())
+    }
+
+    /// Steps past the current key towards `direction`.
+    fn step(&mut self, direction: Direction) -> Result<(), DBError> {
+        self.turn(direction)?;
+        let Some(top) = self.heap.pop() else {
+            return Ok(());
+        };
+
+        // Every source still on the current key holds an older version of it, skip past them all unless
+        // every version surfaces
+        let mut advance = vec![top.source];
+        while !self.all_versions
+            && self
+                .heap
+                .peek()
+                .is_some_and(|next| self.comparator.compare(&next.key, &top.key).is_eq())
+        {
+            advance.extend(self.heap.pop().map(|next| next.source));
+        }
+        for source in advance {
+            match direction {
+                Direction::Forward => self.sources[source].next()?,
+                Direction::Backward => self.sources[source].prev()?,
+            }
+            self.push(source);
+        }
+
+        Ok(())
+    }
+}
+
+impl EntryIterator for MergingIterator<'_> {
+    fn valid(&self) -> bool {
+        !self.heap.is_empty()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.heap.peek().map_or(&[], |top| top.key.as_slice())
+    }
+
+    fn entry(&self) -> Option<&Entry> {
+        self.sources[self.heap.peek()?.source].entry()
+    }
+
+    fn seek_to_first(&mut self) -> Result<(), DBError> {
+        for source in &mut self.sources {
+            source.seek_to_first()?;
+        }
+        self.rebuild_heap(Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<(), DBError> {
+        for source in &mut self.sources {
+            source.seek_to_last()?;
+        }
+        self.rebuild_heap(Direction::Backward);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        for source in &mut self.sources {
+            source.seek(key)?;
+        }
+        self.rebuild_heap(Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        for source in &mut self.sources {
+            source.seek_for_prev(key)?;
+        }
+        self.rebuild_heap(Direction::Backward);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<(), DBError> {
+        self.step(Direction::Forward)
+    }
+
+    fn prev(&mut self) -> Result<(), DBError> {
+        self.step(Direction::Backward)
+    }
+}
+
+/// Wraps an `EntryIterator`, skipping every entry one of the `tombstones` deletes, see
+/// `RangeTombstone::deletes`. The tombstones are checked one by one, which is fine for the handful a set of
+/// tables holds.
+pub struct RangeDeletionIterator<'a, I> {
+    inner: I,
+    tombstones: &'a [RangeTombstone],
+    comparator: &'a dyn Comparator,
+}
+
+impl<'a, I: EntryIterator> RangeDeletionIterator<'a, I> {
+    pub fn new(inner: I, tombstones: &'a [RangeTombstone], comparator: &'a dyn Comparator) -> Self {
+        Self {
+            inner,
+            tombstones,
+            comparator,
+        }
+    }
+
+    /// Steps `inner` on with `next` or `prev`, depending on `forward`, for as long as it is on an entry one
+    /// of the tombstones deletes.
+    fn skip_deleted(&mut self, forward: bool) -> Result<(), DBError> {
+        while let Some(entry) = self.inner.entry() {
+            let key = self.inner.key();
+            let seq_no = entry.seq_no();
+            if !self
+                .tombstones
+                .iter()
+                .any(|tombstone| tombstone.deletes(self.comparator, key, seq_no))
+            {
+                break;
+            }
+            if forward {
+                self.inner.next()?;
+            } else {
+                self.inner.prev()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: EntryIterator> EntryIterator for RangeDeletionIterator<'_, I> {
+    fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.inner.key()
+    }
+
+    fn entry(&self) -> Option<&Entry> {
+        self.inner.entry()
+    }
+
+    fn seek_to_first(&mut self) -> Result<(), DBError> {
+        self.inner.seek_to_first()?;
+        self.skip_deleted(true)
+    }
+
+    fn seek_to_last(&mut self) -> Result<(), DBError> {
+        self.inner.seek_to_last()?;
+        self.skip_deleted(false)
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.inner.seek(key)?;
+        self.skip_deleted(true)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.inner.seek_for_prev(key)?;
+        self.skip_deleted(false)
+    }
+
+    fn next(&mut self) -> Result<(), DBError> {
+        self.inner.next()?;
+        self.skip_deleted(true)
+    }
+
+    fn prev(&mut self) -> Result<(), DBError> {
+        self.inner.prev()?;
+        self.skip_deleted(false)
+    }
+}
+
+/// Wraps an `EntryIterator`, surfacing for every key the newest entry written before `read_seq_no`, i.e. the
+/// version a `Snapshot` at `read_seq_no` reads. Newer entries are skipped, and so are the older versions a
+/// table keeps of a key for the snapshots that still read them, see `SSTableWriter::add`. Each source of a
+/// merge is wrapped on its own, so the version of a key a skipped entry replaced still surfaces from an
+/// older source.
+pub struct SnapshotIterator<I> {
+    inner: I,
+    read_seq_no: u64,
+    forward: bool,
+    // Going backwards `inner` has to pass every version of a key to tell which one is read, so it sits
+    // before the current key, which is kept here along with its entry
+    current: Option<(Vec<u8>, Entry)>,
+}
+
+impl<I: EntryIterator> SnapshotIterator<I> {
+    pub fn new(inner: I, read_seq_no: u64) -> Self {
+        Self {
+            inner,
+            read_seq_no,
+            forward: true,
+            current: None,
+        }
+    }
+
+    /// Steps `inner` on for as long as it is on an entry written at or after `read_seq_no`.
+    fn skip_newer(&mut self) -> Result<(), DBError> {
+        while self
+            .inner
+            .entry()
+            .is_some_and(|entry| entry.seq_no() >= self.read_seq_no)
+        {
+            self.inner.next()?;
+        }
+        Ok(())
+    }
+
+    /// Steps `inner` past every version of `key`, with `next` or `prev` depending on `forward`.
+    fn skip_versions(&mut self, key: &[u8], forward: bool) -> Result<(), DBError> {
+        while self.inner.valid() && self.inner.key() == key {
+            if forward {
+                self.inner.next()?;
+            } else {
+                self.inner.prev()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks `inner` back from the oldest version of its key until a key has a version written before
+    /// `read_seq_no`, and makes the newest of those the current entry.
+    fn read_backward(&mut self) -> Result<(), DBError> {
+        self.forward = false;
+        self.current = None;
+        while self.inner.valid() {
+            let key = self.inner.key().to_vec();
+            let mut read = None;
+            // The versions of a key come newest last this way round
+            while let Some(entry) = self.inner.entry()
+                && self.inner.key() == key.as_slice()
+            {
+                if entry.seq_no() < self.read_seq_no {
+                    read = Some(entry.clone());
+                }
+                self.inner.prev()?;
+            }
+            if let Some(entry) = read {
+                self.current = Some((key, entry));
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: EntryIterator> EntryIterator for SnapshotIterator<I> {
+    fn valid(&self) -> bool {
+        match self.forward {
+            true => self.inner.valid(),
+            false => self.current.is_some(),
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        match &self.current {
+            Some((key, _)) if !self.forward => key,
+            _ => self.inner.key(),
+        }
+    }
+
+    fn entry(&self) -> Option<&Entry> {
+        match self.forward {
+            true => self.inner.entry(),
+            false => self.current.as_ref().map(|(_, entry)| entry),
+        }
+    }
+
+    fn seek_to_first(&mut self) -> Result<(), DBError> {
+        self.forward = true;
+        self.inner.seek_to_first()?;
+        self.skip_newer()
+    }
+
+    fn seek_to_last(&mut self) -> Result<(), DBError> {
+        self.inner.seek_to_last()?;
+        self.read_backward()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.forward = true;
+        self.inner.seek(key)?;
+        self.skip_newer()
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.inner.seek_for_prev(key)?;
+        self.read_backward()
+    }
+
+    fn next(&mut self) -> Result<(), DBError> {
+        let key = match self.forward {
+            true if self.inner.valid() => self.inner.key().to_vec(),
+            true => return Ok(()),
+            false => {
+                let Some((key, _)) = self.current.take() else {
+                    return Ok(());
+                };
+                self.forward = true;
+                self.inner.seek(&key)?;
+                key
+            }
+        };
+        self.skip_versions(&key, true)?;
+        self.skip_newer()
+    }
+
+    fn prev(&mut self) -> Result<(), DBError> {
+        if self.forward {
+            if !self.inner.valid() {
+                return Ok(());
+            }
+            let key = self.inner.key().to_vec();
+            self.skip_versions(&key, false)?;
+        } else if self.current.is_none() {
+            return Ok(());
+        }
+        self.read_backward()
+    }
+}
+
+/// A key and its value, as a `DBIterator` yields them.
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// How many keys a `DBIterator` reads per batch.
+pub(crate) const SCAN_BATCH_LEN: usize = 256;
+
+/// An iterator over the live keys of a range of a `DB`, in key order, along with their values. Tombstones
+/// and the versions they shadow are skipped, range tombstones included. It runs backwards too, e.g.
+/// `db.range(..).rev()` for the last keys first. An error ends the iteration.
+///
+/// Keys are read a batch of `SCAN_BATCH_LEN` at a time, every batch merging the MemTables and tables anew
+/// from just past the last key read from that end. No table is pinned between batches, so flushes and
+/// compactions go on underneath, while borrowing the DB keeps writes out: the iterator sees the DB as it
+/// was when it was created.
+pub struct DBIterator<'a> {
+    db: &'a DB,
+    options: ReadOptions<'a>,
+    // The part of the range no batch has read yet, empty once `used_up`
+    from: Bound<Vec<u8>>,
+    until: Bound<Vec<u8>>,
+    used_up: bool,
+    // The keys read from either end and not yet yielded, both in key order
+    front: VecDeque<KeyValue>,
+    back: VecDeque<KeyValue>,
+}
+
+impl<'a> DBIterator<'a> {
+    pub(crate) fn new(
+        db: &'a DB,
+        from: Bound<Vec<u8>>,
+        until: Bound<Vec<u8>>,
+        options: ReadOptions<'a>,
+    ) -> Self {
+        Self {
+            db,
+            options,
+            from,
+            until,
+            used_up: false,
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+        }
+    }
+
+    /// Reads the next batch at the front of the unread range, or at its back if `reverse`.
+    fn read(&mut self, reverse: bool) -> Result<(), DBError> {
+        let (from, until) = (self.from.as_ref(), self.until.as_ref());
+        let batch = match self.db.scan(
+            from.map(Vec::as_slice),
+            until.map(Vec::as_slice),
+            reverse,
+            SCAN_BATCH_LEN,
+            &self.options,
+        ) {
+            Ok(batch) => batch,
+            Err(e) => {
+                self.used_up = true;
+                self.front.clear();
+                self.back.clear();
+                return Err(e);
+            }
+        };
+
+        // A short batch means the range is used up
+        let last = batch.last().map(|(key, _)| Bound::Excluded(key.clone()));
+        match last {
+            Some(last) if batch.len() == SCAN_BATCH_LEN && reverse => self.until = last,
+            Some(last) if batch.len() == SCAN_BATCH_LEN => self.from = last,
+            _ => self.used_up = true,
+        }
+        if reverse {
+            for key_value in batch {
+                self.back.push_front(key_value);
+            }
+        } else {
+            self.front.extend(batch);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for DBIterator<'_> {
+    type Item = Result<KeyValue, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.front.pop_front() {
+                return Some(Ok(item));
+            }
+            // Whatever is left was read from the back
+            if self.used_up {
+                return self.back.pop_front().map(Ok);
+            }
+            if let Err(e) = self.read(false) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for DBIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.back.pop_back() {
+                return Some(Ok(item));
+            }
+            if self.used_up {
+                return self.front.pop_back().map(Ok);
+            }
+            if let Err(e) = self.read(true) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl FusedIterator for DBIterator<'_> {}
+
+/// A cursor over the live keys of a `DB`, with their values, for callers that need to position it
+/// themselves, e.g. to resume a scan after the last key of a page. It starts out invalid and has to be
+/// positioned with one of the seeks, and stepping off either end leaves it invalid. Keys are passed and
+/// returned encoded, as `key` returns them.
+///
+/// Like a `DBIterator` it reads `SCAN_BATCH_LEN` keys at a time, a step past either end of a batch reads
+/// the next one that way. A failed read leaves the cursor invalid, with the error returned. The bounds of
+/// its `ReadOptions` are the ends it steps off at, a seek past them lands on the nearest key within.
+pub struct DBCursor<'a> {
+    db: &'a DB,
+    options: ReadOptions<'a>,
+    // The keys read around the current one, in key order
+    batch: Vec<KeyValue>,
+    // Where the cursor stands in `batch`, `None` while it is invalid
+    at: Option<usize>,
+}
+
+impl<'a> DBCursor<'a> {
+    pub(crate) fn new(db: &'a DB, options: ReadOptions<'a>) -> Self {
+        Self {
+            db,
+            options,
+            batch: Vec::new(),
+            at: None,
+        }
+    }
+
+    pub fn valid(&self) -> bool {
+        self.at.is_some()
+    }
+
+    /// The key the cursor is at, empty when it is not `valid()`.
+    pub fn key(&self) -> &[u8] {
+        self.current().map_or(&[], |(key, _)| key)
+    }
+
+    /// The value of the key the cursor is at, empty when it is not `valid()`.
+    pub fn value(&self) -> &[u8] {
+        self.current().map_or(&[], |(_, val)| val)
+    }
+
+    pub fn seek_to_first(&mut self) -> Result<(), DBError> {
+        self.read(Bound::Unbounded, Bound::Unbounded, false)
+    }
+
+    pub fn seek_to_last(&mut self) -> Result<(), DBError> {
+        self.read(Bound::Unbounded, Bound::Unbounded, true)
+    }
+
+    /// Positions the cursor at the first key >= `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.read(Bound::Included(key), Bound::Unbounded, false)
+    }
+
+    /// Positions the cursor at the last key <= `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.read(Bound::Unbounded, Bound::Included(key), true)
+    }
+
+    // A cursor step rather than `Iterator::next`, the cursor also has to move backwards
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<(), DBError> {
+        let Some(at) = self.at else {
+            return Ok(());
+        };
+        if at + 1 < self.batch.len() {
+            self.at = Some(at + 1);
+            return Ok(());
+        }
+
+        let key = self.batch[at].0.clone();
+        self.read(Bound::Excluded(&key), Bound::Unbounded, false)
+    }
+
+    pub fn prev(&mut self) -> Result<(), DBError> {
+        let Some(at) = self.at else {
+            return Ok(());
+        };
+        if at > 0 {
+            self.at = Some(at - 1);
+            return Ok(());
+        }
+
+        let key = self.batch[at].0.clone();
+        self.read(Bound::Unbounded, Bound::Excluded(&key), true)
+    }
+
+    fn current(&self) -> Option<&KeyValue> {
+        self.batch.get(self.at?)
+    }
+
+    /// Reads the first batch within `from` and `until`, or the last one if `reverse`, and positions the
+    /// cursor at the key nearest that end.
+    fn read(
+        &mut self,
+        from: Bound<&[u8]>,
+        until: Bound<&[u8]>,
+        reverse: bool,
+    ) -> Result<(), DBError> {
+        self.at = None;
+        let (from, until) = self.options.clamp(self.db.comparator(), from, until);
+        self.batch = self
+            .db
+            .scan(from, until, reverse, SCAN_BATCH_LEN, &self.options)?;
+        if reverse {
+            self.batch.reverse();
+        }
+        self.at = match reverse {
+            false => (!self.batch.is_empty()).then_some(0),
+            true => self.batch.len().checked_sub(1),
+        };
+        Ok(())
+    }
+}
+
+/// Where a `DB::scan_page` left off: the last key of the page, and the seq_no it was read as of. It is
+/// opaque to callers, to be handed out encoded and passed back in to read the next page. Decoding bytes
+/// that aren't an encoded token fails with `DBError::Codec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumeToken {
+    // The seq_no the page was read as of, see `ReadOptions::read_seq_no`
+    pub(crate) seq_no: u64,
+    pub(crate) last_key: Vec<u8>,
+}
+
+impl Encode for ResumeToken {
+    /// [seq_no (8 bytes LE)][last key]
+    fn encode(&self) -> Vec<u8> {
+        [&self.seq_no.to_le_bytes(), self.last_key.as_slice()].concat()
+    }
+}
+
+impl Decode for ResumeToken {
+    fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+        match (read_u64_le(bytes), bytes.get(8..)) {
+            (Some(seq_no), Some(last_key)) if !last_key.is_empty() => Ok(Self {
+                seq_no,
+                last_key: last_key.to_vec(),
+            }),
+            _ => Err(DBError::Codec {
+                context: String::from("malformed resume token"),
+                source: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod iterator_test {
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+    use crate::memtable::MemTable;
+
+    fn mem_table(entries: &[(&str, Entry)]) -> MemTable {
+        entries
+            .iter()
+            .map(|(key, entry)| (key.as_bytes().to_vec(), entry.clone()))
+            .collect()
+    }
+
+    fn value(seq_no: u64, val: &str) -> Entry {
+        Entry::Value {
+            seq_no,
+            val: val.as_bytes().to_vec(),
+            timestamp: None,
+            expires_at: None,
+        }
+    }
+
+    fn collect(iter: &mut impl EntryIterator) -> Vec<(String, Entry)> {
+        let mut out = Vec::new();
+        while let Some(entry) = iter.entry() {
+            out.push((
+                String::from_utf8_lossy(iter.key()).into_owned(),
+                entry.clone(),
+            ));
+            iter.next().unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn merges_in_key_order_with_the_highest_seq_no_winning() {
+        let newer = mem_table(&[
+            ("b", value(5, "b-new")),
+            ("d", Entry::Tombstone { seq_no: 6 }),
+        ]);
+        let older = mem_table(&[
+            ("a", value(1, "a")),
+            ("b", value(2, "b-old")),
+            ("d", value(3, "d")),
+        ]);
+        // Listed as the newest source, but holding an older version of "a"
+        let stale = mem_table(&[("a", value(0, "a-stale")), ("e", value(4, "e"))]);
+
+        let mut iter = MergingIterator::new(
+            vec![
+                Box::new(MemTableIterator::new(&stale)),
+                Box::new(MemTableIterator::new(&newer)),
+                Box::new(MemTableIterator::new(&older)),
+            ],
+            &BytewiseComparator,
+        );
+        iter.seek_to_first().unwrap();
+        assert_eq!(
+            collect(&mut iter),
+            vec![
+                ("a".to_string(), value(1, "a")),
+                ("b".to_string(), value(5, "b-new")),
+                ("d".to_string(), Entry::Tombstone { seq_no: 6 }),
+                ("e".to_string(), value(4, "e")),
+            ]
+        );
+        assert!(!iter.valid());
+
+        iter.seek(b"c").unwrap();
+        assert_eq!(iter.key(), b"d");
+    }
+
+    #[test]
+    fn merges_backwards_and_turns_around() {
+        let newer = mem_table(&[("b", value(5, "b-new")), ("d", value(6, "d"))]);
+        let older = mem_table(&[
+            ("a", value(1, "a")),
+            ("b", value(2, "b-old")),
+            ("c", value(3, "c")),
+        ]);
+        let mut iter = MergingIterator::new(
+            vec![
+                Box::new(MemTableIterator::new(&newer)),
+                Box::new(MemTableIterator::new(&older)),
+            ],
+            &BytewiseComparator,
+        );
+
+        iter.seek_to_last().unwrap();
+        let mut backwards = Vec::new();
+        while let Some(entry) = iter.entry() {
+            backwards.push((
+                String::from_utf8_lossy(iter.key()).into_owned(),
+                entry.clone(),
+            ));
+            iter.prev().unwrap();
+        }
+        assert_eq!(
+            backwards,
+            vec![
+                ("d".to_string(), value(6, "d")),
+                ("c".to_string(), value(3, "c")),
+                ("b".to_string(), value(5, "b-new")),
+                ("a".to_string(), value(1, "a")),
+            ]
+        );
+
+        // Every source moves past the key turned around at, so no older version of it shows up
+        iter.seek(b"b").unwrap();
+        iter.next().unwrap();
+        assert_eq!(iter.key(), b"c");
+        iter.prev().unwrap();
+        assert_eq!(iter.entry(), Some(&value(5, "b-new")));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"a");
+        iter.next().unwrap();
+        assert_eq!(iter.entry(), Some(&value(5, "b-new")));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), b"c");
+
+        iter.seek_for_prev(b"bb").unwrap();
+        assert_eq!(iter.entry(), Some(&value(5, "b-new")));
+    }
+
+    #[test]
+    fn snapshot_iterators_skip_newer_entries_both_ways() {
+        let mem = mem_table(&[
+            ("a", value(1, "a")),
+            ("b", value(7, "b")),
+            ("c", value(3, "c")),
+            ("d", value(9, "d")),
+        ]);
+        let mut iter = SnapshotIterator::new(MemTableIterator::new(&mem), 5);
+        iter.seek_to_first().unwrap();
+        assert_eq!(
+            collect(&mut iter),
+            vec![
+                ("a".to_string(), value(1, "a")),
+                ("c".to_string(), value(3, "c"))
+            ]
+        );
+
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key(), b"c");
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"a");
+        iter.seek(b"b").unwrap();
+        assert_eq!(iter.key(), b"c");
+        iter.seek_for_prev(b"b").unwrap();
+        assert_eq!(iter.key(), b"a");
+    }
+
+    #[test]
+    fn equal_seq_nos_go_to_the_newest_source() {
+        let newer = mem_table(&[("a", value(0, "new"))]);
+        let older = mem_table(&[("a", value(0, "old"))]);
+
+        let mut iter = MergingIterator::new(
+            vec![
+                Box::new(MemTableIterator::new(&newer)),
+                Box::new(MemTableIterator::new(&older)),
+            ],
+            &BytewiseComparator,
+        );
+        iter.seek_to_first().unwrap();
+        assert_eq!(collect(&mut iter), vec![("a".to_string(), value(0, "new"))]);
+    }
+
+    #[test]
+    fn range_tombstones_hide_the_older_entries_they_cover() {
+        let mem = mem_table(&[
+            ("a", value(1, "a")),
+            ("b", value(2, "b")),
+            ("c", value(3, "c")),
+            ("d", value(9, "d")),
+            ("e", value(5, "e")),
+        ]);
+        // "a" and "d" were written after the tombstones covering them, and "e" is past the exclusive end
+        let tombstones = [
+            RangeTombstone {
+                start: b"b".to_vec(),
+                end: b"e".to_vec(),
+                seq_no: 8,
+            },
+            RangeTombstone {
+                start: b"a".to_vec(),
+                end: b"b".to_vec(),
+                seq_no: 0,
+            },
+        ];
+
+        let mut iter = RangeDeletionIterator::new(
+            MemTableIterator::new(&mem),
+            &tombstones,
+            &BytewiseComparator,
+        );
+        iter.seek_to_first().unwrap();
+        assert_eq!(
+            collect(&mut iter),
+            vec![
+                ("a".to_string(), value(1, "a")),
+                ("d".to_string(), value(9, "d")),
+                ("e".to_string(), value(5, "e")),
+            ]
+        );
+
+        iter.seek(b"b").unwrap();
+        assert_eq!(iter.key(), b"d");
+        iter.seek_for_prev(b"c").unwrap();
+        assert_eq!(iter.key(), b"a");
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key(), b"e");
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"d");
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"a");
+    }
+
+    /// Runs over `entries` as a table holding several versions of a key does.
+    struct Versions {
+        entries: Vec<(Vec<u8>, Entry)>,
+        pos: Option<usize>,
+    }
+
+    impl Versions {
+        fn new(entries: &[(&str, Entry)]) -> Self {
+            Self {
+                entries: entries
+                    .iter()
+                    .map(|(key, entry)| (key.as_bytes().to_vec(), entry.clone()))
+                    .collect(),
+                pos: None,
+            }
+        }
+    }
+
+    impl EntryIterator for Versions {
+        fn valid(&self) -> bool {
+            self.pos.is_some()
+        }
+
+        fn key(&self) -> &[u8] {
+            self.pos.map_or(&[], |pos| self.entries[pos].0.as_slice())
+        }
+
+        fn entry(&self) -> Option<&Entry> {
+            self.pos.map(|pos| &self.entries[pos].1)
+        }
+
+        fn seek_to_first(&mut self) -> Result<(), DBError> {
+            self.pos = (!self.entries.is_empty()).then_some(0);
+            Ok(())
+        }
+
+        fn seek_to_last(&mut self) -> Result<(), DBError> {
+            self.pos = self.entries.len().checked_sub(1);
+            Ok(())
+        }
+
+        fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+            self.pos = self.entries.iter().position(|(k, _)| k.as_slice() >= key);
+            Ok(())
+        }
+
+        fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+            self.pos = self.entries.iter().rposition(|(k, _)| k.as_slice() <= key);
+            Ok(())
+        }
+
+        fn next(&mut self) -> Result<(), DBError> {
+            self.pos = self
+                .pos
+                .map(|pos| pos + 1)
+                .filter(|&pos| pos < self.entries.len());
+            Ok(())
+        }
+
+        fn prev(&mut self) -> Result<(), DBError> {
+            self.pos = self.pos.and_then(|pos| pos.checked_sub(1));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn snapshot_iterators_surface_the_version_they_read() {
+        let table = Versions::new(&[
+            ("a", value(8, "a-new")),
+            ("a", value(4, "a-mid")),
+            ("a", value(2, "a-old")),
+            ("b", value(9, "b")),
+            ("c", value(6, "c-new")),
+            ("c", value(1, "c-old")),
+        ]);
+        let mut iter = SnapshotIterator::new(table, 5);
+        iter.seek_to_first().unwrap();
+        assert_eq!(
+            collect(&mut iter),
+            vec![
+                ("a".to_string(), value(4, "a-mid")),
+                ("c".to_string(), value(1, "c-old")),
+            ]
+        );
+
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.entry(), Some(&value(1, "c-old")));
+        iter.prev().unwrap();
+        assert_eq!(iter.entry(), Some(&value(4, "a-mid")));
+        iter.prev().unwrap();
+        assert!(!iter.valid());
+
+        // Turning around moves past every version of the key turned at
+        iter.seek(b"a").unwrap();
+        iter.next().unwrap();
+        assert_eq!(iter.entry(), Some(&value(1, "c-old")));
+        iter.prev().unwrap();
+        assert_eq!(iter.entry(), Some(&value(4, "a-mid")));
+        iter.next().unwrap();
+        assert_eq!(iter.entry(), Some(&value(1, "c-old")));
+        iter.seek_for_prev(b"b").unwrap();
+        assert_eq!(iter.entry(), Some(&value(4, "a-mid")));
+
+        let mut latest = SnapshotIterator::new(
+            Versions::new(&[("a", value(8, "a-new")), ("a", value(4, "a-mid"))]),
+            u64::MAX,
+        );
+        latest.seek_to_first().unwrap();
+        assert_eq!(
+            collect(&mut latest),
+            vec![("a".to_string(), value(8, "a-new"))]
+        );
+    }
+
+    #[test]
+    fn all_versions_merges_surface_every_entry() {
+        let newer = Versions::new(&[
+            ("a", value(7, "a-7")),
+            ("a", value(3, "a-3")),
+            ("b", value(5, "b")),
+        ]);
+        let older = Versions::new(&[("a", value(4, "a-4")), ("c", value(1, "c"))]);
+
+        let mut iter = MergingIterator::all_versions(
+            vec![Box::new(newer), Box::new(older)],
+            &BytewiseComparator,
+        );
+        iter.seek_to_first().unwrap();
+        assert_eq!(
+            collect(&mut iter),
+            vec![
+                ("a".to_string(), value(7, "a-7")),
+                ("a".to_string(), value(4, "a-4")),
+                ("a".to_string(), value(3, "a-3")),
+                ("b".to_string(), value(5, "b")),
+                ("c".to_string(), value(1, "c")),
+            ]
+        );
+    }
+}
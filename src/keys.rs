@@ -0,0 +1,345 @@
+//! Order-preserving key encodings. Keys compare by their bytes, see `BytewiseComparator`, so a range
+//! scan over numbers or composite keys only follows their order if the bytes do: `u64::to_le_bytes` puts
+//! 256 before 1, and a negative `i64` in two's complement after every positive one. A `KeyPart` is written
+//! so that the bytes compare the way the values do, and a tuple of them compares part by part, e.g. all
+//! the keys of `(user_id, timestamp)` for one user are next to each other, oldest first.
+//!
+//! Wrap a key in `Key` to hand it to the DB, or build the bytes with `encode` and read them back with
+//! `decode`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::types::{DBError, Decode, Encode};
+
+/// Ends a byte string part, see `KeyPart for Vec<u8>`.
+const TERMINATOR: [u8; 2] = [0x00, 0x01];
+/// Stands for a 0x00 byte within a byte string part.
+const ESCAPED_ZERO: [u8; 2] = [0x00, 0xff];
+
+/// A KeyPart is a value written into a key so that its bytes compare the way the values do. Numbers and
+/// timestamps take a fixed number of bytes, byte strings and strings end with a terminator, so a part
+/// can be followed by another in a tuple without the two running into each other.
+pub trait KeyPart: Sized {
+    /// Appends the encoding of the part to `buf`.
+    fn write_to(&self, buf: &mut Vec<u8>);
+
+    /// Reads a part off the front of `bytes`, leaving `bytes` at what follows it.
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError>;
+}
+
+/// The order-preserving encoding of `part`.
+pub fn encode<P: KeyPart>(part: &P) -> Vec<u8> {
+    let mut buf = Vec::new();
+    part.write_to(&mut buf);
+    buf
+}
+
+/// Reads back a key written by `encode`, failing if the bytes are malformed or have anything past it.
+pub fn decode<P: KeyPart>(mut bytes: &[u8]) -> Result<P, DBError> {
+    let part = P::read_from(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(malformed("trailing bytes after key"));
+    }
+    Ok(part)
+}
+
+/// Encodes and decodes its `KeyPart` with `encode` and `decode`, to use it as the key of a `DB` or a
+/// `TypedDB`, e.g. `db.put(&Key((user_id, created_at)), &val)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key<T>(pub T);
+
+impl<T: KeyPart> Encode for Key<T> {
+    fn encode(&self) -> Vec<u8> {
+        encode(&self.0)
+    }
+}
+
+impl<T: KeyPart> Decode for Key<T> {
+    fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+        decode(bytes).map(Key)
+    }
+}
+
+fn malformed(what: &str) -> DBError {
+    DBError::Codec {
+        context: format!("malformed key: {what}"),
+        source: None,
+    }
+}
+
+fn read_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], DBError> {
+    let Some((head, rest)) = bytes.split_first_chunk::<N>() else {
+        return Err(malformed("key is too short"));
+    };
+    *bytes = rest;
+    Ok(*head)
+}
+
+fn write_escaped(bytes: &[u8], buf: &mut Vec<u8>) {
+    for &byte in bytes {
+        match byte {
+            0x00 => buf.extend_from_slice(&ESCAPED_ZERO),
+            byte => buf.push(byte),
+        }
+    }
+    buf.extend_from_slice(&TERMINATOR);
+}
+
+/// Big-endian, the most significant byte first.
+impl KeyPart for u64 {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+        read_array(bytes).map(u64::from_be_bytes)
+    }
+}
+
+/// Big-endian with the sign bit flipped, so the negative numbers come first.
+impl KeyPart for i64 {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        ((*self as u64) ^ (1 << 63)).write_to(buf);
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+        u64::read_from(bytes).map(|bits| (bits ^ (1 << 63)) as i64)
+    }
+}
+
+/// The bits of the float with the sign bit flipped for a positive number, and every bit flipped for a
+/// negative one, so the larger its magnitude the earlier it comes. `-0.0` comes right before `0.0`, and a
+/// NaN after infinity, or before negative infinity if its sign bit is set.
+impl KeyPart for f64 {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        let bits = self.to_bits();
+        let bits = match bits >> 63 {
+            0 => bits ^ (1 << 63),
+            _ => !bits,
+        };
+        bits.write_to(buf);
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+        let bits = u64::read_from(bytes)?;
+        let bits = match bits >> 63 {
+            1 => bits ^ (1 << 63),
+            _ => !bits,
+        };
+        Ok(f64::from_bits(bits))
+    }
+}
+
+/// 12 bytes: the whole seconds since the UNIX epoch as an `i64`, negative before it, then the
+/// nanoseconds past them as a big-endian `u32`.
+impl KeyPart for SystemTime {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(e) => {
+                let before = e.duration();
+                match before.subsec_nanos() {
+                    0 => (-(before.as_secs() as i64), 0),
+                    nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            }
+        };
+        secs.write_to(buf);
+        buf.extend_from_slice(&nanos.to_be_bytes());
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+        let secs = i64::read_from(bytes)?;
+        let nanos = u32::from_be_bytes(read_array(bytes)?);
+        if nanos >= 1_000_000_000 {
+            return Err(malformed("timestamp nanoseconds out of range"));
+        }
+        let time = match secs {
+            0.. => UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos)),
+            _ => UNIX_EPOCH
+                .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+                .and_then(|time| time.checked_add(Duration::from_nanos(nanos.into()))),
+        };
+        time.ok_or_else(|| malformed("timestamp out of range"))
+    }
+}
+
+/// The bytes with every 0x00 escaped as 0x00 0xff, ending with 0x00 0x01. The terminator comes before
+/// any escaped byte, so a byte string comes before every longer one it is a prefix of.
+impl KeyPart for Vec<u8> {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        write_escaped(self, buf);
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+        let mut part = Vec::new();
+        loop {
+            match bytes {
+                [0x00, 0x01, rest @ ..] => {
+                    *bytes = rest;
+                    return Ok(part);
+                }
+                [0x00, 0xff, rest @ ..] => {
+                    part.push(0x00);
+                    *bytes = rest;
+                }
+                [0x00, ..] | [] => return Err(malformed("unterminated byte string")),
+                [byte, rest @ ..] => {
+                    part.push(*byte);
+                    *bytes = rest;
+                }
+            }
+        }
+    }
+}
+
+/// Its UTF-8 bytes, the way `Vec<u8>` writes them, so strings compare by their bytes.
+impl KeyPart for String {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        write_escaped(self.as_bytes(), buf);
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+        let part = Vec::<u8>::read_from(bytes)?;
+        String::from_utf8(part).map_err(|e| DBError::Codec {
+            context: String::from("malformed key: string is not UTF-8"),
+            source: Some(Box::new(e)),
+        })
+    }
+}
+
+/// A tuple is its parts one after the other, so tuples compare part by part.
+macro_rules! tuple_key_part {
+    ($($part:ident),+) => {
+        impl<$($part: KeyPart),+> KeyPart for ($($part,)+) {
+            #[allow(non_snake_case)]
+            fn write_to(&self, buf: &mut Vec<u8>) {
+                let ($($part,)+) = self;
+                $($part.write_to(buf);)+
+            }
+
+            fn read_from(bytes: &mut &[u8]) -> Result<Self, DBError> {
+                Ok(($($part::read_from(bytes)?,)+))
+            }
+        }
+    };
+}
+
+tuple_key_part!(A);
+tuple_key_part!(A, B);
+tuple_key_part!(A, B, C);
+tuple_key_part!(A, B, C, D);
+tuple_key_part!(A, B, C, D, E);
+
+#[cfg(test)]
+mod keys_test {
+    use super::*;
+
+    fn assert_sorted<P: KeyPart + PartialEq + std::fmt::Debug>(parts: &[P]) {
+        let encoded: Vec<Vec<u8>> = parts.iter().map(encode).collect();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+        for (part, encoded) in parts.iter().zip(&encoded) {
+            assert_eq!(&decode::<P>(encoded).unwrap(), part);
+        }
+    }
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_sorted(&[0u64, 1, 255, 256, 1 << 32, u64::MAX]);
+        assert_sorted(&[i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_sorted(&[
+            f64::NEG_INFINITY,
+            -1e300,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.5,
+            1e300,
+            f64::INFINITY,
+        ]);
+        assert_eq!(encode(&7u64), [0, 0, 0, 0, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    fn timestamps_sort_by_time() {
+        let epoch = UNIX_EPOCH;
+        assert_sorted(&[
+            epoch - Duration::new(10, 1),
+            epoch - Duration::from_secs(10),
+            epoch - Duration::from_nanos(1),
+            epoch,
+            epoch + Duration::from_nanos(1),
+            epoch + Duration::new(10, 999_999_999),
+            epoch + Duration::from_secs(11),
+        ]);
+        assert_eq!(encode(&epoch).len(), 12);
+    }
+
+    #[test]
+    fn byte_strings_sort_before_their_extensions() {
+        assert_sorted(&[
+            b"".to_vec(),
+            b"\x00".to_vec(),
+            b"\x00\x00".to_vec(),
+            b"\x00\x01".to_vec(),
+            b"a".to_vec(),
+            b"a\x00".to_vec(),
+            b"a\x00b".to_vec(),
+            b"ab".to_vec(),
+            b"b".to_vec(),
+            b"\xff".to_vec(),
+        ]);
+        assert_sorted(&[
+            "".to_string(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "é".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn tuples_sort_part_by_part() {
+        assert_sorted(&[
+            ("a".to_string(), -1i64),
+            ("a".to_string(), 5),
+            ("a\0".to_string(), i64::MIN),
+            ("ab".to_string(), 0),
+            ("b".to_string(), 0),
+        ]);
+        assert_sorted(&[
+            (1u64, b"x".to_vec(), 2.5f64),
+            (1, b"x".to_vec(), 3.0),
+            (1, b"y".to_vec(), 0.0),
+        ]);
+
+        // All the keys of one user share the encoding of the user as a prefix
+        let user = encode(&("alice".to_string(),));
+        let key = encode(&("alice".to_string(), 42u64));
+        assert!(key.starts_with(&user));
+        assert!(!encode(&("alicee".to_string(), 42u64)).starts_with(&user));
+    }
+
+    #[test]
+    fn malformed_keys_fail_to_decode() {
+        assert!(decode::<u64>(&[0; 7]).is_err());
+        assert!(decode::<u64>(&[0; 9]).is_err());
+        assert!(decode::<Vec<u8>>(b"abc").is_err());
+        assert!(decode::<Vec<u8>>(b"a\x00\x02\x00\x01").is_err());
+        assert!(decode::<String>(b"\xff\x00\x01").is_err());
+        assert!(
+            decode::<SystemTime>(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]).is_err()
+        );
+        assert!(decode::<(u64, String)>(&encode(&7u64)).is_err());
+    }
+
+    #[test]
+    fn keys_encode_for_the_db() {
+        let key = Key(("alice".to_string(), 42u64));
+        assert_eq!(key.encode(), encode(&key.0));
+        assert_eq!(Key::decode(&key.encode()).unwrap(), key);
+    }
+}
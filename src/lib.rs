@@ -1,24 +1,107 @@
-use crate::entry::Entry;
-use crate::memtable::MemTable;
-use crate::sstable::{SSTableMeta, SSTableReader};
+#![allow(clippy::upper_case_acronyms)]
+
+use crate::background::{BackgroundWorker, Job};
+use crate::batch::WriteBatch;
+use crate::block::DEFAULT_RESTART_INTERVAL;
+use crate::checksum::ChecksumType;
+use crate::clock::{Clock, SystemClock};
+use crate::column_family::{ColumnFamily, ColumnFamilyHandle, ColumnFamilyWal};
+use crate::compaction::{
+    CompactionFilter, CompactionOptions, CompactionPicker, CompactionStats, CompactionStyle,
+    LeveledCompactionPicker, PlannedCompaction,
+};
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::compression::CompressionType;
+use crate::encryption::Encryptor;
+use crate::entry::{Entry, RangeTombstone};
+use crate::flush::{FlushOptions, ImmutableMemTable, PendingFlush};
+use crate::index::{INDEX_KEY_PREFIX, Index};
+use crate::iterator::{
+    DBCursor, DBIterator, EntryIterator, KeyValue, MemTableIterator, MergingIterator,
+    RangeDeletionIterator, ResumeToken, SnapshotIterator,
+};
+use crate::listener::EventListener;
+use crate::manifest::{Manifest, VersionEdit};
+use crate::memtable::{BloomMemTable, MemTableKind, MemTableRep, SupersededEntries};
+use crate::merge::MergeOperator;
+use crate::snapshot::Snapshot;
+use crate::sstable::{
+    BloomFilterPolicy, DEFAULT_BLOCK_SIZE, FilterPolicy, SSTableConfig, SSTableMeta,
+    SSTableReadMode, SSTableReadOptions, SSTableReader, TableProperties, TableVerifyReport,
+    WriterProgress, parse_table_file_name,
+};
+use crate::subscription::{Subscribers, Subscription};
+use crate::table_cache::{DEFAULT_MAX_OPEN_FILES, TableCache};
+use crate::transaction::Transaction;
 use crate::types::{DBError, Decode, Encode};
-use crate::wal::{Op, SyncPolicy, WAL, WALRecord};
-use std::collections::BTreeMap;
-use std::error::Error;
-use std::fs::File;
+use crate::version::VersionSet;
+use crate::wal::{
+    DEFAULT_MAX_RECORD_LEN, DEFAULT_WAL_SEGMENT_SIZE, Op, ReplayProgress, ReplayReport, SyncPolicy,
+    WAL, WALArchiveConfig, WALConfig, WALRecord,
+};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
-mod entry;
+mod arena;
+mod background;
+pub mod batch;
+pub mod block;
+pub mod bloom;
+pub mod checksum;
+pub mod clock;
+pub mod column_family;
+pub mod compaction;
+pub mod comparator;
+pub mod compression;
+pub mod encryption;
+pub mod entry;
+mod flush;
+pub mod index;
+pub mod iterator;
+pub mod keys;
+pub mod listener;
 mod manifest;
-mod memtable;
-mod sstable;
-mod types;
-mod wal;
+pub mod memtable;
+pub mod merge;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(all(feature = "mmap", not(unix)))]
+compile_error!(
+    "the `mmap` feature maps SSTables through mmap(2) and is only available on unix targets"
+);
+#[cfg(feature = "serde")]
+pub mod serde_codec;
+pub mod skiplist;
+pub mod snapshot;
+pub mod sstable;
+pub mod subscription;
+pub mod table_cache;
+pub mod transaction;
+pub mod typed;
+pub mod types;
+mod version;
+pub mod wal;
 
-const DEFAULT_SS_TABLE_DIR: &'static str = ".lsm/sstables";
-const DEFAULT_WAL_DIR: &'static str = ".lsm/wal";
+const DEFAULT_SS_TABLE_DIR: &str = ".lsm/sstables";
+const DEFAULT_WAL_DIR: &str = ".lsm/wal";
 const DEFAULT_SS_L0_COMPACT_THRESHOLD: u32 = 100;
-const DEFAULT_MAX_RECORD_LEN: u32 = 1024 * 1000; // 1MiB
+const DEFAULT_LEVEL_BASE_SIZE: u64 = 10 * 1024 * 1024; // 10MiB
+const DEFAULT_LEVEL_MULTIPLIER: u64 = 10;
+const DEFAULT_L0_SLOWDOWN_WRITES_TRIGGER: u32 = 2 * DEFAULT_SS_L0_COMPACT_THRESHOLD;
+const DEFAULT_L0_STOP_WRITES_TRIGGER: u32 = 3 * DEFAULT_SS_L0_COMPACT_THRESHOLD;
+const DEFAULT_SOFT_PENDING_COMPACTION_BYTES_LIMIT: u64 = 64 * 1024 * 1024 * 1024; // 64GiB
+const DEFAULT_HARD_PENDING_COMPACTION_BYTES_LIMIT: u64 = 256 * 1024 * 1024 * 1024; // 256GiB
+const DEFAULT_TARGET_FILE_SIZE: u64 = 64 * 1024 * 1024; // 64MiB
+const DEFAULT_WRITE_STALL_DELAY: Duration = Duration::from_millis(1);
+const DEFAULT_WRITE_BUFFER_SIZE: u64 = 64 * 1024 * 1024; // 64MiB
+// What the MemTable bloom filter is sized for when nothing bounds the MemTable
+const DEFAULT_MEMTABLE_BLOOM_ENTRIES: u64 = 1 << 16;
+
+pub type FlushProgressCallback = Arc<dyn Fn(&WriterProgress) + Send + Sync>;
+pub type WalReplayProgressCallback = Arc<dyn Fn(&ReplayProgress) + Send + Sync>;
 
 /// The DBOpts used to configure the LSMDB
 /// TODO: We can use the builder pattern here
@@ -26,11 +109,118 @@ pub struct DBConfig {
     // The max size in terms of capacity ie number of objects the MemTable will hold prior to SSTable write
     // `u32` is tentative
     pub memtable_max_size: Option<u32>,
+    // The MemTable is also flushed once it holds this many bytes, see `MemTableRep::size`. Range tombstones
+    // count with their bounds. `None` leaves flushing to `memtable_max_size` alone
+    pub write_buffer_size: Option<u64>,
+    // The structure holding the MemTable, see `MemTableKind`
+    pub memtable_kind: MemTableKind,
+    // Puts a bloom filter with this false positive rate, e.g. `0.01`, in front of the MemTable so reads of
+    // keys it doesn't hold skip searching it, see `BloomMemTable`. Sized for `memtable_max_size` entries or
+    // as many 64 byte entries as fit in `write_buffer_size`, whichever is fewer
+    pub memtable_bloom_false_positive_rate: Option<f64>,
+    // Stores the time of every put alongside its value, in the WAL and SSTables too, at the cost of 8 bytes
+    // per value. Read back with `DB::get_with_metadata`
+    pub record_write_time: bool,
+    // What write times and TTL expiries are measured by, see `Clock`. Tests set a `ManualClock`
+    pub clock: Arc<dyn Clock>,
+    // The order keys are kept and scanned in, see `Comparator`. It is recorded in the manifest and every
+    // SSTable, a DB can only be reopened with a comparator of the same name
+    pub comparator: Arc<dyn Comparator>,
     pub ss_table_dir: PathBuf,
-    pub wal_file: PathBuf,
+    // The directory holding the WAL segments
+    pub wal_dir: PathBuf,
+    // The WAL moves on to a new segment once the current one would grow past this many bytes
+    pub wal_segment_size: u64,
+    // Reserves `wal_segment_size` bytes of disk for a segment up front rather than growing it append by
+    // append, which saves every sync persisting the file's new size
+    pub wal_preallocate: bool,
+    // Keeps up to this many segments no longer needed around to be written over by new ones, see
+    // `WALConfig::recycle_files` for the trade-off
+    pub wal_recycle_files: usize,
+    // Starts writing the WAL back to disk every this many bytes appended, even under `SyncPolicy::Never`,
+    // rather than leaving it all to the OS. 0 turns it off
+    pub wal_bytes_per_sync: u64,
+    // Archives the WAL segments no longer needed instead of deleting them, e.g. for point-in-time recovery
+    // or shipping the log elsewhere. Can't be combined with `wal_recycle_files`
+    pub wal_archive: Option<WALArchiveConfig>,
+    // Compresses the values logged to the WAL, which cuts the bytes written and synced for large values
+    pub wal_compression: CompressionType,
+    // Encrypts the WAL at rest, the keys are up to the `Encryptor`. A WAL written with an encryptor can
+    // only be reopened with one that decrypts it
+    pub wal_encryptor: Option<Arc<dyn Encryptor>>,
     pub wal_sync_policy: SyncPolicy,
     pub max_record_len: u32,
+    pub compaction_style: CompactionStyle,
+    // L0 is compacted into L1 once it holds more than this many SSTables
     pub ss_l0_compact_threshold: u32,
+    // With this set, L0 is first merged into a single L0 table once it holds this many tables, which keeps
+    // reads fast at a fraction of the cost of merging into L1. L0 then also moves into L1 once it holds
+    // more than `level_base_size` bytes. Must be at least 2 and at most `ss_l0_compact_threshold`
+    pub ss_l0_intra_compact_threshold: Option<u32>,
+    // The target size in bytes of L1. Every level below is allowed `level_multiplier` times the size of
+    // the one above it, a level over its target is compacted into the next one
+    pub level_base_size: u64,
+    pub level_multiplier: u64,
+    // Decides which tables to compact when, `None` picks them as laid out in the `compaction` module docs
+    // using the settings above
+    pub compaction_picker: Option<Arc<dyn CompactionPicker>>,
+    // Sees every value compaction writes and may drop or rewrite it, see `CompactionFilter`
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    // Folds the operands written with `DB::merge` into values, see `MergeOperator`. A DB holding operands
+    // cannot be read, nor its WAL replayed, without it
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // The max number of threads a single compaction is split across, each merging a key range of its own
+    pub max_subcompactions: usize,
+    // Compaction cuts its output into tables of about this many bytes, so a large merge doesn't leave one huge
+    // table behind for the next compaction to rewrite in full. L0 outputs are never cut, `None` writes one
+    // table per subcompaction
+    pub target_file_size: Option<u64>,
+    // Tables written longer ago than this are compacted even when nothing else calls for it, so the
+    // `compaction_filter` and tombstone garbage collection eventually get to cold data too
+    pub periodic_compaction_age: Option<Duration>,
+    // Every write is delayed by `write_stall_delay` while L0 holds at least `l0_slowdown_writes_trigger`
+    // tables, or compaction is at least `soft_pending_compaction_bytes_limit` bytes behind, so compaction
+    // gets a chance to catch up. Only applies to `CompactionStyle::Leveled`, `None` disables a limit
+    pub l0_slowdown_writes_trigger: Option<u32>,
+    pub soft_pending_compaction_bytes_limit: Option<u64>,
+    pub write_stall_delay: Duration,
+    // Past these limits writes fail with `DBError::Busy` instead, until compaction has caught up
+    pub l0_stop_writes_trigger: Option<u32>,
+    pub hard_pending_compaction_bytes_limit: Option<u64>,
+    // The target size of SSTable data blocks. Larger blocks favour scans, smaller blocks favour point reads
+    pub block_size: usize,
+    // The number of entries between two restart points i.e. full keys, in SSTable data blocks
+    pub block_restart_interval: usize,
+    // Compression applied to SSTable data blocks. Codecs other than `None` need their cargo feature enabled
+    pub compression: CompressionType,
+    // Builds the filter stored in each SSTable and probes it on reads. `None` writes tables without one,
+    // and tables written under a different policy are read without their filter
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    // The checksum guarding WAL records and SSTable blocks. Every file records the one it was written
+    // with, so it can be changed between opens
+    pub checksum: ChecksumType,
+    // How SSTables are read, `SSTableReadMode::Mmap` is available behind the `mmap` cargo feature
+    pub ss_table_read_mode: SSTableReadMode,
+    // The max number of SSTable readers, and so file descriptors, kept open at once
+    pub max_open_files: usize,
+    // Re-read every SSTable on open and check its whole-file checksum. Slower to open, but damage is
+    // caught at startup rather than by the first query that touches it
+    pub verify_ss_tables_on_open: bool,
+    // Called with the progress of every MemTable flush, see `WriterProgress`
+    pub on_flush_progress: Option<FlushProgressCallback>,
+    // Called with the progress of the WAL replay on open, see `ReplayProgress`
+    pub on_wal_replay_progress: Option<WalReplayProgressCallback>,
+    // Told whenever a flush or compaction starts, finishes or fails, see `EventListener`
+    pub event_listeners: Vec<Arc<dyn EventListener>>,
+    // The configs the column families found on open are opened with, by name, see `DB::create_cf`. Those
+    // without one here get the `DBConfig::default()` tunables
+    pub column_families: HashMap<String, DBConfig>,
+    // Whether the column families log to WALs of their own or share this DB's, see `ColumnFamilyWal`.
+    // Only the DB's own config has a say, not those of its column families
+    pub column_family_wal: ColumnFamilyWal,
+    // The secondary indexes every put and delete keeps up to date, see `index`. They are not persisted, a
+    // DB is to be opened with the same indexes every time or have them rebuilt, see `DB::rebuild_index`
+    pub indexes: Vec<Arc<dyn Index>>,
     disable_wal_memtable_replay_on_load: bool,
 }
 
@@ -44,68 +234,701 @@ impl Default for DBConfig {
 
         Self {
             memtable_max_size: Some(100),
+            write_buffer_size: Some(DEFAULT_WRITE_BUFFER_SIZE),
+            memtable_kind: MemTableKind::default(),
+            memtable_bloom_false_positive_rate: None,
+            record_write_time: false,
+            clock: Arc::new(SystemClock),
+            comparator: Arc::new(BytewiseComparator),
             ss_table_dir: ss_table_path,
-            wal_file: wal_path,
+            wal_dir: wal_path,
+            wal_segment_size: DEFAULT_WAL_SEGMENT_SIZE,
+            wal_preallocate: false,
+            wal_recycle_files: 0,
+            wal_bytes_per_sync: 0,
+            wal_archive: None,
+            wal_compression: CompressionType::None,
+            wal_encryptor: None,
+            compaction_style: CompactionStyle::default(),
             ss_l0_compact_threshold: DEFAULT_SS_L0_COMPACT_THRESHOLD,
+            ss_l0_intra_compact_threshold: None,
+            level_base_size: DEFAULT_LEVEL_BASE_SIZE,
+            level_multiplier: DEFAULT_LEVEL_MULTIPLIER,
+            compaction_picker: None,
+            compaction_filter: None,
+            merge_operator: None,
+            max_subcompactions: 1,
+            target_file_size: Some(DEFAULT_TARGET_FILE_SIZE),
+            periodic_compaction_age: None,
+            l0_slowdown_writes_trigger: Some(DEFAULT_L0_SLOWDOWN_WRITES_TRIGGER),
+            soft_pending_compaction_bytes_limit: Some(DEFAULT_SOFT_PENDING_COMPACTION_BYTES_LIMIT),
+            write_stall_delay: DEFAULT_WRITE_STALL_DELAY,
+            l0_stop_writes_trigger: Some(DEFAULT_L0_STOP_WRITES_TRIGGER),
+            hard_pending_compaction_bytes_limit: Some(DEFAULT_HARD_PENDING_COMPACTION_BYTES_LIMIT),
             wal_sync_policy: SyncPolicy::Always,
             max_record_len: DEFAULT_MAX_RECORD_LEN,
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            compression: CompressionType::None,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            checksum: ChecksumType::Crc32,
+            ss_table_read_mode: SSTableReadMode::default(),
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            verify_ss_tables_on_open: false,
+            on_flush_progress: None,
+            on_wal_replay_progress: None,
+            event_listeners: Vec::new(),
+            column_families: HashMap::new(),
+            column_family_wal: ColumnFamilyWal::Separate,
+            indexes: Vec::new(),
             disable_wal_memtable_replay_on_load: false,
         }
     }
 }
 
+impl DBConfig {
+    /// An empty MemTable of `memtable_kind`, behind a bloom filter if configured.
+    fn new_mem_table(&self) -> Box<dyn MemTableRep> {
+        let mem_table = self
+            .memtable_kind
+            .new_mem_table_with(self.comparator.clone());
+        let Some(false_positive_rate) = self.memtable_bloom_false_positive_rate else {
+            return mem_table;
+        };
+
+        let max_entries = self.memtable_max_size.map(|max_size| max_size as u64);
+        let fitting_entries = self.write_buffer_size.map(|max_bytes| max_bytes / 64);
+        let expected_items = match (max_entries, fitting_entries) {
+            (Some(max_entries), Some(fitting_entries)) => max_entries.min(fitting_entries),
+            (Some(entries), None) | (None, Some(entries)) => entries,
+            (None, None) => DEFAULT_MEMTABLE_BLOOM_ENTRIES,
+        };
+        Box::new(BloomMemTable::new(
+            mem_table,
+            expected_items as usize,
+            false_positive_rate,
+        ))
+    }
+
+    fn ss_table_config(&self) -> SSTableConfig {
+        SSTableConfig {
+            block_size: self.block_size,
+            block_restart_interval: self.block_restart_interval,
+            compression: self.compression,
+            filter_policy: self.filter_policy.clone(),
+            checksum: self.checksum,
+            comparator: self.comparator.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    fn leveled_compaction_picker(&self) -> LeveledCompactionPicker {
+        LeveledCompactionPicker {
+            l0_compact_threshold: self.ss_l0_compact_threshold,
+            l0_intra_compact_threshold: self.ss_l0_intra_compact_threshold,
+            level_base_size: self.level_base_size,
+            level_multiplier: self.level_multiplier,
+        }
+    }
+
+    fn compaction_options(&self) -> CompactionOptions {
+        CompactionOptions {
+            style: self.compaction_style,
+            picker: self
+                .compaction_picker
+                .clone()
+                .unwrap_or_else(|| Arc::new(self.leveled_compaction_picker())),
+            filter: self.compaction_filter.clone(),
+            merge_operator: self.merge_operator.clone(),
+            clock: self.clock.clone(),
+            max_subcompactions: self.max_subcompactions,
+            target_file_size: self.target_file_size,
+            periodic_compaction_age: self.periodic_compaction_age,
+            listeners: self.event_listeners.clone(),
+            comparator: self.comparator.clone(),
+        }
+    }
+
+    fn wal_config(&self) -> WALConfig {
+        WALConfig {
+            sync: self.wal_sync_policy,
+            max_record_len: self.max_record_len,
+            segment_size: self.wal_segment_size,
+            preallocate: self.wal_preallocate,
+            recycle_files: self.wal_recycle_files,
+            bytes_per_sync: self.wal_bytes_per_sync,
+            archive: self.wal_archive,
+            compression: self.wal_compression,
+            encryptor: self.wal_encryptor.clone(),
+            checksum: self.checksum,
+        }
+    }
+
+    fn ss_table_read_options(&self) -> SSTableReadOptions {
+        SSTableReadOptions {
+            mode: self.ss_table_read_mode,
+            filter_policy: self.filter_policy.clone(),
+            comparator: self.comparator.clone(),
+        }
+    }
+}
+
+/// A value along with what the DB knows about its write, see `DB::get_with_metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueWithMetadata {
+    pub val: Vec<u8>,
+    pub seq_no: u64,
+    // When the value was written, in milliseconds since the UNIX epoch. `None` for values written without
+    // `DBConfig::record_write_time`
+    pub timestamp: Option<u64>,
+    // When the value expires, by the same measure, for one written with `DB::put_with_ttl`
+    pub expires_at: Option<u64>,
+    // Where the read found the value
+    pub origin: ValueOrigin,
+}
+
+/// Where a read found a value, see `DB::get_raw_with_meta`. A value folded from merge operands was found
+/// where its newest operand was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueOrigin {
+    // The MemTable taking writes
+    MemTable,
+    // The MemTable frozen for a flush still under way
+    ImmutableMemTable,
+    // The table `file_no` at `level`. `cached` if the `TableCache` had the table open already, otherwise
+    // reading the value opened it
+    Table {
+        file_no: u64,
+        level: u32,
+        cached: bool,
+    },
+}
+
+/// An entry along with where a read found it, see `DB::find_entry_with_origin`.
+type FoundEntry = (Entry, ValueOrigin);
+
+/// Per-write durability, overriding `DBConfig::wal_sync_policy` for a single write.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    // Fsyncs the WAL before the write returns, whatever the `wal_sync_policy`
+    pub sync: bool,
+    // Skips the WAL altogether, the write is lost if the DB goes down before its MemTable is flushed. Meant
+    // for bulk loads that can be redone from scratch, cannot be combined with `sync`
+    pub disable_wal: bool,
+}
+
+impl WriteOptions {
+    fn validate(&self) -> Result<(), DBError> {
+        if self.sync && self.disable_wal {
+            return Err(DBError::InvalidConfig {
+                what: "a write cannot both sync and disable the wal",
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Per-read knobs for point reads and scans, see `get_raw_opt` and `iter_opt`.
+#[derive(Debug, Clone)]
+pub struct ReadOptions<'a> {
+    // Reads the DB as of the snapshot rather than as it is now
+    pub snapshot: Option<&'a Snapshot>,
+    // The smallest key a scan yields, encoded. Point reads ignore the bounds
+    pub lower_bound: Option<Vec<u8>>,
+    // The key a scan stops before, encoded
+    pub upper_bound: Option<Vec<u8>>,
+    // Keeps the tables read open in the `TableCache`. Best turned off for one-off scans, which would
+    // otherwise push the tables in use out of the cache
+    pub fill_cache: bool,
+    // Checks the checksum of every SSTable block read. Turning it off saves hashing the blocks, at the risk
+    // of reading garbage out of a damaged one
+    pub verify_checksums: bool,
+}
+
+impl Default for ReadOptions<'_> {
+    fn default() -> Self {
+        Self {
+            snapshot: None,
+            lower_bound: None,
+            upper_bound: None,
+            fill_cache: true,
+            verify_checksums: true,
+        }
+    }
+}
+
+impl ReadOptions<'_> {
+    /// Every write before this seq_no is read, see `Snapshot::seq_no`.
+    fn read_seq_no(&self) -> u64 {
+        self.snapshot.map_or(u64::MAX, Snapshot::seq_no)
+    }
+
+    /// Narrows the range within `from` and `until` down to the bounds, in the order of `comparator`.
+    fn clamp<'k>(
+        &'k self,
+        comparator: &dyn Comparator,
+        from: Bound<&'k [u8]>,
+        until: Bound<&'k [u8]>,
+    ) -> (Bound<&'k [u8]>, Bound<&'k [u8]>) {
+        let from = match (from, &self.lower_bound) {
+            (Bound::Included(key) | Bound::Excluded(key), Some(lower))
+                if comparator.compare(key, lower).is_lt() =>
+            {
+                Bound::Included(lower.as_slice())
+            }
+            (Bound::Unbounded, Some(lower)) => Bound::Included(lower.as_slice()),
+            (from, _) => from,
+        };
+        let until = match (until, &self.upper_bound) {
+            (Bound::Included(key) | Bound::Excluded(key), Some(upper))
+                if comparator.compare(key, upper).is_ge() =>
+            {
+                Bound::Excluded(upper.as_slice())
+            }
+            (Bound::Unbounded, Some(upper)) => Bound::Excluded(upper.as_slice()),
+            (until, _) => until,
+        };
+        (from, until)
+    }
+}
+
 /// DB represents the actual LSM-Tree. In it we have the following core components
 /// 1. `mt`: The MemTable representing an in-memory cache for the inserted data
 /// 2. `opts`: The options subpplied to the DBOpts
-/// 3. `manifest`: The main configuration file holding the state of the LSM-Tree.
-/// 4. ``
+/// 3. `versions`: The live SSTables and the manifest logging them, shared with the background worker.
+/// 4. `background`: The worker threads flushes and compactions run on.
 pub struct DB {
-    mem_table: MemTable,
-    ss_meta: Vec<SSTableMeta>,
-    ss_reader: Option<SSTableReader>,
-    // manifest: Option<Manifest>,
-    wal: wal::WAL,
+    mem_table: Box<dyn MemTableRep>,
+    // The entries the MemTable overwrote that live snapshots still read, flushed along with it, see
+    // `keep_for_snapshots`
+    mem_superseded: SupersededEntries,
+    // The range tombstones written since the MemTable was last flushed, flushed along with it
+    mem_range_tombstones: Vec<RangeTombstone>,
+    // The MemTable being flushed in the background, if any, see `freeze_mem_table`
+    pending_flush: Arc<PendingFlush>,
+    versions: Arc<Mutex<VersionSet>>,
+    // Readers are opened lazily on first access and reused for subsequent reads
+    table_cache: Arc<TableCache>,
+    background: BackgroundWorker,
+    wal: Arc<WAL>,
+    // Who the writes of the DB are held in the WAL by, see `WAL::hold`. 0 but for a column family sharing
+    // the WAL of its DB, whose records are tagged with it, see `ColumnFamilyWal::Shared`
+    wal_owner: u32,
+    subscribers: Subscribers,
     opts: DBConfig,
     next_seq_no: u64,
+    // What the WAL replay on open brought back
+    wal_replay: ReplayReport,
+    // In the order they were opened, which `ColumnFamilyHandle`s index
+    column_families: Vec<ColumnFamily>,
 }
 
 impl DB {
     pub fn new(opts: Option<DBConfig>) -> Result<Self, DBError> {
-        let opt = if let Some(opt) = opts {
-            opt
-        } else {
-            DBConfig::default()
-        };
+        Self::open(opts.unwrap_or_default(), None)
+    }
+
+    /// Opens the DB configured with `opt`. A column family sharing the WAL of its DB is given it in
+    /// `shared_wal`, along with the id its records are tagged with.
+    fn open(opt: DBConfig, shared_wal: Option<(Arc<WAL>, u32)>) -> Result<Self, DBError> {
+        if opt.max_open_files == 0 {
+            return Err(DBError::InvalidConfig {
+                what: "max_open_files must be greater than 0",
+            });
+        }
+
+        if let (Some(slowdown), Some(stop)) =
+            (opt.l0_slowdown_writes_trigger, opt.l0_stop_writes_trigger)
+            && slowdown >= stop
+        {
+            return Err(DBError::InvalidConfig {
+                what: "l0_slowdown_writes_trigger must be below l0_stop_writes_trigger",
+            });
+        }
 
-        let mut mem_table = BTreeMap::new();
+        if opt
+            .ss_l0_intra_compact_threshold
+            .is_some_and(|threshold| threshold < 2 || threshold > opt.ss_l0_compact_threshold)
+        {
+            return Err(DBError::InvalidConfig {
+                what: "ss_l0_intra_compact_threshold must be at least 2 and at most ss_l0_compact_threshold",
+            });
+        }
+
+        // Table creation times only have second precision
+        if opt
+            .periodic_compaction_age
+            .is_some_and(|age| age < Duration::from_secs(1))
+        {
+            return Err(DBError::InvalidConfig {
+                what: "periodic_compaction_age must be at least a second",
+            });
+        }
+
+        index::validate(&opt.indexes)?;
+
+        if opt.max_subcompactions == 0 {
+            return Err(DBError::InvalidConfig {
+                what: "max_subcompactions must be greater than 0",
+            });
+        }
+
+        if opt.target_file_size == Some(0) {
+            return Err(DBError::InvalidConfig {
+                what: "target_file_size must be greater than 0",
+            });
+        }
+
+        if matches!(opt.wal_sync_policy, SyncPolicy::EveryN(0))
+            || matches!(opt.wal_sync_policy, SyncPolicy::Interval(interval) if interval.is_zero())
+        {
+            return Err(DBError::InvalidConfig {
+                what: "wal_sync_policy must sync every N > 0 records, or at an interval greater than zero",
+            });
+        }
+
+        if opt.memtable_kind == (MemTableKind::Sharded { shards: 0 }) {
+            return Err(DBError::InvalidConfig {
+                what: "a sharded memtable needs at least one shard",
+            });
+        }
+
+        if opt
+            .memtable_bloom_false_positive_rate
+            .is_some_and(|rate| !(rate > 0.0 && rate < 1.0))
+        {
+            return Err(DBError::InvalidConfig {
+                what: "memtable_bloom_false_positive_rate must be between 0 and 1",
+            });
+        }
+
+        if opt.wal_segment_size == 0 {
+            return Err(DBError::InvalidConfig {
+                what: "wal_segment_size must be greater than 0",
+            });
+        }
+
+        if opt.wal_archive.is_some() && opt.wal_recycle_files > 0 {
+            return Err(DBError::InvalidConfig {
+                what: "wal_archive and wal_recycle_files cannot be combined, archived segments are never recycled",
+            });
+        }
+
+        if opt.column_family_wal == ColumnFamilyWal::Shared && opt.wal_recycle_files > 0 {
+            return Err(DBError::InvalidConfig {
+                what: "wal_recycle_files cannot be combined with a shared column family WAL, see ColumnFamilyWal::Shared",
+            });
+        }
+
+        if opt.level_base_size == 0 || opt.level_multiplier == 0 {
+            return Err(DBError::InvalidConfig {
+                what: "level_base_size and level_multiplier must be greater than 0",
+            });
+        }
+
+        if !opt.compression.is_supported() || !opt.wal_compression.is_supported() {
+            return Err(DBError::InvalidConfig {
+                what: "compression codec is not compiled in, enable its cargo feature",
+            });
+        }
 
-        let wal_file = File::open(opt.wal_file.clone()).map_err(|e| DBError::Io {
-            op: "failed to open wal_file",
-            path: opt.wal_file.clone(),
+        // Index entries live in a keyspace past every key in byte order, see `index`
+        if !opt.indexes.is_empty() && !comparator::is_bytewise(opt.comparator.as_ref()) {
+            return Err(DBError::InvalidConfig {
+                what: "indexes need the bytewise comparator",
+            });
+        }
+
+        std::fs::create_dir_all(&opt.ss_table_dir).map_err(|e| DBError::Io {
+            op: "failed to create ss_table_dir",
+            path: opt.ss_table_dir.clone(),
             source: e,
         })?;
 
-        let mut wal = WAL::new(
-            opt.wal_file.clone(),
-            opt.wal_sync_policy,
-            opt.max_record_len,
-        )?;
+        let (wal, wal_owner) = match shared_wal {
+            Some((wal, wal_id)) => (wal, wal_id),
+            None => (
+                Arc::new(WAL::new(opt.wal_dir.clone(), opt.wal_config())?),
+                0,
+            ),
+        };
+        let pending_flush = Arc::new(PendingFlush::default());
 
-        if !opt.disable_wal_memtable_replay_on_load {
-            wal.replay_into(wal_file, &mut mem_table)?;
-        } else {
-            // log this
-        }
+        let adopt_unknown_tables = !Manifest::exists(&opt.ss_table_dir);
+        let versions = Arc::new(Mutex::new(VersionSet::new(Manifest::open(
+            &opt.ss_table_dir,
+            opt.comparator.name(),
+        )?)));
+        let table_cache = Arc::new(TableCache::new(
+            opt.max_open_files,
+            opt.ss_table_read_options(),
+        ));
+        let background = BackgroundWorker::spawn(
+            versions.clone(),
+            table_cache.clone(),
+            opt.compaction_options(),
+            opt.ss_table_config(),
+            FlushOptions {
+                pending: pending_flush.clone(),
+                wal: wal.clone(),
+                wal_owner,
+                listeners: opt.event_listeners.clone(),
+                on_progress: opt.on_flush_progress.clone(),
+            },
+        )?;
 
-        Ok(Self {
-            mem_table,
-            ss_meta: vec![],
-            ss_reader: None,
-            // manifest: None,
-            wal: wal,
+        let mut db = Self {
+            mem_table: opt.new_mem_table(),
+            mem_superseded: SupersededEntries::new(),
+            mem_range_tombstones: Vec::new(),
+            pending_flush,
+            versions,
+            table_cache,
+            background,
+            wal,
+            wal_owner,
+            subscribers: Subscribers::default(),
             opts: opt,
             next_seq_no: 0,
-        })
+            wal_replay: ReplayReport::default(),
+            column_families: Vec::new(),
+        };
+        db.recover_ss_tables(adopt_unknown_tables)?;
+        // The column families are opened first, the WAL may hold their writes. It is held in full until
+        // replayed, as they may flush while opening
+        db.wal.hold_from(db.wal_owner, 0);
+        db.open_column_families()?;
+        db.replay_wal()?;
+        if db.mem_table.is_empty() && db.mem_range_tombstones.is_empty() {
+            db.wal.release_hold(db.wal_owner);
+        }
+        // The tables may have been left over their limits by the last run
+        db.background.schedule(Job::Compact);
+
+        Ok(db)
+    }
+
+    /// Brings back the writes that were never flushed from the WAL. New writes pick up after the newest
+    /// write found, whether it was flushed or is only in the WAL.
+    ///
+    /// The writes of the column families sharing the WAL are handed to them, see `replay_routed`. A column
+    /// family that no longer shares it has them flushed right away, as the WAL is not held for it any more.
+    fn replay_wal(&mut self) -> Result<(), DBError> {
+        // The WAL may still hold writes that were flushed right before a crash, those are skipped
+        let flushed_seq_no = self.versions().manifest.next_seq_no();
+        self.next_seq_no = flushed_seq_no;
+
+        if self.opts.disable_wal_memtable_replay_on_load {
+            return Ok(());
+        }
+        // Its writes are in the WAL of its DB, which replays them
+        if self.wal_owner != 0 {
+            return self.replay_own_wal();
+        }
+
+        let on_progress = |progress: &ReplayProgress| {
+            if let Some(on_wal_replay_progress) = &self.opts.on_wal_replay_progress {
+                on_wal_replay_progress(progress);
+            }
+        };
+        let now = self.now_millis();
+        let mut routed = HashSet::new();
+        let families = &mut self.column_families;
+        let mut route = |wal_id, records| {
+            let family = column_family::get_by_wal_id(families, wal_id).ok_or(DBError::WAL {
+                what: "wal: record of an unknown column family",
+                err: None,
+            })?;
+            routed.insert(wal_id);
+            family.db.replay_routed(records)
+        };
+        let report = self.wal.replay_routing_into(
+            flushed_seq_no,
+            self.mem_table.as_mut(),
+            &mut self.mem_range_tombstones,
+            (self.opts.merge_operator.as_deref(), now),
+            &mut route,
+            on_progress,
+        )?;
+        if let Some(last_seq_no) = report.last_seq_no {
+            self.next_seq_no = self.next_seq_no.max(last_seq_no + 1);
+        }
+        self.wal_replay = report;
+
+        if self.opts.column_family_wal == ColumnFamilyWal::Separate {
+            for wal_id in routed {
+                let family = column_family::get_by_wal_id(&mut self.column_families, wal_id)
+                    .expect("records were routed to it");
+                family.db.flush_mem_table()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the `records` of this column family that the WAL of its DB holds, see `replay_wal`.
+    fn replay_routed(&mut self, records: Vec<WALRecord>) -> Result<(), DBError> {
+        let flushed_seq_no = self.versions().manifest.next_seq_no();
+        for record in records {
+            if record.seq_no() < flushed_seq_no {
+                continue;
+            }
+            self.next_seq_no = self.next_seq_no.max(record.seq_no() + 1);
+            self.wal.hold_from(self.wal_owner, 0);
+            let now = self.now_millis();
+            let merge_operator = self.opts.merge_operator.as_deref();
+            wal::apply_record(
+                record,
+                self.mem_table.as_mut(),
+                &mut self.mem_range_tombstones,
+                merge_operator,
+                now,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Replays and flushes what the WAL of a column family's own holds from before it shared the WAL of its
+    /// DB, see `ColumnFamilyWal::Shared`. The WAL is removed after, unless it archives its segments.
+    fn replay_own_wal(&mut self) -> Result<(), DBError> {
+        if !self.opts.wal_dir.exists() {
+            return Ok(());
+        }
+
+        let wal = WAL::new(self.opts.wal_dir.clone(), self.opts.wal_config())?;
+        let report = wal.replay_merging_into(
+            self.next_seq_no,
+            self.mem_table.as_mut(),
+            &mut self.mem_range_tombstones,
+            self.opts.merge_operator.as_deref(),
+            self.opts.clock.now_millis(),
+            |_| {},
+        )?;
+        if let Some(last_seq_no) = report.last_seq_no {
+            self.next_seq_no = self.next_seq_no.max(last_seq_no + 1);
+        }
+        self.flush_mem_table()?;
+        wal.truncate()?;
+        drop(wal);
+
+        if self.opts.wal_archive.is_none() {
+            std::fs::remove_dir_all(&self.opts.wal_dir).map_err(|e| DBError::Io {
+                op: "failed to remove the wal_dir of a column family sharing its DB's WAL",
+                path: self.opts.wal_dir.clone(),
+                source: e,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Brings back the SSTables recorded in the manifest and reconciles them against the contents of the
+    /// `ss_table_dir`:
+    /// - leftover `.tmp` files from an interrupted flush are removed.
+    /// - tables the manifest does not know about are orphans of a flush that crashed before its edit was
+    ///   logged, their contents are still in the WAL so they are removed too.
+    /// - a table the manifest knows about but which is missing from the directory fails the load.
+    ///
+    /// With `verify_ss_tables_on_open` every table is also re-read in full and checked against the checksum
+    /// recorded in its footer and in the manifest, so a truncated or bit-rotted table fails the load.
+    ///
+    /// A directory written before the manifest existed has its tables adopted from their footers instead.
+    fn recover_ss_tables(&mut self, adopt_unknown_tables: bool) -> Result<(), DBError> {
+        let mut versions = version::lock(&self.versions);
+        let dir = std::fs::read_dir(&self.opts.ss_table_dir).map_err(|e| DBError::Io {
+            op: "failed to read ss_table_dir",
+            path: self.opts.ss_table_dir.clone(),
+            source: e,
+        })?;
+
+        let mut found = HashSet::new();
+        let mut adopted = Vec::new();
+        for dir_entry in dir {
+            let path = dir_entry
+                .map_err(|e| DBError::Io {
+                    op: "failed to read ss_table_dir entry",
+                    path: self.opts.ss_table_dir.clone(),
+                    source: e,
+                })?
+                .path();
+
+            let remove = |op| {
+                std::fs::remove_file(&path).map_err(|e| DBError::Io {
+                    op,
+                    path: path.clone(),
+                    source: e,
+                })
+            };
+
+            // A table that never made it to its final name was being written when we crashed
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                remove("failed to remove partially written ss_table")?;
+                continue;
+            }
+
+            let Some(file_no) = parse_table_file_name(&path) else {
+                continue;
+            };
+            versions.manifest.mark_file_no_used(file_no);
+
+            if versions.manifest.contains(file_no) {
+                found.insert(file_no);
+            } else if adopt_unknown_tables {
+                let reader = SSTableReader::open_with_options(
+                    path.clone(),
+                    &self.opts.ss_table_read_options(),
+                )?;
+                adopted.push(SSTableMeta::from_properties(
+                    file_no,
+                    path.to_string_lossy().into_owned(),
+                    reader.properties(),
+                    reader.file_size(),
+                    reader.file_checksum(),
+                ));
+                self.table_cache.insert(file_no, reader);
+            } else {
+                remove("failed to remove orphaned ss_table")?;
+            }
+        }
+
+        if let Some(missing) = versions
+            .manifest
+            .tables()
+            .find(|meta| !found.contains(&meta.file_no()))
+        {
+            return Err(DBError::Corruption {
+                what: "manifest references a missing ss_table",
+                path: PathBuf::from(missing.path()),
+                offset: 0,
+            });
+        }
+
+        if self.opts.verify_ss_tables_on_open {
+            for meta in versions.manifest.tables() {
+                let reader = self.table_cache.get(meta)?;
+                if reader.file_checksum() != meta.file_checksum() {
+                    return Err(DBError::Corruption {
+                        what: "ss_table checksum does not match the manifest",
+                        path: PathBuf::from(meta.path()),
+                        offset: 0,
+                    });
+                }
+                reader.verify_file_checksum()?;
+            }
+        }
+
+        if !adopted.is_empty() {
+            versions.manifest.log_edit(VersionEdit {
+                added: adopted,
+                removed: Vec::new(),
+            })?;
+        }
+
+        let tables: Vec<SSTableMeta> = versions.manifest.tables().cloned().collect();
+        for meta in tables {
+            versions.install(meta);
+        }
+
+        Ok(())
     }
 
     /// Put will attempt to add the new K, V pair. In the event a key match takes place, if the new value
@@ -114,30 +937,123 @@ impl DB {
     /// Put will always make use of the `next_seq_no` in `Self` first, and only increment after,
     /// so callers need to ensure that any operation that prepares the
     /// LSM-Tree for receiving new data, take this into account
+    ///
+    /// A key and value too large to be logged within `max_record_len` fail with `DBError::Codec`, and
+    /// nothing is written. Writes skipping the WAL are never replayed, so they are not held to the limit.
     pub fn put<K: Encode, V: Encode>(&mut self, key: &K, val: &V) -> Result<(), DBError> {
+        self.put_opt(key, val, &WriteOptions::default())
+    }
+
+    /// `put` with its durability decided by `write_opts` rather than the `wal_sync_policy` alone, see
+    /// `WriteOptions`.
+    pub fn put_opt<K: Encode, V: Encode>(
+        &mut self,
+        key: &K,
+        val: &V,
+        write_opts: &WriteOptions,
+    ) -> Result<(), DBError> {
+        self.put_expiring(key, val, None, write_opts)
+    }
+
+    /// `put` of a value that expires `ttl` from now, as told by the `DBConfig::clock`. From then on reads
+    /// take the key for missing, as if deleted, and compaction drops the value.
+    pub fn put_with_ttl<K: Encode, V: Encode>(
+        &mut self,
+        key: &K,
+        val: &V,
+        ttl: Duration,
+    ) -> Result<(), DBError> {
+        self.put_with_ttl_opt(key, val, ttl, &WriteOptions::default())
+    }
+
+    pub fn put_with_ttl_opt<K: Encode, V: Encode>(
+        &mut self,
+        key: &K,
+        val: &V,
+        ttl: Duration,
+        write_opts: &WriteOptions,
+    ) -> Result<(), DBError> {
+        let expires_at = self.now_millis().saturating_add(ttl.as_millis() as u64);
+        self.put_expiring(key, val, Some(expires_at), write_opts)
+    }
+
+    /// `put_opt` of a value expiring at `expires_at`, if set, in milliseconds since the UNIX epoch.
+    fn put_expiring<K: Encode, V: Encode>(
+        &mut self,
+        key: &K,
+        val: &V,
+        expires_at: Option<u64>,
+        write_opts: &WriteOptions,
+    ) -> Result<(), DBError> {
+        // The index entries are written along with the value
+        if !self.opts.indexes.is_empty() {
+            let mut batch = WriteBatch::new();
+            match expires_at {
+                Some(expires_at) => batch.put_expiring(key.encode(), &val.encode(), expires_at),
+                None => batch.put(key, val),
+            };
+            return self.write(batch, write_opts);
+        }
+
+        write_opts.validate()?;
+        self.stall_writes()?;
+
         let encoded_key = key.encode();
         let encoded_val = val.encode();
 
+        // Checked before the write is logged, replay would take a record without a key for corruption
+        if encoded_key.is_empty() {
+            return Err(DBError::Codec {
+                context: String::from("key cannot be empty"),
+                source: None,
+            });
+        }
+
+        let timestamp = self.opts.record_write_time.then(|| self.now_millis());
+
         // Insert into WAL
         // TODO: see if we can prevent multiple clones
-        let wal_record = WALRecord::new(
-            Op::Put,
-            self.next_seq_no,
-            encoded_key.clone(),
-            encoded_val.clone(),
-        );
-        self.wal.append(&wal_record)?;
+        let wal_record = match expires_at {
+            Some(expires_at) => WALRecord::new(
+                Op::ExpiringPut,
+                self.next_seq_no,
+                encoded_key.clone(),
+                [&expires_at.to_le_bytes(), encoded_val.as_slice()].concat(),
+            ),
+            None => WALRecord::new(
+                Op::Put,
+                self.next_seq_no,
+                encoded_key.clone(),
+                encoded_val.clone(),
+            ),
+        }
+        .with_timestamp(timestamp);
+        self.log_write(&wal_record, write_opts)?;
 
         // Insert into MemTable
-        memtable::put(
-            &mut self.mem_table,
-            encoded_key,
-            encoded_val,
-            self.next_seq_no,
-        )?;
+        self.keep_for_snapshots(&encoded_key, self.next_seq_no);
+        match expires_at {
+            Some(expires_at) => memtable::put_expiring(
+                self.mem_table.as_mut(),
+                encoded_key,
+                encoded_val,
+                self.next_seq_no,
+                timestamp,
+                expires_at,
+            )?,
+            None => memtable::put_with_timestamp(
+                self.mem_table.as_mut(),
+                encoded_key,
+                encoded_val,
+                self.next_seq_no,
+                timestamp,
+            )?,
+        }
 
         self.next_seq_no += 1;
 
+        self.maybe_flush_mem_table()?;
+
         Ok(())
     }
 
@@ -154,260 +1070,5161 @@ impl DB {
     //
     // To completely delete a key, we set a Tombstone, to let compaction know it should not be compacted again.
     pub fn delete<K: Encode>(&mut self, key: &K) -> Result<(), DBError> {
+        self.delete_opt(key, &WriteOptions::default())
+    }
+
+    /// `delete` with its durability decided by `write_opts` rather than the `wal_sync_policy` alone, see
+    /// `WriteOptions`.
+    pub fn delete_opt<K: Encode>(
+        &mut self,
+        key: &K,
+        write_opts: &WriteOptions,
+    ) -> Result<(), DBError> {
+        // The index entries are deleted along with the key
+        if !self.opts.indexes.is_empty() {
+            let mut batch = WriteBatch::new();
+            batch.delete(key);
+            return self.write(batch, write_opts);
+        }
+
+        write_opts.validate()?;
+        self.stall_writes()?;
+
         let encoded_key = key.encode();
 
-        if encoded_key.len() == 0 {
+        if encoded_key.is_empty() {
             return Err(DBError::Codec {
                 context: String::from("key cannot be empty"),
                 source: None,
             });
         }
 
-        self.mem_table
-            .entry(encoded_key)
-            .or_insert(Entry::Tombstone {
-                seq_no: self.next_seq_no,
-            });
+        let wal_record = WALRecord::new(
+            Op::Delete,
+            self.next_seq_no,
+            encoded_key.clone(),
+            Vec::new(),
+        );
+        self.log_write(&wal_record, write_opts)?;
+
+        self.keep_for_snapshots(&encoded_key, self.next_seq_no);
+        memtable::delete(self.mem_table.as_mut(), encoded_key, self.next_seq_no)?;
 
         self.next_seq_no += 1;
 
+        self.maybe_flush_mem_table()?;
+
         Ok(())
     }
 
-    pub fn get_typed<K: Encode, V: Decode>(&self, key: &K) -> Result<Option<V>, DBError> {
-        match self.get_raw(key)? {
-            Some(data) => Ok(Some(V::decode(data.as_ref())?)),
-            None => Ok(None)
+    /// Deletes every key in the half-open range `[start, end)` with a single range tombstone, which is far
+    /// cheaper than deleting the keys one by one when pruning a large range, e.g. a whole prefix. Keys
+    /// written after the call are not affected. A `start` at or after `end` is an empty range and deletes
+    /// nothing.
+    ///
+    /// Reads skip the deleted keys straight away, the space they take is reclaimed as compaction gets to
+    /// them, see `compaction`.
+    pub fn delete_range<K: Encode>(&mut self, start: &K, end: &K) -> Result<(), DBError> {
+        self.stall_writes()?;
+
+        let (start, end) = (start.encode(), end.encode());
+        if start.is_empty() {
+            return Err(DBError::Codec {
+                context: String::from("key cannot be empty"),
+                source: None,
+            });
+        }
+        if self.comparator().compare(&start, &end).is_ge() {
+            return Ok(());
         }
-    }
 
-    pub fn get_raw<K: Encode>(&self, key: &K) -> Result<Option<Vec<u8>>, DBError> {
-        let encoded_key = key.encode();
+        let wal_record = WALRecord::new(
+            Op::DeleteRange,
+            self.next_seq_no,
+            start.clone(),
+            end.clone(),
+        );
+        self.log_write(&wal_record, &WriteOptions::default())?;
 
-        let val = match self.mem_table.get(&encoded_key) {
-            Some(entry) => match entry {
-                Entry::Value { seq_no, val } => Some(val.clone()),
-                Entry::Tombstone { seq_no } => None,
-            },
-            None => None,
-        };
-        
-        // TODO: Implement SSTables if cache miss
-        
-        Ok(val)
-    }
-}
+        self.mem_range_tombstones.push(RangeTombstone {
+            start,
+            end,
+            seq_no: self.next_seq_no,
+        });
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::OpenOptions;
+        self.next_seq_no += 1;
 
-    const TEST_DATA_DIR: &'static str = "test_data";
-    const SS_TABLE_DIR: &'static str = "sstb";
-    const WAL_DIR: &'static str = "wal";
+        self.maybe_flush_mem_table()?;
 
-    type TestEncoder = String;
-    impl Encode for TestEncoder {
-        fn encode(&self) -> Vec<u8> {
-            self.as_bytes().to_owned()
-        }
+        Ok(())
     }
 
-    impl Decode for TestEncoder {
-        fn decode(bytes: &[u8]) -> Result<Self, DBError> {
-            match String::from_utf8(bytes.to_owned()) {
-                Ok(data) => Ok(data),
-                Err(e) => Err(DBError::Codec {
-                    context: String::from("failed to convert bytes"),
-                    source: Some(Box::new(e)),
-                }),
-            }
-        }
+    /// Merges `operand` into the value of `key` with the `DBConfig::merge_operator`, without reading the
+    /// value first. The operand is logged and written like a value, folded into whatever the MemTable
+    /// holds for the key, and into the older versions of the key as reads and compactions come across
+    /// them, see `merge`. Fails with `DBError::InvalidConfig` when no merge operator is configured.
+    pub fn merge<K: Encode, V: Encode>(&mut self, key: &K, operand: &V) -> Result<(), DBError> {
+        self.merge_opt(key, operand, &WriteOptions::default())
     }
 
-    fn test_default_config(wal_file_name: &str, preserve_wal: bool) -> DBConfig {
-        let mut ss_table_path = PathBuf::new();
-        ss_table_path.push(TEST_DATA_DIR);
-        ss_table_path.push(SS_TABLE_DIR);
+    /// `merge` with its durability decided by `write_opts`, see `WriteOptions`.
+    pub fn merge_opt<K: Encode, V: Encode>(
+        &mut self,
+        key: &K,
+        operand: &V,
+        write_opts: &WriteOptions,
+    ) -> Result<(), DBError> {
+        write_opts.validate()?;
+        self.merge_operator()?;
+        self.stall_writes()?;
 
-        let mut wal_path = PathBuf::new();
-        wal_path.push(TEST_DATA_DIR);
-        wal_path.push(WAL_DIR);
-        wal_path.push(format!("{}_wal.wl", wal_file_name));
-
-        // println!("wal_path: {:?}", wal_path);
-        
-        let mut wal_file_opts = OpenOptions::new();
-        wal_file_opts
-            .create(true)
-            .write(true)
-            .read(true);
-        
-        if !preserve_wal {
-            wal_file_opts
-                .truncate(true);
+        let encoded_key = key.encode();
+        if encoded_key.is_empty() {
+            return Err(DBError::Codec {
+                context: String::from("key cannot be empty"),
+                source: None,
+            });
+        }
+
+        let timestamp = self.opts.record_write_time.then(|| self.now_millis());
+        let wal_record = WALRecord::new(Op::Merge, self.next_seq_no, encoded_key, operand.encode())
+            .with_timestamp(timestamp);
+        self.log_write(&wal_record, write_opts)?;
+
+        self.apply_write(wal_record)?;
+        self.next_seq_no += 1;
+
+        self.maybe_flush_mem_table()
+    }
+
+    fn merge_operator(&self) -> Result<&dyn MergeOperator, DBError> {
+        self.opts
+            .merge_operator
+            .as_deref()
+            .ok_or(DBError::InvalidConfig {
+                what: "merge needs a merge operator, see DBConfig::merge_operator",
+            })
+    }
+
+    /// Writes `new` to `key`, or deletes it if `new` is None, but only if the key holds `expected` at the
+    /// time, None standing for no value at all. On a mismatch nothing is written and the value the key
+    /// does hold is returned in the inner `Err`. Writes take `&mut self`, so no other write can come in
+    /// between the check and the write.
+    pub fn compare_and_swap<K: Encode>(
+        &mut self,
+        key: &K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<Result<(), Option<Vec<u8>>>, DBError> {
+        let current = self.get_raw(key)?;
+        if current.as_deref() != expected {
+            return Ok(Err(current));
+        }
+        match new {
+            Some(val) => self.put(key, &RawValue(val))?,
+            None => self.delete(key)?,
+        }
+        Ok(Ok(()))
+    }
+
+    /// Reads `key`, hands its value to `f`, None if it has none, and writes back what `f` returns, deleting
+    /// the key on None. The new value is returned. As with `compare_and_swap` no other write can come in
+    /// between the read and the write; for updates that don't need to see the value, see `merge`.
+    pub fn update<K, F>(&mut self, key: &K, f: F) -> Result<Option<Vec<u8>>, DBError>
+    where
+        K: Encode,
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let current = self.get_raw(key)?;
+        let new = f(current.as_deref());
+        match (&new, current) {
+            (Some(val), _) => self.put(key, &RawValue(val))?,
+            (None, Some(_)) => self.delete(key)?,
+            // Nothing to delete
+            (None, None) => {}
+        }
+        Ok(new)
+    }
+
+    /// Commits every write in `batch` atomically, either all of them or none. They are logged as a single
+    /// WAL batch record, so replay never brings back part of the batch, and take consecutive seq_nos in
+    /// the order they were added. The MemTable is only flushed once the whole batch is in it.
+    ///
+    /// The writes the batch has for column families are committed along with the others, which takes
+    /// `ColumnFamilyWal::Shared`, the batch fails with `DBError::InvalidConfig` otherwise. They take the
+    /// seq_nos of their column family, and are handed to its subscriptions.
+    ///
+    /// A batch holding a write with an empty key, or too large to be logged within `max_record_len`,
+    /// fails with `DBError::Codec` and nothing is written. Subscriptions get every write of the batch as
+    /// a record of its own.
+    pub fn write(&mut self, batch: WriteBatch, write_opts: &WriteOptions) -> Result<(), DBError> {
+        write_opts.validate()?;
+        let (batch, cf_batches) = batch.into_parts();
+        if !cf_batches.is_empty() && self.opts.column_family_wal != ColumnFamilyWal::Shared {
+            return Err(DBError::InvalidConfig {
+                what: "a batch can only span column families sharing the WAL, see ColumnFamilyWal::Shared",
+            });
+        }
+
+        let mut records = self.batch_records(batch)?;
+        let mut cf_records = Vec::new();
+        for (cf, batch) in cf_batches {
+            let records = self.cf(&cf).batch_records(batch)?;
+            if !records.is_empty() {
+                cf_records.push((cf, records));
+            }
+        }
+        if records.is_empty() && cf_records.is_empty() {
+            return Ok(());
+        }
+
+        if !write_opts.disable_wal {
+            self.log_batch(&mut records, &cf_records, write_opts.sync)?;
+        }
+        if !records.is_empty() {
+            self.apply_batch(records)?;
+        }
+        for (cf, records) in cf_records {
+            self.cf_mut(&cf).apply_batch(records)?;
+        }
+        Ok(())
+    }
+
+    /// The WAL records of the writes in `batch`, along with the index updates they call for, numbered from
+    /// the next seq_no. Waits first while compaction is falling behind, see `stall_writes`.
+    fn batch_records(&self, batch: WriteBatch) -> Result<Vec<WALRecord>, DBError> {
+        if batch.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.stall_writes()?;
+        let batch = match self.opts.indexes.is_empty() {
+            true => batch,
+            false => index::with_index_updates(self, batch)?,
+        };
+
+        let timestamp = self.opts.record_write_time.then(|| self.now_millis());
+        let mut records = Vec::with_capacity(batch.len());
+        for op in batch.into_ops() {
+            if op.key.is_empty() {
+                return Err(DBError::Codec {
+                    context: String::from("key cannot be empty"),
+                    source: None,
+                });
+            }
+            // An empty range deletes nothing, see `delete_range`
+            if op.op == Op::DeleteRange && self.comparator().compare(&op.key, &op.val).is_ge() {
+                continue;
+            }
+            if op.op == Op::Merge {
+                self.merge_operator()?;
+            }
+
+            let seq_no = self.next_seq_no + records.len() as u64;
+            let record = WALRecord::new(op.op, seq_no, op.key, op.val);
+            records.push(match record.op() {
+                Op::Put | Op::ExpiringPut | Op::Merge => record.with_timestamp(timestamp),
+                _ => record,
+            });
+        }
+        Ok(records)
+    }
+
+    /// Logs the `records` of a batch as a single WAL batch record, along with the `cf_records` it has for
+    /// column families sharing the WAL, each tagged with its column family, so they all commit together.
+    /// `records` is left as it was.
+    fn log_batch(
+        &self,
+        records: &mut Vec<WALRecord>,
+        cf_records: &[(ColumnFamilyHandle, Vec<WALRecord>)],
+        sync: bool,
+    ) -> Result<(), DBError> {
+        if self.wal_owner != 0 {
+            self.wal.hold(self.wal_owner);
+            self.wal
+                .append(&wal::column_family_record(self.wal_owner, records)?, sync)?;
+            return Ok(());
+        }
+
+        if !records.is_empty() {
+            self.wal.hold(self.wal_owner);
+        }
+        let own_records = records.len();
+        for (cf, cf_records) in cf_records {
+            self.wal.hold(cf.wal_id());
+            records.push(wal::column_family_record(cf.wal_id(), cf_records)?);
+        }
+        let appended = self.wal.append_batch(records, sync);
+        records.truncate(own_records);
+        appended.map(|_| ())
+    }
+
+    /// Applies the `records` of a batch once logged, see `write`.
+    fn apply_batch(&mut self, records: Vec<WALRecord>) -> Result<(), DBError> {
+        for record in &records {
+            self.subscribers.publish(record);
+        }
+
+        self.next_seq_no += records.len() as u64;
+        for record in records {
+            self.apply_write(record)?;
+        }
+
+        self.maybe_flush_mem_table()
+    }
+
+    /// Applies a logged `record` to the MemTable just as replay does, once what it overwrites that live
+    /// snapshots still read is set aside.
+    ///
+    /// A merge operand is folded into the entry it lands on, unless that entry was set aside: reads and
+    /// compactions fold the operand into the entry set aside anyway, so folding it in here too would
+    /// count the entry twice. It is stored unfolded instead.
+    fn apply_write(&mut self, record: WALRecord) -> Result<(), DBError> {
+        let kept = *record.op() != Op::DeleteRange
+            && self.keep_for_snapshots(record.key(), record.seq_no());
+        if kept && *record.op() == Op::Merge {
+            let entry = Entry::Merge {
+                seq_no: record.seq_no(),
+                operand: record.val().to_vec(),
+                timestamp: record.timestamp(),
+            };
+            self.mem_table.insert(record.key().to_vec(), entry);
+            return Ok(());
+        }
+
+        let now = self.now_millis();
+        let (mem_table, range_tombstones) =
+            (self.mem_table.as_mut(), &mut self.mem_range_tombstones);
+        let merge_operator = self.opts.merge_operator.as_deref();
+        wal::apply_record(record, mem_table, range_tombstones, merge_operator, now)
+    }
+
+    /// Sets the MemTable's entry for `encoded_key` aside before the write at `seq_no` overwrites it, if a
+    /// live snapshot still reads it, i.e. was taken after the entry was written but before `seq_no`.
+    /// Returns whether it was set aside.
+    fn keep_for_snapshots(&mut self, encoded_key: &[u8], seq_no: u64) -> bool {
+        let Some(entry) = self.mem_table.get(encoded_key) else {
+            return false;
+        };
+        let kept = self.versions().snapshot_within(entry.seq_no(), seq_no);
+        if kept {
+            self.mem_superseded
+                .insert(encoded_key.to_vec(), entry.clone());
+        }
+        kept
+    }
+
+    /// Appends `wal_record` to the WAL unless `write_opts` skips it, and hands it to the subscriptions
+    /// once committed.
+    fn log_write(&self, wal_record: &WALRecord, write_opts: &WriteOptions) -> Result<(), DBError> {
+        if !write_opts.disable_wal {
+            self.wal.hold(self.wal_owner);
+            match self.wal_owner {
+                0 => self.wal.append(wal_record, write_opts.sync)?,
+                wal_id => self.wal.append(
+                    &wal::column_family_record(wal_id, std::slice::from_ref(wal_record))?,
+                    write_opts.sync,
+                )?,
+            };
+        }
+        self.subscribers.publish(wal_record);
+        Ok(())
+    }
+
+    /// Takes a `Snapshot` of every write committed so far, to read the DB as it is now through
+    /// `get_raw_at` however it changes after.
+    ///
+    /// Taking one is cheap, it only registers its seq_no. While it lives, the entries the MemTable
+    /// overwrites that it still reads are set aside and flushed along with the MemTable, and compactions
+    /// keep the versions of a key it reads next to the newer ones. A long-lived snapshot thus holds on to
+    /// the space of everything overwritten after it was taken.
+    pub fn snapshot(&self) -> Result<Snapshot, DBError> {
+        Ok(Snapshot::new(self.next_seq_no, self.versions.clone()))
+    }
+
+    /// Begins an optimistic transaction reading the DB as it is now, see `Transaction`. It takes a
+    /// snapshot, with what that costs.
+    pub fn begin_transaction(&self) -> Result<Transaction, DBError> {
+        Ok(Transaction::new(self.snapshot()?))
+    }
+
+    /// Subscribes to every write committed from now on, see `Subscription`.
+    pub fn subscribe(&self) -> Subscription {
+        self.subscribers.subscribe()
+    }
+
+    /// Creates the column family `name`, configured with `opts` but for its directories, see
+    /// `column_family`. It is opened along with the DB from then on, with the config
+    /// `DBConfig::column_families` has for it. Creating a column family that exists fails with
+    /// `DBError::InvalidConfig`, as does creating one within a column family sharing the WAL of its DB.
+    pub fn create_cf(&mut self, name: &str, opts: DBConfig) -> Result<ColumnFamilyHandle, DBError> {
+        if self.cf_handle(name).is_some() {
+            return Err(DBError::InvalidConfig {
+                what: "column family already exists",
+            });
+        }
+        if self.wal_owner != 0 {
+            return Err(DBError::InvalidConfig {
+                what: "a column family sharing the WAL of its DB cannot have column families of its own",
+            });
+        }
+        let id = self.column_families.len();
+        let family = ColumnFamily::open(&self.opts, &self.wal, id, name, opts)?;
+        self.versions().manifest.add_column_family(name)?;
+        self.column_families.push(family);
+        Ok(self.cf_handle(name).expect("column family was just added"))
+    }
+
+    /// The handle to the column family `name`, if it was created.
+    pub fn cf_handle(&self, name: &str) -> Option<ColumnFamilyHandle> {
+        column_family::handle(&self.column_families, name)
+    }
+
+    /// The DB holding the column family `cf`, for everything the `_cf` methods don't cover.
+    ///
+    /// # Panics
+    ///
+    /// If `cf` is a handle from another DB, as do all the `_cf` methods.
+    pub fn cf(&self, cf: &ColumnFamilyHandle) -> &DB {
+        &column_family::get(&self.column_families, cf).db
+    }
+
+    pub fn cf_mut(&mut self, cf: &ColumnFamilyHandle) -> &mut DB {
+        &mut column_family::get_mut(&mut self.column_families, cf).db
+    }
+
+    pub fn put_cf<K: Encode, V: Encode>(
+        &mut self,
+        cf: &ColumnFamilyHandle,
+        key: &K,
+        val: &V,
+    ) -> Result<(), DBError> {
+        self.cf_mut(cf).put(key, val)
+    }
+
+    pub fn get_cf<K: Encode>(
+        &self,
+        cf: &ColumnFamilyHandle,
+        key: &K,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        self.cf(cf).get_raw(key)
+    }
+
+    pub fn delete_cf<K: Encode>(
+        &mut self,
+        cf: &ColumnFamilyHandle,
+        key: &K,
+    ) -> Result<(), DBError> {
+        self.cf_mut(cf).delete(key)
+    }
+
+    /// Iterates over every live key of the column family `cf` in key order, along with its value.
+    pub fn iter_cf(&self, cf: &ColumnFamilyHandle) -> DBIterator<'_> {
+        self.cf(cf).iter()
+    }
+
+    /// Opens the column families the manifest records, with the configs of `DBConfig::column_families`.
+    fn open_column_families(&mut self) -> Result<(), DBError> {
+        let names: Vec<String> = self
+            .versions()
+            .manifest
+            .column_families()
+            .map(String::from)
+            .collect();
+        let mut configs = std::mem::take(&mut self.opts.column_families);
+        for (id, name) in names.iter().enumerate() {
+            let opts = configs.remove(name).unwrap_or_default();
+            self.column_families
+                .push(ColumnFamily::open(&self.opts, &self.wal, id, name, opts)?);
+        }
+        Ok(())
+    }
+
+    /// Holds back a write while compaction is falling behind, see `DBConfig::l0_slowdown_writes_trigger`.
+    /// Pending compaction bytes are estimated with the leveled targets even under a custom picker.
+    fn stall_writes(&self) -> Result<(), DBError> {
+        if self.opts.compaction_style != CompactionStyle::Leveled {
+            return Ok(());
+        }
+
+        let (l0_tables, pending_bytes) = {
+            let versions = self.versions();
+            let l0_tables = versions
+                .ss_meta
+                .iter()
+                .filter(|meta| meta.level() == 0)
+                .count() as u64;
+            let pending_bytes = compaction::pending_compaction_bytes(
+                &versions.ss_meta,
+                &self.opts.leveled_compaction_picker(),
+            );
+            (l0_tables, pending_bytes)
+        };
+        let reached = |limit: Option<u64>, value: u64| limit.is_some_and(|limit| value >= limit);
+
+        if reached(self.opts.l0_stop_writes_trigger.map(u64::from), l0_tables) {
+            return Err(DBError::Busy {
+                what: "too many L0 ss_tables, writes are stopped until compaction catches up",
+            });
+        }
+        if reached(self.opts.hard_pending_compaction_bytes_limit, pending_bytes) {
+            return Err(DBError::Busy {
+                what: "too many pending compaction bytes, writes are stopped until compaction catches up",
+            });
+        }
+        if reached(
+            self.opts.l0_slowdown_writes_trigger.map(u64::from),
+            l0_tables,
+        ) || reached(self.opts.soft_pending_compaction_bytes_limit, pending_bytes)
+        {
+            std::thread::sleep(self.opts.write_stall_delay);
+        }
+
+        Ok(())
+    }
+
+    pub fn get_typed<K: Encode, V: Decode>(&self, key: &K) -> Result<Option<V>, DBError> {
+        self.get_typed_opt(key, &ReadOptions::default())
+    }
+
+    /// `get_typed` with per-read options, see `ReadOptions`.
+    pub fn get_typed_opt<K: Encode, V: Decode>(
+        &self,
+        key: &K,
+        read_opts: &ReadOptions,
+    ) -> Result<Option<V>, DBError> {
+        match self.get_raw_opt(key, read_opts)? {
+            Some(data) => Ok(Some(V::decode(data.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// get_raw returns the latest value for `key`. The MemTable is checked first as it always holds the
+    /// most recent writes, then the MemTable being flushed if any, after which the SSTables are consulted
+    /// newest-to-oldest. The first table (or MemTable) that knows about the key wins, so a
+    /// `Entry::Tombstone` stops the search and a deleted key never resurrects from an older table.
+    ///
+    /// The range tombstones of every table searched on the way are gathered too, the entry found is only
+    /// returned if none of them is newer.
+    pub fn get_raw<K: Encode>(&self, key: &K) -> Result<Option<Vec<u8>>, DBError> {
+        self.get_raw_opt(key, &ReadOptions::default())
+    }
+
+    /// `get_raw` with per-read options, see `ReadOptions`.
+    pub fn get_raw_opt<K: Encode>(
+        &self,
+        key: &K,
+        read_opts: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        Ok(self
+            .get_entry(&key.encode(), read_opts)?
+            .and_then(entry_value))
+    }
+
+    /// `get_raw` as of `snapshot`: the value `key` held when the snapshot was taken, whatever was
+    /// written since.
+    pub fn get_raw_at<K: Encode>(
+        &self,
+        key: &K,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        let read_opts = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        self.get_raw_opt(key, &read_opts)
+    }
+
+    /// `get_raw` along with the seq_no the value was written at, where it was found and, with
+    /// `DBConfig::record_write_time` set, when it was written.
+    pub fn get_with_metadata<K: Encode>(
+        &self,
+        key: &K,
+    ) -> Result<Option<ValueWithMetadata>, DBError> {
+        self.get_raw_with_meta(key, &ReadOptions::default())
+    }
+
+    /// `get_with_metadata` with per-read options, e.g. to tell which table a read through a snapshot is
+    /// served from.
+    pub fn get_raw_with_meta<K: Encode>(
+        &self,
+        key: &K,
+        read_opts: &ReadOptions,
+    ) -> Result<Option<ValueWithMetadata>, DBError> {
+        let (found, deleted_at) = self.find_entry_with_origin(&key.encode(), read_opts)?;
+        let now = self.now_millis();
+        let Some((entry, origin)) = found else {
+            return Ok(None);
+        };
+        Ok(visible_entry(entry, deleted_at)
+            .filter(|entry| !entry.is_expired(now))
+            .and_then(|entry| match entry {
+                Entry::Value {
+                    seq_no,
+                    val,
+                    timestamp,
+                    expires_at,
+                } => Some(ValueWithMetadata {
+                    val,
+                    seq_no,
+                    timestamp,
+                    expires_at,
+                    origin,
+                }),
+                Entry::Tombstone { .. } | Entry::Merge { .. } => None,
+            }))
+    }
+
+    /// Whether `key` holds a live value, as `get_raw` would find it, without handing the value out.
+    pub fn contains_key<K: Encode>(&self, key: &K) -> Result<bool, DBError> {
+        let entry = self.get_entry(&key.encode(), &ReadOptions::default())?;
+        Ok(entry.is_some_and(|entry| matches!(entry, Entry::Value { .. })))
+    }
+
+    /// Whether `key` may hold a live value, answered from the MemTables and the tables' filters and
+    /// indexes alone, never reading a data block. A `false` is certain, a `true` may be a false positive
+    /// to be settled with `contains_key`, e.g. for a key a table's filter matches or a range deletion
+    /// deleted.
+    pub fn key_may_exist<K: Encode>(&self, key: &K) -> Result<bool, DBError> {
+        let encoded_key = key.encode();
+        let now = self.now_millis();
+        // The newest entry in a MemTable settles it, as in `find_entry`
+        let frozen = self.pending_flush.get();
+        let mem_tables = std::iter::once(self.mem_table.as_ref())
+            .chain(frozen.iter().map(|frozen| frozen.mem_table.as_ref()));
+        for mem_table in mem_tables {
+            match mem_table.get(&encoded_key) {
+                Some(entry @ Entry::Value { .. }) => return Ok(!entry.is_expired(now)),
+                Some(Entry::Merge { .. }) => return Ok(true),
+                Some(Entry::Tombstone { .. }) => return Ok(false),
+                None => {}
+            }
+        }
+
+        for meta in &self.versions().ss_meta {
+            if meta.may_contain_key(self.comparator(), &encoded_key)
+                && self.table_cache.get(meta)?.may_contain(&encoded_key)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Looks up every key of `keys` at once, returning what `get_raw` would for each, in the same order.
+    /// The keys are sorted and searched for one source at a time, so each table is opened once, its
+    /// filter and index probed for all the keys it could hold, and a data block holding several of them
+    /// read just once, see `SSTableReader::multi_get`. A key whose lookup fails doesn't fail the others.
+    pub fn multi_get<K: Encode>(&self, keys: &[K]) -> Vec<Result<Option<Vec<u8>>, DBError>> {
+        let read_opts = ReadOptions::default();
+        let read_seq_no = read_opts.read_seq_no();
+        let readable = |entry: &&Entry| entry.seq_no() < read_seq_no;
+
+        let encoded: Vec<Vec<u8>> = keys.iter().map(Encode::encode).collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| self.comparator().compare(&encoded[a], &encoded[b]));
+        // What was found for each key, None while it is still searched for, and as in `get_entry` the
+        // newest range tombstone covering it in the sources searched so far
+        let mut found: Vec<Option<Result<Option<Entry>, DBError>>> =
+            keys.iter().map(|_| None).collect();
+        let mut deleted_at = vec![None; keys.len()];
+        // Keys of a table that couldn't be opened, and keys with merge operands to fold, are looked up again
+        // alone once the tables are unlocked: each then gets an error of its own, or the operands folded as
+        // `get_entry` does
+        let mut retry = Vec::new();
+
+        let frozen = self.pending_flush.get();
+        let mem_tables = std::iter::once((
+            self.mem_table.as_ref(),
+            self.mem_range_tombstones.as_slice(),
+        ))
+        .chain(frozen.iter().map(|frozen| {
+            (
+                frozen.mem_table.as_ref(),
+                frozen.range_tombstones.as_slice(),
+            )
+        }));
+        for (mem_table, range_tombstones) in mem_tables {
+            for &i in &order {
+                if found[i].is_some() {
+                    continue;
+                }
+                let covering = entry::covering_seq_no_before(
+                    self.comparator(),
+                    range_tombstones,
+                    &encoded[i],
+                    read_seq_no,
+                );
+                deleted_at[i] = deleted_at[i].max(covering);
+                match mem_table.get(&encoded[i]).filter(readable) {
+                    Some(Entry::Merge { .. }) => {
+                        found[i] = Some(Ok(None));
+                        retry.push(i);
+                    }
+                    Some(entry) => found[i] = Some(Ok(visible_entry(entry.clone(), deleted_at[i]))),
+                    None => {}
+                }
+            }
+        }
+
+        {
+            let versions = self.versions();
+            for meta in &versions.ss_meta {
+                if meta.min_seq_no() >= read_seq_no {
+                    continue;
+                }
+                let wanted: Vec<usize> = order
+                    .iter()
+                    .copied()
+                    .filter(|&i| {
+                        found[i].is_none() && meta.may_contain_key(self.comparator(), &encoded[i])
+                    })
+                    .collect();
+                if wanted.is_empty() {
+                    continue;
+                }
+
+                let reader = match self.table(meta, &read_opts) {
+                    Ok(reader) => reader,
+                    Err(_) => {
+                        for i in wanted {
+                            found[i] = Some(Ok(None));
+                            retry.push(i);
+                        }
+                        continue;
+                    }
+                };
+                let wanted_keys: Vec<&[u8]> =
+                    wanted.iter().map(|&i| encoded[i].as_slice()).collect();
+                let entries =
+                    reader.multi_get_with_checksums(&wanted_keys, read_opts.verify_checksums);
+                for (i, entry) in wanted.into_iter().zip(entries) {
+                    let covering = entry::covering_seq_no_before(
+                        self.comparator(),
+                        reader.range_tombstones(),
+                        &encoded[i],
+                        read_seq_no,
+                    );
+                    deleted_at[i] = deleted_at[i].max(covering);
+                    match entry {
+                        Ok(Some(Entry::Merge { seq_no, .. })) if seq_no < read_seq_no => {
+                            found[i] = Some(Ok(None));
+                            retry.push(i);
+                        }
+                        Ok(Some(entry)) if readable(&&entry) => {
+                            found[i] = Some(Ok(visible_entry(entry, deleted_at[i])));
+                        }
+                        Ok(_) => {}
+                        Err(e) => found[i] = Some(Err(e)),
+                    }
+                }
+            }
+        }
+        for i in retry {
+            found[i] = Some(self.get_entry(&encoded[i], &read_opts));
+        }
+
+        let now = self.now_millis();
+        found
+            .into_iter()
+            .map(|found| {
+                let entry = found.unwrap_or(Ok(None))?;
+                Ok(entry
+                    .filter(|entry| !entry.is_expired(now))
+                    .and_then(entry_value))
+            })
+            .collect()
+    }
+
+    /// The latest entry of `encoded_key` the `read_opts` see that no range tombstone deletes, see `get_raw`.
+    /// Entries written after the snapshot read at are skipped, the version they replaced is found further
+    /// down.
+    fn get_entry(
+        &self,
+        encoded_key: &[u8],
+        read_opts: &ReadOptions,
+    ) -> Result<Option<Entry>, DBError> {
+        let (entry, deleted_at) = self.find_entry(encoded_key, read_opts)?;
+        let now = self.now_millis();
+        Ok(entry
+            .and_then(|entry| visible_entry(entry, deleted_at))
+            .filter(|entry| !entry.is_expired(now)))
+    }
+
+    /// The seq_no of the last write to `encoded_key`, be it a put, a delete or a range deletion covering
+    /// it. None if it was never written, or its writes were all compacted away.
+    fn last_write_seq_no(&self, encoded_key: &[u8]) -> Result<Option<u64>, DBError> {
+        let (entry, deleted_at) = self.find_entry(encoded_key, &ReadOptions::default())?;
+        Ok(entry.map(|entry| entry.seq_no()).max(deleted_at))
+    }
+
+    /// The latest entry of `encoded_key` the `read_opts` see, whether deleted by a range tombstone or not,
+    /// along with the seq_no of the newest range tombstone covering it, if any. Once an entry is found
+    /// the older sources are left alone, their range tombstones are older than the entry anyway. Merge
+    /// operands don't end the search, they are folded into the entry found beneath them.
+    fn find_entry(
+        &self,
+        encoded_key: &[u8],
+        read_opts: &ReadOptions,
+    ) -> Result<(Option<Entry>, Option<u64>), DBError> {
+        let (found, deleted_at) = self.find_entry_with_origin(encoded_key, read_opts)?;
+        Ok((found.map(|(entry, _)| entry), deleted_at))
+    }
+
+    /// `find_entry` along with where the newest version of the key was found, be it the entry itself or
+    /// the newest of the merge operands folded into it.
+    fn find_entry_with_origin(
+        &self,
+        encoded_key: &[u8],
+        read_opts: &ReadOptions,
+    ) -> Result<(Option<FoundEntry>, Option<u64>), DBError> {
+        let read_seq_no = read_opts.read_seq_no();
+        let covering = |tombstones: &[RangeTombstone]| {
+            entry::covering_seq_no_before(self.comparator(), tombstones, encoded_key, read_seq_no)
+        };
+        let readable = |entry: &&Entry| entry.seq_no() < read_seq_no;
+        // The merge operands found on the way down, newest first, see `fold_merges`, and where the first
+        // one was
+        let mut merges = Vec::new();
+        let mut merges_origin = None;
+        let fold = |merges: Vec<Entry>, beneath: Option<Entry>, deleted_at, origin: ValueOrigin| {
+            let (entry, deleted_at) = self.fold_merges(encoded_key, merges, beneath, deleted_at)?;
+            Ok((entry.map(|entry| (entry, origin)), deleted_at))
+        };
+
+        // A MemTable's entry is the newest of the key, the snapshots it is too new for read the one it
+        // overwrote. A merge operand written over a snapshot goes on in the entry set aside beneath it, see
+        // `apply_write`
+        let mut deleted_at = covering(&self.mem_range_tombstones);
+        let mut found = self
+            .mem_table
+            .get(encoded_key)
+            .filter(readable)
+            .or_else(|| self.mem_superseded.get_before(encoded_key, read_seq_no));
+        while let Some(entry) = found {
+            match entry {
+                Entry::Merge { .. } => {
+                    merges.push(entry.clone());
+                    merges_origin = Some(ValueOrigin::MemTable);
+                    found = self.mem_superseded.get_before(encoded_key, entry.seq_no());
+                }
+                entry => {
+                    return fold(
+                        merges,
+                        Some(entry.clone()),
+                        deleted_at,
+                        ValueOrigin::MemTable,
+                    );
+                }
+            }
+        }
+
+        // Taken before the tables, its table may be installed in between but is then searched too
+        if let Some(frozen) = self.pending_flush.get() {
+            deleted_at = deleted_at.max(covering(&frozen.range_tombstones));
+            let mut found = frozen
+                .mem_table
+                .get(encoded_key)
+                .filter(readable)
+                .or_else(|| frozen.superseded.get_before(encoded_key, read_seq_no));
+            while let Some(entry) = found {
+                let origin = merges_origin.unwrap_or(ValueOrigin::ImmutableMemTable);
+                match entry {
+                    Entry::Merge { .. } => {
+                        merges.push(entry.clone());
+                        merges_origin = Some(origin);
+                        found = frozen.superseded.get_before(encoded_key, entry.seq_no());
+                    }
+                    entry => return fold(merges, Some(entry.clone()), deleted_at, origin),
+                }
+            }
+        }
+
+        // Tables whose key range cannot hold the key are skipped without touching their files, and so are
+        // tables written after `read_seq_no`
+        let versions = self.versions();
+        for meta in &versions.ss_meta {
+            if !meta.may_contain_key(self.comparator(), encoded_key)
+                || meta.min_seq_no() >= read_seq_no
+            {
+                continue;
+            }
+
+            let cached = self.table_cache.contains(meta.file_no());
+            let reader = self.table(meta, read_opts)?;
+            deleted_at = deleted_at.max(covering(reader.range_tombstones()));
+            // A table may hold the versions of the key a snapshot reads under a merge operand
+            let mut found = reader.get_at(encoded_key, read_seq_no, read_opts.verify_checksums)?;
+            while let Some(entry) = found {
+                let origin = merges_origin.unwrap_or(ValueOrigin::Table {
+                    file_no: meta.file_no(),
+                    level: meta.level(),
+                    cached,
+                });
+                match entry {
+                    Entry::Merge { .. } => {
+                        let seq_no = entry.seq_no();
+                        merges.push(entry);
+                        merges_origin = Some(origin);
+                        found = reader.get_at(encoded_key, seq_no, read_opts.verify_checksums)?;
+                    }
+                    entry => return fold(merges, Some(entry), deleted_at, origin),
+                }
+            }
+        }
+
+        match merges_origin {
+            Some(origin) => fold(merges, None, deleted_at, origin),
+            None => Ok((None, deleted_at)),
+        }
+    }
+
+    /// Folds `merges`, the merge operands of `encoded_key` found by `find_entry` newest first, into
+    /// `beneath`, the entry found under them, see `MergeOperator`. Every source was searched, so nothing
+    /// found beneath them means there is nothing. The operands a range tombstone at `deleted_at` deletes
+    /// are left out along with everything under them. Without operands `beneath` is passed through.
+    fn fold_merges(
+        &self,
+        encoded_key: &[u8],
+        mut merges: Vec<Entry>,
+        beneath: Option<Entry>,
+        deleted_at: Option<u64>,
+    ) -> Result<(Option<Entry>, Option<u64>), DBError> {
+        if merges.is_empty() {
+            return Ok((beneath, deleted_at));
+        }
+        let Some(merge_operator) = self.opts.merge_operator.as_deref() else {
+            return Err(DBError::InvalidConfig {
+                what: "merge operands cannot be read without a merge operator, see DBConfig::merge_operator",
+            });
+        };
+
+        let deleted =
+            |entry: &Entry| deleted_at.is_some_and(|deleted_at| entry.seq_no() < deleted_at);
+        merges.retain(|entry| !deleted(entry));
+        if merges.is_empty() {
+            return Ok((None, deleted_at));
+        }
+        let beneath = beneath.filter(|entry| !deleted(entry));
+        let now = self.now_millis();
+        let entry = merge::fold(
+            merge_operator,
+            encoded_key,
+            &merges,
+            Some(beneath.as_ref()),
+            now,
+        );
+        Ok((Some(entry), deleted_at))
+    }
+
+    /// The reader of the table `meta`, cached only if the `read_opts` fill the cache.
+    fn table(
+        &self,
+        meta: &SSTableMeta,
+        read_opts: &ReadOptions,
+    ) -> Result<Arc<SSTableReader>, DBError> {
+        match read_opts.fill_cache {
+            true => self.table_cache.get(meta),
+            false => self.table_cache.get_without_caching(meta),
+        }
+    }
+
+    /// Iterates over every live key in key order, along with its value, see `DBIterator`.
+    pub fn iter(&self) -> DBIterator<'_> {
+        DBIterator::new(
+            self,
+            Bound::Unbounded,
+            Bound::Unbounded,
+            ReadOptions::default(),
+        )
+    }
+
+    /// Iterates over the live keys within the bounds of `read_opts` in key order, along with their values,
+    /// as they were when its snapshot was taken if it has one.
+    pub fn iter_opt<'a>(&'a self, read_opts: &ReadOptions<'a>) -> DBIterator<'a> {
+        let (from, until) = read_opts.clamp(self.comparator(), Bound::Unbounded, Bound::Unbounded);
+        let to_vec = |bound: Bound<&[u8]>| bound.map(<[u8]>::to_vec);
+        DBIterator::new(self, to_vec(from), to_vec(until), read_opts.clone())
+    }
+
+    /// Iterates over the live keys within `range` in key order, along with their values, e.g.
+    /// `db.range(start..end)`. Keys compare by their encoding, see `DBIterator`.
+    pub fn range<K: Encode, R: RangeBounds<K>>(&self, range: R) -> DBIterator<'_> {
+        let encode = |bound: Bound<&K>| bound.map(Encode::encode);
+        DBIterator::new(
+            self,
+            encode(range.start_bound()),
+            encode(range.end_bound()),
+            ReadOptions::default(),
+        )
+    }
+
+    /// Iterates over the live keys whose encoding starts with that of `prefix`, in key order, along with
+    /// their values. The keys sharing a prefix are next to each other in byte order and in its reverse, a
+    /// `Comparator` that scatters them leaves out the ones outside the range they span in byte order.
+    pub fn scan_prefix<K: Encode>(&self, prefix: &K) -> DBIterator<'_> {
+        let prefix = prefix.encode();
+        let successor = prefix_successor(&prefix).map_or(Bound::Unbounded, Bound::Excluded);
+        // The keys sharing the prefix follow it in byte order, but lead up to it in reverse byte order
+        let extended = [prefix.as_slice(), &[0]].concat();
+        if self.comparator().compare(&prefix, &extended).is_lt() {
+            DBIterator::new(
+                self,
+                Bound::Included(prefix),
+                successor,
+                ReadOptions::default(),
+            )
+        } else {
+            DBIterator::new(
+                self,
+                successor,
+                Bound::Included(prefix),
+                ReadOptions::default(),
+            )
+        }
+    }
+
+    /// A `DBCursor` over every live key, to be positioned with one of its seeks.
+    pub fn cursor(&self) -> DBCursor<'_> {
+        DBCursor::new(self, ReadOptions::default())
+    }
+
+    /// `cursor` with per-read options, its seeks stay within `read_opts`' bounds.
+    pub fn cursor_opt<'a>(&'a self, read_opts: &ReadOptions<'a>) -> DBCursor<'a> {
+        DBCursor::new(self, read_opts.clone())
+    }
+
+    /// Up to `limit` live keys within `range` in key order, along with their values, and the `ResumeToken`
+    /// to pass back in for the next page, none once the range is used up. Every page is read anew from just
+    /// past the last key of the one before, so writes in between show up in the pages still to come.
+    pub fn scan_page<K: Encode, R: RangeBounds<K>>(
+        &self,
+        range: R,
+        limit: usize,
+        resume_token: Option<&ResumeToken>,
+    ) -> Result<(Vec<KeyValue>, Option<ResumeToken>), DBError> {
+        self.scan_page_opt(range, limit, resume_token, &ReadOptions::default())
+    }
+
+    /// `scan_page` with per-read options. Paging through a snapshot reads every page as of it, a token only
+    /// resumes a scan read as of the same seq_no and fails with `DBError::InvalidConfig` otherwise. A
+    /// `limit` of 0 fails the same way.
+    pub fn scan_page_opt<K: Encode, R: RangeBounds<K>>(
+        &self,
+        range: R,
+        limit: usize,
+        resume_token: Option<&ResumeToken>,
+        read_opts: &ReadOptions,
+    ) -> Result<(Vec<KeyValue>, Option<ResumeToken>), DBError> {
+        if limit == 0 {
+            return Err(DBError::InvalidConfig {
+                what: "page limit must be greater than 0",
+            });
+        }
+        let seq_no = read_opts.read_seq_no();
+        let encode = |bound: Bound<&K>| bound.map(Encode::encode);
+        let from = match resume_token {
+            Some(token) if token.seq_no != seq_no => {
+                return Err(DBError::InvalidConfig {
+                    what: "resume token was read as of another snapshot",
+                });
+            }
+            Some(token) => Bound::Excluded(token.last_key.clone()),
+            None => encode(range.start_bound()),
+        };
+        let until = encode(range.end_bound());
+        let (from, until) = (
+            from.as_ref().map(Vec::as_slice),
+            until.as_ref().map(Vec::as_slice),
+        );
+        let (from, until) = read_opts.clamp(self.comparator(), from, until);
+
+        // One key past the page tells whether there is another one
+        let mut page = self.scan(from, until, false, limit.saturating_add(1), read_opts)?;
+        if page.len() <= limit {
+            return Ok((page, None));
+        }
+        page.truncate(limit);
+        let last_key = page[limit - 1].0.clone();
+        Ok((page, Some(ResumeToken { seq_no, last_key })))
+    }
+
+    /// The first live key in key order along with its value, none if the DB holds no live key.
+    pub fn first_key_value(&self) -> Result<Option<KeyValue>, DBError> {
+        self.end_key_value(false)
+    }
+
+    /// The last live key in key order along with its value, none if the DB holds no live key.
+    pub fn last_key_value(&self) -> Result<Option<KeyValue>, DBError> {
+        self.end_key_value(true)
+    }
+
+    /// Deletes the first live key in key order, returning it along with the value it held, e.g. to take
+    /// the head off a queue keyed by sequence. Writes go through `&mut self`, so no other write lands
+    /// between finding the key and deleting it.
+    pub fn pop_first(&mut self) -> Result<Option<KeyValue>, DBError> {
+        self.pop_end(false)
+    }
+
+    /// `pop_first` for the last live key in key order.
+    pub fn pop_last(&mut self) -> Result<Option<KeyValue>, DBError> {
+        self.pop_end(true)
+    }
+
+    fn end_key_value(&self, last: bool) -> Result<Option<KeyValue>, DBError> {
+        let mut found = self.scan(
+            Bound::Unbounded,
+            Bound::Unbounded,
+            last,
+            1,
+            &ReadOptions::default(),
+        )?;
+        Ok(found.pop())
+    }
+
+    fn pop_end(&mut self, last: bool) -> Result<Option<KeyValue>, DBError> {
+        let Some((key, val)) = self.end_key_value(last)? else {
+            return Ok(None);
+        };
+        self.delete(&RawValue(&key))?;
+        Ok(Some((key, val)))
+    }
+
+    /// The live keys whose values the index `name` indexes under `index_key`, in key order, along with
+    /// their values, see `index`. Looking up an index missing from `DBConfig::indexes` fails with
+    /// `DBError::InvalidConfig`.
+    pub fn get_by_index<K: Encode>(
+        &self,
+        name: &str,
+        index_key: &K,
+    ) -> Result<Vec<KeyValue>, DBError> {
+        let index_key = index_key.encode();
+        self.index_range(
+            name,
+            Bound::Included(&index_key[..]),
+            Bound::Included(&index_key[..]),
+        )
+    }
+
+    /// The live keys whose values the index `name` indexes within `from` and `until`, ordered by index key
+    /// and then by key, along with their values, see `get_by_index`.
+    pub fn index_range(
+        &self,
+        name: &str,
+        from: Bound<&[u8]>,
+        until: Bound<&[u8]>,
+    ) -> Result<Vec<KeyValue>, DBError> {
+        let index = self.index(name)?;
+        let (from, until) = index::entry_bounds(name, from, until);
+        let (from, until) = (
+            from.as_ref().map(Vec::as_slice),
+            until.as_ref().map(Vec::as_slice),
+        );
+        let entries =
+            self.scan_keyspace(from, until, false, usize::MAX, &ReadOptions::default())?;
+        index::resolve(self, index.as_ref(), entries)
+    }
+
+    /// Drops every entry of the index `name` and indexes every live value anew, e.g. for an index added to
+    /// `DBConfig::indexes` of a DB holding values already, or one left stale by merges and range deletions.
+    /// The entries are written a batch at a time, each logged well within `max_record_len`.
+    pub fn rebuild_index(&mut self, name: &str) -> Result<(), DBError> {
+        let index = self.index(name)?.clone();
+        let (start, end) = index::index_bounds(name);
+        self.delete_range(&RawValue(&start), &RawValue(&end))?;
+
+        // Half the limit leaves room for the batch's own record, and the column family's around it
+        let max_batch_len = self.opts.max_record_len as usize / 2;
+        let mut batches = vec![WriteBatch::new()];
+        let mut batch_len = 0;
+        for item in self.iter() {
+            let (key, val) = item?;
+            let Some(index_key) = index.index_key(&val) else {
+                continue;
+            };
+            let entry_key = index::entry_key(name, &index_key, &key);
+            let len = wal::BATCHED_RECORD_OVERHEAD + entry_key.len() + key.len();
+            if batch_len + len > max_batch_len && batch_len > 0 {
+                batches.push(WriteBatch::new());
+                batch_len = 0;
+            }
+            batch_len += len;
+            let batch = batches.last_mut().expect("there is always a batch");
+            batch.put(&RawValue(&entry_key), &RawValue(&key));
+        }
+        for batch in batches {
+            self.write(batch, &WriteOptions::default())?;
+        }
+        Ok(())
+    }
+
+    fn index(&self, name: &str) -> Result<&Arc<dyn Index>, DBError> {
+        self.opts
+            .indexes
+            .iter()
+            .find(|index| index.name() == name)
+            .ok_or(DBError::InvalidConfig {
+                what: "unknown index",
+            })
+    }
+
+    /// Up to `limit` live keys within `from` and `until` in key order, or the last ones in reverse key
+    /// order if `reverse`, along with their values. The keyspace of the indexes is left out, see `index`.
+    fn scan(
+        &self,
+        from: Bound<&[u8]>,
+        until: Bound<&[u8]>,
+        reverse: bool,
+        limit: usize,
+        read_opts: &ReadOptions,
+    ) -> Result<Vec<KeyValue>, DBError> {
+        let until = match until {
+            _ if self.opts.indexes.is_empty() => until,
+            Bound::Included(key) | Bound::Excluded(key) if key < INDEX_KEY_PREFIX => until,
+            _ => Bound::Excluded(INDEX_KEY_PREFIX),
+        };
+        self.scan_keyspace(from, until, reverse, limit, read_opts)
+    }
+
+    /// `scan` over every key, index entries included. The MemTables and every table overlapping the range
+    /// are merged newest first, so as in `get_raw` the latest version of a key wins and is dropped if it is
+    /// a tombstone or a range tombstone deletes it.
+    fn scan_keyspace(
+        &self,
+        from: Bound<&[u8]>,
+        until: Bound<&[u8]>,
+        reverse: bool,
+        limit: usize,
+        read_opts: &ReadOptions,
+    ) -> Result<Vec<KeyValue>, DBError> {
+        let read_seq_no = read_opts.read_seq_no();
+        // Taken before the tables, see `get_entry`
+        let frozen = self.pending_flush.get();
+        let readers = self
+            .versions()
+            .ss_meta
+            .iter()
+            .filter(|meta| {
+                overlaps_bounds(self.comparator(), meta, from, until)
+                    && meta.min_seq_no() < read_seq_no
+            })
+            .map(|meta| self.table(meta, read_opts))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let range_tombstones: Vec<RangeTombstone> = self
+            .mem_range_tombstones
+            .iter()
+            .chain(frozen.iter().flat_map(|frozen| &frozen.range_tombstones))
+            .chain(readers.iter().flat_map(|reader| reader.range_tombstones()))
+            .filter(|tombstone| tombstone.seq_no < read_seq_no)
+            .cloned()
+            .collect();
+        // Every source skips what the snapshot doesn't see on its own, the merge would otherwise drop the
+        // older versions it reads. The entries the MemTables overwrote that it still reads are scanned
+        // alongside them. A MemTable split into several runs, e.g. a `ShardedMemTable`, is merged run by
+        // run
+        let superseded: Vec<Box<dyn MemTableRep>> = std::iter::once(&self.mem_superseded)
+            .chain(frozen.iter().map(|frozen| &frozen.superseded))
+            .filter(|superseded| !superseded.is_empty())
+            .map(|superseded| superseded.read_at(read_seq_no, self.opts.comparator.clone()))
+            .collect();
+        let mem_tables = std::iter::once(self.mem_table.as_ref())
+            .chain(frozen.iter().map(|frozen| frozen.mem_table.as_ref()))
+            .chain(superseded.iter().map(Box::as_ref));
+        let mut sources: Vec<Box<dyn EntryIterator + '_>> = mem_tables
+            .flat_map(|mem_table| mem_table.runs())
+            .map(|run| {
+                let iter = MemTableIterator::new(run);
+                Box::new(SnapshotIterator::new(iter, read_seq_no)) as Box<dyn EntryIterator>
+            })
+            .collect();
+        sources.extend(readers.iter().map(|reader| {
+            let iter = reader.iter_with_checksums(read_opts.verify_checksums);
+            Box::new(SnapshotIterator::new(iter, read_seq_no)) as Box<dyn EntryIterator>
+        }));
+        let mut iter = RangeDeletionIterator::new(
+            MergingIterator::new(sources, self.comparator()),
+            &range_tombstones,
+            self.comparator(),
+        );
+
+        // Starting from whichever end of the range is read first
+        let step =
+            |iter: &mut RangeDeletionIterator<_>| if reverse { iter.prev() } else { iter.next() };
+        let (start, in_range) = match reverse {
+            false => (from, (Bound::Unbounded, until)),
+            true => (until, (from, Bound::Unbounded)),
+        };
+        match (start, reverse) {
+            (Bound::Included(key) | Bound::Excluded(key), false) => iter.seek(key)?,
+            (Bound::Included(key) | Bound::Excluded(key), true) => iter.seek_for_prev(key)?,
+            (Bound::Unbounded, false) => iter.seek_to_first()?,
+            (Bound::Unbounded, true) => iter.seek_to_last()?,
+        }
+        if let Bound::Excluded(key) = start
+            && iter.valid()
+            && self.comparator().compare(iter.key(), key).is_eq()
+        {
+            step(&mut iter)?;
+        }
+
+        let now = self.now_millis();
+        let mut batch = Vec::new();
+        while batch.len() < limit
+            && let Some(entry) = iter.entry()
+            && comparator::in_bounds(self.comparator(), iter.key(), in_range.0, in_range.1)
+        {
+            match entry {
+                Entry::Value { .. } if entry.is_expired(now) => {}
+                Entry::Value { val, .. } => batch.push((iter.key().to_vec(), val.clone())),
+                // Folded into the older versions of the key, which the merge skipped
+                Entry::Merge { .. } => {
+                    if let Some(val) = self.get_entry(iter.key(), read_opts)?.and_then(entry_value)
+                    {
+                        batch.push((iter.key().to_vec(), val));
+                    }
+                }
+                Entry::Tombstone { .. } => {}
+            }
+            step(&mut iter)?;
+        }
+        Ok(batch)
+    }
+
+    /// Returns the `TableProperties` of every live SSTable, newest first, showing how entries, tombstones
+    /// and bytes are spread across tables and levels.
+    pub fn table_properties(&self) -> Result<Vec<TableProperties>, DBError> {
+        self.versions()
+            .ss_meta
+            .iter()
+            .map(|meta| {
+                let mut props = self.table_cache.get(meta)?.properties().clone();
+                // A trivial move changes the level without rewriting the table
+                props.level = meta.level();
+                Ok(props)
+            })
+            .collect()
+    }
+
+    /// What the WAL replay brought back when the DB was opened: the records replayed and skipped, and the
+    /// highest seq_no found. Empty when replay on load is disabled.
+    pub fn wal_replay_report(&self) -> &ReplayReport {
+        &self.wal_replay
+    }
+
+    /// Reports how the live SSTables are spread across levels and how much flushing and compacting has
+    /// cost so far, see `CompactionStats`.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        let versions = self.versions();
+        versions.stats.clone().with_tables(&versions.ss_meta)
+    }
+
+    /// Lays out the compactions the background worker would run right now, in order, without running
+    /// them. Useful to tell why the tree takes the space it does, or why writes are stalling.
+    pub fn compaction_plan(&self) -> Vec<PlannedCompaction> {
+        let now = self.now_millis() / 1000;
+        let versions = self.versions();
+        compaction::plan(
+            &versions.ss_meta,
+            &self.opts.compaction_options(),
+            versions.manifest.next_file_no(),
+            now,
+        )
+    }
+
+    /// Verifies every live SSTable, see `SSTableReader::verify`. Returns one report per table, newest first.
+    pub fn verify_all(&self) -> Result<Vec<TableVerifyReport>, DBError> {
+        self.versions()
+            .ss_meta
+            .iter()
+            .map(|meta| self.table_cache.get(meta)?.verify())
+            .collect()
+    }
+
+    /// Compacts every SSTable overlapping the inclusive range `[start, end]` down to the last level, flushing
+    /// the MemTable first so its writes are included. Shadowed versions within the range are dropped on the
+    /// way, but for those a live `Snapshot` still reads, which reclaims the space of overwritten values
+    /// without waiting for the automatic triggers.
+    ///
+    /// Blocks until done. A `start` after `end` is an empty range and compacts nothing. Not supported with
+    /// `CompactionStyle::Fifo`, which never merges tables.
+    pub fn compact_range<K: Encode>(&mut self, start: &K, end: &K) -> Result<(), DBError> {
+        if self.opts.compaction_style != CompactionStyle::Leveled {
+            return Err(DBError::InvalidConfig {
+                what: "compact_range needs CompactionStyle::Leveled",
+            });
+        }
+
+        let (start, end) = (start.encode(), end.encode());
+        if self.comparator().compare(&start, &end).is_gt() {
+            return Ok(());
+        }
+
+        self.flush_mem_table()?;
+        self.background.compact_range(start, end)
+    }
+
+    /// Blocks until every flush and compaction scheduled so far has run. Only waits for the flushes while
+    /// compactions are paused.
+    pub fn wait_for_compactions(&self) {
+        self.background.wait();
+    }
+
+    /// Stops compactions from running, e.g. to quiesce disk I/O while a filesystem snapshot is taken. Blocks
+    /// until the compaction in flight, if any, has finished, no compaction runs after that until
+    /// `resume_compactions` is called once for every pause. Flushes still happen, so writes keep going
+    /// until the write stall limits kick in, and `compact_range` fails with `DBError::Busy` meanwhile.
+    pub fn pause_compactions(&self) {
+        self.background.pause();
+    }
+
+    /// Undoes a `pause_compactions`, compactions scheduled in the meantime run once every pause is undone.
+    pub fn resume_compactions(&self) {
+        self.background.resume();
+    }
+
+    /// Returns the first error a background compaction failed with since the last call, if any. A failed
+    /// compaction leaves its inputs in place, so no data is lost, but the tree stays over its limits until a
+    /// later compaction succeeds.
+    pub fn take_background_error(&self) -> Option<DBError> {
+        self.background.take_error()
+    }
+
+    /// Waits for the background work in flight to finish and shuts the worker down, along with those of the
+    /// column families, surfacing any error they ran into. Dropping the DB does the same but has to swallow
+    /// the error.
+    pub fn close(mut self) -> Result<(), DBError> {
+        self.background.shutdown();
+        let mut result = match self.background.take_error() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+        // Every column family is closed, the first error wins
+        for family in std::mem::take(&mut self.column_families) {
+            result = result.and(family.db.close());
+        }
+        result
+    }
+
+    /// Flushes the MemTable once it holds `memtable_max_size` entries, range tombstones and the entries
+    /// set aside for snapshots included, or
+    /// `write_buffer_size` bytes. With both set to `None` the MemTable is never flushed.
+    fn maybe_flush_mem_table(&mut self) -> Result<(), DBError> {
+        let len =
+            self.mem_table.len() + self.mem_superseded.len() + self.mem_range_tombstones.len();
+        let size = self.mem_table.size()
+            + self.mem_superseded.size()
+            + self
+                .mem_range_tombstones
+                .iter()
+                .map(|tombstone| tombstone.start.len() + tombstone.end.len())
+                .sum::<usize>();
+        let full = self
+            .opts
+            .memtable_max_size
+            .is_some_and(|max_size| len >= max_size as usize)
+            || self
+                .opts
+                .write_buffer_size
+                .is_some_and(|max_bytes| size as u64 >= max_bytes);
+        if full {
+            return self.freeze_mem_table();
+        }
+        Ok(())
+    }
+
+    /// Flushes the MemTable and waits for its table to be installed.
+    fn flush_mem_table(&mut self) -> Result<(), DBError> {
+        self.freeze_mem_table()?;
+        self.pending_flush.wait()
+    }
+
+    /// Freezes the MemTable, along with its range tombstones, and swaps in an empty one so writes go on
+    /// while the background worker flushes the frozen one, see `flush::flush`. The WAL moves on to a new
+    /// segment, so the segments holding the frozen writes can be truncated once flushed.
+    ///
+    /// Only one MemTable is frozen at a time, a MemTable filling up before the last one is flushed waits
+    /// for it. If that flush failed its error is returned and the flush retried, writes are then kept in
+    /// the active MemTable until it succeeds.
+    fn freeze_mem_table(&mut self) -> Result<(), DBError> {
+        if self.mem_table.is_empty() && self.mem_range_tombstones.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self.pending_flush.wait() {
+            self.background.schedule(Job::Flush);
+            return Err(e);
+        }
+
+        let wal_segment_no = self.wal.start_segment()?;
+        self.wal.freeze_hold(self.wal_owner);
+        let mem_table = std::mem::replace(&mut self.mem_table, self.opts.new_mem_table());
+        self.pending_flush.set(ImmutableMemTable {
+            mem_table,
+            superseded: std::mem::take(&mut self.mem_superseded),
+            range_tombstones: std::mem::take(&mut self.mem_range_tombstones),
+            wal_segment_no,
+        });
+        self.background.schedule(Job::Flush);
+
+        Ok(())
+    }
+
+    /// Blocks until the MemTable frozen for flushing, if any, has been flushed, returning the error its
+    /// flush failed with. A failed flush is retried on the next write that fills the MemTable.
+    pub fn wait_for_flush(&self) -> Result<(), DBError> {
+        self.pending_flush.wait()
+    }
+
+    fn versions(&self) -> MutexGuard<'_, VersionSet> {
+        version::lock(&self.versions)
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.opts.clock.now_millis()
+    }
+
+    /// The order the DB keeps its keys in, see `DBConfig::comparator`.
+    pub(crate) fn comparator(&self) -> &dyn Comparator {
+        self.opts.comparator.as_ref()
+    }
+}
+
+/// A value that is already encoded, written as is.
+struct RawValue<'a>(&'a [u8]);
+
+impl Encode for RawValue<'_> {
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+/// The value `entry` holds, none for a tombstone. The entries `get_entry` finds have their merge operands
+/// folded into values already.
+fn entry_value(entry: Entry) -> Option<Vec<u8>> {
+    match entry {
+        Entry::Value { val, .. } => Some(val),
+        Entry::Tombstone { .. } | Entry::Merge { .. } => None,
+    }
+}
+
+/// The smallest key greater than every key starting with `prefix`, `None` if there is none, i.e. when
+/// `prefix` is all 0xff bytes.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+/// Whether the key range of `meta` overlaps the range within `from` and `until`.
+fn overlaps_bounds(
+    comparator: &dyn Comparator,
+    meta: &SSTableMeta,
+    from: Bound<&[u8]>,
+    until: Bound<&[u8]>,
+) -> bool {
+    comparator::in_bounds(comparator, meta.largest_key(), from, Bound::Unbounded)
+        && comparator::before(comparator, meta.smallest_key(), until)
+}
+
+/// `entry`, unless a range tombstone at `deleted_at` is newer.
+fn visible_entry(entry: Entry, deleted_at: Option<u64>) -> Option<Entry> {
+    if deleted_at.is_some_and(|deleted_at| entry.seq_no() < deleted_at) {
+        return None;
+    }
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::comparator::ReverseBytewiseComparator;
+    use crate::listener::FlushJobInfo;
+    use crate::sstable::SSTableWriter;
+    use crate::typed::TypedDB;
+    use crate::wal::SEGMENT_HEADER_LEN;
+
+    const TEST_DATA_DIR: &str = "test_data";
+    const SS_TABLE_DIR: &str = "sstb";
+    const WAL_DIR: &str = "wal";
+
+    type TestEncoder = String;
+
+    fn test_default_config(wal_file_name: &str, preserve_wal: bool) -> DBConfig {
+        let mut ss_table_path = PathBuf::new();
+        ss_table_path.push(TEST_DATA_DIR);
+        ss_table_path.push(SS_TABLE_DIR);
+        ss_table_path.push(wal_file_name);
+
+        let mut wal_path = PathBuf::new();
+        wal_path.push(TEST_DATA_DIR);
+        wal_path.push(WAL_DIR);
+        wal_path.push(wal_file_name);
+
+        if !preserve_wal {
+            let _ = std::fs::remove_dir_all(&wal_path);
+            let _ = std::fs::remove_dir_all(&ss_table_path);
+        }
+
+        DBConfig {
+            memtable_max_size: Some(1000),
+            write_buffer_size: Some(DEFAULT_WRITE_BUFFER_SIZE),
+            memtable_kind: MemTableKind::default(),
+            memtable_bloom_false_positive_rate: None,
+            record_write_time: false,
+            clock: Arc::new(SystemClock),
+            comparator: Arc::new(BytewiseComparator),
+            ss_table_dir: ss_table_path,
+            wal_dir: wal_path,
+            wal_segment_size: DEFAULT_WAL_SEGMENT_SIZE,
+            wal_preallocate: false,
+            wal_recycle_files: 0,
+            wal_bytes_per_sync: 0,
+            wal_archive: None,
+            wal_compression: CompressionType::None,
+            wal_encryptor: None,
+            wal_sync_policy: SyncPolicy::Always,
+            compaction_style: CompactionStyle::default(),
+            ss_l0_compact_threshold: 1000,
+            ss_l0_intra_compact_threshold: None,
+            level_base_size: DEFAULT_LEVEL_BASE_SIZE,
+            level_multiplier: DEFAULT_LEVEL_MULTIPLIER,
+            compaction_picker: None,
+            compaction_filter: None,
+            merge_operator: None,
+            max_subcompactions: 1,
+            target_file_size: Some(DEFAULT_TARGET_FILE_SIZE),
+            periodic_compaction_age: None,
+            l0_slowdown_writes_trigger: None,
+            soft_pending_compaction_bytes_limit: None,
+            write_stall_delay: DEFAULT_WRITE_STALL_DELAY,
+            l0_stop_writes_trigger: None,
+            hard_pending_compaction_bytes_limit: None,
+            max_record_len: DEFAULT_MAX_RECORD_LEN,
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            compression: CompressionType::None,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            checksum: ChecksumType::Crc32,
+            ss_table_read_mode: SSTableReadMode::default(),
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            verify_ss_tables_on_open: false,
+            on_flush_progress: None,
+            on_wal_replay_progress: None,
+            event_listeners: Vec::new(),
+            column_families: HashMap::new(),
+            column_family_wal: ColumnFamilyWal::Separate,
+            indexes: Vec::new(),
+            disable_wal_memtable_replay_on_load: false,
+        }
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut db = DB::new(Some(test_default_config("insert_and_get", false))).unwrap();
+
+        let key: TestEncoder = "key-1".to_string();
+        let val: TestEncoder = "some-val".to_string();
+
+        assert_eq!(db.next_seq_no, 0);
+
+        db.put(&key, &val).unwrap();
+
+        assert_eq!(db.mem_table.len(), 1);
+        assert_eq!(db.next_seq_no, 1);
+
+        let kbytes = key.encode();
+        let vbytes = val.encode();
+
+        assert_eq!(
+            db.mem_table.get(kbytes.as_slice()),
+            Some(&Entry::Value {
+                seq_no: 0,
+                val: vbytes,
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+
+        // Add Next Key
+        {
+            let key_2: TestEncoder = "key-2".to_string();
+            let val_2: TestEncoder = "some-val_2".to_string();
+
+            assert_eq!(db.next_seq_no, 1);
+
+            db.put(&key_2, &val_2).unwrap();
+
+            assert_eq!(db.mem_table.len(), 2);
+            assert_eq!(db.next_seq_no, 2);
+
+            let kbytes = key_2.encode();
+            let vbytes = val_2.encode();
+
+            assert_eq!(
+                db.mem_table.get(kbytes.as_slice()),
+                Some(&Entry::Value {
+                    seq_no: 1,
+                    val: vbytes,
+                    timestamp: None,
+                    expires_at: None,
+                })
+            )
+        }
+    }
+
+    #[test]
+    fn insert_empty_key() {
+        let mut db = DB::new(Some(test_default_config("insert_empty_key", false))).unwrap();
+
+        let key: TestEncoder = "".to_string();
+        let val: TestEncoder = "s1".to_string();
+
+        let res = db.put(&key, &val);
+
+        assert!(matches!(
+            res.err(),
+            Some(DBError::Codec {
+                context: _,
+                source: _
+            })
+        ));
+    }
+
+    #[test]
+    fn insert_duplicate_and_get() {
+        let mut db = DB::new(Some(test_default_config("insert_duplicate_and_get", false))).unwrap();
+
+        let key: TestEncoder = "k1".to_string();
+        let val: TestEncoder = "s1".to_string();
+
+        db.put(&key, &val).unwrap();
+
+        let kbytes = key.encode();
+        let vbytes = val.encode();
+
+        assert_eq!(
+            db.mem_table.get(kbytes.as_slice()),
+            Some(&Entry::Value {
+                seq_no: 0,
+                val: vbytes,
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+
+        //WHEN: Add duplicate key
+        {
+            let dup_key: TestEncoder = "k1".to_string();
+            let val_2: TestEncoder = "s2".to_string();
+
+            db.put(&dup_key, &val_2).unwrap();
+
+            assert_eq!(db.next_seq_no, 2);
+            assert_eq!(db.mem_table.len(), 1);
+
+            let dup_key_bytes = dup_key.encode();
+            let val_bytes = val_2.encode();
+
+            assert_eq!(
+                db.mem_table.get(&dup_key_bytes),
+                Some(&Entry::Value {
+                    seq_no: 1,
+                    val: val_bytes.clone(),
+                    timestamp: None,
+                    expires_at: None,
+                })
+            );
+            assert_eq!(
+                db.mem_table.get(&kbytes),
+                Some(&Entry::Value {
+                    seq_no: 1,
+                    val: val_bytes,
+                    timestamp: None,
+                    expires_at: None,
+                })
+            )
+        }
+    }
+
+    #[test]
+    fn delete_empty() {
+        // An empty key is turned away before anything is logged, so db.seq_no does not move
+        let mut db = DB::new(Some(test_default_config("delete_empty", false))).unwrap();
+
+        let res = db.delete(&TestEncoder::new());
+
+        assert!(matches!(res.err(), Some(DBError::Codec { .. })));
+        assert_eq!(db.next_seq_no, 0);
+        assert!(db.mem_table.is_empty());
+    }
+
+    #[test]
+    fn delete_empty_key() {
+        let mut db = DB::new(Some(test_default_config("delete_empty_key", false))).unwrap();
+        let key: TestEncoder = "k1".to_string();
+        db.put(&key, &"s1".to_string()).unwrap();
+
+        let res = db.delete(&"".to_string());
+
+        assert!(matches!(res.err(), Some(DBError::Codec { .. })));
+        assert_eq!(db.next_seq_no, 1);
+        assert_eq!(db.mem_table.len(), 1);
+        assert_eq!(db.get_raw(&key).unwrap(), Some(b"s1".to_vec()));
+    }
+
+    #[test]
+    fn delete_on_key_that_doesnt_exist() {
+        // Deletes are blind: telling whether the key exists would take a search of every SSTable, so a
+        // tombstone is written regardless, shadowing any value of the key a table may still hold
+        let mut db = DB::new(Some(test_default_config(
+            "delete_on_key_that_doesnt_exist",
+            false,
+        )))
+        .unwrap();
+        let key: TestEncoder = "missing".to_string();
+
+        db.delete(&key).unwrap();
+
+        assert_eq!(db.next_seq_no, 1);
+        assert_eq!(
+            db.mem_table.get(&key.encode()),
+            Some(&Entry::Tombstone { seq_no: 0 })
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), None);
+    }
+
+    #[test]
+    fn delete_ok() {
+        let mut db = DB::new(Some(test_default_config("delete_ok", false))).unwrap();
+        let key: TestEncoder = "k1".to_string();
+        let other: TestEncoder = "k2".to_string();
+        db.put(&key, &"s1".to_string()).unwrap();
+        db.put(&other, &"s2".to_string()).unwrap();
+
+        db.delete(&key).unwrap();
+
+        // The tombstone replaces the value in the MemTable rather than sitting next to it
+        assert_eq!(db.next_seq_no, 3);
+        assert_eq!(db.mem_table.len(), 2);
+        assert_eq!(
+            db.mem_table.get(&key.encode()),
+            Some(&Entry::Tombstone { seq_no: 2 })
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), None);
+        assert_eq!(db.get_raw(&other).unwrap(), Some(b"s2".to_vec()));
+    }
+
+    #[test]
+    fn insert_delete_insert_ok() {
+        let name = "insert_delete_insert_ok";
+        let mut opts = test_default_config(name, false);
+        // Every other write flushes, so the key goes back and forth between the MemTable and the tables
+        opts.memtable_max_size = Some(2);
+        let mut db = DB::new(Some(opts)).unwrap();
+        let key: TestEncoder = "k1".to_string();
+        let get = |db: &DB| db.get_typed::<TestEncoder, TestEncoder>(&key).unwrap();
+
+        db.put(&key, &"s1".to_string()).unwrap();
+        db.delete(&key).unwrap();
+        assert_eq!(get(&db), None);
+        db.put(&key, &"s2".to_string()).unwrap();
+        assert_eq!(get(&db), Some("s2".to_string()));
+        db.delete(&key).unwrap();
+        db.wait_for_flush().unwrap();
+        assert_eq!(get(&db), None);
+        db.put(&key, &"s3".to_string()).unwrap();
+        assert_eq!(get(&db), Some("s3".to_string()));
+        drop(db);
+
+        // Replay brings back the same result
+        let db = DB::new(Some(test_default_config(name, true))).unwrap();
+        assert_eq!(get(&db), Some("s3".to_string()));
+    }
+
+    fn write_ss_table(
+        name: &str,
+        file_no: u64,
+        level: u32,
+        entries: &[(&str, Entry)],
+    ) -> SSTableMeta {
+        let mut path = PathBuf::from(TEST_DATA_DIR);
+        path.push(SS_TABLE_DIR);
+        std::fs::create_dir_all(&path).unwrap();
+        path.push(format!("{name}_{file_no}.sst"));
+
+        let mut writer = SSTableWriter::new(path, file_no, level).unwrap();
+        for (key, entry) in entries {
+            writer.add(key.as_bytes(), entry).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn get_falls_back_to_ss_tables() {
+        let mut db = DB::new(Some(test_default_config(
+            "get_falls_back_to_ss_tables",
+            false,
+        )))
+        .unwrap();
+
+        let value = |seq_no, val: &str| Entry::Value {
+            seq_no,
+            val: val.as_bytes().to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+
+        let older = write_ss_table(
+            "get_falls_back_to_ss_tables",
+            1,
+            0,
+            &[
+                ("a", value(0, "a-old")),
+                ("b", value(1, "b-old")),
+                ("c", value(2, "c-old")),
+            ],
+        );
+        let newer = write_ss_table(
+            "get_falls_back_to_ss_tables",
+            2,
+            0,
+            &[
+                ("b", value(3, "b-new")),
+                ("c", Entry::Tombstone { seq_no: 4 }),
+            ],
+        );
+        db.versions().install(older);
+        db.versions().install(newer);
+
+        let get = |db: &DB, key: &str| {
+            db.get_typed::<TestEncoder, TestEncoder>(&key.to_string())
+                .unwrap()
+        };
+
+        assert_eq!(get(&db, "a"), Some("a-old".to_string()));
+        assert_eq!(get(&db, "b"), Some("b-new".to_string()));
+        // Tombstone in the newer table shadows the older value
+        assert_eq!(get(&db, "c"), None);
+        assert_eq!(get(&db, "d"), None);
+
+        // MemTable shadows every table
+        db.put(&"a".to_string(), &"a-mem".to_string()).unwrap();
+        assert_eq!(get(&db, "a"), Some("a-mem".to_string()));
+    }
+
+    #[test]
+    fn get_skips_ss_tables_outside_their_key_range() {
+        let name = "get_skips_ss_tables_outside_their_key_range";
+        let db = DB::new(Some(test_default_config(name, false))).unwrap();
+
+        let value = |seq_no, val: &str| Entry::Value {
+            seq_no,
+            val: val.as_bytes().to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+        db.versions().install(write_ss_table(
+            name,
+            1,
+            0,
+            &[("a", value(0, "a")), ("c", value(1, "c"))],
+        ));
+        db.versions().install(write_ss_table(
+            name,
+            2,
+            0,
+            &[("x", value(2, "x")), ("z", value(3, "z"))],
+        ));
+
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"d".to_string())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"y".to_string())
+                .unwrap(),
+            None
+        );
+        // Only the table whose range covers "y" was ever opened, "d" falls between both tables
+        assert!(!db.table_cache.contains(1));
+        assert!(db.table_cache.contains(2));
+    }
+
+    #[test]
+    fn flush_reports_progress() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let mut opts = test_default_config("flush_reports_progress", false);
+        opts.memtable_max_size = Some(3);
+        let flushed_entries = Arc::new(AtomicU64::new(0));
+        let counter = flushed_entries.clone();
+        opts.on_flush_progress = Some(Arc::new(move |progress: &WriterProgress| {
+            counter.store(progress.entries, Ordering::SeqCst);
+        }));
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for i in 0..2 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+        assert_eq!(flushed_entries.load(Ordering::SeqCst), 0);
+        db.put(&"key-2".to_string(), &"val-2".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+        assert_eq!(flushed_entries.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn flush_mem_table_when_full() {
+        let mut opts = test_default_config("flush_mem_table_when_full", false);
+        opts.memtable_max_size = Some(3);
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for i in 0..7 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 2);
+        assert_eq!(db.mem_table.len(), 1);
+        // Newest table first
+        assert_eq!(db.versions().ss_meta[0].file_no(), 2);
+        assert_eq!(db.versions().ss_meta[0].smallest_key(), b"key-3");
+        assert_eq!(db.versions().ss_meta[0].largest_key(), b"key-5");
+
+        for i in 0..7 {
+            assert_eq!(
+                db.get_typed::<TestEncoder, TestEncoder>(&format!("key-{i}"))
+                    .unwrap(),
+                Some(format!("val-{i}"))
+            );
+        }
+    }
+
+    #[test]
+    fn table_properties_of_live_tables() {
+        let mut opts = test_default_config("table_properties_of_live_tables", false);
+        opts.memtable_max_size = Some(3);
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in 0..5 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+        db.delete(&"key-9".to_string()).unwrap();
+
+        db.wait_for_flush().unwrap();
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 2);
+        // Newest first
+        assert_eq!(props[0].smallest_key, b"key-3");
+        assert_eq!(props[0].largest_key, b"key-9");
+        assert_eq!(props[0].entry_count, 3);
+        assert_eq!(props[0].tombstone_count, 1);
+        assert_eq!(props[1].entry_count, 3);
+        assert_eq!(props[1].tombstone_count, 0);
+        assert!(
+            props
+                .iter()
+                .all(|p| p.level == 0 && p.data_block_count == 1)
+        );
+    }
+
+    #[test]
+    fn verify_all_checks_every_live_table() {
+        let mut opts = test_default_config("verify_all_checks_every_live_table", false);
+        opts.memtable_max_size = Some(2);
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in 0..6 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+
+        db.wait_for_flush().unwrap();
+        let reports = db.verify_all().unwrap();
+        assert_eq!(reports.len(), 3);
+        assert!(reports.iter().all(|report| report.is_ok()));
+        assert_eq!(reports.iter().map(|r| r.entries_checked).sum::<u64>(), 6);
+        assert_eq!(
+            reports[0].path,
+            PathBuf::from(db.versions().ss_meta[0].path())
+        );
+    }
+
+    #[test]
+    fn reopen_loads_ss_tables_from_manifest() {
+        let mut opts = test_default_config("reopen_loads_ss_tables_from_manifest", false);
+        opts.memtable_max_size = Some(2);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for i in 0..4 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 2);
+        drop(db);
+
+        let mut opts = test_default_config("reopen_loads_ss_tables_from_manifest", true);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let db = DB::new(Some(opts)).unwrap();
+
+        assert!(db.mem_table.is_empty());
+        assert_eq!(db.versions().ss_meta.len(), 2);
+        assert_eq!(db.versions().manifest.next_file_no(), 3);
+        assert_eq!(db.versions().ss_meta[0].file_no(), 2);
+        assert_eq!(db.versions().ss_meta[0].smallest_key(), b"key-2");
+        assert_eq!(db.versions().ss_meta[0].largest_key(), b"key-3");
+        // Recovered from the manifest
+        assert_eq!(db.versions().ss_meta[0].min_seq_no(), 2);
+        assert_eq!(db.versions().ss_meta[0].max_seq_no(), 3);
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"key-1".to_string())
+                .unwrap(),
+            Some("val-1".to_string())
+        );
+    }
+
+    #[test]
+    fn l0_compacts_into_l1_once_over_threshold() {
+        let name = "l0_compacts_into_l1_once_over_threshold";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(2);
+        opts.ss_l0_compact_threshold = 2;
+        opts.disable_wal_memtable_replay_on_load = true;
+        let ss_table_dir = opts.ss_table_dir.clone();
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        let get = |db: &DB, key: &str| {
+            db.get_typed::<TestEncoder, TestEncoder>(&key.to_string())
+                .unwrap()
+        };
+
+        db.put(&"a".to_string(), &"a-1".to_string()).unwrap();
+        db.put(&"b".to_string(), &"b-1".to_string()).unwrap();
+        db.put(&"a".to_string(), &"a-2".to_string()).unwrap();
+        db.put(&"c".to_string(), &"c-1".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 2);
+
+        // The third L0 table goes over the threshold
+        db.delete(&"b".to_string()).unwrap();
+        db.put(&"d".to_string(), &"d-1".to_string()).unwrap();
+        db.wait_for_compactions();
+
+        assert_eq!(db.versions().ss_meta.len(), 1);
+        assert_eq!(db.versions().ss_meta[0].level(), 1);
+        assert_eq!(db.versions().ss_meta[0].file_no(), 4);
+        assert_eq!(db.versions().ss_meta[0].smallest_key(), b"a");
+        assert_eq!(db.versions().ss_meta[0].largest_key(), b"d");
+        for file_no in 1..=3 {
+            assert!(
+                !ss_table_dir
+                    .join(crate::sstable::table_file_name(file_no))
+                    .exists()
+            );
+            assert!(!db.table_cache.contains(file_no));
+        }
+
+        assert_eq!(get(&db, "a"), Some("a-2".to_string()));
+        assert_eq!(get(&db, "b"), None);
+        assert_eq!(get(&db, "c"), Some("c-1".to_string()));
+        assert_eq!(get(&db, "d"), Some("d-1".to_string()));
+        drop(db);
+
+        let mut opts = test_default_config(name, true);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 1);
+        assert_eq!(db.versions().ss_meta[0].level(), 1);
+        assert_eq!(get(&db, "a"), Some("a-2".to_string()));
+        assert_eq!(get(&db, "b"), None);
+    }
+
+    #[test]
+    fn levels_are_kept_within_their_target_sizes() {
+        let mut opts = test_default_config("levels_are_kept_within_their_target_sizes", false);
+        opts.memtable_max_size = Some(20);
+        opts.ss_l0_compact_threshold = 1;
+        opts.level_base_size = 2048;
+        opts.level_multiplier = 2;
+        opts.disable_wal_memtable_replay_on_load = true;
+        let picker = opts.leveled_compaction_picker();
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for i in 0..2000 {
+            db.put(&format!("key-{:04}", i % 500), &format!("val-{i}"))
+                .unwrap();
+        }
+
+        db.wait_for_compactions();
+        let versions = db.versions();
+        for level in 1..compaction::NUM_LEVELS {
+            let mut tables: Vec<&SSTableMeta> = versions
+                .ss_meta
+                .iter()
+                .filter(|meta| meta.level() == level)
+                .collect();
+            if let Some(target) = picker.level_target_size(level) {
+                let size: u64 = tables.iter().map(|meta| meta.file_size()).sum();
+                assert!(
+                    size <= target,
+                    "L{level} holds {size} bytes, target {target}"
+                );
+            }
+
+            tables.sort_by(|a, b| a.smallest_key().cmp(b.smallest_key()));
+            for pair in tables.windows(2) {
+                assert!(pair[0].largest_key() < pair[1].smallest_key());
+            }
+        }
+        assert!(versions.ss_meta.iter().any(|meta| meta.level() > 1));
+        drop(versions);
+
+        // The last round of puts wrote val-1500..val-1999
+        for i in 1500..2000 {
+            assert_eq!(
+                db.get_typed::<TestEncoder, TestEncoder>(&format!("key-{:04}", i % 500))
+                    .unwrap(),
+                Some(format!("val-{i}"))
+            );
+        }
+    }
+
+    #[test]
+    fn fifo_compaction_drops_the_oldest_tables() {
+        let name = "fifo_compaction_drops_the_oldest_tables";
+        let open = |preserve: bool, max_total_size: Option<u64>| {
+            let mut opts = test_default_config(name, preserve);
+            opts.memtable_max_size = Some(10);
+            opts.ss_l0_compact_threshold = 1;
+            opts.compaction_style = CompactionStyle::Fifo {
+                max_total_size,
+                max_age: None,
+            };
+            opts.disable_wal_memtable_replay_on_load = true;
+            DB::new(Some(opts)).unwrap()
+        };
+
+        let mut db = open(false, None);
+        for i in 0..10 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+        let table_size = db.versions().ss_meta[0].file_size();
+        drop(db);
+
+        // Later tables hold longer values, leave them some room
+        let mut db = open(true, Some(table_size * 7 / 2));
+        for i in 10..100 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.wait_for_compactions();
+
+        // Never merged, only the newest three tables are left
+        assert_eq!(db.versions().ss_meta.len(), 3);
+        assert!(db.versions().ss_meta.iter().all(|meta| meta.level() == 0));
+        let total_size: u64 = db
+            .versions()
+            .ss_meta
+            .iter()
+            .map(|meta| meta.file_size())
+            .sum();
+        assert!(total_size <= table_size * 7 / 2);
+
+        let get = |key: &str| {
+            db.get_typed::<TestEncoder, TestEncoder>(&key.to_string())
+                .unwrap()
+        };
+        assert_eq!(get("key-000"), None);
+        assert_eq!(get("key-069"), None);
+        assert_eq!(get("key-070"), Some("val-70".to_string()));
+        assert_eq!(get("key-099"), Some("val-99".to_string()));
+    }
+
+    #[test]
+    fn compact_range_pushes_the_range_to_the_last_level() {
+        let mut opts =
+            test_default_config("compact_range_pushes_the_range_to_the_last_level", false);
+        opts.memtable_max_size = Some(10);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for round in 0..3 {
+            for i in 0..20 {
+                db.put(&format!("key-{i:03}"), &format!("val-{i}-{round}"))
+                    .unwrap();
+            }
+        }
+        db.put(&"key-100".to_string(), &"val-100".to_string())
+            .unwrap();
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 6);
+
+        db.compact_range(&"key-005".to_string(), &"key-010".to_string())
+            .unwrap();
+
+        let last_level = compaction::NUM_LEVELS - 1;
+        assert!(db.mem_table.is_empty());
+        {
+            let versions = db.versions();
+            assert!(
+                versions
+                    .ss_meta
+                    .iter()
+                    .all(|meta| meta.level() == last_level)
+            );
+            // Every L0 table overlapped the range, so the shadowed versions are all gone
+            assert_eq!(versions.ss_meta.len(), 1);
+        }
+        let props = db.table_properties().unwrap();
+        assert_eq!(props[0].entry_count, 21);
+
+        for i in 0..20 {
+            assert_eq!(
+                db.get_typed::<TestEncoder, TestEncoder>(&format!("key-{i:03}"))
+                    .unwrap(),
+                Some(format!("val-{i}-2"))
+            );
+        }
+
+        // An empty range is a no-op
+        db.compact_range(&"z".to_string(), &"a".to_string())
+            .unwrap();
+    }
+
+    #[test]
+    fn reverse_comparator_orders_keys_largest_first() {
+        let name = "reverse_comparator_orders_keys_largest_first";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(10);
+        opts.comparator = Arc::new(ReverseBytewiseComparator);
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        let keys = |items: Vec<KeyValue>| -> Vec<String> {
+            items
+                .into_iter()
+                .map(|(key, _)| String::from_utf8(key).unwrap())
+                .collect()
+        };
+        let expected: Vec<String> = [19, 18, 17, 16, 5, 4, 3, 2, 1, 0]
+            .iter()
+            .map(|i| format!("key-{i:03}"))
+            .collect();
+
+        for i in 0..20 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+        assert!(!db.versions().ss_meta.is_empty());
+        // The range starts at the larger key, the other way around it is empty
+        db.delete_range(&"key-005".to_string(), &"key-015".to_string())
+            .unwrap();
+        assert!(db.mem_range_tombstones.is_empty());
+        db.delete_range(&"key-015".to_string(), &"key-005".to_string())
+            .unwrap();
+        assert_eq!(keys(db.iter().collect::<Result<_, _>>().unwrap()), expected);
+
+        db.compact_range(&"key-019".to_string(), &"key-000".to_string())
+            .unwrap();
+        assert_eq!(
+            db.table_properties().unwrap()[0].comparator_name,
+            "lsmdb.ReverseBytewiseComparator"
+        );
+        assert_eq!(keys(db.iter().collect::<Result<_, _>>().unwrap()), expected);
+        let ranged = db
+            .range("key-017".to_string().."key-003".to_string())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(keys(ranged), ["key-017", "key-016", "key-005", "key-004"]);
+        let prefixed = db
+            .scan_prefix(&"key-01".to_string())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(keys(prefixed), ["key-019", "key-018", "key-017", "key-016"]);
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"key-004".to_string())
+                .unwrap(),
+            Some("val-4".to_string())
+        );
+        drop(db);
+
+        // The order is part of the data, the DB only reopens under the comparator it was written with
+        assert!(matches!(
+            DB::new(Some(test_default_config(name, true))),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        let mut opts = test_default_config(name, true);
+        opts.comparator = Arc::new(ReverseBytewiseComparator);
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(keys(db.iter().collect::<Result<_, _>>().unwrap()), expected);
+    }
+
+    #[test]
+    fn delete_range_hides_older_keys_until_compacted_away() {
+        let mut opts =
+            test_default_config("delete_range_hides_older_keys_until_compacted_away", false);
+        opts.memtable_max_size = Some(10);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        let get = |db: &DB, i: u32| {
+            db.get_typed::<TestEncoder, TestEncoder>(&format!("key-{i:03}"))
+                .unwrap()
+        };
+        let expect_deleted = |db: &DB| {
+            assert_eq!(get(db, 4), Some("val-4".to_string()));
+            assert_eq!(get(db, 5), None);
+            assert_eq!(get(db, 9), None);
+            assert_eq!(get(db, 10), Some("val-10-new".to_string()));
+            assert_eq!(get(db, 14), None);
+            assert_eq!(get(db, 15), Some("val-15".to_string()));
+        };
+
+        for i in 0..20 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 2);
+
+        db.delete_range(&"key-005".to_string(), &"key-015".to_string())
+            .unwrap();
+        // Written after the tombstone, so it stays
+        db.put(&"key-010".to_string(), &"val-10-new".to_string())
+            .unwrap();
+        expect_deleted(&db);
+
+        db.flush_mem_table().unwrap();
+        assert!(db.mem_range_tombstones.is_empty());
+        assert_eq!(db.table_properties().unwrap()[0].range_deletion_count, 1);
+        expect_deleted(&db);
+
+        // At the last level the tombstone has nothing left to delete and goes too
+        db.compact_range(&"key-000".to_string(), &"key-019".to_string())
+            .unwrap();
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].entry_count, 11);
+        assert_eq!(props[0].range_deletion_count, 0);
+        expect_deleted(&db);
+
+        // An empty range deletes nothing
+        db.delete_range(&"key-019".to_string(), &"key-000".to_string())
+            .unwrap();
+        assert!(db.mem_range_tombstones.is_empty());
+        assert!(matches!(
+            db.delete_range(&String::new(), &"key-000".to_string()),
+            Err(DBError::Codec { .. })
+        ));
+    }
+
+    #[test]
+    fn compaction_output_is_split_at_target_file_size() {
+        let mut opts = test_default_config("compaction_output_is_split_at_target_file_size", false);
+        opts.memtable_max_size = Some(100);
+        opts.target_file_size = Some(1024);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for i in 0..300 {
+            db.put(
+                &format!("key-{i:03}"),
+                &format!("val-{i:03}-{}", "x".repeat(32)),
+            )
+            .unwrap();
+        }
+        db.delete_range(&"key-100".to_string(), &"key-200".to_string())
+            .unwrap();
+        // Rewritten after the tombstone, so the outputs are cut within its range
+        for i in 150..200 {
+            db.put(
+                &format!("key-{i:03}"),
+                &format!("new-{i:03}-{}", "x".repeat(32)),
+            )
+            .unwrap();
+        }
+        db.flush_mem_table().unwrap();
+        // A live snapshot keeps the range tombstone around at the last level
+        db.versions().snapshots.insert(0, 1);
+
+        db.compact_range(&"key-000".to_string(), &"key-299".to_string())
+            .unwrap();
+
+        let mut tables = db.versions().ss_meta.clone();
+        assert!(tables.len() > 2);
+        tables.sort_by(|a, b| a.smallest_key().cmp(b.smallest_key()));
+        for pair in tables.windows(2) {
+            assert!(pair[0].largest_key() <= pair[1].smallest_key());
+        }
+        let (last, full) = tables.split_last().unwrap();
+        assert!(full.iter().all(|meta| meta.file_size() >= 1024));
+        assert_eq!(last.largest_key(), b"key-299");
+
+        // The tombstone was cut up along with the tables it spans
+        let props = db.table_properties().unwrap();
+        assert_eq!(
+            props.iter().map(|props| props.entry_count).sum::<u64>(),
+            250
+        );
+        assert!(
+            props
+                .iter()
+                .map(|props| props.range_deletion_count)
+                .sum::<u64>()
+                > 1
+        );
+        for i in [0, 99, 100, 149, 150, 199, 200, 299] {
+            let val = db
+                .get_typed::<TestEncoder, TestEncoder>(&format!("key-{i:03}"))
+                .unwrap();
+            assert_eq!(val.is_some(), !(100..150).contains(&i), "key-{i:03}");
+        }
+    }
+
+    #[test]
+    fn event_listeners_see_flushes_and_compactions() {
+        #[derive(Debug, Default)]
+        struct Recorder {
+            events: Mutex<Vec<String>>,
+        }
+
+        impl Recorder {
+            fn record(&self, event: String) {
+                self.events.lock().unwrap().push(event);
+            }
+        }
+
+        impl EventListener for Recorder {
+            fn on_flush_start(&self, info: &FlushJobInfo) {
+                self.record(format!("flush_start {} {}", info.file_no, info.entries));
+            }
+
+            fn on_flush_finish(&self, info: &FlushJobInfo) {
+                let table = info.table.as_ref().unwrap();
+                self.record(format!("flush_finish {}", table.file_no()));
+            }
+
+            fn on_flush_error(&self, info: &FlushJobInfo, _error: &DBError) {
+                self.record(format!("flush_error {}", info.file_no));
+            }
+
+            fn on_compaction_start(&self, info: &listener::CompactionJobInfo) {
+                assert!(info.outputs.is_empty());
+                self.record(format!(
+                    "compaction_start {:?} {}",
+                    info.reason,
+                    info.inputs.len()
+                ));
+            }
+
+            fn on_compaction_finish(&self, info: &listener::CompactionJobInfo) {
+                self.record(format!(
+                    "compaction_finish L{} {} {}",
+                    info.output_level,
+                    info.outputs.len(),
+                    info.trivial_move
+                ));
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let mut opts = test_default_config("event_listeners_see_flushes_and_compactions", false);
+        opts.memtable_max_size = Some(2);
+        opts.ss_l0_compact_threshold = 1;
+        opts.event_listeners = vec![recorder.clone()];
+        opts.disable_wal_memtable_replay_on_load = true;
+        let ss_table_dir = opts.ss_table_dir.clone();
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for key in ["a", "b", "c", "d"] {
+            db.put(&key.to_string(), &key.to_string()).unwrap();
+            db.wait_for_compactions();
+        }
+        assert_eq!(
+            recorder
+                .events
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<_>>(),
+            [
+                "flush_start 1 2",
+                "flush_finish 1",
+                "flush_start 2 2",
+                "flush_finish 2",
+                "compaction_start Picker 2",
+                "compaction_finish L1 1 false",
+            ]
+        );
+
+        // Writes go on while the flush fails, the frozen MemTable stays around to be retried
+        db.put(&"e".to_string(), &"e".to_string()).unwrap();
+        std::fs::remove_dir_all(&ss_table_dir).unwrap();
+        db.put(&"f".to_string(), &"f".to_string()).unwrap();
+        assert!(db.wait_for_flush().is_err());
+        assert_eq!(
+            recorder
+                .events
+                .lock()
+                .unwrap()
+                .drain(..)
+                .collect::<Vec<_>>(),
+            ["flush_start 4 2", "flush_error 4"]
+        );
+        assert!(db.mem_table.is_empty());
+        assert_eq!(db.pending_flush.get().unwrap().mem_table.len(), 2);
+        assert_eq!(db.get_raw(&"f".to_string()).unwrap(), Some(b"f".to_vec()));
+    }
+
+    #[test]
+    fn writes_and_reads_go_on_while_a_flush_is_pending() {
+        // Holds every flush back until released
+        #[derive(Debug)]
+        struct Gate {
+            release: Mutex<std::sync::mpsc::Receiver<()>>,
+        }
+
+        impl EventListener for Gate {
+            fn on_flush_start(&self, _info: &FlushJobInfo) {
+                let _ = self.release.lock().unwrap().recv();
+            }
+        }
+
+        let (release, receiver) = std::sync::mpsc::channel();
+        let mut opts =
+            test_default_config("writes_and_reads_go_on_while_a_flush_is_pending", false);
+        opts.memtable_max_size = Some(3);
+        opts.event_listeners = vec![Arc::new(Gate {
+            release: Mutex::new(receiver),
+        })];
+        let mut db = DB::new(Some(opts)).unwrap();
+        // Dropped before the DB, which would otherwise wait on the held back flush if an assert fails
+        let release = release;
+        let get = |db: &DB, key: &str| {
+            db.get_typed::<TestEncoder, TestEncoder>(&key.to_string())
+                .unwrap()
+        };
+
+        db.put(&"a".to_string(), &"a-1".to_string()).unwrap();
+        db.put(&"b".to_string(), &"b-1".to_string()).unwrap();
+        db.put(&"c".to_string(), &"c-1".to_string()).unwrap();
+        db.put(&"a".to_string(), &"a-2".to_string()).unwrap();
+        db.delete_range(&"b".to_string(), &"c".to_string()).unwrap();
+
+        // The first three writes are frozen, the rest went to the new MemTable
+        assert!(db.versions().ss_meta.is_empty());
+        assert_eq!(db.pending_flush.get().unwrap().mem_table.len(), 3);
+        assert_eq!(db.mem_table.len(), 1);
+        assert_eq!(get(&db, "a"), Some("a-2".to_string()));
+        assert_eq!(get(&db, "b"), None);
+        assert_eq!(get(&db, "c"), Some("c-1".to_string()));
+
+        release.send(()).unwrap();
+        db.wait_for_flush().unwrap();
+        assert!(db.pending_flush.get().is_none());
+        assert_eq!(db.versions().ss_meta.len(), 1);
+        assert_eq!(get(&db, "a"), Some("a-2".to_string()));
+        assert_eq!(get(&db, "b"), None);
+        assert_eq!(get(&db, "c"), Some("c-1".to_string()));
+    }
+
+    #[test]
+    fn wal_is_truncated_once_flushed() {
+        let name = "wal_is_truncated_once_flushed";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(2);
+        let wal_dir = opts.wal_dir.clone();
+        let mut db = DB::new(Some(opts)).unwrap();
+        let wal_segment_lens = || {
+            let mut lens = std::fs::read_dir(&wal_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .collect::<Vec<_>>();
+            lens.sort_unstable();
+            lens
+        };
+
+        db.put(&"a".to_string(), &"a".to_string()).unwrap();
+        assert!(wal_segment_lens()[0] > 0);
+        db.put(&"b".to_string(), &"b".to_string()).unwrap();
+        // Every segment but the new, empty one is gone
+        db.wait_for_flush().unwrap();
+        assert_eq!(wal_segment_lens(), vec![SEGMENT_HEADER_LEN]);
+        db.put(&"c".to_string(), &"c".to_string()).unwrap();
+        drop(db);
+
+        // Only the write since the flush is replayed, the rest comes from the table
+        let mut opts = test_default_config(name, true);
+        opts.memtable_max_size = Some(2);
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(db.mem_table.len(), 1);
+        for key in ["a", "b", "c"] {
+            assert_eq!(
+                db.get_raw(&key.to_string()).unwrap(),
+                Some(key.as_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn wal_segments_rotate_and_replay_in_order() {
+        let name = "wal_segments_rotate_and_replay_in_order";
+        let mut opts = test_default_config(name, false);
+        // Room for about two records per segment
+        opts.wal_segment_size = 64;
+        let wal_dir = opts.wal_dir.clone();
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in 0..10 {
+            db.put(&format!("key{i}"), &format!("val{i}")).unwrap();
+        }
+        // The newest write to a key has to win, whichever segment it ended up in
+        db.put(&"key0".to_string(), &"new".to_string()).unwrap();
+        drop(db);
+
+        let mut segments = std::fs::read_dir(&wal_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        segments.sort();
+        let expected = (1..=segments.len() as u64)
+            .map(crate::wal::segment_file_name)
+            .collect::<Vec<_>>();
+        assert!(segments.len() > 2);
+        assert_eq!(segments, expected);
+
+        let mut opts = test_default_config(name, true);
+        opts.wal_segment_size = 64;
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(db.mem_table.len(), 10);
+        assert_eq!(
+            db.get_raw(&"key0".to_string()).unwrap(),
+            Some(b"new".to_vec())
+        );
+        assert_eq!(
+            db.get_raw(&"key9".to_string()).unwrap(),
+            Some(b"val9".to_vec())
+        );
+    }
+
+    #[test]
+    fn write_batch_commits_every_write_at_once() {
+        let name = "write_batch_commits_every_write_at_once";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for key in ["a", "b", "c"] {
+            db.put(&key.to_string(), &key.to_string()).unwrap();
+        }
+        let subscription = db.subscribe();
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put(&"d".to_string(), &"d".to_string())
+            .delete(&"a".to_string())
+            .delete_range(&"b".to_string(), &"c".to_string())
+            // An empty range is left out
+            .delete_range(&"c".to_string(), &"c".to_string())
+            .put(&"a".to_string(), &"a2".to_string());
+        db.write(batch, &WriteOptions::default()).unwrap();
+
+        let seq_nos: Vec<_> = std::iter::from_fn(|| subscription.try_next())
+            .map(|record| record.seq_no())
+            .collect();
+        assert_eq!(seq_nos, [3, 4, 5, 6]);
+        let read = |db: &DB| ["a", "b", "c", "d"].map(|key| db.get_raw(&key.to_string()).unwrap());
+        let expected = [
+            Some(b"a2".to_vec()),
+            None,
+            Some(b"c".to_vec()),
+            Some(b"d".to_vec()),
+        ];
+        assert_eq!(read(&db), expected);
+        assert_eq!(
+            db.get_with_metadata(&"a".to_string())
+                .unwrap()
+                .unwrap()
+                .seq_no,
+            6
+        );
+        drop(db);
+
+        // Logged as a single record and replayed whole
+        let db = DB::new(Some(test_default_config(name, true))).unwrap();
+        assert_eq!(read(&db), expected);
+        assert_eq!(db.wal_replay_report().last_seq_no, Some(6));
+    }
+
+    #[test]
+    fn write_batch_with_an_invalid_write_writes_nothing() {
+        let name = "write_batch_with_an_invalid_write_writes_nothing";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let mut batch = WriteBatch::new();
+        batch
+            .put(&"a".to_string(), &"a".to_string())
+            .put(&"".to_string(), &"b".to_string());
+        assert!(matches!(
+            db.write(batch, &WriteOptions::default()),
+            Err(DBError::Codec { .. })
+        ));
+
+        let mut batch = WriteBatch::new();
+        batch.put(
+            &"a".to_string(),
+            &"a".repeat(DEFAULT_MAX_RECORD_LEN as usize),
+        );
+        assert!(matches!(
+            db.write(batch, &WriteOptions::default()),
+            Err(DBError::Codec { .. })
+        ));
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), None);
+
+        // Seq_nos are only taken by writes committed
+        db.write(WriteBatch::new(), &WriteOptions::default())
+            .unwrap();
+        db.put(&"b".to_string(), &"b".to_string()).unwrap();
+        assert_eq!(
+            db.get_with_metadata(&"b".to_string())
+                .unwrap()
+                .unwrap()
+                .seq_no,
+            0
+        );
+        drop(db);
+
+        let db = DB::new(Some(test_default_config(name, true))).unwrap();
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), None);
+    }
+
+    #[test]
+    fn snapshots_read_the_db_as_it_was_when_taken() {
+        let name = "snapshots_read_the_db_as_it_was_when_taken";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for key in ["a", "b", "c", "d"] {
+            db.put(&key.to_string(), &format!("{key}-1")).unwrap();
+        }
+        let snapshot = db.snapshot().unwrap();
+        assert_eq!(snapshot.seq_no(), 4);
+
+        db.put(&"a".to_string(), &"a-2".to_string()).unwrap();
+        db.delete(&"b".to_string()).unwrap();
+        db.delete_range(&"c".to_string(), &"e".to_string()).unwrap();
+        db.put(&"e".to_string(), &"e-2".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+
+        for (key, now, then) in [
+            ("a", Some("a-2"), Some("a-1")),
+            ("b", None, Some("b-1")),
+            ("c", None, Some("c-1")),
+            ("d", None, Some("d-1")),
+            ("e", Some("e-2"), None),
+        ] {
+            let key = key.to_string();
+            assert_eq!(
+                db.get_raw(&key).unwrap(),
+                now.map(|val| val.as_bytes().to_vec())
+            );
+            assert_eq!(
+                db.get_raw_at(&key, &snapshot).unwrap(),
+                then.map(|val| val.as_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn snapshots_keep_the_versions_overwritten_after_them() {
+        let name = "snapshots_keep_the_versions_overwritten_after_them";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        db.put(&"a".to_string(), &"a-1".to_string()).unwrap();
+        db.put(&"b".to_string(), &"b-1".to_string()).unwrap();
+        let snapshot = db.snapshot().unwrap();
+        // Taking a snapshot leaves the MemTable be
+        assert_eq!(db.mem_table.len(), 2);
+        assert!(db.pending_flush.get().is_none());
+
+        db.put(&"a".to_string(), &"a-2".to_string()).unwrap();
+        db.put(&"a".to_string(), &"a-3".to_string()).unwrap();
+        db.delete(&"b".to_string()).unwrap();
+        db.put(&"c".to_string(), &"c-2".to_string()).unwrap();
+        // Only the versions the snapshot reads are set aside, no snapshot reads "a-2"
+        assert_eq!(db.mem_superseded.len(), 2);
+
+        let kvs = |pairs: &[(&str, &str)]| -> Vec<KeyValue> {
+            pairs
+                .iter()
+                .map(|(key, val)| (key.as_bytes().to_vec(), val.as_bytes().to_vec()))
+                .collect()
+        };
+        let check = |db: &DB| {
+            let at_snapshot = ReadOptions {
+                snapshot: Some(&snapshot),
+                ..ReadOptions::default()
+            };
+            let now: Vec<KeyValue> = db.iter().map(Result::unwrap).collect();
+            assert_eq!(now, kvs(&[("a", "a-3"), ("c", "c-2")]));
+            let then: Vec<KeyValue> = db.iter_opt(&at_snapshot).map(Result::unwrap).collect();
+            assert_eq!(then, kvs(&[("a", "a-1"), ("b", "b-1")]));
+            let then: Vec<KeyValue> = db
+                .iter_opt(&at_snapshot)
+                .rev()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(then, kvs(&[("b", "b-1"), ("a", "a-1")]));
+            assert_eq!(
+                db.get_raw_at(&"a".to_string(), &snapshot).unwrap(),
+                Some(b"a-1".to_vec())
+            );
+            assert_eq!(db.get_raw(&"b".to_string()).unwrap(), None);
+        };
+        check(&db);
+        db.flush_mem_table().unwrap();
+        check(&db);
+
+        // The compaction goes ahead, keeping the versions the snapshot reads next to the newest
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        check(&db);
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].entry_count, 5);
+
+        // Once it is released the next compaction drops them
+        drop(snapshot);
+        assert!(db.versions().snapshots.is_empty());
+        db.put(&"c".to_string(), &"c-3".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].entry_count, 2);
+        let now: Vec<KeyValue> = db.iter().map(Result::unwrap).collect();
+        assert_eq!(now, kvs(&[("a", "a-3"), ("c", "c-3")]));
+    }
+
+    #[test]
+    fn iter_merges_the_mem_tables_and_every_level() {
+        let name = "iter_merges_the_mem_tables_and_every_level";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let mut expected = std::collections::BTreeMap::new();
+        let mut put = |db: &mut DB, key: String, val: String| {
+            db.put(&key, &val).unwrap();
+            expected.insert(key.into_bytes(), val.into_bytes());
+        };
+
+        // More keys than fit in a batch, pushed down to the last level
+        for i in 0..600 {
+            put(&mut db, format!("key-{i:03}"), format!("val-{i}"));
+        }
+        db.compact_range(&"key-000".to_string(), &"key-999".to_string())
+            .unwrap();
+        // Shadowed and deleted from L0
+        for i in (0..600).step_by(7) {
+            put(&mut db, format!("key-{i:03}"), format!("new-{i}"));
+        }
+        db.delete(&"key-001".to_string()).unwrap();
+        db.delete_range(&"key-100".to_string(), &"key-300".to_string())
+            .unwrap();
+        db.flush_mem_table().unwrap();
+        // And from the MemTable, whose writes are newer than the range tombstone
+        put(&mut db, "key-150".to_string(), "val-150".to_string());
+        db.delete(&"key-599".to_string()).unwrap();
+        expected.retain(|key, _| {
+            let range_deleted = (b"key-100".as_slice()..b"key-300").contains(&key.as_slice());
+            key != b"key-001" && key != b"key-599" && !range_deleted
+        });
+        expected.insert(b"key-150".to_vec(), b"val-150".to_vec());
+
+        let all: Vec<_> = db.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(all, expected.clone().into_iter().collect::<Vec<_>>());
+
+        let range: Vec<_> = db
+            .range("key-090".to_string().."key-160".to_string())
+            .map(Result::unwrap)
+            .collect();
+        let keys: Vec<_> = range
+            .iter()
+            .map(|(key, _)| String::from_utf8_lossy(key).into_owned())
+            .collect();
+        assert_eq!(keys.first().map(String::as_str), Some("key-090"));
+        assert_eq!(keys.last().map(String::as_str), Some("key-150"));
+        assert_eq!(keys.len(), 11);
+
+        let prefixed: Vec<_> = db
+            .scan_prefix(&"key-15".to_string())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(prefixed, [(b"key-150".to_vec(), b"val-150".to_vec())]);
+        assert_eq!(db.range("key-600".to_string()..).count(), 0);
+    }
+
+    #[test]
+    fn iter_runs_backwards_and_from_both_ends() {
+        let name = "iter_runs_backwards_and_from_both_ends";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for i in 0..700 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.flush_mem_table().unwrap();
+        for i in (0..700).step_by(3) {
+            db.delete(&format!("key-{i:03}")).unwrap();
+        }
+        let expected: Vec<_> = (0..700)
+            .filter(|i| i % 3 != 0)
+            .map(|i| format!("key-{i:03}"))
+            .collect();
+        let keys = |items: Vec<KeyValue>| -> Vec<String> {
+            items
+                .into_iter()
+                .map(|(key, _)| String::from_utf8(key).unwrap())
+                .collect()
+        };
+
+        let backwards = keys(db.iter().rev().collect::<Result<_, _>>().unwrap());
+        assert_eq!(
+            backwards,
+            expected.iter().rev().cloned().collect::<Vec<_>>()
+        );
+
+        let latest: Vec<_> = db
+            .scan_prefix(&"key-1".to_string())
+            .rev()
+            .take(3)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(keys(latest), ["key-199", "key-197", "key-196"]);
+
+        // Taking from both ends meets in the middle, every key once
+        let mut iter = db.range("key-010".to_string()..="key-690".to_string());
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        loop {
+            match (iter.next(), iter.next_back()) {
+                (None, None) => break,
+                (item, back_item) => {
+                    front.extend(item.map(Result::unwrap));
+                    back.extend(back_item.map(Result::unwrap));
+                }
+            }
+        }
+        back.reverse();
+        front.extend(back);
+        let within: Vec<_> = expected
+            .iter()
+            .filter(|key| ("key-010"..="key-690").contains(&key.as_str()))
+            .cloned()
+            .collect();
+        assert_eq!(keys(front), within);
+    }
+
+    #[test]
+    fn cursor_pages_through_the_db_and_resumes_from_a_key() {
+        let name = "cursor_pages_through_the_db_and_resumes_from_a_key";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for i in 0..600 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.flush_mem_table().unwrap();
+        db.delete(&"key-300".to_string()).unwrap();
+
+        let mut cursor = db.cursor();
+        assert!(!cursor.valid());
+        assert_eq!(cursor.key(), b"");
+
+        // Pages of 100, each resumed from the last key of the one before
+        let mut pages = 0;
+        let mut last_key: Option<Vec<u8>> = None;
+        loop {
+            match &last_key {
+                Some(key) => {
+                    cursor.seek(key).unwrap();
+                    cursor.next().unwrap();
+                }
+                None => cursor.seek_to_first().unwrap(),
+            }
+            let mut page = 0;
+            while cursor.valid() && page < 100 {
+                last_key = Some(cursor.key().to_vec());
+                cursor.next().unwrap();
+                page += 1;
+            }
+            if page == 0 {
+                break;
+            }
+            pages += 1;
+        }
+        assert_eq!(pages, 6);
+        assert_eq!(last_key.as_deref(), Some(b"key-599".as_slice()));
+
+        cursor.seek_for_prev(b"key-300").unwrap();
+        assert_eq!(cursor.key(), b"key-299");
+        assert_eq!(cursor.value(), b"val-299");
+        cursor.next().unwrap();
+        assert_eq!(cursor.key(), b"key-301");
+
+        // Walking backwards across batch boundaries sees every key
+        cursor.seek_to_last().unwrap();
+        let mut count = 0;
+        while cursor.valid() {
+            count += 1;
+            cursor.prev().unwrap();
+        }
+        assert_eq!(count, 599);
+
+        cursor.seek(b"key-599").unwrap();
+        cursor.next().unwrap();
+        assert!(!cursor.valid());
+        cursor.seek(b"zzz").unwrap();
+        assert!(!cursor.valid());
+    }
+
+    #[test]
+    fn read_options_bound_scans_and_read_through_snapshots() {
+        let name = "read_options_bound_scans_and_read_through_snapshots";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for key in ["a", "b", "c", "d", "e"] {
+            db.put(&key.to_string(), &format!("{key}-1")).unwrap();
+        }
+        let snapshot = db.snapshot().unwrap();
+        db.put(&"b".to_string(), &"b-2".to_string()).unwrap();
+        db.delete(&"c".to_string()).unwrap();
+        db.delete_range(&"d".to_string(), &"e".to_string()).unwrap();
+        db.put(&"bb".to_string(), &"bb-2".to_string()).unwrap();
+
+        let bounded = ReadOptions {
+            lower_bound: Some(b"b".to_vec()),
+            upper_bound: Some(b"e".to_vec()),
+            ..ReadOptions::default()
+        };
+        fn keys(iter: impl Iterator<Item = Result<KeyValue, DBError>>) -> Vec<String> {
+            iter.map(|item| String::from_utf8(item.unwrap().0).unwrap())
+                .collect()
+        }
+        assert_eq!(keys(db.iter_opt(&bounded)), ["b", "bb"]);
+        let at_snapshot = ReadOptions {
+            snapshot: Some(&snapshot),
+            ..bounded.clone()
+        };
+        assert_eq!(keys(db.iter_opt(&at_snapshot)), ["b", "c", "d"]);
+        assert_eq!(keys(db.iter_opt(&at_snapshot).rev()), ["d", "c", "b"]);
+        let val: Option<String> = db.get_typed_opt(&"b".to_string(), &at_snapshot).unwrap();
+        assert_eq!(val.as_deref(), Some("b-1"));
+
+        // Seeks past the bounds land on the nearest key within them
+        let mut cursor = db.cursor_opt(&at_snapshot);
+        cursor.seek(b"a").unwrap();
+        assert_eq!(cursor.key(), b"b");
+        cursor.seek_for_prev(b"z").unwrap();
+        assert_eq!(cursor.key(), b"d");
+        cursor.next().unwrap();
+        assert!(!cursor.valid());
+
+        // Reads that don't fill the cache leave it as it was
+        db.flush_mem_table().unwrap();
+        for meta in db.versions().ss_meta.iter() {
+            db.table_cache.evict(meta.file_no());
+        }
+        let uncached = ReadOptions {
+            fill_cache: false,
+            verify_checksums: false,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            db.get_raw_opt(&"a".to_string(), &uncached).unwrap(),
+            Some(b"a-1".to_vec())
+        );
+        assert_eq!(db.iter_opt(&uncached).count(), 4);
+        assert!(db.table_cache.is_empty());
+        db.get_raw(&"a".to_string()).unwrap();
+        assert!(!db.table_cache.is_empty());
+    }
+
+    #[test]
+    fn multi_get_finds_what_get_raw_finds() {
+        let name = "multi_get_finds_what_get_raw_finds";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for i in 0..600 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.flush_mem_table().unwrap();
+        db.delete_range(&"key-100".to_string(), &"key-200".to_string())
+            .unwrap();
+        db.delete(&"key-300".to_string()).unwrap();
+        db.put(&"key-150".to_string(), &"val-150-2".to_string())
+            .unwrap();
+        db.put(&"key-400".to_string(), &"val-400-2".to_string())
+            .unwrap();
+
+        // Unsorted, with a duplicate, missing keys and keys found in every source
+        let keys: Vec<String> = [
+            "key-599", "key-150", "key-000", "key-300", "key-150", "key-120", "key-400", "key-999",
+            "a", "key-042",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let expected: Vec<_> = keys.iter().map(|key| db.get_raw(key).unwrap()).collect();
+        let found: Vec<_> = db
+            .multi_get(&keys)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(found, expected);
+        assert_eq!(found[1].as_deref(), Some(&b"val-150-2"[..]));
+        assert_eq!(found[3], None);
+        assert_eq!(found[5], None);
+        assert!(db.multi_get::<String>(&[]).is_empty());
+    }
+
+    #[test]
+    fn typed_dbs_decode_what_they_read() {
+        let name = "typed_dbs_decode_what_they_read";
+        let db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let mut db: TypedDB<String, String> = TypedDB::new(db);
+        for i in 0..300 {
+            db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+        }
+        db.delete(&"key-001".to_string()).unwrap();
+
+        assert_eq!(
+            db.get(&"key-002".to_string()).unwrap().as_deref(),
+            Some("val-2")
+        );
+        assert_eq!(db.get(&"key-001".to_string()).unwrap(), None);
+        let first: Vec<_> = db.iter().take(2).map(Result::unwrap).collect();
+        assert_eq!(
+            first,
+            [
+                ("key-000".to_string(), "val-0".to_string()),
+                ("key-002".to_string(), "val-2".to_string())
+            ]
+        );
+        let keys: Vec<_> = db
+            .range("key-100".to_string().."key-103".to_string())
+            .rev()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, ["key-102", "key-101", "key-100"]);
+
+        // A value that doesn't decode is an error, but only for its own key
+        struct NotUtf8;
+        impl Encode for NotUtf8 {
+            fn encode(&self) -> Vec<u8> {
+                vec![0xff]
+            }
+        }
+        db.db_mut().put(&"key-100".to_string(), &NotUtf8).unwrap();
+        assert!(matches!(
+            db.get(&"key-100".to_string()),
+            Err(DBError::Codec { .. })
+        ));
+        let items: Vec<_> = db
+            .range("key-099".to_string()..="key-101".to_string())
+            .collect();
+        assert_eq!(items.len(), 3);
+        assert!(items[0].is_ok() && items[1].is_err() && items[2].is_ok());
+    }
+
+    #[test]
+    fn transactions_fail_to_commit_over_keys_written_since_read() {
+        let name = "transactions_fail_to_commit_over_keys_written_since_read";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for key in ["a", "b", "c"] {
+            db.put(&key.to_string(), &format!("{key}-1")).unwrap();
+        }
+
+        // Reads see the transaction's own writes, the DB as of the snapshot otherwise
+        let mut txn = db.begin_transaction().unwrap();
+        let a: Option<String> = txn.get_typed(&db, &"a".to_string()).unwrap();
+        assert_eq!(a.as_deref(), Some("a-1"));
+        txn.put(&"a".to_string(), &"a-2".to_string())
+            .delete_range(&"b".to_string(), &"c".to_string());
+        db.put(&"c".to_string(), &"c-2".to_string()).unwrap();
+        assert_eq!(
+            txn.get_raw(&db, &"a".to_string()).unwrap(),
+            Some(b"a-2".to_vec())
+        );
+        assert_eq!(txn.get_raw(&db, &"b".to_string()).unwrap(), None);
+        // Blind writes don't conflict, a key written since the snapshot but never read doesn't either
+        txn.put(&"d".to_string(), &"d-1".to_string());
+        txn.commit(&mut db).unwrap();
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), Some(b"a-2".to_vec()));
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"d".to_string()).unwrap(), Some(b"d-1".to_vec()));
+
+        // A read key written since, even by a range deletion, fails the commit and nothing is written
+        for range_deletion in [false, true] {
+            let mut txn = db.begin_transaction().unwrap();
+            txn.get_raw(&db, &"c".to_string()).unwrap();
+            txn.put(&"e".to_string(), &"e-1".to_string());
+            match range_deletion {
+                false => db.put(&"c".to_string(), &"c-3".to_string()).unwrap(),
+                true => db.delete_range(&"b".to_string(), &"z".to_string()).unwrap(),
+            }
+            match txn.commit(&mut db) {
+                Err(DBError::Conflict { key }) => assert_eq!(key, b"c"),
+                result => panic!("expected a conflict, got {result:?}"),
+            }
+            assert_eq!(db.get_raw(&"e".to_string()).unwrap(), None);
+        }
+
+        // So does one flushed to a table since
+        let mut txn = db.begin_transaction().unwrap();
+        assert_eq!(
+            txn.get_raw(&db, &"a".to_string()).unwrap(),
+            Some(b"a-2".to_vec())
+        );
+        db.put(&"a".to_string(), &"a-3".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        assert!(matches!(txn.commit(&mut db), Err(DBError::Conflict { .. })));
+    }
+
+    #[test]
+    fn compare_and_swap_only_writes_over_the_expected_value() {
+        let name = "compare_and_swap_only_writes_over_the_expected_value";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let key = "lease".to_string();
+
+        assert_eq!(
+            db.compare_and_swap(&key, None, Some(b"owner-1")).unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            db.compare_and_swap(&key, None, Some(b"owner-2")).unwrap(),
+            Err(Some(b"owner-1".to_vec()))
+        );
+        db.flush_mem_table().unwrap();
+        assert_eq!(
+            db.compare_and_swap(&key, Some(b"owner-1"), Some(b"owner-2"))
+                .unwrap(),
+            Ok(())
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), Some(b"owner-2".to_vec()));
+
+        // Swapping in None deletes the key, after which only an absent key matches
+        assert_eq!(
+            db.compare_and_swap(&key, Some(b"owner-2"), None).unwrap(),
+            Ok(())
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), None);
+        assert_eq!(
+            db.compare_and_swap(&key, Some(b"owner-2"), Some(b"owner-3"))
+                .unwrap(),
+            Err(None)
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), None);
+    }
+
+    #[test]
+    fn column_families_keep_their_keys_and_configs_apart() {
+        let name = "column_families_keep_their_keys_and_configs_apart";
+        let cf_config = |memtable_max_size| DBConfig {
+            memtable_max_size: Some(memtable_max_size),
+            ..test_default_config(name, true)
+        };
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        assert_eq!(db.cf_handle("index"), None);
+        let index = db.create_cf("index", cf_config(2)).unwrap();
+        assert_eq!(db.cf_handle("index").as_ref(), Some(&index));
+        assert!(matches!(
+            db.create_cf("index", cf_config(2)),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        assert!(matches!(
+            db.create_cf("../index", cf_config(2)),
+            Err(DBError::InvalidConfig { .. })
+        ));
+
+        let key = "alice".to_string();
+        db.put(&key, &"data".to_string()).unwrap();
+        db.put_cf(&index, &key, &"index-1".to_string()).unwrap();
+        db.put_cf(&index, &"bob".to_string(), &"index-2".to_string())
+            .unwrap();
+        db.put_cf(&index, &"carol".to_string(), &"index-3".to_string())
+            .unwrap();
+        db.delete_cf(&index, &"carol".to_string()).unwrap();
+        assert_eq!(db.get_raw(&key).unwrap(), Some(b"data".to_vec()));
+        assert_eq!(db.get_raw(&"bob".to_string()).unwrap(), None);
+        assert_eq!(db.get_cf(&index, &key).unwrap(), Some(b"index-1".to_vec()));
+        // The column family flushes at its own memtable_max_size
+        db.cf(&index).wait_for_flush().unwrap();
+        assert!(!db.cf(&index).table_properties().unwrap().is_empty());
+        assert!(db.table_properties().unwrap().is_empty());
+
+        let scanned: Vec<KeyValue> = db.iter_cf(&index).map(Result::unwrap).collect();
+        assert_eq!(
+            scanned,
+            [
+                (b"alice".to_vec(), b"index-1".to_vec()),
+                (b"bob".to_vec(), b"index-2".to_vec())
+            ]
+        );
+
+        // Reopened from the manifest, with the config given for it
+        db.close().unwrap();
+        let mut opts = test_default_config(name, true);
+        opts.column_families
+            .insert("index".to_string(), cf_config(2));
+        let db = DB::new(Some(opts)).unwrap();
+        let index = db.cf_handle("index").unwrap();
+        assert_eq!(index.name(), "index");
+        assert_eq!(db.cf(&index).opts.memtable_max_size, Some(2));
+        assert_eq!(
+            db.get_cf(&index, &"bob".to_string()).unwrap(),
+            Some(b"index-2".to_vec())
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), Some(b"data".to_vec()));
+    }
+
+    #[test]
+    fn column_families_sharing_the_wal_commit_batches_together() {
+        let name = "column_families_sharing_the_wal_commit_batches_together";
+        let open = |preserve, column_family_wal| {
+            let opts = DBConfig {
+                column_family_wal,
+                ..test_default_config(name, preserve)
+            };
+            DB::new(Some(opts)).unwrap()
+        };
+        let get_cf =
+            |db: &DB, cf: &ColumnFamilyHandle, key: &str| db.get_cf(cf, &key.to_string()).unwrap();
+
+        let mut db = open(false, ColumnFamilyWal::Shared);
+        let index = db
+            .create_cf("index", test_default_config(name, true))
+            .unwrap();
+        let mut batch = WriteBatch::new();
+        batch
+            .put(&"alice".to_string(), &"data".to_string())
+            .put_cf(&index, &"alice".to_string(), &"index-1".to_string())
+            .put_cf(&index, &"bob".to_string(), &"index-2".to_string())
+            .delete_cf(&index, &"bob".to_string());
+        assert_eq!(batch.len(), 4);
+        db.write(batch, &WriteOptions::default()).unwrap();
+        db.put_cf(&index, &"carol".to_string(), &"index-3".to_string())
+            .unwrap();
+        assert_eq!(get_cf(&db, &index, "alice"), Some(b"index-1".to_vec()));
+        assert_eq!(get_cf(&db, &index, "bob"), None);
+        // Everything went to the DB's WAL
+        assert!(!db.cf(&index).opts.wal_dir.exists());
+
+        // The DB's flush leaves the segments the column family still needs
+        db.flush_mem_table().unwrap();
+        drop(db);
+        let mut db = open(true, ColumnFamilyWal::Shared);
+        let index = db.cf_handle("index").unwrap();
+        assert_eq!(get_cf(&db, &index, "alice"), Some(b"index-1".to_vec()));
+        assert_eq!(get_cf(&db, &index, "carol"), Some(b"index-3".to_vec()));
+        assert_eq!(get_cf(&db, &index, "bob"), None);
+        assert_eq!(
+            db.get_raw(&"alice".to_string()).unwrap(),
+            Some(b"data".to_vec())
+        );
+        db.put_cf(&index, &"dave".to_string(), &"index-4".to_string())
+            .unwrap();
+
+        // Going back to separate WALs, the column family flushes what the DB's WAL held for it
+        drop(db);
+        let mut db = open(true, ColumnFamilyWal::Separate);
+        let index = db.cf_handle("index").unwrap();
+        assert!(!db.cf(&index).table_properties().unwrap().is_empty());
+        assert_eq!(get_cf(&db, &index, "dave"), Some(b"index-4".to_vec()));
+        let mut batch = WriteBatch::new();
+        batch.put_cf(&index, &"erin".to_string(), &"index-5".to_string());
+        assert!(matches!(
+            db.write(batch, &WriteOptions::default()),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        db.put_cf(&index, &"erin".to_string(), &"index-5".to_string())
+            .unwrap();
+
+        // And sharing it again, the writes in the column family's own WAL are flushed
+        drop(db);
+        let db = open(true, ColumnFamilyWal::Shared);
+        let index = db.cf_handle("index").unwrap();
+        assert_eq!(get_cf(&db, &index, "erin"), Some(b"index-5".to_vec()));
+        assert!(!db.cf(&index).opts.wal_dir.exists());
+
+        let recycling = DBConfig {
+            column_family_wal: ColumnFamilyWal::Shared,
+            wal_recycle_files: 1,
+            ..test_default_config(name, true)
+        };
+        drop(db);
+        assert!(matches!(
+            DB::new(Some(recycling)),
+            Err(DBError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn values_with_a_ttl_expire_on_reads_and_compaction() {
+        let name = "values_with_a_ttl_expire_on_reads_and_compaction";
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let open = |preserve| {
+            let mut opts = test_default_config(name, preserve);
+            opts.clock = clock.clone();
+            DB::new(Some(opts)).unwrap()
+        };
+        let get = |db: &DB, key: &str| {
+            db.get_typed::<TestEncoder, TestEncoder>(&key.to_string())
+                .unwrap()
+        };
+
+        let mut db = open(false);
+        db.put_with_ttl(
+            &"session".to_string(),
+            &"s".to_string(),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        db.put(&"forever".to_string(), &"f".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        db.put_with_ttl(
+            &"logged".to_string(),
+            &"l".to_string(),
+            Duration::from_secs(20),
+        )
+        .unwrap();
+        let session = db
+            .get_with_metadata(&"session".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.expires_at, Some(1_010_000));
+
+        // The expiry is replayed from the WAL along with the value
+        drop(db);
+        let mut db = open(true);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(get(&db, "session"), None);
+        assert_eq!(get(&db, "logged").as_deref(), Some("l"));
+        assert_eq!(get(&db, "forever").as_deref(), Some("f"));
+        let scanned: Vec<KeyValue> = db.iter().map(Result::unwrap).collect();
+        assert_eq!(
+            scanned,
+            [
+                (b"forever".to_vec(), b"f".to_vec()),
+                (b"logged".to_vec(), b"l".to_vec())
+            ]
+        );
+        assert_eq!(
+            db.multi_get(&["session".to_string()])[0].as_ref().unwrap(),
+            &None
+        );
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(get(&db, "logged"), None);
+        db.flush_mem_table().unwrap();
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].entry_count, 1);
+        assert_eq!(get(&db, "forever").as_deref(), Some("f"));
+    }
+
+    #[test]
+    fn indexes_follow_puts_and_deletes() {
+        let name = "indexes_follow_puts_and_deletes";
+        let open = |preserve, indexes| {
+            let mut opts = test_default_config(name, preserve);
+            opts.indexes = indexes;
+            DB::new(Some(opts)).unwrap()
+        };
+        // Values are "<city>:<name>", indexed by city
+        let city = || {
+            index::from_fn("city", |val| {
+                val.split(|&b| b == b':').next().map(<[u8]>::to_vec)
+            })
+        };
+        let keys = |found: Vec<KeyValue>| found.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+
+        let mut db = open(false, vec![city()]);
+        db.put(&"1".to_string(), &"paris:ann".to_string()).unwrap();
+        db.put(&"2".to_string(), &"oslo:bob".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        db.put(&"3".to_string(), &"paris:cid".to_string()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(&"4".to_string(), &"rome:dan".to_string());
+        batch.put(&"4".to_string(), &"oslo:dan".to_string());
+        db.write(batch, &WriteOptions::default()).unwrap();
+
+        let paris = db.get_by_index("city", &"paris".to_string()).unwrap();
+        assert_eq!(
+            paris,
+            [
+                (b"1".to_vec(), b"paris:ann".to_vec()),
+                (b"3".to_vec(), b"paris:cid".to_vec())
+            ]
+        );
+        assert_eq!(
+            keys(db.get_by_index("city", &"oslo".to_string()).unwrap()),
+            [b"2", b"4"]
+        );
+        assert!(
+            db.get_by_index("city", &"rome".to_string())
+                .unwrap()
+                .is_empty()
+        );
+
+        // Moving a value to another index key and deleting one drop their old entries
+        db.put(&"1".to_string(), &"rome:ann".to_string()).unwrap();
+        db.delete(&"2".to_string()).unwrap();
+        assert_eq!(
+            keys(db.get_by_index("city", &"paris".to_string()).unwrap()),
+            [b"3"]
+        );
+        assert_eq!(
+            keys(db.get_by_index("city", &"oslo".to_string()).unwrap()),
+            [b"4"]
+        );
+        let range = db
+            .index_range("city", Bound::Included(b"p"), Bound::Unbounded)
+            .unwrap();
+        assert_eq!(keys(range), [b"3", b"1"]);
+        assert!(matches!(
+            db.get_by_index("age", &"1".to_string()),
+            Err(DBError::InvalidConfig { .. })
+        ));
+
+        // Iterating leaves the index entries out
+        let scanned: Vec<Vec<u8>> = db.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(scanned, [b"1", b"3", b"4"]);
+        assert_eq!(db.iter().rev().count(), 3);
+
+        // An index added later is empty until rebuilt
+        drop(db);
+        let initial = index::from_fn("initial", |val| {
+            let at = val.iter().position(|&b| b == b':')?;
+            val.get(at + 1..at + 2).map(<[u8]>::to_vec)
+        });
+        let mut db = open(true, vec![city(), initial]);
+        assert!(
+            db.get_by_index("initial", &"d".to_string())
+                .unwrap()
+                .is_empty()
+        );
+        db.rebuild_index("initial").unwrap();
+        assert_eq!(
+            keys(db.get_by_index("initial", &"d".to_string()).unwrap()),
+            [b"4"]
+        );
+        assert_eq!(
+            keys(db.get_by_index("city", &"rome".to_string()).unwrap()),
+            [b"1"]
+        );
+
+        let invalid = DB::new(Some({
+            let mut opts = test_default_config(name, true);
+            opts.indexes = vec![city(), city()];
+            opts
+        }));
+        assert!(matches!(invalid, Err(DBError::InvalidConfig { .. })));
+    }
+
+    #[test]
+    fn rebuilding_an_index_larger_than_a_wal_record_writes_it_in_batches() {
+        let name = "rebuilding_an_index_larger_than_a_wal_record_writes_it_in_batches";
+        let mut opts = test_default_config(name, false);
+        opts.max_record_len = 256;
+        opts.indexes = vec![index::from_fn("even", |val| {
+            let n: u32 = std::str::from_utf8(val).ok()?.parse().ok()?;
+            n.is_multiple_of(2).then(|| b"even".to_vec())
+        })];
+        let mut db = DB::new(Some(opts)).unwrap();
+        for n in 0..100 {
+            db.put(&format!("{n:03}"), &n.to_string()).unwrap();
+        }
+
+        // Its entries take several times `max_record_len`
+        db.rebuild_index("even").unwrap();
+        let found = db.get_by_index("even", &"even".to_string()).unwrap();
+        assert_eq!(found.len(), 50);
+        assert_eq!(found[0], (b"000".to_vec(), b"0".to_vec()));
+        assert_eq!(found[49], (b"098".to_vec(), b"98".to_vec()));
+    }
+
+    #[test]
+    fn contains_key_and_key_may_exist() {
+        let name = "contains_key_and_key_may_exist";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let key = |key: &str| key.to_string();
+        db.put(&key("a"), &key("1")).unwrap();
+        db.put(&key("c"), &key("3")).unwrap();
+        db.flush_mem_table().unwrap();
+        db.put(&key("b"), &key("2")).unwrap();
+        db.delete(&key("c")).unwrap();
+
+        for (k, live) in [("a", true), ("b", true), ("c", false), ("d", false)] {
+            assert_eq!(db.contains_key(&key(k)).unwrap(), live, "{k}");
+        }
+        assert!(db.key_may_exist(&key("a")).unwrap());
+        assert!(db.key_may_exist(&key("b")).unwrap());
+        // Settled by the tombstone in the MemTable, and by the table's key range
+        assert!(!db.key_may_exist(&key("c")).unwrap());
+        assert!(!db.key_may_exist(&key("d")).unwrap());
+
+        // Flushed, the tombstone is only found by reading the table's data block
+        db.flush_mem_table().unwrap();
+        assert!(!db.contains_key(&key("c")).unwrap());
+        assert!(db.key_may_exist(&key("c")).unwrap());
+    }
+
+    #[test]
+    fn pops_take_keys_off_both_ends() {
+        let name = "pops_take_keys_off_both_ends";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        assert_eq!(db.first_key_value().unwrap(), None);
+        assert_eq!(db.pop_last().unwrap(), None);
+
+        for key in ["b", "d", "a"] {
+            db.put(&key.to_string(), &key.to_uppercase()).unwrap();
+        }
+        db.flush_mem_table().unwrap();
+        db.put(&"c".to_string(), &"C".to_string()).unwrap();
+        db.put(&"e".to_string(), &"E".to_string()).unwrap();
+        db.delete(&"e".to_string()).unwrap();
+
+        assert_eq!(
+            db.first_key_value().unwrap(),
+            Some((b"a".to_vec(), b"A".to_vec()))
+        );
+        assert_eq!(
+            db.last_key_value().unwrap(),
+            Some((b"d".to_vec(), b"D".to_vec()))
+        );
+        assert_eq!(
+            db.pop_first().unwrap(),
+            Some((b"a".to_vec(), b"A".to_vec()))
+        );
+        assert_eq!(db.pop_last().unwrap(), Some((b"d".to_vec(), b"D".to_vec())));
+        assert_eq!(
+            db.pop_first().unwrap(),
+            Some((b"b".to_vec(), b"B".to_vec()))
+        );
+        assert_eq!(db.first_key_value().unwrap(), db.last_key_value().unwrap());
+        assert_eq!(db.pop_last().unwrap(), Some((b"c".to_vec(), b"C".to_vec())));
+        assert_eq!(db.pop_first().unwrap(), None);
+        assert_eq!(db.iter().count(), 0);
+    }
+
+    #[test]
+    fn scan_pages_resume_where_the_last_one_ended() {
+        let name = "scan_pages_resume_where_the_last_one_ended";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for i in 0..7 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+        let keys = |page: &[KeyValue]| page.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        let range = "key-1".to_string()..="key-5".to_string();
+
+        let (page, token) = db.scan_page(range.clone(), 2, None).unwrap();
+        assert_eq!(keys(&page), [b"key-1", b"key-2"]);
+        // Tokens are handed out and back in encoded
+        let token = ResumeToken::decode(&token.unwrap().encode()).unwrap();
+        db.delete(&"key-3".to_string()).unwrap();
+        let (page, token) = db.scan_page(range.clone(), 2, Some(&token)).unwrap();
+        // The range ends with the page
+        assert_eq!(keys(&page), [b"key-4", b"key-5"]);
+        assert!(token.is_none());
+        let (page, token) = db.scan_page(range, 5, None).unwrap();
+        assert_eq!(page.len(), 4);
+        assert!(token.is_none());
+
+        // Pages read through a snapshot only resume through it
+        let snapshot = db.snapshot().unwrap();
+        let read_opts = ReadOptions {
+            snapshot: Some(&snapshot),
+            ..ReadOptions::default()
+        };
+        let (_, token) = db
+            .scan_page_opt::<String, _>(.., 3, None, &read_opts)
+            .unwrap();
+        db.put(&"key-3".to_string(), &"val-3".to_string()).unwrap();
+        let (page, _) = db
+            .scan_page_opt::<String, _>(.., 3, token.as_ref(), &read_opts)
+            .unwrap();
+        assert_eq!(keys(&page), [b"key-4", b"key-5", b"key-6"]);
+        assert!(matches!(
+            db.scan_page::<String, _>(.., 3, token.as_ref()),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        assert!(matches!(
+            ResumeToken::decode(b"short"),
+            Err(DBError::Codec { .. })
+        ));
+        assert!(matches!(
+            db.scan_page::<String, _>(.., 0, None),
+            Err(DBError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_tell_where_the_value_was_found() {
+        let name = "reads_tell_where_the_value_was_found";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let origin = |db: &DB, key: &str| {
+            db.get_with_metadata(&key.to_string())
+                .unwrap()
+                .unwrap()
+                .origin
+        };
+        db.put(&"flushed".to_string(), &"a".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        db.put(&"fresh".to_string(), &"b".to_string()).unwrap();
+
+        assert_eq!(origin(&db, "fresh"), ValueOrigin::MemTable);
+        let file_no = db.versions().ss_meta[0].file_no();
+        db.table_cache.evict(file_no);
+        assert_eq!(
+            origin(&db, "flushed"),
+            ValueOrigin::Table {
+                file_no,
+                level: 0,
+                cached: false
+            }
+        );
+        assert_eq!(
+            origin(&db, "flushed"),
+            ValueOrigin::Table {
+                file_no,
+                level: 0,
+                cached: true
+            }
+        );
+
+        // As of a snapshot, the overwritten value is still found in the table
+        let snapshot = db.snapshot().unwrap();
+        db.put(&"flushed".to_string(), &"c".to_string()).unwrap();
+        assert_eq!(origin(&db, "flushed"), ValueOrigin::MemTable);
+        let read_opts = ReadOptions {
+            snapshot: Some(&snapshot),
+            ..ReadOptions::default()
+        };
+        let meta = db
+            .get_raw_with_meta(&"flushed".to_string(), &read_opts)
+            .unwrap()
+            .unwrap();
+        assert_eq!((meta.val.as_slice(), meta.seq_no), (&b"a"[..], 0));
+        assert!(
+            matches!(meta.origin, ValueOrigin::Table { file_no: found, .. } if found == file_no)
+        );
+        assert_eq!(
+            db.get_raw_with_meta(&"missing".to_string(), &read_opts)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn update_writes_back_what_the_closure_returns() {
+        let name = "update_writes_back_what_the_closure_returns";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let key = "visits".to_string();
+        let increment = |val: Option<&[u8]>| {
+            let count = val.map_or(0, |val| u64::from_le_bytes(val.try_into().unwrap()));
+            Some((count + 1).to_le_bytes().to_vec())
+        };
+
+        assert_eq!(
+            db.update(&key, increment).unwrap(),
+            Some(1u64.to_le_bytes().to_vec())
+        );
+        db.flush_mem_table().unwrap();
+        assert_eq!(
+            db.update(&key, increment).unwrap(),
+            Some(2u64.to_le_bytes().to_vec())
+        );
+        assert_eq!(db.get_raw(&key).unwrap(), Some(2u64.to_le_bytes().to_vec()));
+
+        // Returning None deletes the key
+        assert_eq!(db.update(&key, |_| None).unwrap(), None);
+        assert_eq!(db.get_raw(&key).unwrap(), None);
+        let mut seen = Some(Vec::new());
+        db.update(&key, |val| {
+            seen = val.map(<[u8]>::to_vec);
+            None
+        })
+        .unwrap();
+        assert_eq!(seen, None);
+    }
+
+    #[test]
+    fn prefix_successor_skips_trailing_0xff_bytes() {
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(&[b'a', 0xff, 0xff]), Some(b"b".to_vec()));
+        assert_eq!(prefix_successor(&[0xff]), None);
+        assert_eq!(prefix_successor(b""), None);
+    }
+
+    #[test]
+    fn subscriptions_receive_every_committed_write() {
+        let name = "subscriptions_receive_every_committed_write";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        db.put(&"before".to_string(), &"before".to_string())
+            .unwrap();
+
+        let subscription = db.subscribe();
+        let skip_wal = WriteOptions {
+            disable_wal: true,
+            ..WriteOptions::default()
+        };
+        db.put(&"a".to_string(), &"a".to_string()).unwrap();
+        db.delete_opt(&"a".to_string(), &skip_wal).unwrap();
+        db.delete_range(&"b".to_string(), &"d".to_string()).unwrap();
+        // Rejected writes are never committed
+        assert!(db.put(&"".to_string(), &"e".to_string()).is_err());
+
+        let changes: Vec<_> = std::iter::from_fn(|| subscription.try_next())
+            .map(|record| {
+                (
+                    record.seq_no(),
+                    record.op().clone(),
+                    record.key().to_vec(),
+                    record.val().to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (1, Op::Put, b"a".to_vec(), b"a".to_vec()),
+                (2, Op::Delete, b"a".to_vec(), Vec::new()),
+                (3, Op::DeleteRange, b"b".to_vec(), b"d".to_vec()),
+            ]
+        );
+
+        // The feed ends with the DB
+        drop(db);
+        assert_eq!(subscription.count(), 0);
+    }
+
+    #[test]
+    fn write_options_can_skip_the_wal() {
+        let name = "write_options_can_skip_the_wal";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        let skip_wal = WriteOptions {
+            disable_wal: true,
+            ..WriteOptions::default()
+        };
+        let sync = WriteOptions {
+            sync: true,
+            ..WriteOptions::default()
+        };
+        db.put_opt(&"a".to_string(), &"a".to_string(), &skip_wal)
+            .unwrap();
+        db.put_opt(&"b".to_string(), &"b".to_string(), &sync)
+            .unwrap();
+        db.delete_opt(&"b".to_string(), &skip_wal).unwrap();
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), Some(b"a".to_vec()));
+
+        let sync_without_wal = WriteOptions {
+            sync: true,
+            disable_wal: true,
+        };
+        assert!(matches!(
+            db.put_opt(&"c".to_string(), &"c".to_string(), &sync_without_wal),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        drop(db);
+
+        // Only the write that went through the WAL survives a reopen
+        let db = DB::new(Some(test_default_config(name, true))).unwrap();
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), Some(b"b".to_vec()));
+        assert_eq!(db.get_raw(&"c".to_string()).unwrap(), None);
+    }
+
+    #[test]
+    fn skip_list_mem_table_flushes_and_replays() {
+        let name = "skip_list_mem_table_flushes_and_replays";
+        let config = |preserve| DBConfig {
+            memtable_max_size: Some(3),
+            memtable_kind: MemTableKind::SkipList,
+            ..test_default_config(name, preserve)
+        };
+        let mut db = DB::new(Some(config(false))).unwrap();
+        for key in ["c", "a", "b", "e", "d"] {
+            db.put(&key.to_string(), &key.to_string()).unwrap();
+        }
+        db.put(&"e".to_string(), &"e2".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 1);
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), Some(b"a".to_vec()));
+        drop(db);
+
+        let db = DB::new(Some(config(true))).unwrap();
+        let keys: Vec<_> = db.mem_table.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![b"d", b"e"]);
+        assert_eq!(db.get_raw(&"e".to_string()).unwrap(), Some(b"e2".to_vec()));
+    }
+
+    #[test]
+    fn write_time_is_recorded_through_flushes_and_replay() {
+        let name = "write_time_is_recorded_through_flushes_and_replay";
+        let config = |preserve| DBConfig {
+            record_write_time: true,
+            ..test_default_config(name, preserve)
+        };
+        let mut db = DB::new(Some(config(false))).unwrap();
+        let before = SystemClock.now_millis();
+        db.put(&"flushed".to_string(), &"a".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        db.put(&"logged".to_string(), &"b".to_string()).unwrap();
+        let after = SystemClock.now_millis();
+
+        let flushed = db
+            .get_with_metadata(&"flushed".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!((flushed.val.as_slice(), flushed.seq_no), (&b"a"[..], 0));
+        assert!(
+            flushed
+                .timestamp
+                .is_some_and(|timestamp| (before..=after).contains(&timestamp))
+        );
+        let logged = db
+            .get_with_metadata(&"logged".to_string())
+            .unwrap()
+            .unwrap();
+        db.delete(&"flushed".to_string()).unwrap();
+        assert_eq!(db.get_with_metadata(&"flushed".to_string()).unwrap(), None);
+        drop(db);
+
+        let db = DB::new(Some(config(true))).unwrap();
+        assert_eq!(
+            db.get_with_metadata(&"logged".to_string()).unwrap(),
+            Some(logged)
+        );
+        drop(db);
+
+        // Values written without it have none
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        db.put(&"key".to_string(), &"val".to_string()).unwrap();
+        let untimed = db.get_with_metadata(&"key".to_string()).unwrap().unwrap();
+        assert_eq!(untimed.timestamp, None);
+    }
+
+    #[test]
+    fn hash_linked_mem_table_flushes_sorted_and_replays() {
+        let name = "hash_linked_mem_table_flushes_sorted_and_replays";
+        let config = |preserve| DBConfig {
+            memtable_max_size: Some(3),
+            memtable_kind: MemTableKind::HashLinked,
+            ..test_default_config(name, preserve)
+        };
+        let mut db = DB::new(Some(config(false))).unwrap();
+        for key in ["c", "a", "b", "e", "d"] {
+            db.put(&key.to_string(), &key.to_string()).unwrap();
+        }
+        db.put(&"e".to_string(), &"e2".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+
+        let versions = db.versions().ss_meta.clone();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].smallest_key(), b"a");
+        assert_eq!(versions[0].largest_key(), b"c");
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), Some(b"b".to_vec()));
+        drop(db);
+
+        let db = DB::new(Some(config(true))).unwrap();
+        let keys: Vec<_> = db.mem_table.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![b"d", b"e"]);
+        assert_eq!(db.get_raw(&"e".to_string()).unwrap(), Some(b"e2".to_vec()));
+    }
+
+    #[test]
+    fn sharded_mem_table_flushes_into_one_sorted_table() {
+        let name = "sharded_mem_table_flushes_into_one_sorted_table";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(40);
+        opts.memtable_kind = MemTableKind::Sharded { shards: 4 };
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in (0..40).rev() {
+            db.put(&format!("key{i:02}"), &format!("val{i}")).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+
+        let versions = db.versions().ss_meta.clone();
+        assert_eq!(versions.len(), 1);
+        let reader = db.table_cache.get(&versions[0]).unwrap();
+        let mut iter = reader.iter();
+        iter.seek_to_first().unwrap();
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.next().unwrap();
+        }
+        assert_eq!(
+            keys,
+            (0..40).map(|i| format!("key{i:02}")).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            db.get_raw(&"key17".to_string()).unwrap(),
+            Some(b"val17".to_vec())
+        );
+
+        let mut opts = test_default_config(name, false);
+        opts.memtable_kind = MemTableKind::Sharded { shards: 0 };
+        assert!(matches!(
+            DB::new(Some(opts)),
+            Err(DBError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn bloom_filtered_mem_table_serves_reads_across_flushes() {
+        let name = "bloom_filtered_mem_table_serves_reads_across_flushes";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(50);
+        opts.memtable_bloom_false_positive_rate = Some(0.01);
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in 0..120 {
+            db.put(&format!("key{i:03}"), &format!("val{i}")).unwrap();
+        }
+        db.delete(&"key010".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+
+        assert_eq!(db.versions().ss_meta.len(), 2);
+        assert_eq!(
+            db.get_raw(&"key020".to_string()).unwrap(),
+            Some(b"val20".to_vec())
+        );
+        assert_eq!(
+            db.get_raw(&"key115".to_string()).unwrap(),
+            Some(b"val115".to_vec())
+        );
+        assert_eq!(db.get_raw(&"key010".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"missing".to_string()).unwrap(), None);
+
+        let mut opts = test_default_config(name, false);
+        opts.memtable_bloom_false_positive_rate = Some(1.0);
+        assert!(matches!(
+            DB::new(Some(opts)),
+            Err(DBError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn mem_table_is_flushed_at_write_buffer_size() {
+        let name = "mem_table_is_flushed_at_write_buffer_size";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = None;
+        opts.write_buffer_size = Some(10 * 1024);
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        // Small values stay well below the limit
+        for i in 0..10 {
+            db.put(&format!("small{i}"), &"v".to_string()).unwrap();
+        }
+        assert!(db.versions().ss_meta.is_empty());
+
+        // A few large ones go over it
+        db.put(&"large0".to_string(), &"v".repeat(6 * 1024))
+            .unwrap();
+        assert!(db.versions().ss_meta.is_empty());
+        db.put(&"large1".to_string(), &"v".repeat(6 * 1024))
+            .unwrap();
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 1);
+        assert!(db.mem_table.is_empty());
+        assert_eq!(db.mem_table.size(), 0);
+    }
+
+    #[test]
+    fn puts_over_max_record_len_are_rejected() {
+        let name = "puts_over_max_record_len_are_rejected";
+        let mut opts = test_default_config(name, false);
+        opts.max_record_len = 64;
+        let mut db = DB::new(Some(opts)).unwrap();
+        db.put(&"a".to_string(), &"a".to_string()).unwrap();
+        assert!(matches!(
+            db.put(&"b".to_string(), &"b".repeat(64)),
+            Err(DBError::Codec { .. })
+        ));
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), None);
+        db.put(&"c".to_string(), &"c".to_string()).unwrap();
+        drop(db);
+
+        let mut opts = test_default_config(name, true);
+        opts.max_record_len = 64;
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"c".to_string()).unwrap(), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    fn next_seq_no_is_recovered_on_reopen() {
+        let name = "next_seq_no_is_recovered_on_reopen";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(2);
+        let mut db = DB::new(Some(opts)).unwrap();
+        // Flushed, and so gone from the WAL
+        db.put(&"a".to_string(), &"a".to_string()).unwrap();
+        db.put(&"b".to_string(), &"b".to_string()).unwrap();
+        // Only in the WAL
+        db.put(&"c".to_string(), &"c".to_string()).unwrap();
+        drop(db);
+
+        let mut opts = test_default_config(name, true);
+        opts.memtable_max_size = Some(2);
+        let mut db = DB::new(Some(opts)).unwrap();
+        assert_eq!(db.next_seq_no, 3);
+        assert_eq!(db.wal_replay_report().last_seq_no, Some(2));
+        assert_eq!(db.wal_replay_report().progress.records, 1);
+
+        // Overwrites must win over what was replayed
+        db.put(&"c".to_string(), &"c2".to_string()).unwrap();
+        assert_eq!(db.get_raw(&"c".to_string()).unwrap(), Some(b"c2".to_vec()));
+        db.put(&"d".to_string(), &"d".to_string()).unwrap();
+        drop(db);
+
+        // Everything was flushed, the manifest alone knows where sequence numbers got to
+        let mut opts = test_default_config(name, true);
+        opts.memtable_max_size = Some(2);
+        let db = DB::new(Some(opts)).unwrap();
+        assert!(db.mem_table.is_empty());
+        assert_eq!(db.next_seq_no, 5);
+    }
+
+    #[test]
+    fn recycled_wal_segments_only_replay_unflushed_writes() {
+        let name = "recycled_wal_segments_only_replay_unflushed_writes";
+        let config = |preserve_wal| {
+            let mut opts = test_default_config(name, preserve_wal);
+            opts.memtable_max_size = Some(2);
+            opts.wal_segment_size = 4096;
+            opts.wal_preallocate = true;
+            opts.wal_recycle_files = 2;
+            opts
+        };
+        let mut db = DB::new(Some(config(false))).unwrap();
+        // Every flush truncates the WAL, so the segments written before it are recycled by the next ones
+        for key in ["a", "b", "c", "d", "e"] {
+            db.put(&key.to_string(), &key.to_string()).unwrap();
+        }
+        drop(db);
+
+        let db = DB::new(Some(config(true))).unwrap();
+        let keys: Vec<_> = db.mem_table.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![b"e"]);
+        assert_eq!(db.next_seq_no, 5);
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), Some(b"a".to_vec()));
+    }
+
+    #[test]
+    fn delete_is_replayed_from_the_wal() {
+        let name = "delete_is_replayed_from_the_wal";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(2);
+        let mut db = DB::new(Some(opts)).unwrap();
+        db.put(&"a".to_string(), &"a".to_string()).unwrap();
+        db.put(&"b".to_string(), &"b".to_string()).unwrap();
+        // "a" is only in the table by now, the tombstone only in the MemTable and the WAL
+        db.delete(&"a".to_string()).unwrap();
+        drop(db);
+
+        let mut opts = test_default_config(name, true);
+        opts.memtable_max_size = Some(2);
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(
+            db.mem_table.get(b"a".as_slice()),
+            Some(&Entry::Tombstone { seq_no: 2 })
+        );
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn delete_range_is_replayed_from_the_wal() {
+        let name = "delete_range_is_replayed_from_the_wal";
+        let mut db = DB::new(Some(test_default_config(name, false))).unwrap();
+        for key in ["a", "b", "c"] {
+            db.put(&key.to_string(), &key.to_string()).unwrap();
         }
+        db.delete_range(&"a".to_string(), &"c".to_string()).unwrap();
+        drop(db);
 
-        wal_file_opts.open(wal_path.clone())
-        .unwrap();
-        
-        DBConfig {
-            memtable_max_size: Some(1000),
-            ss_table_dir: ss_table_path,
-            wal_file: wal_path,
-            wal_sync_policy: SyncPolicy::Always,
-            ss_l0_compact_threshold: 1000,
-            max_record_len: DEFAULT_MAX_RECORD_LEN,
-            disable_wal_memtable_replay_on_load: false,
+        let db = DB::new(Some(test_default_config(name, true))).unwrap();
+        assert_eq!(db.mem_range_tombstones.len(), 1);
+        assert_eq!(db.get_raw(&"a".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"b".to_string()).unwrap(), None);
+        assert_eq!(db.get_raw(&"c".to_string()).unwrap(), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    fn bottommost_compaction_drops_tombstones_unless_a_snapshot_needs_them() {
+        let compact_with_snapshot = |name: &str, snapshot: Option<u64>| {
+            let mut opts = test_default_config(name, false);
+            opts.memtable_max_size = Some(10);
+            opts.disable_wal_memtable_replay_on_load = true;
+            let mut db = DB::new(Some(opts)).unwrap();
+            if let Some(seq_no) = snapshot {
+                db.versions().snapshots.insert(seq_no, 1);
+            }
+
+            for i in 0..10 {
+                db.put(&format!("key-{i:03}"), &format!("val-{i}")).unwrap();
+            }
+            for i in 0..5 {
+                db.delete(&format!("key-{i:03}")).unwrap();
+                db.put(&format!("key-{:03}", 100 + i), &format!("val-{}", 100 + i))
+                    .unwrap();
+            }
+            db.compact_range(&"key-000".to_string(), &"key-999".to_string())
+                .unwrap();
+
+            for i in 0..5 {
+                assert_eq!(db.get_raw(&format!("key-{i:03}")).unwrap(), None);
+            }
+            db.table_properties().unwrap()
+        };
+
+        let props = compact_with_snapshot("bottommost_compaction_drops_tombstones", None);
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].tombstone_count, 0);
+        assert_eq!(props[0].entry_count, 10);
+
+        // The snapshot is older than the deletes, which therefore have to stay
+        let props = compact_with_snapshot("bottommost_compaction_keeps_tombstones", Some(0));
+        assert_eq!(props[0].tombstone_count, 5);
+        assert_eq!(props[0].entry_count, 15);
+    }
+
+    #[test]
+    fn compaction_filter_drops_and_rewrites_values() {
+        #[derive(Debug)]
+        struct ExpiringFilter;
+
+        impl CompactionFilter for ExpiringFilter {
+            fn name(&self) -> &'static str {
+                "test.ExpiringFilter"
+            }
+
+            fn filter(
+                &self,
+                _level: u32,
+                key: &[u8],
+                value: &[u8],
+            ) -> compaction::CompactionDecision {
+                if value == b"expired" {
+                    compaction::CompactionDecision::Remove
+                } else if key.starts_with(b"upper-") {
+                    compaction::CompactionDecision::ChangeValue(
+                        String::from_utf8_lossy(value).to_uppercase().encode(),
+                    )
+                } else {
+                    compaction::CompactionDecision::Keep
+                }
+            }
         }
+
+        let mut opts = test_default_config("compaction_filter_drops_and_rewrites_values", false);
+        opts.disable_wal_memtable_replay_on_load = true;
+        opts.compaction_filter = Some(Arc::new(ExpiringFilter));
+        let mut db = DB::new(Some(opts)).unwrap();
+        let (first, last) = ("a".to_string(), "z".to_string());
+
+        db.put(&"key".to_string(), &"old".to_string()).unwrap();
+        db.put(&"upper-key".to_string(), &"shout".to_string())
+            .unwrap();
+        db.compact_range(&first, &last).unwrap();
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"upper-key".to_string())
+                .unwrap(),
+            Some("SHOUT".to_string())
+        );
+
+        // Removed above the last level, "key" must not fall back to the "old" value sitting in it
+        db.put(&"key".to_string(), &"expired".to_string()).unwrap();
+        db.compact_range(&first, &last).unwrap();
+        assert_eq!(db.get_raw(&"key".to_string()).unwrap(), None);
+
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].entry_count, 1);
+        assert_eq!(props[0].tombstone_count, 0);
     }
 
     #[test]
-    fn insert_and_get() {
-        let mut db = DB::new(Some(test_default_config("insert_and_get", false))).unwrap();
+    fn merge_operands_fold_on_reads_compactions_and_replay() {
+        /// Appends operands to a comma separated list.
+        #[derive(Debug)]
+        struct Append;
 
-        let key: TestEncoder = "key-1".to_string();
-        let val: TestEncoder = "some-val".to_string();
+        impl MergeOperator for Append {
+            fn name(&self) -> &'static str {
+                "test.Append"
+            }
 
-        assert_eq!(db.next_seq_no, 0);
+            fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+                match existing {
+                    Some(existing) => [existing, b",", operand].concat(),
+                    None => operand.to_vec(),
+                }
+            }
+        }
 
-        db.put(&key, &val);
+        let name = "merge_operands_fold_on_reads_compactions_and_replay";
+        let open = |preserve| {
+            let mut opts = test_default_config(name, preserve);
+            opts.merge_operator = Some(Arc::new(Append));
+            DB::new(Some(opts)).unwrap()
+        };
+        let mut db = open(false);
+        let (list, fresh) = ("list".to_string(), "fresh".to_string());
+        let get = |db: &DB, key: &String| db.get_typed::<TestEncoder, TestEncoder>(key).unwrap();
 
-        assert_eq!(db.mem_table.len(), 1);
-        assert_eq!(db.next_seq_no, 1);
+        // Operands stack up in the MemTable over a value in a table, and again once flushed
+        db.put(&list, &"a".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        db.merge(&list, &"b".to_string()).unwrap();
+        db.merge(&list, &"c".to_string()).unwrap();
+        assert_eq!(get(&db, &list).as_deref(), Some("a,b,c"));
+        db.flush_mem_table().unwrap();
+        db.merge(&list, &"d".to_string()).unwrap();
+        assert_eq!(get(&db, &list).as_deref(), Some("a,b,c,d"));
+        let mut batch = WriteBatch::new();
+        batch.merge(&fresh, &"x".to_string());
+        db.write(batch, &WriteOptions::default()).unwrap();
 
-        let kbytes = key.encode();
-        let vbytes = val.encode();
+        let scanned: Vec<KeyValue> = db.iter().map(Result::unwrap).collect();
+        assert_eq!(
+            scanned,
+            [
+                (b"fresh".to_vec(), b"x".to_vec()),
+                (b"list".to_vec(), b"a,b,c,d".to_vec())
+            ]
+        );
+        let found = db.multi_get(&[list.clone(), fresh.clone()]);
+        assert_eq!(found[0].as_ref().unwrap().as_deref(), Some(&b"a,b,c,d"[..]));
+        assert_eq!(found[1].as_ref().unwrap().as_deref(), Some(&b"x"[..]));
+
+        // Compaction folds the operands into the value underneath
+        db.flush_mem_table().unwrap();
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(get(&db, &list).as_deref(), Some("a,b,c,d"));
+        let props = db.table_properties().unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].raw_value_bytes, "a,b,c,d".len() as u64 + 1);
 
+        // A range deletion leaves the operands after it nothing to apply to, while a snapshot taken before
+        // keeps reading the value it saw, compactions included
+        let snapshot = db.snapshot().unwrap();
+        db.merge(&list, &"e".to_string()).unwrap();
+        db.delete_range(&"l".to_string(), &"m".to_string()).unwrap();
+        db.merge(&list, &"f".to_string()).unwrap();
+        assert_eq!(get(&db, &list).as_deref(), Some("f"));
         assert_eq!(
-            db.mem_table.get(kbytes.as_slice()),
-            Some(&Entry::Value {
-                seq_no: 0,
-                val: vbytes
-            })
+            db.get_raw_at(&list, &snapshot).unwrap(),
+            Some(b"a,b,c,d".to_vec())
+        );
+        db.flush_mem_table().unwrap();
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(get(&db, &list).as_deref(), Some("f"));
+        assert_eq!(
+            db.get_raw_at(&list, &snapshot).unwrap(),
+            Some(b"a,b,c,d".to_vec())
         );
+        drop(snapshot);
 
-        // Add Next Key
-        {
-            let key_2: TestEncoder = "key-2".to_string();
-            let val_2: TestEncoder = "some-val_2".to_string();
+        // Operands written since the last flush are replayed from the WAL
+        db.merge(&fresh, &"y".to_string()).unwrap();
+        drop(db);
+        let mut db = open(true);
+        assert_eq!(get(&db, &fresh).as_deref(), Some("x,y"));
+        assert_eq!(get(&db, &list).as_deref(), Some("f"));
 
-            assert_eq!(db.next_seq_no, 1);
+        db.opts.merge_operator = None;
+        assert!(matches!(
+            db.merge(&fresh, &"z".to_string()),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        assert!(matches!(
+            db.get_raw(&fresh),
+            Err(DBError::InvalidConfig { .. })
+        ));
+    }
 
-            db.put(&key_2, &val_2);
+    /// Adds up merge operands written as decimal numbers.
+    #[derive(Debug)]
+    struct Counter;
 
-            assert_eq!(db.mem_table.len(), 2);
-            assert_eq!(db.next_seq_no, 2);
+    impl MergeOperator for Counter {
+        fn name(&self) -> &'static str {
+            "test.Counter"
+        }
 
-            let kbytes = key_2.encode();
-            let vbytes = val_2.encode();
+        fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+            let read =
+                |bytes: &[u8]| -> u64 { std::str::from_utf8(bytes).unwrap().parse().unwrap() };
+            (existing.map_or(0, read) + read(operand))
+                .to_string()
+                .into_bytes()
+        }
+    }
+
+    #[test]
+    fn merge_operands_over_a_snapshot_are_counted_once() {
+        let name = "merge_operands_over_a_snapshot_are_counted_once";
+        let mut opts = test_default_config(name, false);
+        opts.merge_operator = Some(Arc::new(Counter));
+        let mut db = DB::new(Some(opts)).unwrap();
+        let (key, one) = ("counter".to_string(), "1".to_string());
+        let get = |db: &DB| db.get_typed::<TestEncoder, TestEncoder>(&key).unwrap();
+
+        db.merge(&key, &one).unwrap();
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(get(&db).as_deref(), Some("1"));
+
+        // The second operand is set aside for the snapshot, the third is not folded into it
+        db.merge(&key, &one).unwrap();
+        let snapshot = db.snapshot().unwrap();
+        db.merge(&key, &one).unwrap();
+        assert_eq!(get(&db).as_deref(), Some("3"));
+        assert_eq!(db.get_raw_at(&key, &snapshot).unwrap(), Some(b"2".to_vec()));
+        let scanned: Vec<KeyValue> = db.iter().map(Result::unwrap).collect();
+        assert_eq!(scanned, [(b"counter".to_vec(), b"3".to_vec())]);
+        db.flush_mem_table().unwrap();
+        assert_eq!(get(&db).as_deref(), Some("3"));
+        assert_eq!(
+            db.multi_get(std::slice::from_ref(&key))[0]
+                .as_ref()
+                .unwrap(),
+            &Some(b"3".to_vec())
+        );
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(get(&db).as_deref(), Some("3"));
+        assert_eq!(db.get_raw_at(&key, &snapshot).unwrap(), Some(b"2".to_vec()));
+
+        drop(snapshot);
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(get(&db).as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn merge_operands_onto_an_expired_value_start_from_nothing() {
+        let name = "merge_operands_onto_an_expired_value_start_from_nothing";
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let open = |preserve| {
+            let mut opts = test_default_config(name, preserve);
+            opts.clock = clock.clone();
+            opts.merge_operator = Some(Arc::new(Counter));
+            DB::new(Some(opts)).unwrap()
+        };
+        let get = |db: &DB, key: &str| {
+            db.get_with_metadata(&key.to_string())
+                .unwrap()
+                .map(|found| (found.val, found.expires_at))
+        };
+        let (five, one) = ("5".to_string(), "1".to_string());
+
+        // Folded in the MemTable as the operand is written, and on reads over a table
+        let mut db = open(false);
+        let (in_mem, in_table) = ("in_mem".to_string(), "in_table".to_string());
+        db.put_with_ttl(&in_mem, &five, Duration::from_secs(1))
+            .unwrap();
+        db.put_with_ttl(&in_table, &five, Duration::from_secs(1))
+            .unwrap();
+        db.flush_mem_table().unwrap();
+        db.put_with_ttl(&in_mem, &five, Duration::from_secs(1))
+            .unwrap();
+        clock.advance(Duration::from_secs(2));
+        db.merge(&in_mem, &one).unwrap();
+        db.merge(&in_table, &one).unwrap();
+        assert_eq!(get(&db, "in_mem"), Some((b"1".to_vec(), None)));
+        assert_eq!(get(&db, "in_table"), Some((b"1".to_vec(), None)));
+
+        // And by compaction, and on replay
+        db.flush_mem_table().unwrap();
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(get(&db, "in_table"), Some((b"1".to_vec(), None)));
+        let replayed = "replayed".to_string();
+        db.put_with_ttl(&replayed, &five, Duration::from_secs(1))
+            .unwrap();
+        clock.advance(Duration::from_secs(2));
+        db.merge(&replayed, &one).unwrap();
+        drop(db);
+        let db = open(true);
+        assert_eq!(get(&db, "replayed"), Some((b"1".to_vec(), None)));
+        assert_eq!(get(&db, "in_mem"), Some((b"1".to_vec(), None)));
+    }
+
+    #[test]
+    fn non_overlapping_tables_are_moved_down_without_a_rewrite() {
+        let name = "non_overlapping_tables_are_moved_down_without_a_rewrite";
+        let open = |preserve| {
+            let mut opts = test_default_config(name, preserve);
+            opts.disable_wal_memtable_replay_on_load = true;
+            DB::new(Some(opts)).unwrap()
+        };
+        let last_level = compaction::NUM_LEVELS - 1;
+
+        let mut db = open(false);
+        db.put(&"a".to_string(), &"1".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        let file_no = db.versions().ss_meta[0].file_no();
+
+        db.compact_range(&"a".to_string(), &"z".to_string())
+            .unwrap();
+        assert_eq!(db.versions().ss_meta[0].file_no(), file_no);
+        assert_eq!(db.versions().ss_meta[0].level(), last_level);
+        assert_eq!(db.table_properties().unwrap()[0].level, last_level);
+        drop(db);
 
+        let mut db = open(true);
+        assert_eq!(db.versions().ss_meta[0].file_no(), file_no);
+        assert_eq!(db.versions().ss_meta[0].level(), last_level);
+
+        // A table holding a tombstone is rewritten instead, which drops the tombstone altogether
+        db.delete(&"b".to_string()).unwrap();
+        db.compact_range(&"b".to_string(), &"b".to_string())
+            .unwrap();
+        let versions = db.versions();
+        assert_eq!(versions.ss_meta.len(), 1);
+        assert_eq!(versions.ss_meta[0].file_no(), file_no);
+    }
+
+    #[test]
+    fn subcompactions_split_the_output_into_disjoint_tables() {
+        let mut opts = test_default_config(
+            "subcompactions_split_the_output_into_disjoint_tables",
+            false,
+        );
+        opts.memtable_max_size = Some(10);
+        opts.max_subcompactions = 3;
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for round in 0..2 {
+            for i in 0..30 {
+                db.put(&format!("key-{i:03}"), &format!("val-{i}-{round}"))
+                    .unwrap();
+            }
+        }
+        db.compact_range(&"key-000".to_string(), &"key-999".to_string())
+            .unwrap();
+
+        {
+            let versions = db.versions();
+            assert_eq!(versions.ss_meta.len(), 3);
+            let mut ranges: Vec<(&[u8], &[u8])> = versions
+                .ss_meta
+                .iter()
+                .map(|meta| (meta.smallest_key(), meta.largest_key()))
+                .collect();
+            ranges.sort();
+            assert!(ranges.windows(2).all(|pair| pair[0].1 < pair[1].0));
+        }
+        let entries: u64 = db
+            .table_properties()
+            .unwrap()
+            .iter()
+            .map(|p| p.entry_count)
+            .sum();
+        assert_eq!(entries, 30);
+
+        for i in 0..30 {
             assert_eq!(
-                db.mem_table.get(kbytes.as_slice()),
-                Some(&Entry::Value {
-                    seq_no: 1,
-                    val: vbytes
-                })
-            )
+                db.get_typed::<TestEncoder, TestEncoder>(&format!("key-{i:03}"))
+                    .unwrap(),
+                Some(format!("val-{i}-1"))
+            );
         }
     }
 
     #[test]
-    fn insert_empty_key() {
-        let mut db = DB::new(Some(test_default_config("insert_empty_key", false))).unwrap();
+    fn custom_compaction_picker_decides_what_to_compact() {
+        // Compacts as soon as there are two L0 tables, but names only the newest of them
+        #[derive(Debug)]
+        struct EagerPicker;
 
-        let key: TestEncoder = "".to_string();
-        let val: TestEncoder = "s1".to_string();
+        impl CompactionPicker for EagerPicker {
+            fn name(&self) -> &'static str {
+                "test.EagerPicker"
+            }
 
-        let res = db.put(&key, &val);
+            fn pick(&self, tables: &[SSTableMeta]) -> Option<compaction::CompactionPick> {
+                let l0: Vec<&SSTableMeta> =
+                    tables.iter().filter(|meta| meta.level() == 0).collect();
+                (l0.len() >= 2).then(|| compaction::CompactionPick {
+                    level: 0,
+                    output_level: 1,
+                    file_nos: vec![l0[0].file_no()],
+                })
+            }
+        }
 
-        matches!(
-            res.err(),
-            Some(DBError::Codec {
-                context: _,
-                source: _
-            })
+        let mut opts =
+            test_default_config("custom_compaction_picker_decides_what_to_compact", false);
+        opts.memtable_max_size = Some(10);
+        opts.compaction_picker = Some(Arc::new(EagerPicker));
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for round in 0..2 {
+            for i in 0..10 {
+                db.put(&format!("key-{i:03}"), &format!("val-{i}-{round}"))
+                    .unwrap();
+            }
+        }
+        db.wait_for_compactions();
+
+        // Both L0 tables went, leaving the older one behind would shadow the newer values
+        {
+            let versions = db.versions();
+            assert_eq!(versions.ss_meta.len(), 1);
+            assert_eq!(versions.ss_meta[0].level(), 1);
+        }
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"key-003".to_string())
+                .unwrap(),
+            Some("val-3-1".to_string())
         );
     }
 
     #[test]
-    fn insert_duplicate_and_get() {
-        let mut db = DB::new(Some(test_default_config("insert_duplicate_and_get", false))).unwrap();
+    fn intra_l0_compaction_merges_l0_into_a_single_l0_table() {
+        let mut opts = test_default_config(
+            "intra_l0_compaction_merges_l0_into_a_single_l0_table",
+            false,
+        );
+        opts.memtable_max_size = Some(10);
+        opts.ss_l0_intra_compact_threshold = Some(2);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
 
-        let key: TestEncoder = "k1".to_string();
-        let val: TestEncoder = "s1".to_string();
+        for round in 0..2 {
+            for i in 0..10 {
+                db.put(&format!("key-{i:03}"), &format!("val-{i}-{round}"))
+                    .unwrap();
+            }
+        }
+        db.wait_for_compactions();
+
+        {
+            let versions = db.versions();
+            assert_eq!(versions.ss_meta.len(), 1);
+            assert_eq!(versions.ss_meta[0].level(), 0);
+        }
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"key-003".to_string())
+                .unwrap(),
+            Some("val-3-1".to_string())
+        );
+        assert_eq!(db.compaction_stats().levels[0].compactions, 1);
+    }
 
-        db.put(&key, &val);
+    #[test]
+    fn intra_l0_compaction_racing_a_flush_stays_beneath_it() {
+        use std::sync::mpsc;
 
-        let kbytes = key.encode();
-        let vbytes = val.encode();
+        let mut opts =
+            test_default_config("intra_l0_compaction_racing_a_flush_stays_beneath_it", false);
+        opts.memtable_max_size = Some(1);
+        opts.ss_l0_intra_compact_threshold = Some(2);
+        opts.disable_wal_memtable_replay_on_load = true;
+        // Once armed, holds the next flush up mid-write until released
+        let gate = Arc::new(Mutex::new(None::<(mpsc::Sender<()>, mpsc::Receiver<()>)>));
+        let armed = gate.clone();
+        opts.on_flush_progress = Some(Arc::new(move |_: &WriterProgress| {
+            let held = armed.lock().unwrap().take();
+            if let Some((entered, release)) = held {
+                let _ = entered.send(());
+                let _ = release.recv();
+            }
+        }));
+        let mut db = DB::new(Some(opts)).unwrap();
+        let key = "key".to_string();
 
+        db.pause_compactions();
+        db.put(&"other".to_string(), &"val".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+        db.put(&key, &"v1".to_string()).unwrap();
+        db.wait_for_flush().unwrap();
+
+        // The flush of v2 takes its file number before the compaction of the older tables starts, so the
+        // compaction's output gets the higher one
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        *gate.lock().unwrap() = Some((entered_tx, release_rx));
+        db.put(&key, &"v2".to_string()).unwrap();
+        entered_rx.recv().unwrap();
+        db.resume_compactions();
+        let started = std::time::Instant::now();
+        while db.compaction_stats().levels[0].compactions == 0 {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        release_tx.send(()).unwrap();
+        db.wait_for_flush().unwrap();
+
+        {
+            let versions = db.versions();
+            assert_eq!(versions.ss_meta.len(), 2);
+            assert!(versions.ss_meta[0].file_no() < versions.ss_meta[1].file_no());
+        }
         assert_eq!(
-            db.mem_table.get(kbytes.as_slice()),
-            Some(&Entry::Value {
-                seq_no: 0,
-                val: vbytes
-            })
+            db.get_typed::<TestEncoder, TestEncoder>(&key).unwrap(),
+            Some("v2".to_string())
         );
+        let scanned: Vec<KeyValue> = db.iter().map(Result::unwrap).collect();
+        assert_eq!(scanned[0], (key.encode(), b"v2".to_vec()));
+    }
 
-        //WHEN: Add duplicate key
-        {
-            let dup_key: TestEncoder = "k1".to_string();
-            let val_2: TestEncoder = "s2".to_string();
+    #[test]
+    fn writes_are_stopped_while_l0_is_too_deep() {
+        let mut opts = test_default_config("writes_are_stopped_while_l0_is_too_deep", false);
+        opts.memtable_max_size = Some(10);
+        opts.l0_slowdown_writes_trigger = Some(1);
+        opts.write_stall_delay = Duration::ZERO;
+        opts.l0_stop_writes_trigger = Some(2);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
 
-            db.put(&dup_key, &val_2);
+        for i in 0..20 {
+            db.put(&format!("key-{i:03}"), &"val".to_string()).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 2);
 
-            assert_eq!(db.next_seq_no, 2);
-            assert_eq!(db.mem_table.len(), 1);
+        let key = "key-020".to_string();
+        assert!(matches!(
+            db.put(&key, &"val".to_string()),
+            Err(DBError::Busy { .. })
+        ));
+        assert!(matches!(db.delete(&key), Err(DBError::Busy { .. })));
+        assert_eq!(db.get_raw(&key).unwrap(), None);
 
-            let dup_key_bytes = dup_key.encode();
-            let val_bytes = val_2.encode();
+        // Writes go through again once compaction has emptied L0
+        db.compact_range(&"key-000".to_string(), &"key-999".to_string())
+            .unwrap();
+        db.put(&key, &"val".to_string()).unwrap();
+        drop(db);
 
-            assert_eq!(
-                db.mem_table.get(&dup_key_bytes),
-                Some(&Entry::Value {
-                    seq_no: 1,
-                    val: val_bytes.clone()
-                })
-            );
-            assert_eq!(
-                db.mem_table.get(&kbytes),
-                Some(&Entry::Value {
-                    seq_no: 1,
-                    val: val_bytes
-                })
-            )
+        let mut opts = test_default_config("writes_are_stopped_while_l0_is_too_deep", false);
+        opts.l0_slowdown_writes_trigger = Some(2);
+        opts.l0_stop_writes_trigger = Some(2);
+        assert!(matches!(
+            DB::new(Some(opts)),
+            Err(DBError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn compaction_stats_report_levels_and_amplification() {
+        let mut opts =
+            test_default_config("compaction_stats_report_levels_and_amplification", false);
+        opts.memtable_max_size = Some(10);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        for round in 0..3 {
+            for i in 0..10 {
+                db.put(&format!("key-{i:03}"), &format!("val-{i}-{round}"))
+                    .unwrap();
+            }
+        }
+        db.wait_for_flush().unwrap();
+        let stats = db.compaction_stats();
+        assert_eq!(stats.flushes, 3);
+        assert_eq!(stats.levels[0].files, 3);
+        assert_eq!(stats.read_amplification(), 3);
+        assert_eq!(stats.write_amplification(), 1.0);
+        let flushed = stats.flush_bytes_written;
+        assert_eq!(stats.levels[0].bytes, flushed);
+
+        db.compact_range(&"key-000".to_string(), &"key-999".to_string())
+            .unwrap();
+        let stats = db.compaction_stats();
+        assert_eq!(stats.levels[0].files, 0);
+        assert_eq!(stats.read_amplification(), 1);
+
+        // L0 was merged into L1, from where the single output was moved on down
+        let l1 = &stats.levels[1];
+        assert_eq!(l1.compactions, 1);
+        assert_eq!(l1.bytes_read_from_upper_level, flushed);
+        assert!(l1.bytes_written > 0 && l1.bytes_written < flushed);
+        assert!(l1.write_amplification() < 1.0);
+
+        let last = &stats.levels[compaction::NUM_LEVELS as usize - 1];
+        assert_eq!((last.files, last.compactions), (1, 0));
+        assert_eq!(last.bytes_moved, l1.bytes_written);
+        assert!(stats.write_amplification() > 1.0);
+    }
+
+    #[test]
+    fn paused_compactions_run_once_resumed() {
+        let mut opts = test_default_config("paused_compactions_run_once_resumed", false);
+        opts.memtable_max_size = Some(10);
+        opts.ss_l0_compact_threshold = 1;
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+        let l0_tables = |db: &DB| {
+            db.versions()
+                .ss_meta
+                .iter()
+                .filter(|meta| meta.level() == 0)
+                .count()
+        };
+
+        db.pause_compactions();
+        db.pause_compactions();
+        for i in 0..30 {
+            db.put(&format!("key-{i:03}"), &"val".to_string()).unwrap();
         }
+        db.wait_for_compactions();
+        assert_eq!(l0_tables(&db), 3);
+        assert!(matches!(
+            db.compact_range(&"a".to_string(), &"z".to_string()),
+            Err(DBError::Busy { .. })
+        ));
+
+        // Still paused once more
+        db.resume_compactions();
+        db.wait_for_compactions();
+        assert_eq!(l0_tables(&db), 3);
+
+        db.resume_compactions();
+        db.wait_for_compactions();
+        assert_eq!(l0_tables(&db), 0);
+
+        // Dropping a paused DB must not hang on the held back compaction
+        db.pause_compactions();
+        db.put(&"key-100".to_string(), &"val".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
     }
 
     #[test]
-    fn delete_empty() {
-        // This should error - prevent any change that db.seq_no increases
-        assert!(false)
+    fn compaction_plan_matches_what_compaction_then_does() {
+        let mut opts =
+            test_default_config("compaction_plan_matches_what_compaction_then_does", false);
+        opts.memtable_max_size = Some(10);
+        opts.ss_l0_compact_threshold = 1;
+        opts.level_base_size = 1;
+        opts.level_multiplier = 1;
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        db.pause_compactions();
+        for i in 0..20 {
+            db.put(&format!("key-{i:03}"), &"val".to_string()).unwrap();
+        }
+        db.wait_for_flush().unwrap();
+        let l0: Vec<SSTableMeta> = db.versions().ss_meta.clone();
+        let next_file_no = db.versions().manifest.next_file_no();
+
+        // L0 goes into L1, which is then over its one byte target and so on down to the last level
+        let plan = db.compaction_plan();
+        assert_eq!(plan.len(), compaction::NUM_LEVELS as usize - 1);
+        assert_eq!(plan[0].inputs, l0);
+        assert_eq!(plan[0].output_level, 1);
+        assert_eq!(
+            plan[0].estimated_output_size,
+            l0.iter().map(|meta| meta.file_size()).sum::<u64>()
+        );
+        for (step, planned) in plan.iter().enumerate().skip(1) {
+            assert_eq!(planned.output_level, step as u32 + 1);
+            assert_eq!(planned.inputs.len(), 1);
+            assert_eq!(planned.inputs[0].file_no(), next_file_no + step as u64 - 1);
+        }
+
+        // Planning changes nothing
+        assert_eq!(db.versions().ss_meta, l0);
+        db.resume_compactions();
+        db.wait_for_compactions();
+        assert!(db.compaction_plan().is_empty());
+        assert_eq!(db.versions().ss_meta[0].level(), compaction::NUM_LEVELS - 1);
     }
 
     #[test]
-    fn delete_empty_key() {
-        // 1. This should error - empty key violation
-        assert!(false)
+    fn periodic_compactions_go_by_the_configured_clock() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut opts =
+            test_default_config("periodic_compactions_go_by_the_configured_clock", false);
+        opts.clock = clock.clone();
+        opts.periodic_compaction_age = Some(Duration::from_secs(3600));
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        db.pause_compactions();
+        db.put(&"key".to_string(), &"val".to_string()).unwrap();
+        db.flush_mem_table().unwrap();
+        assert_eq!(db.versions().ss_meta[0].creation_time(), 1_000);
+        assert!(db.compaction_plan().is_empty());
+
+        clock.advance(Duration::from_secs(3601));
+        let plan = db.compaction_plan();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].reason, compaction::CompactionReason::Periodic);
+
+        // The rewritten table is as old as the clock says, so it is left alone from then on
+        db.resume_compactions();
+        db.wait_for_compactions();
+        assert_eq!(db.versions().ss_meta[0].level(), 1);
+        assert_eq!(db.versions().ss_meta[0].creation_time(), 4_601);
+        assert!(db.compaction_plan().is_empty());
     }
 
     #[test]
-    fn delete_on_key_that_doesnt_exist() {
-        // 1. This should error - no precedent to return anything - if some this means i always need to return something on delete, this isn't stable and
-        // hard to reason about with both memtable & sstable
-        assert!(false)
+    fn background_compaction_errors_are_reported() {
+        let mut opts = test_default_config("background_compaction_errors_are_reported", false);
+        opts.memtable_max_size = Some(2);
+        opts.ss_l0_compact_threshold = 1;
+        opts.disable_wal_memtable_replay_on_load = true;
+        let mut db = DB::new(Some(opts)).unwrap();
+
+        db.put(&"a".to_string(), &"a".to_string()).unwrap();
+        db.put(&"b".to_string(), &"b".to_string()).unwrap();
+        db.wait_for_compactions();
+        assert!(db.take_background_error().is_none());
+
+        // The next compaction can't read its first input
+        let lost = db.versions().ss_meta[0].clone();
+        std::fs::remove_file(lost.path()).unwrap();
+        db.put(&"c".to_string(), &"c".to_string()).unwrap();
+        db.put(&"d".to_string(), &"d".to_string()).unwrap();
+        db.wait_for_compactions();
+
+        assert!(matches!(
+            db.take_background_error(),
+            Some(DBError::Io { .. })
+        ));
+        assert!(db.take_background_error().is_none());
+        // The failed compaction left its inputs in place
+        assert_eq!(db.versions().ss_meta.len(), 2);
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"d".to_string())
+                .unwrap(),
+            Some("d".to_string())
+        );
+
+        db.put(&"e".to_string(), &"e".to_string()).unwrap();
+        db.put(&"f".to_string(), &"f".to_string()).unwrap();
+        assert!(matches!(db.close(), Err(DBError::Io { .. })));
     }
 
     #[test]
-    fn delete_ok() {
-        assert!(false)
+    fn reopen_removes_partially_written_ss_tables() {
+        let mut opts = test_default_config("reopen_removes_partially_written_ss_tables", false);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let ss_table_dir = opts.ss_table_dir.clone();
+        drop(DB::new(Some(opts)).unwrap());
+
+        let tmp = crate::sstable::tmp_path(&ss_table_dir.join("000001.sst"));
+        std::fs::write(&tmp, b"half a table").unwrap();
+
+        let mut opts = test_default_config("reopen_removes_partially_written_ss_tables", true);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let db = DB::new(Some(opts)).unwrap();
+
+        assert!(!tmp.exists());
+        assert!(db.versions().ss_meta.is_empty());
+        assert_eq!(db.versions().manifest.next_file_no(), 1);
     }
 
     #[test]
-    fn insert_delete_insert_ok() {
-        assert!(false)
+    fn reopen_reconciles_ss_table_dir_with_manifest() {
+        let name = "reopen_reconciles_ss_table_dir_with_manifest";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(2);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let ss_table_dir = opts.ss_table_dir.clone();
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in 0..4 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+        drop(db);
+        assert!(ss_table_dir.join("000001.sst").exists());
+
+        // A table that was renamed into place but never made it into the manifest
+        let orphan = ss_table_dir.join("000007.sst");
+        std::fs::copy(ss_table_dir.join("000001.sst"), &orphan).unwrap();
+
+        let mut opts = test_default_config(name, true);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let db = DB::new(Some(opts)).unwrap();
+        assert!(!orphan.exists());
+        assert_eq!(db.versions().ss_meta.len(), 2);
+        // The orphan's number is never handed out again
+        assert_eq!(db.versions().manifest.next_file_no(), 8);
+        drop(db);
+
+        std::fs::remove_file(ss_table_dir.join("000002.sst")).unwrap();
+        let mut opts = test_default_config(name, true);
+        opts.disable_wal_memtable_replay_on_load = true;
+        assert!(matches!(
+            DB::new(Some(opts)),
+            Err(DBError::Corruption { .. })
+        ));
+    }
+
+    #[test]
+    fn verify_ss_tables_on_open_catches_bit_rot() {
+        let name = "verify_ss_tables_on_open_catches_bit_rot";
+        let mut opts = test_default_config(name, false);
+        opts.memtable_max_size = Some(2);
+        opts.disable_wal_memtable_replay_on_load = true;
+        let ss_table_dir = opts.ss_table_dir.clone();
+        let mut db = DB::new(Some(opts)).unwrap();
+        for i in 0..2 {
+            db.put(&format!("key-{i}"), &format!("val-{i}")).unwrap();
+        }
+        drop(db);
+
+        let reopen = |verify| {
+            let mut opts = test_default_config(name, true);
+            opts.disable_wal_memtable_replay_on_load = true;
+            opts.verify_ss_tables_on_open = verify;
+            DB::new(Some(opts))
+        };
+        reopen(true).unwrap();
+
+        // Flip a byte inside the data block, opening without verification does not notice
+        let path = ss_table_dir.join("000001.sst");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[30] ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(reopen(false).is_ok());
+        assert!(matches!(
+            reopen(true),
+            Err(DBError::Corruption {
+                what: "sstable: file checksum mismatch",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn open_adopts_ss_tables_without_a_manifest() {
+        let name = "open_adopts_ss_tables_without_a_manifest";
+        let opts = test_default_config(name, false);
+        std::fs::create_dir_all(&opts.ss_table_dir).unwrap();
+        let mut writer = SSTableWriter::new(opts.ss_table_dir.join("3.sst"), 3, 0).unwrap();
+        writer
+            .add(
+                b"key",
+                &Entry::Value {
+                    seq_no: 0,
+                    val: b"val".to_vec(),
+                    timestamp: None,
+                    expires_at: None,
+                },
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let db = DB::new(Some(opts)).unwrap();
+        assert_eq!(db.versions().ss_meta.len(), 1);
+        assert!(db.versions().manifest.contains(3));
+        assert_eq!(db.versions().manifest.next_file_no(), 4);
+        assert_eq!(
+            db.get_typed::<TestEncoder, TestEncoder>(&"key".to_string())
+                .unwrap(),
+            Some("val".to_string())
+        );
     }
 
     #[test]
@@ -424,21 +6241,16 @@ mod tests {
         // Drop the db
         drop(db);
 
-        let mut new_db = DB::new(Some(test_default_config("simulate_replay",true))).unwrap();
+        let new_db = DB::new(Some(test_default_config("simulate_replay", true))).unwrap();
 
-        assert!(
-            matches!(
-                new_db.get_typed::<TestEncoder, TestEncoder>(&key).unwrap(), 
-                Some(val)
-            )
+        assert_eq!(
+            new_db.get_typed::<TestEncoder, TestEncoder>(&key).unwrap(),
+            Some(val)
         );
-        
-        assert!(
-            matches!(
-                new_db.get_typed::<TestEncoder, TestEncoder>(&key2).unwrap(), 
-                Some(val2)
-            )
+
+        assert_eq!(
+            new_db.get_typed::<TestEncoder, TestEncoder>(&key2).unwrap(),
+            Some(val2)
         )
-        
     }
 }
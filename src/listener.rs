@@ -0,0 +1,56 @@
+//! Hooks for watching the DB's background work from the outside, e.g. to export metrics or raise alerts
+//! when compaction keeps failing. Listeners are registered through `DBConfig::event_listeners`.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::compaction::CompactionReason;
+use crate::sstable::SSTableMeta;
+use crate::types::DBError;
+
+/// An EventListener is told whenever a MemTable flush or a compaction starts, finishes or fails. Every
+/// method does nothing by default, so a listener only implements the events it cares about.
+///
+/// Flushes and compactions are both reported from the background worker, which waits for the listener to
+/// return. A listener must therefore be quick, and must not call back into the DB.
+pub trait EventListener: fmt::Debug + Send + Sync {
+    fn on_flush_start(&self, _info: &FlushJobInfo) {}
+
+    fn on_flush_finish(&self, _info: &FlushJobInfo) {}
+
+    /// The flush failed with `error`, the MemTable stays frozen until the flush is retried.
+    fn on_flush_error(&self, _info: &FlushJobInfo, _error: &DBError) {}
+
+    fn on_compaction_start(&self, _info: &CompactionJobInfo) {}
+
+    fn on_compaction_finish(&self, _info: &CompactionJobInfo) {}
+
+    /// The compaction failed with `error`, its inputs are left in place.
+    fn on_compaction_error(&self, _info: &CompactionJobInfo, _error: &DBError) {}
+}
+
+/// A MemTable flush into a new L0 table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushJobInfo {
+    pub file_no: u64,
+    // Entries in the MemTable, range tombstones included
+    pub entries: u64,
+    // The table written, only once the flush has finished
+    pub table: Option<SSTableMeta>,
+    // Zero on start
+    pub elapsed: Duration,
+}
+
+/// A compaction of `inputs` into `output_level`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionJobInfo {
+    pub reason: CompactionReason,
+    pub output_level: u32,
+    pub inputs: Vec<SSTableMeta>,
+    // The tables written, only once the compaction has finished. A trivial move lists the moved table
+    pub outputs: Vec<SSTableMeta>,
+    // Whether the only input was moved down without being rewritten
+    pub trivial_move: bool,
+    // Zero on start
+    pub elapsed: Duration,
+}
@@ -1,5 +1,678 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::comparator::BYTEWISE_COMPARATOR_NAME;
+use crate::sstable::{SSTableMeta, TableProperties, table_file_name};
+use crate::types::{DBError, read_u32_le, read_u64_le, sync_parent_dir};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+/// [len u32][crc u32]
+const RECORD_HEADER_LEN: usize = 4 + 4;
+
+const TAG_ADD_TABLE: u8 = 1;
+const TAG_REMOVE_TABLE: u8 = 2;
+const TAG_NEXT_FILE_NO: u8 = 3;
+const TAG_NEXT_SEQ_NO: u8 = 4;
+const TAG_COLUMN_FAMILY: u8 = 5;
+const TAG_COMPARATOR: u8 = 6;
+
+/// A VersionEdit is a set of changes to the live SSTables that is applied to the manifest atomically, e.g.
+/// a flush adds one table while a compaction removes its inputs and adds its outputs in a single edit.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct VersionEdit {
+    pub(crate) added: Vec<SSTableMeta>,
+    pub(crate) removed: Vec<u64>,
+}
+
 /// The Manifest maintains a record of all the SSTables and provides necessary configuration data to bring back the LSM Tree
-struct Manifest {
-    wal_path: String,
-    ss_table_path: String,
-}
\ No newline at end of file
+///
+/// It is an append-only log of `VersionEdit`s stored as `MANIFEST` in the `ss_table_dir`. Every record is
+///
+/// [len u32][crc u32][edit bytes]
+///
+/// with the crc covering the edit bytes. An edit is a sequence of tagged changes:
+///
+/// [TAG_ADD_TABLE][file_no u64][level u32][min_seq u64][max_seq u64][smallest_len u32][smallest bytes]
+///     [largest_len u32][largest bytes][creation_time u64][file_size u64][file_checksum u32]
+/// [TAG_REMOVE_TABLE][file_no u64]
+/// [TAG_NEXT_FILE_NO][next_file_no u64]
+/// [TAG_NEXT_SEQ_NO][next_seq_no u64]
+/// [TAG_COLUMN_FAMILY][name_len u32][name bytes]
+/// [TAG_COMPARATOR][name_len u32][name bytes]
+///
+/// The manifest also allocates file numbers, so every SSTable is named after a number that is never
+/// reused (see `table_file_name`). It keeps track of the sequence numbers the tables it adds were written
+/// with too, so the DB never hands out a `seq_no` that was already flushed, even once compaction has
+/// dropped the entries that used it. Like them the names of the DB's column families are logged with
+/// every edit, see `DB::create_cf`, and so is the name of the `Comparator` the tables are sorted with. A
+/// manifest that records none was written under `BytewiseComparator`.
+///
+/// A record torn by a crash mid-append is dropped on open since the edit it held never took effect. On
+/// every open the log is rewritten as a single snapshot record so it does not grow without bound.
+pub(crate) struct Manifest {
+    dir: PathBuf,
+    path: PathBuf,
+    file: File,
+    tables: BTreeMap<u64, SSTableMeta>,
+    next_file_no: u64,
+    // One past the highest `seq_no` of any table ever added
+    next_seq_no: u64,
+    column_families: BTreeSet<String>,
+    comparator: String,
+}
+
+impl Manifest {
+    pub(crate) fn exists(dir: &Path) -> bool {
+        dir.join(MANIFEST_FILE_NAME).exists()
+    }
+
+    /// Opens the manifest in `dir`, creating an empty one if there is none, and replays its edits. Fails if
+    /// the tables it holds were sorted by a comparator other than the one named `comparator`.
+    pub(crate) fn open(dir: &Path, comparator: &str) -> Result<Self, DBError> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+
+        let mut tables = BTreeMap::new();
+        let mut next_file_no = 1;
+        let mut next_seq_no = 0;
+        let mut column_families = BTreeSet::new();
+        let mut recorded_comparator = None;
+        if path.exists() {
+            let buf = std::fs::read(&path).map_err(|e| DBError::Io {
+                op: "manifest: failed to read file",
+                path: path.clone(),
+                source: e,
+            })?;
+            replay(
+                dir,
+                &buf,
+                &mut tables,
+                &mut next_file_no,
+                &mut next_seq_no,
+                &mut column_families,
+                &mut recorded_comparator,
+            )?;
+            let recorded = recorded_comparator
+                .as_deref()
+                .unwrap_or(BYTEWISE_COMPARATOR_NAME);
+            if recorded != comparator {
+                return Err(DBError::InvalidConfig {
+                    what: "manifest: DB was written under a different comparator",
+                });
+            }
+        }
+        // Manifests written before `TAG_NEXT_SEQ_NO` existed only have the live tables to go by
+        next_seq_no = next_seq_no.max(seq_no_after(tables.values()));
+
+        let snapshot = VersionEdit {
+            added: tables.values().cloned().collect(),
+            removed: Vec::new(),
+        };
+        let file = write_snapshot(
+            &path,
+            &encode_edit(
+                &snapshot,
+                next_file_no,
+                next_seq_no,
+                &column_families,
+                comparator,
+            ),
+        )?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            path,
+            file,
+            tables,
+            next_file_no,
+            next_seq_no,
+            column_families,
+            comparator: comparator.to_string(),
+        })
+    }
+
+    /// The live SSTables, in `file_no` order.
+    pub(crate) fn tables(&self) -> impl Iterator<Item = &SSTableMeta> {
+        self.tables.values()
+    }
+
+    pub(crate) fn contains(&self, file_no: u64) -> bool {
+        self.tables.contains_key(&file_no)
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn next_file_no(&self) -> u64 {
+        self.next_file_no
+    }
+
+    /// One past the highest `seq_no` any table logged so far was written with.
+    pub(crate) fn next_seq_no(&self) -> u64 {
+        self.next_seq_no
+    }
+
+    /// The names of the column families created so far, in name order.
+    pub(crate) fn column_families(&self) -> impl Iterator<Item = &str> {
+        self.column_families.iter().map(String::as_str)
+    }
+
+    /// Durably records the column family `name`, it is brought back on every open from then on.
+    pub(crate) fn add_column_family(&mut self, name: &str) -> Result<(), DBError> {
+        if !self.column_families.insert(name.to_string()) {
+            return Ok(());
+        }
+        let logged = self.log_edit(VersionEdit::default());
+        if logged.is_err() {
+            self.column_families.remove(name);
+        }
+        logged
+    }
+
+    /// Allocates a new file number. It is persisted along with the next edit that gets logged.
+    pub(crate) fn new_file_no(&mut self) -> u64 {
+        let file_no = self.next_file_no;
+        self.next_file_no += 1;
+        file_no
+    }
+
+    /// Makes sure `file_no` is never handed out, e.g. for a file found on disk that the manifest does not know.
+    pub(crate) fn mark_file_no_used(&mut self, file_no: u64) {
+        self.next_file_no = self.next_file_no.max(file_no + 1);
+    }
+
+    /// The path of the SSTable numbered `file_no`.
+    pub(crate) fn table_path(&self, file_no: u64) -> PathBuf {
+        self.dir.join(table_file_name(file_no))
+    }
+
+    /// Durably appends `edit` to the log and applies it. Once this returns the edit survives a crash.
+    pub(crate) fn log_edit(&mut self, edit: VersionEdit) -> Result<(), DBError> {
+        let next_seq_no = self.next_seq_no.max(seq_no_after(&edit.added));
+        let record = encode_record(&encode_edit(
+            &edit,
+            self.next_file_no,
+            next_seq_no,
+            &self.column_families,
+            &self.comparator,
+        ));
+
+        self.file
+            .write_all(&record)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| DBError::Io {
+                op: "manifest: failed to append edit",
+                path: self.path.clone(),
+                source: e,
+            })?;
+
+        self.next_seq_no = next_seq_no;
+        apply(&mut self.tables, edit);
+
+        Ok(())
+    }
+}
+
+/// One past the highest `seq_no` in any of `tables`.
+fn seq_no_after<'a>(tables: impl IntoIterator<Item = &'a SSTableMeta>) -> u64 {
+    tables
+        .into_iter()
+        .map(|meta| meta.max_seq_no() + 1)
+        .max()
+        .unwrap_or_default()
+}
+
+fn apply(tables: &mut BTreeMap<u64, SSTableMeta>, edit: VersionEdit) {
+    for file_no in edit.removed {
+        tables.remove(&file_no);
+    }
+    for meta in edit.added {
+        tables.insert(meta.file_no(), meta);
+    }
+}
+
+/// Writes `edit` as the only record of a fresh log at `path`, swapping it in atomically, and returns the
+/// log opened for appending.
+fn write_snapshot(path: &Path, edit: &[u8]) -> Result<File, DBError> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let io_err = |op: &'static str, path: &Path| {
+        let path = path.to_path_buf();
+        move |e| DBError::Io {
+            op,
+            path,
+            source: e,
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .map_err(io_err("manifest: failed to create file", &tmp_path))?;
+    file.write_all(&encode_record(edit))
+        .and_then(|_| file.sync_all())
+        .map_err(io_err("manifest: failed to write snapshot", &tmp_path))?;
+    drop(file);
+
+    std::fs::rename(&tmp_path, path)
+        .map_err(io_err("manifest: failed to rename tmp file", &tmp_path))?;
+    sync_parent_dir(path)?;
+
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(io_err("manifest: failed to open file", path))
+}
+
+/// Replays the log read from the manifest in `dir`.
+fn replay(
+    dir: &Path,
+    buf: &[u8],
+    tables: &mut BTreeMap<u64, SSTableMeta>,
+    next_file_no: &mut u64,
+    next_seq_no: &mut u64,
+    column_families: &mut BTreeSet<String>,
+    comparator: &mut Option<String>,
+) -> Result<(), DBError> {
+    let path = dir.join(MANIFEST_FILE_NAME);
+    let mut offset = 0;
+    while offset < buf.len() {
+        let corruption = |what: &'static str| DBError::Corruption {
+            what,
+            path: path.to_path_buf(),
+            offset: offset as u64,
+        };
+
+        let Some(header) = buf.get(offset..offset + RECORD_HEADER_LEN) else {
+            // Torn header, the append never completed
+            break;
+        };
+        let len = read_u32_le(header).unwrap_or_default() as usize;
+        let crc = read_u32_le(&header[4..]).unwrap_or_default();
+
+        let start = offset + RECORD_HEADER_LEN;
+        let Some(payload) = buf.get(start..start + len) else {
+            // Torn payload, the append never completed
+            break;
+        };
+
+        if crc32fast::hash(payload) != crc {
+            // Only the final record can legitimately be torn, anything before it is real damage
+            if start + len == buf.len() {
+                break;
+            }
+            return Err(corruption("manifest: record crc mismatch"));
+        }
+
+        let edit = decode_edit(
+            dir,
+            payload,
+            next_file_no,
+            next_seq_no,
+            column_families,
+            comparator,
+        )
+        .ok_or_else(|| corruption("manifest: malformed edit"))?;
+        apply(tables, edit);
+
+        offset = start + len;
+    }
+
+    Ok(())
+}
+
+fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let len: u32 = payload.len().try_into().expect("manifest edit too large");
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+fn encode_edit(
+    edit: &VersionEdit,
+    next_file_no: u64,
+    next_seq_no: u64,
+    column_families: &BTreeSet<String>,
+    comparator: &str,
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    for meta in &edit.added {
+        buf.push(TAG_ADD_TABLE);
+        buf.extend_from_slice(&meta.file_no().to_le_bytes());
+        buf.extend_from_slice(&meta.level().to_le_bytes());
+        buf.extend_from_slice(&meta.min_seq_no().to_le_bytes());
+        buf.extend_from_slice(&meta.max_seq_no().to_le_bytes());
+        for key in [meta.smallest_key(), meta.largest_key()] {
+            let key_len: u32 = key.len().try_into().expect("key is too large");
+            buf.extend_from_slice(&key_len.to_le_bytes());
+            buf.extend_from_slice(key);
+        }
+        buf.extend_from_slice(&meta.creation_time().to_le_bytes());
+        buf.extend_from_slice(&meta.file_size().to_le_bytes());
+        buf.extend_from_slice(&meta.file_checksum().to_le_bytes());
+    }
+
+    for file_no in &edit.removed {
+        buf.push(TAG_REMOVE_TABLE);
+        buf.extend_from_slice(&file_no.to_le_bytes());
+    }
+
+    buf.push(TAG_NEXT_FILE_NO);
+    buf.extend_from_slice(&next_file_no.to_le_bytes());
+
+    buf.push(TAG_NEXT_SEQ_NO);
+    buf.extend_from_slice(&next_seq_no.to_le_bytes());
+
+    for name in column_families {
+        let name_len: u32 = name
+            .len()
+            .try_into()
+            .expect("column family name is too large");
+        buf.push(TAG_COLUMN_FAMILY);
+        buf.extend_from_slice(&name_len.to_le_bytes());
+        buf.extend_from_slice(name.as_bytes());
+    }
+
+    let comparator_len: u32 = comparator
+        .len()
+        .try_into()
+        .expect("comparator name is too large");
+    buf.push(TAG_COMPARATOR);
+    buf.extend_from_slice(&comparator_len.to_le_bytes());
+    buf.extend_from_slice(comparator.as_bytes());
+
+    buf
+}
+
+fn decode_edit(
+    dir: &Path,
+    buf: &[u8],
+    next_file_no: &mut u64,
+    next_seq_no: &mut u64,
+    column_families: &mut BTreeSet<String>,
+    comparator: &mut Option<String>,
+) -> Option<VersionEdit> {
+    let mut edit = VersionEdit::default();
+    let mut offset = 0;
+
+    while offset < buf.len() {
+        let tag = buf[offset];
+        offset += 1;
+
+        match tag {
+            TAG_ADD_TABLE => {
+                let file_no = read_u64_le(buf.get(offset..)?)?;
+                offset += 8;
+                let level = read_u32_le(buf.get(offset..)?)?;
+                offset += 4;
+                let min_seq_no = read_u64_le(buf.get(offset..)?)?;
+                offset += 8;
+                let max_seq_no = read_u64_le(buf.get(offset..)?)?;
+                offset += 8;
+
+                let mut keys = [Vec::new(), Vec::new()];
+                for key in &mut keys {
+                    let key_len = read_u32_le(buf.get(offset..)?)? as usize;
+                    offset += 4;
+                    *key = buf.get(offset..offset + key_len)?.to_vec();
+                    offset += key_len;
+                }
+                let [smallest_key, largest_key] = keys;
+                let creation_time = read_u64_le(buf.get(offset..)?)?;
+                offset += 8;
+                let file_size = read_u64_le(buf.get(offset..)?)?;
+                offset += 8;
+                let file_checksum = read_u32_le(buf.get(offset..)?)?;
+                offset += 4;
+
+                let props = TableProperties {
+                    level,
+                    min_seq_no,
+                    max_seq_no,
+                    creation_time,
+                    smallest_key,
+                    largest_key,
+                    ..Default::default()
+                };
+                let path = dir.join(table_file_name(file_no));
+                edit.added.push(SSTableMeta::from_properties(
+                    file_no,
+                    path.to_string_lossy().into_owned(),
+                    &props,
+                    file_size,
+                    file_checksum,
+                ));
+            }
+            TAG_REMOVE_TABLE => {
+                edit.removed.push(read_u64_le(buf.get(offset..)?)?);
+                offset += 8;
+            }
+            TAG_NEXT_FILE_NO => {
+                *next_file_no = (*next_file_no).max(read_u64_le(buf.get(offset..)?)?);
+                offset += 8;
+            }
+            TAG_NEXT_SEQ_NO => {
+                *next_seq_no = (*next_seq_no).max(read_u64_le(buf.get(offset..)?)?);
+                offset += 8;
+            }
+            TAG_COLUMN_FAMILY => {
+                let name_len = read_u32_le(buf.get(offset..)?)? as usize;
+                offset += 4;
+                let name = std::str::from_utf8(buf.get(offset..offset + name_len)?).ok()?;
+                column_families.insert(name.to_string());
+                offset += name_len;
+            }
+            TAG_COMPARATOR => {
+                let name_len = read_u32_le(buf.get(offset..)?)? as usize;
+                offset += 4;
+                let name = std::str::from_utf8(buf.get(offset..offset + name_len)?).ok()?;
+                *comparator = Some(name.to_string());
+                offset += name_len;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(edit)
+}
+
+#[cfg(test)]
+mod manifest_test {
+    use super::*;
+
+    const TEST_DATA_DIR: &str = "test_data/manifest";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = PathBuf::from(TEST_DATA_DIR).join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn meta(dir: &Path, file_no: u64, smallest: &str, largest: &str) -> SSTableMeta {
+        let props = TableProperties {
+            min_seq_no: file_no * 10,
+            max_seq_no: file_no * 10 + 9,
+            smallest_key: smallest.as_bytes().to_vec(),
+            largest_key: largest.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        SSTableMeta::from_properties(
+            file_no,
+            dir.join(table_file_name(file_no))
+                .to_string_lossy()
+                .into_owned(),
+            &props,
+            file_no * 1000,
+            file_no as u32 * 31,
+        )
+    }
+
+    #[test]
+    fn edits_survive_reopen() {
+        let dir = test_dir("edits_survive_reopen");
+        assert!(!Manifest::exists(&dir));
+
+        let mut manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        assert!(Manifest::exists(&dir));
+
+        let (one, two, three) = (
+            manifest.new_file_no(),
+            manifest.new_file_no(),
+            manifest.new_file_no(),
+        );
+        assert_eq!((one, two, three), (1, 2, 3));
+
+        manifest
+            .log_edit(VersionEdit {
+                added: vec![meta(&dir, one, "a", "c"), meta(&dir, two, "d", "f")],
+                removed: vec![],
+            })
+            .unwrap();
+        manifest
+            .log_edit(VersionEdit {
+                added: vec![meta(&dir, three, "a", "f")],
+                removed: vec![one, two],
+            })
+            .unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        let tables: Vec<_> = manifest.tables().cloned().collect();
+        assert_eq!(tables, vec![meta(&dir, three, "a", "f")]);
+        assert_eq!(manifest.next_file_no(), 4);
+        assert_eq!(manifest.table_path(42), dir.join("000042.sst"));
+    }
+
+    #[test]
+    fn next_seq_no_outlives_the_tables_that_used_it() {
+        let dir = test_dir("next_seq_no_outlives_the_tables_that_used_it");
+        let mut manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        assert_eq!(manifest.next_seq_no(), 0);
+
+        let file_no = manifest.new_file_no();
+        manifest
+            .log_edit(VersionEdit {
+                added: vec![meta(&dir, file_no, "a", "b")],
+                removed: vec![],
+            })
+            .unwrap();
+        assert_eq!(manifest.next_seq_no(), 20);
+        // e.g. a bottommost compaction that dropped every entry
+        manifest
+            .log_edit(VersionEdit {
+                added: vec![],
+                removed: vec![file_no],
+            })
+            .unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        assert_eq!(manifest.tables().count(), 0);
+        assert_eq!(manifest.next_seq_no(), 20);
+    }
+
+    #[test]
+    fn torn_final_record_is_dropped() {
+        let dir = test_dir("torn_final_record_is_dropped");
+        let mut manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        let file_no = manifest.new_file_no();
+        manifest
+            .log_edit(VersionEdit {
+                added: vec![meta(&dir, file_no, "a", "b")],
+                removed: vec![],
+            })
+            .unwrap();
+        drop(manifest);
+
+        // Half an edit that never finished writing
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let mut buf = std::fs::read(&path).unwrap();
+        let torn = encode_record(&encode_edit(
+            &VersionEdit {
+                added: vec![meta(&dir, 7, "x", "z")],
+                removed: vec![file_no],
+            },
+            8,
+            80,
+            &BTreeSet::new(),
+            BYTEWISE_COMPARATOR_NAME,
+        ));
+        buf.extend_from_slice(&torn[..torn.len() / 2]);
+        std::fs::write(&path, &buf).unwrap();
+
+        let manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        assert!(manifest.contains(file_no));
+        assert!(!manifest.contains(7));
+        assert_eq!(manifest.next_file_no(), 2);
+    }
+
+    #[test]
+    fn corrupt_record_before_the_tail_fails_open() {
+        let dir = test_dir("corrupt_record_before_the_tail_fails_open");
+        let mut manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        for _ in 0..2 {
+            let file_no = manifest.new_file_no();
+            manifest
+                .log_edit(VersionEdit {
+                    added: vec![meta(&dir, file_no, "a", "b")],
+                    removed: vec![],
+                })
+                .unwrap();
+        }
+        drop(manifest);
+
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let mut buf = std::fs::read(&path).unwrap();
+        buf[RECORD_HEADER_LEN] ^= 0xff;
+        std::fs::write(&path, &buf).unwrap();
+
+        assert!(matches!(
+            Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME),
+            Err(DBError::Corruption { .. })
+        ));
+    }
+
+    #[test]
+    fn column_families_survive_reopen_and_later_edits() {
+        let dir = test_dir("column_families_survive_reopen_and_later_edits");
+        let mut manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        manifest.add_column_family("index").unwrap();
+        manifest.add_column_family("data").unwrap();
+        manifest.add_column_family("index").unwrap();
+        let file_no = manifest.new_file_no();
+        manifest
+            .log_edit(VersionEdit {
+                added: vec![meta(&dir, file_no, "a", "b")],
+                removed: vec![],
+            })
+            .unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME).unwrap();
+        assert_eq!(
+            manifest.column_families().collect::<Vec<_>>(),
+            ["data", "index"]
+        );
+        assert!(manifest.contains(file_no));
+    }
+
+    #[test]
+    fn comparator_must_match_the_recorded_one() {
+        let dir = test_dir("comparator_must_match_the_recorded_one");
+        drop(Manifest::open(&dir, "test.Reverse").unwrap());
+
+        assert!(matches!(
+            Manifest::open(&dir, BYTEWISE_COMPARATOR_NAME),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        Manifest::open(&dir, "test.Reverse").unwrap();
+    }
+}
@@ -1,45 +1,752 @@
-use crate::entry::Entry;
+use crate::bloom::{self, BloomFilter};
+use crate::comparator::{self, BytewiseComparator, Comparator};
+use crate::entry::{self, Entry, RangeTombstone};
+use crate::merge::{self, MergeOperator};
+use crate::skiplist::SkipList;
 use crate::types::{DBError, ERR_CONFIG_EMPTY_KEY};
-use std::collections::{BTreeMap};
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, OnceLock};
 
-// pub trait MemTableExt {
-//     fn get(&self, key: &[u8]) -> Option<&Entry>;
-//     fn put(&mut self, key: Vec<u8>, val: Vec<u8>, seq_no: u64);
-//     fn delete(&mut self, key: Vec<u8>, seq_no: u64);
-//     // TODO:
-//     // fn iter();
-//     // fn range();
-// }
+// A rough count of the bytes an entry costs a `MemTable` beyond its key and value: the key and entry
+// themselves, and their share of a tree node
+const ENTRY_OVERHEAD: usize = 64;
 
-pub type MemTable = BTreeMap<Vec<u8>, Entry>;
+// The same for a `HashLinkedMemTable`: the slot, its bucket and its place in the sorted order
+const SLOT_OVERHEAD: usize = 104;
 
-pub fn put(mem: &mut MemTable, key: Vec<u8>, val: Vec<u8>, seq_no: u64) -> Result<(), DBError> {
-    if key.len() == 0 {
+/// The default MemTable, a `BTreeMap` that keeps count of the bytes it holds. Its keys are in byte order,
+/// whatever the DB's `Comparator`, see `MemTableKind::new_mem_table_with`.
+#[derive(Debug, Clone, Default)]
+pub struct MemTable {
+    entries: BTreeMap<Vec<u8>, Entry>,
+    size: usize,
+}
+
+impl MemTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FromIterator<(Vec<u8>, Entry)> for MemTable {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Entry)>>(iter: I) -> Self {
+        let mut mem_table = Self::new();
+        for (key, entry) in iter {
+            mem_table.insert(key, entry);
+        }
+        mem_table
+    }
+}
+
+/// The entries of a `MemTableRep` in key order, or reverse key order for `iter_before`, tombstones included.
+pub type MemTableIter<'a> = Box<dyn Iterator<Item = (&'a [u8], &'a Entry)> + 'a>;
+
+/// A MemTableRep is the structure holding the latest entry of every key written since the last flush. The
+/// DB only ever goes through this trait, so the structure can be picked per workload with `MemTableKind`.
+pub trait MemTableRep: Send + Sync {
+    fn get(&self, key: &[u8]) -> Option<&Entry>;
+
+    /// Stores `entry` under `key`, unless the key already holds an entry with a seq_no at least as high.
+    fn insert(&mut self, key: Vec<u8>, entry: Entry);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Roughly the bytes of memory held: every key and value, and a fixed overhead per entry. Flushes are
+    /// triggered on it, see `DBConfig::write_buffer_size`.
+    fn size(&self) -> usize;
+
+    /// The entries from `from` on, in key order.
+    fn iter_from(&self, from: Bound<&[u8]>) -> MemTableIter<'_>;
+
+    fn iter(&self) -> MemTableIter<'_> {
+        self.iter_from(Bound::Unbounded)
+    }
+
+    /// The entries before `until`, in reverse key order.
+    fn iter_before(&self, until: Bound<&[u8]>) -> MemTableIter<'_>;
+
+    /// The sorted runs the entries are split into, with no key in more than one. A flush merges them into
+    /// a single sorted stream. Most MemTables keep everything in one run and return just themselves.
+    fn runs(&self) -> Vec<&dyn MemTableRep>;
+
+    fn clear(&mut self);
+
+    /// The order the keys are kept in, byte order unless the MemTable was built with another.
+    fn comparator(&self) -> &dyn Comparator {
+        &BytewiseComparator
+    }
+}
+
+impl MemTableRep for MemTable {
+    fn get(&self, key: &[u8]) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        match self.entries.get_mut(key.as_slice()) {
+            Some(current) if current.seq_no() < entry.seq_no() => {
+                // The entry replaced is freed along with its value
+                self.size = self.size - val_len(current) + val_len(&entry);
+                *current = entry;
+            }
+            Some(_) => {}
+            None => {
+                self.size += key.len() + val_len(&entry) + ENTRY_OVERHEAD;
+                self.entries.insert(key, entry);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn iter_from(&self, from: Bound<&[u8]>) -> MemTableIter<'_> {
+        Box::new(
+            self.entries
+                .range::<[u8], _>((from, Bound::Unbounded))
+                .map(|(key, entry)| (key.as_slice(), entry)),
+        )
+    }
+
+    fn iter_before(&self, until: Bound<&[u8]>) -> MemTableIter<'_> {
+        Box::new(
+            self.entries
+                .range::<[u8], _>((Bound::Unbounded, until))
+                .rev()
+                .map(|(key, entry)| (key.as_slice(), entry)),
+        )
+    }
+
+    fn runs(&self) -> Vec<&dyn MemTableRep> {
+        vec![self]
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+}
+
+/// The entries a MemTable overwrote while a live snapshot still reads them, see `DB::snapshot`. A
+/// `MemTableRep` only holds the latest entry of a key, so the one it replaces is set aside here first, and
+/// flushed along with the MemTable, each key's versions right after its latest entry.
+#[derive(Debug, Clone, Default)]
+pub struct SupersededEntries {
+    // The versions of a key, oldest first
+    versions: HashMap<Vec<u8>, Vec<Entry>>,
+    len: usize,
+    size: usize,
+}
+
+impl SupersededEntries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `entry` aside, the version of `key` the MemTable is about to overwrite. It is newer than any
+    /// version of the key already set aside.
+    pub fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        self.size += key.len() + val_len(&entry) + ENTRY_OVERHEAD;
+        self.len += 1;
+        self.versions.entry(key).or_default().push(entry);
+    }
+
+    /// The newest version of `key` written before `read_seq_no`, i.e. the one a snapshot at `read_seq_no`
+    /// reads when the MemTable's entry is newer.
+    pub fn get_before(&self, key: &[u8], read_seq_no: u64) -> Option<&Entry> {
+        self.versions(key)
+            .find(|entry| entry.seq_no() < read_seq_no)
+    }
+
+    /// The versions of `key` set aside, newest first.
+    pub fn versions(&self, key: &[u8]) -> impl Iterator<Item = &Entry> {
+        self.versions.get(key).into_iter().flatten().rev()
+    }
+
+    /// The version of every key a snapshot at `read_seq_no` reads, in a MemTable keeping the order of
+    /// `comparator`, to scan them along with the MemTable.
+    pub fn read_at(
+        &self,
+        read_seq_no: u64,
+        comparator: Arc<dyn Comparator>,
+    ) -> Box<dyn MemTableRep> {
+        let mut mem = MemTableKind::BTree.new_mem_table_with(comparator);
+        for key in self.versions.keys() {
+            if let Some(entry) = self.get_before(key, read_seq_no) {
+                mem.insert(key.clone(), entry.clone());
+            }
+        }
+        mem
+    }
+
+    /// How many versions are set aside.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Roughly the bytes of memory held, counted as `MemTableRep::size` does.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// A BloomMemTable puts a `BloomFilter` in front of another MemTable, so `get` on a key never written
+/// answers without searching it. Every key inserted goes into the filter, which is sized up front and
+/// only emptied along with the MemTable. Past the number of keys it was sized for it rules out fewer and
+/// fewer keys, but never a key that was written.
+pub struct BloomMemTable {
+    inner: Box<dyn MemTableRep>,
+    filter: BloomFilter,
+    // What `filter` was sized with, to build it anew on `clear`
+    expected_items: usize,
+    false_positive_rate: f64,
+}
+
+impl BloomMemTable {
+    pub fn new(
+        inner: Box<dyn MemTableRep>,
+        expected_items: usize,
+        false_positive_rate: f64,
+    ) -> Self {
+        Self {
+            inner,
+            filter: BloomFilter::new(expected_items, false_positive_rate),
+            expected_items,
+            false_positive_rate,
+        }
+    }
+
+    /// Whether `key` may be in the MemTable, `false` only for a key that was never inserted.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter.may_contain(key)
+    }
+}
+
+impl MemTableRep for BloomMemTable {
+    fn get(&self, key: &[u8]) -> Option<&Entry> {
+        if !self.filter.may_contain(key) {
+            return None;
+        }
+        self.inner.get(key)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        self.filter.insert(&key);
+        self.inner.insert(key, entry);
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size() + self.filter.size()
+    }
+
+    fn iter_from(&self, from: Bound<&[u8]>) -> MemTableIter<'_> {
+        self.inner.iter_from(from)
+    }
+
+    fn iter_before(&self, until: Bound<&[u8]>) -> MemTableIter<'_> {
+        self.inner.iter_before(until)
+    }
+
+    fn runs(&self) -> Vec<&dyn MemTableRep> {
+        self.inner.runs()
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.filter = BloomFilter::new(self.expected_items, self.false_positive_rate);
+    }
+
+    fn comparator(&self) -> &dyn Comparator {
+        self.inner.comparator()
+    }
+}
+
+/// A ShardedMemTable spreads keys over a number of `SkipList`s by their hash. Writers on different
+/// threads mostly land on different lists, each with its own arena, so `insert_shared` contends far less
+/// than on a single list. Every shard is sorted on its own: iterating merges them, and so does a flush.
+pub struct ShardedMemTable {
+    shards: Box<[SkipList]>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl ShardedMemTable {
+    /// A MemTable of `shards` lists, at least one.
+    pub fn new(shards: usize) -> Self {
+        Self::with_comparator(shards, Arc::new(BytewiseComparator))
+    }
+
+    /// A MemTable of `shards` lists, at least one, each keeping its keys in the order of `comparator`.
+    pub fn with_comparator(shards: usize, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| SkipList::with_comparator(comparator.clone()))
+                .collect(),
+            comparator,
+        }
+    }
+
+    /// `MemTableRep::insert` through a shared reference, safe to call from many threads at once.
+    pub fn insert_shared(&self, key: Vec<u8>, entry: Entry) {
+        self.shard(&key).insert_shared(key, entry)
+    }
+
+    fn shard(&self, key: &[u8]) -> &SkipList {
+        let shard = bloom::hash(key) % self.shards.len() as u64;
+        &self.shards[shard as usize]
+    }
+}
+
+impl MemTableRep for ShardedMemTable {
+    fn get(&self, key: &[u8]) -> Option<&Entry> {
+        self.shard(key).get(key)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        self.insert_shared(key, entry)
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    fn size(&self) -> usize {
+        self.shards.iter().map(|shard| shard.size()).sum()
+    }
+
+    fn iter_from(&self, from: Bound<&[u8]>) -> MemTableIter<'_> {
+        Box::new(MergedRuns {
+            runs: self
+                .shards
+                .iter()
+                .map(|shard| shard.iter_from(from).peekable())
+                .collect(),
+            comparator: self.comparator.as_ref(),
+            reverse: false,
+        })
+    }
+
+    fn iter_before(&self, until: Bound<&[u8]>) -> MemTableIter<'_> {
+        Box::new(MergedRuns {
+            runs: self
+                .shards
+                .iter()
+                .map(|shard| shard.iter_before(until).peekable())
+                .collect(),
+            comparator: self.comparator.as_ref(),
+            reverse: true,
+        })
+    }
+
+    fn runs(&self) -> Vec<&dyn MemTableRep> {
+        self.shards
+            .iter()
+            .map(|shard| shard as &dyn MemTableRep)
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        for shard in self.shards.iter_mut() {
+            shard.clear();
+        }
+    }
+
+    fn comparator(&self) -> &dyn Comparator {
+        self.comparator.as_ref()
+    }
+}
+
+/// A HashLinkedMemTable finds keys by their hash, so `get` and `insert` take the same time however many
+/// keys it holds. Entries sit in a list in the order their keys were first written, each linked to the
+/// one written before it whose key fell in the same bucket.
+///
+/// Nothing is kept in key order. The first ordered read after a new key sorts the keys, which a flush
+/// does once but a scan between writes does every time, so it suits workloads that never scan the
+/// MemTable before it is flushed.
+#[derive(Debug)]
+pub struct HashLinkedMemTable {
+    // The hash of a key to the last slot written whose key has that hash
+    buckets: HashMap<u64, usize>,
+    // In the order keys were first written
+    slots: Vec<Slot>,
+    // The slots in key order, sorted on the first ordered read since a key was added
+    sorted: OnceLock<Vec<usize>>,
+    size: usize,
+    comparator: Arc<dyn Comparator>,
+}
+
+#[derive(Debug)]
+struct Slot {
+    key: Vec<u8>,
+    entry: Entry,
+    // The slot written before this one in the same bucket
+    collision: Option<usize>,
+}
+
+impl Default for HashLinkedMemTable {
+    fn default() -> Self {
+        Self::with_comparator(Arc::new(BytewiseComparator))
+    }
+}
+
+impl HashLinkedMemTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A MemTable sorting its keys in the order of `comparator` when they are read in order.
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            slots: Vec::new(),
+            sorted: OnceLock::new(),
+            size: 0,
+            comparator,
+        }
+    }
+
+    fn find(&self, hash: u64, key: &[u8]) -> Option<usize> {
+        let mut at = self.buckets.get(&hash).copied();
+        while let Some(slot) = at {
+            if self.slots[slot].key == key {
+                return Some(slot);
+            }
+            at = self.slots[slot].collision;
+        }
+        None
+    }
+
+    fn sorted(&self) -> &[usize] {
+        self.sorted.get_or_init(|| {
+            let mut sorted: Vec<usize> = (0..self.slots.len()).collect();
+            sorted.sort_unstable_by(|a, b| {
+                self.comparator
+                    .compare(&self.slots[*a].key, &self.slots[*b].key)
+            });
+            sorted
+        })
+    }
+}
+
+impl MemTableRep for HashLinkedMemTable {
+    fn get(&self, key: &[u8]) -> Option<&Entry> {
+        let slot = self.find(bloom::hash(key), key)?;
+        Some(&self.slots[slot].entry)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        let hash = bloom::hash(&key);
+        match self.find(hash, &key) {
+            Some(slot) => {
+                let current = &mut self.slots[slot].entry;
+                if current.seq_no() < entry.seq_no() {
+                    self.size = self.size - val_len(current) + val_len(&entry);
+                    *current = entry;
+                }
+            }
+            None => {
+                self.size += key.len() + val_len(&entry) + SLOT_OVERHEAD;
+                let collision = self.buckets.insert(hash, self.slots.len());
+                self.slots.push(Slot {
+                    key,
+                    entry,
+                    collision,
+                });
+                self.sorted.take();
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn iter_from(&self, from: Bound<&[u8]>) -> MemTableIter<'_> {
+        let sorted = self.sorted();
+        let cmp = |slot: usize, key: &[u8]| self.comparator.compare(&self.slots[slot].key, key);
+        let start = match from {
+            Bound::Included(from) => sorted.partition_point(|&slot| cmp(slot, from).is_lt()),
+            Bound::Excluded(from) => sorted.partition_point(|&slot| cmp(slot, from).is_le()),
+            Bound::Unbounded => 0,
+        };
+        Box::new(sorted[start..].iter().map(|&slot| {
+            let slot = &self.slots[slot];
+            (slot.key.as_slice(), &slot.entry)
+        }))
+    }
+
+    fn iter_before(&self, until: Bound<&[u8]>) -> MemTableIter<'_> {
+        let sorted = self.sorted();
+        let end = sorted.partition_point(|&slot| {
+            comparator::before(self.comparator.as_ref(), &self.slots[slot].key, until)
+        });
+        Box::new(sorted[..end].iter().rev().map(|&slot| {
+            let slot = &self.slots[slot];
+            (slot.key.as_slice(), &slot.entry)
+        }))
+    }
+
+    fn runs(&self) -> Vec<&dyn MemTableRep> {
+        vec![self]
+    }
+
+    fn clear(&mut self) {
+        self.buckets.clear();
+        self.slots.clear();
+        self.sorted.take();
+        self.size = 0;
+    }
+
+    fn comparator(&self) -> &dyn Comparator {
+        self.comparator.as_ref()
+    }
+}
+
+/// Runs holding disjoint keys, merged by taking the smallest head every step, or the largest for runs in
+/// `reverse` order. Unlike the `MergingIterator` it hands out plain references, the price being a scan of
+/// every run per step.
+struct MergedRuns<'a> {
+    runs: Vec<Peekable<MemTableIter<'a>>>,
+    comparator: &'a dyn Comparator,
+    reverse: bool,
+}
+
+impl<'a> Iterator for MergedRuns<'a> {
+    type Item = (&'a [u8], &'a Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let heads = self
+            .runs
+            .iter_mut()
+            .filter_map(|run| Some((run.peek()?.0, run)));
+        let comparator = self.comparator;
+        let next = if self.reverse {
+            heads.max_by(|(a, _), (b, _)| comparator.compare(a, b))
+        } else {
+            heads.min_by(|(a, _), (b, _)| comparator.compare(a, b))
+        };
+        next.and_then(|(_, run)| run.next())
+    }
+}
+
+/// The bytes of the value or operand `entry` holds, none for a tombstone.
+pub(crate) fn val_len(entry: &Entry) -> usize {
+    match entry {
+        Entry::Value { val, .. } => val.len(),
+        Entry::Merge { operand, .. } => operand.len(),
+        Entry::Tombstone { .. } => 0,
+    }
+}
+
+/// The structure backing the DB's MemTable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemTableKind {
+    /// A `BTreeMap`, the most compact in memory.
+    #[default]
+    BTree,
+    /// A `SkipList`, whose readers never block and which takes inserts from many threads at once. Its nodes
+    /// and keys come out of an arena freed in one go, which suits heavy ingest.
+    SkipList,
+    /// A `ShardedMemTable` of this many skiplists, for many writer threads.
+    Sharded { shards: usize },
+    /// A `HashLinkedMemTable`, for point reads and writes in constant time when the MemTable is never
+    /// scanned before it is flushed.
+    HashLinked,
+}
+
+impl MemTableKind {
+    pub fn new_mem_table(self) -> Box<dyn MemTableRep> {
+        self.new_mem_table_with(Arc::new(BytewiseComparator))
+    }
+
+    /// A MemTable keeping its keys in the order of `comparator`. A `BTreeMap` can only keep byte order, so
+    /// `BTree` stands for a `SkipList` under any other.
+    pub fn new_mem_table_with(self, comparator: Arc<dyn Comparator>) -> Box<dyn MemTableRep> {
+        match self {
+            MemTableKind::BTree if comparator::is_bytewise(comparator.as_ref()) => {
+                Box::new(MemTable::new())
+            }
+            MemTableKind::BTree | MemTableKind::SkipList => {
+                Box::new(SkipList::with_comparator(comparator))
+            }
+            MemTableKind::Sharded { shards } => {
+                Box::new(ShardedMemTable::with_comparator(shards, comparator))
+            }
+            MemTableKind::HashLinked => Box::new(HashLinkedMemTable::with_comparator(comparator)),
+        }
+    }
+}
+
+pub fn put(
+    mem: &mut dyn MemTableRep,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    seq_no: u64,
+) -> Result<(), DBError> {
+    put_with_timestamp(mem, key, val, seq_no, None)
+}
+
+/// `put` of a value written at `timestamp`, in milliseconds since the UNIX epoch, see `Entry::Value`.
+pub fn put_with_timestamp(
+    mem: &mut dyn MemTableRep,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    seq_no: u64,
+    timestamp: Option<u64>,
+) -> Result<(), DBError> {
+    insert_value(mem, key, val, seq_no, timestamp, None)
+}
+
+/// `put_with_timestamp` of a value expiring at `expires_at`, by the same measure, see `Entry::Value`.
+pub fn put_expiring(
+    mem: &mut dyn MemTableRep,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    seq_no: u64,
+    timestamp: Option<u64>,
+    expires_at: u64,
+) -> Result<(), DBError> {
+    insert_value(mem, key, val, seq_no, timestamp, Some(expires_at))
+}
+
+fn insert_value(
+    mem: &mut dyn MemTableRep,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    seq_no: u64,
+    timestamp: Option<u64>,
+    expires_at: Option<u64>,
+) -> Result<(), DBError> {
+    if key.is_empty() {
         return Err(DBError::Codec {
             context: String::from(ERR_CONFIG_EMPTY_KEY),
             source: None,
         });
     }
 
-    mem.entry(key)
-        .and_modify(|v| {
-            if v.seq_no() < seq_no {
-                *v = Entry::Value {
-                    seq_no: seq_no,
-                    val: val.clone(), // I dont want to re-clone here
-                };
-            }
-        })
-        .or_insert(Entry::Value {
-            seq_no: seq_no,
-            val: val,
+    mem.insert(
+        key,
+        Entry::Value {
+            seq_no,
+            val,
+            timestamp,
+            expires_at,
+        },
+    );
+
+    Ok(())
+}
+
+/// Writes a tombstone for `key` at `seq_no`, replacing whatever older entry the key holds. A key the
+/// MemTable doesn't hold gets one too, as older values of it may still be in the SSTables.
+pub fn delete(mem: &mut dyn MemTableRep, key: Vec<u8>, seq_no: u64) -> Result<(), DBError> {
+    if key.is_empty() {
+        return Err(DBError::Codec {
+            context: String::from(ERR_CONFIG_EMPTY_KEY),
+            source: None,
+        });
+    }
+
+    mem.insert(key, Entry::Tombstone { seq_no });
+
+    Ok(())
+}
+
+/// Writes the merge `operand` for `key` at `seq_no`, folded right away into the entry the key holds with
+/// `operator`. A value or tombstone becomes the value the operand makes of it, and so does a key one of
+/// the `range_tombstones` written alongside the MemTable deletes. An operand meets the one already there,
+/// otherwise it is left for reads and compaction to fold into the value found in the SSTables. A value
+/// that had expired by `now` (milliseconds since the unix epoch) is folded as if the key held none.
+pub fn merge(
+    mem: &mut dyn MemTableRep,
+    range_tombstones: &[RangeTombstone],
+    (operator, now): (&dyn MergeOperator, u64),
+    key: Vec<u8>,
+    operand: Vec<u8>,
+    seq_no: u64,
+    timestamp: Option<u64>,
+) -> Result<(), DBError> {
+    if key.is_empty() {
+        return Err(DBError::Codec {
+            context: String::from(ERR_CONFIG_EMPTY_KEY),
+            source: None,
         });
+    }
+
+    let new = Entry::Merge {
+        seq_no,
+        operand,
+        timestamp,
+    };
+    let deleted_at = entry::covering_seq_no(mem.comparator(), range_tombstones, &key);
+    let existing = mem
+        .get(&key)
+        .filter(|entry| deleted_at.is_none_or(|deleted_at| entry.seq_no() >= deleted_at));
+    let merged = match existing {
+        Some(existing @ Entry::Merge { .. }) => {
+            merge::fold(operator, &key, &[new, existing.clone()], None, now)
+        }
+        Some(existing) => merge::fold(operator, &key, &[new], Some(Some(existing)), now),
+        None if deleted_at.is_some() => merge::fold(operator, &key, &[new], Some(None), now),
+        None => new,
+    };
+    mem.insert(key, merged);
 
     Ok(())
 }
 
+/// Every entry of `mem` in key order, tombstones included. This is what a flush writes out.
+pub fn iter(mem: &dyn MemTableRep) -> MemTableIter<'_> {
+    mem.iter()
+}
+
+/// The entries of `mem` with a key within `range`, e.g. `start..end`, in key order and tombstones included.
+pub fn range<'a, 'k>(
+    mem: &'a dyn MemTableRep,
+    range: impl RangeBounds<&'k [u8]>,
+) -> MemTableIter<'a> {
+    let end = range.end_bound().map(|end| end.to_vec());
+    Box::new(
+        mem.iter_from(range.start_bound().cloned())
+            .take_while(move |(key, _)| {
+                comparator::before(mem.comparator(), key, end.as_ref().map(Vec::as_slice))
+            }),
+    )
+}
+
+/// The entries of `mem` whose key starts with `prefix`, in key order and tombstones included.
+pub fn scan_prefix<'a>(mem: &'a dyn MemTableRep, prefix: &[u8]) -> MemTableIter<'a> {
+    let prefix = prefix.to_vec();
+    Box::new(
+        mem.iter_from(Bound::Included(&prefix))
+            .take_while(move |(key, _)| key.starts_with(&prefix)),
+    )
+}
+
 #[cfg(test)]
-mod memtable {
+mod memtable_test {
     use super::*;
 
     #[test]
@@ -57,7 +764,9 @@ mod memtable {
             mem.get(key.as_slice()),
             Some(&Entry::Value {
                 seq_no: 0,
-                val: val
+                val,
+                timestamp: None,
+                expires_at: None,
             })
         );
 
@@ -74,9 +783,324 @@ mod memtable {
                 mem.get(key_2.clone().as_slice()),
                 Some(&Entry::Value {
                     seq_no: 1,
-                    val: val_2.clone()
+                    val: val_2.clone(),
+                    timestamp: None,
+                    expires_at: None,
                 })
             )
         }
     }
+
+    #[test]
+    fn delete_supersedes_older_entries() {
+        for kind in [
+            MemTableKind::BTree,
+            MemTableKind::SkipList,
+            MemTableKind::HashLinked,
+        ] {
+            let mut mem = kind.new_mem_table();
+            put(mem.as_mut(), b"key".to_vec(), b"val".to_vec(), 1).unwrap();
+
+            delete(mem.as_mut(), b"key".to_vec(), 2).unwrap();
+            assert_eq!(
+                mem.get(b"key"),
+                Some(&Entry::Tombstone { seq_no: 2 }),
+                "{kind:?}"
+            );
+
+            // Older writes, e.g. replayed out of order, don't bring the value back
+            put(mem.as_mut(), b"key".to_vec(), b"val".to_vec(), 1).unwrap();
+            assert_eq!(
+                mem.get(b"key"),
+                Some(&Entry::Tombstone { seq_no: 2 }),
+                "{kind:?}"
+            );
+
+            delete(mem.as_mut(), b"missing".to_vec(), 3).unwrap();
+            assert_eq!(
+                mem.get(b"missing"),
+                Some(&Entry::Tombstone { seq_no: 3 }),
+                "{kind:?}"
+            );
+            assert!(matches!(
+                delete(mem.as_mut(), Vec::new(), 4),
+                Err(DBError::Codec { .. })
+            ));
+            assert_eq!(mem.len(), 2, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn size_counts_keys_values_and_overhead() {
+        let kinds = [
+            MemTableKind::BTree,
+            MemTableKind::SkipList,
+            MemTableKind::Sharded { shards: 3 },
+            MemTableKind::HashLinked,
+        ];
+        for kind in kinds {
+            let mut mem = kind.new_mem_table();
+            put(mem.as_mut(), b"key".to_vec(), vec![0; 1000], 0).unwrap();
+            let one = mem.size();
+            assert!(one > 1003 && one < 1500, "{kind:?}: {one}");
+
+            // Older entries are ignored
+            put(mem.as_mut(), b"key".to_vec(), vec![0; 5000], 0).unwrap();
+            assert_eq!(mem.size(), one, "{kind:?}");
+
+            mem.insert(b"key".to_vec(), Entry::Tombstone { seq_no: 1 });
+            match kind {
+                // The value goes with the entry replaced
+                MemTableKind::BTree | MemTableKind::HashLinked => {
+                    assert_eq!(mem.size(), one - 1000)
+                }
+                // Replaced entries are kept until the list goes
+                _ => assert!(mem.size() > one),
+            }
+
+            mem.clear();
+            assert_eq!(mem.size(), 0, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn range_and_prefix_scans_include_tombstones() {
+        for kind in [
+            MemTableKind::BTree,
+            MemTableKind::SkipList,
+            MemTableKind::HashLinked,
+        ] {
+            let mut mem = kind.new_mem_table();
+            for (seq_no, key) in ["a", "ab", "abc", "b", "ba", "c"].into_iter().enumerate() {
+                put(
+                    mem.as_mut(),
+                    key.as_bytes().to_vec(),
+                    b"val".to_vec(),
+                    seq_no as u64,
+                )
+                .unwrap();
+            }
+            mem.insert(b"ab".to_vec(), Entry::Tombstone { seq_no: 10 });
+            let keys = |iter: MemTableIter<'_>| {
+                iter.map(|(key, _)| String::from_utf8(key.to_vec()).unwrap())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(
+                keys(iter(mem.as_ref())),
+                ["a", "ab", "abc", "b", "ba", "c"],
+                "{kind:?}"
+            );
+            assert_eq!(
+                keys(range(mem.as_ref(), b"ab".as_slice()..b"ba")),
+                ["ab", "abc", "b"],
+                "{kind:?}"
+            );
+            assert_eq!(
+                keys(range(mem.as_ref(), b"ab".as_slice()..=b"ba")),
+                ["ab", "abc", "b", "ba"]
+            );
+            assert_eq!(
+                keys(range(mem.as_ref(), ..b"ab".as_slice())),
+                ["a"],
+                "{kind:?}"
+            );
+            assert_eq!(
+                keys(range(mem.as_ref(), b"bb".as_slice()..)),
+                ["c"],
+                "{kind:?}"
+            );
+            assert!(
+                keys(range(mem.as_ref(), b"b".as_slice()..b"b")).is_empty(),
+                "{kind:?}"
+            );
+
+            assert_eq!(
+                keys(scan_prefix(mem.as_ref(), b"ab")),
+                ["ab", "abc"],
+                "{kind:?}"
+            );
+            assert_eq!(keys(scan_prefix(mem.as_ref(), b"")).len(), 6, "{kind:?}");
+            assert!(keys(scan_prefix(mem.as_ref(), b"d")).is_empty(), "{kind:?}");
+            let (_, entry) = scan_prefix(mem.as_ref(), b"ab").next().unwrap();
+            assert_eq!(entry, &Entry::Tombstone { seq_no: 10 });
+        }
+    }
+
+    #[test]
+    fn bloom_mem_table_rules_out_keys_never_written() {
+        for kind in [
+            MemTableKind::BTree,
+            MemTableKind::SkipList,
+            MemTableKind::HashLinked,
+        ] {
+            let mut mem = BloomMemTable::new(kind.new_mem_table(), 1000, 0.01);
+            for i in 0..1000u64 {
+                put(&mut mem, format!("key{i}").into_bytes(), b"val".to_vec(), i).unwrap();
+            }
+            mem.insert(b"key7".to_vec(), Entry::Tombstone { seq_no: 1000 });
+
+            assert_eq!(mem.len(), 1000);
+            assert!((0..1000).all(|i| mem.get(format!("key{i}").as_bytes()).is_some()));
+            assert_eq!(mem.get(b"key7"), Some(&Entry::Tombstone { seq_no: 1000 }));
+            let false_positives = (0..10_000)
+                .filter(|i| mem.may_contain(format!("missing{i}").as_bytes()))
+                .count();
+            assert!(false_positives < 300, "{kind:?}: {false_positives}");
+            assert!((0..10_000).all(|i| mem.get(format!("missing{i}").as_bytes()).is_none()));
+            assert!(mem.size() > kind.new_mem_table().size() + 1000);
+
+            mem.clear();
+            assert!(mem.is_empty());
+            assert!(!mem.may_contain(b"key0"));
+        }
+    }
+
+    #[test]
+    fn sharded_mem_table_merges_its_shards_in_key_order() {
+        let mut sharded = ShardedMemTable::new(4);
+        let mut map = MemTable::new();
+        for i in 0..500u64 {
+            let key = format!("key{:03}", i * 7 % 300).into_bytes();
+            let entry = match i % 4 {
+                0 => Entry::Tombstone { seq_no: i },
+                _ => Entry::Value {
+                    seq_no: i,
+                    val: format!("val{i}").into_bytes(),
+                    timestamp: None,
+                    expires_at: None,
+                },
+            };
+            sharded.insert(key.clone(), entry.clone());
+            map.insert(key, entry);
+        }
+
+        // Every shard got a share of the keys
+        assert!(sharded.runs().iter().all(|run| run.len() > 30));
+        assert_eq!(sharded.len(), map.len());
+        assert!(sharded.iter().eq(map.iter()));
+        let from = b"key150".as_slice();
+        for from in [
+            Bound::Included(from),
+            Bound::Excluded(from),
+            Bound::Included(b"z"),
+        ] {
+            assert!(sharded.iter_from(from).eq(map.iter_from(from)), "{from:?}");
+            assert!(
+                sharded.iter_before(from).eq(map.iter_before(from)),
+                "{from:?}"
+            );
+        }
+        let forward: Vec<_> = map.iter().collect();
+        assert!(
+            map.iter_before(Bound::Unbounded)
+                .eq(forward.into_iter().rev())
+        );
+        assert_eq!(sharded.get(b"key007"), map.get(b"key007"));
+        assert_eq!(
+            sharded.size(),
+            sharded.runs().iter().map(|run| run.size()).sum::<usize>()
+        );
+
+        sharded.clear();
+        assert!(sharded.is_empty());
+        assert_eq!(sharded.iter().count(), 0);
+    }
+
+    #[test]
+    fn hash_linked_mem_table_sorts_keys_added_since_the_last_scan() {
+        let mut hashed = HashLinkedMemTable::new();
+        let mut map = MemTable::new();
+        for i in 0..600u64 {
+            let key = format!("key{:03}", i * 7 % 400).into_bytes();
+            let entry = match i % 5 {
+                0 => Entry::Tombstone { seq_no: i },
+                _ => Entry::Value {
+                    seq_no: i,
+                    val: format!("val{i}").into_bytes(),
+                    timestamp: None,
+                    expires_at: None,
+                },
+            };
+            hashed.insert(key.clone(), entry.clone());
+            map.insert(key, entry);
+
+            // Scanning between writes sees every key written so far
+            if i % 150 == 0 {
+                assert!(hashed.iter().eq(map.iter()), "{i}");
+            }
+        }
+
+        assert_eq!(hashed.len(), map.len());
+        assert_eq!(
+            hashed.size(),
+            map.size() + hashed.len() * (SLOT_OVERHEAD - ENTRY_OVERHEAD)
+        );
+        assert!(hashed.iter().eq(map.iter()));
+        let from = b"key150".as_slice();
+        for from in [
+            Bound::Included(from),
+            Bound::Excluded(from),
+            Bound::Included(b"z"),
+        ] {
+            assert!(hashed.iter_from(from).eq(map.iter_from(from)), "{from:?}");
+            assert!(
+                hashed.iter_before(from).eq(map.iter_before(from)),
+                "{from:?}"
+            );
+        }
+        assert!((0..400).all(|i| {
+            let key = format!("key{i:03}");
+            hashed.get(key.as_bytes()) == map.get(key.as_bytes())
+        }));
+        assert_eq!(hashed.get(b"missing"), None);
+
+        hashed.clear();
+        assert!(hashed.is_empty());
+        assert_eq!(hashed.size(), 0);
+        assert_eq!(hashed.iter().count(), 0);
+    }
+
+    #[test]
+    fn sharded_mem_table_takes_inserts_from_many_threads() {
+        let sharded = ShardedMemTable::new(8);
+        let sharded = &sharded;
+        std::thread::scope(|scope| {
+            for writer in 0..4u64 {
+                scope.spawn(move || {
+                    for i in 0..500u64 {
+                        let key = format!("key{writer}-{i:03}").into_bytes();
+                        sharded.insert_shared(key, Entry::Tombstone { seq_no: i });
+                    }
+                });
+            }
+        });
+
+        assert_eq!(sharded.len(), 2000);
+        let keys: Vec<_> = sharded.iter().map(|(key, _)| key).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn superseded_entries_hand_out_the_version_a_snapshot_reads() {
+        let mut superseded = SupersededEntries::new();
+        superseded.insert(b"a".to_vec(), Entry::Tombstone { seq_no: 1 });
+        superseded.insert(b"a".to_vec(), Entry::Tombstone { seq_no: 4 });
+        superseded.insert(b"b".to_vec(), Entry::Tombstone { seq_no: 6 });
+        assert_eq!(superseded.len(), 3);
+
+        let seq_nos: Vec<_> = superseded.versions(b"a").map(Entry::seq_no).collect();
+        assert_eq!(seq_nos, vec![4, 1]);
+        assert_eq!(superseded.get_before(b"a", 4).map(Entry::seq_no), Some(1));
+        assert_eq!(superseded.get_before(b"a", 9).map(Entry::seq_no), Some(4));
+        assert_eq!(superseded.get_before(b"a", 1), None);
+        assert_eq!(superseded.get_before(b"c", 9), None);
+
+        let mem = superseded.read_at(5, Arc::new(BytewiseComparator));
+        let read: Vec<_> = mem
+            .iter()
+            .map(|(key, entry)| (key, entry.seq_no()))
+            .collect();
+        assert_eq!(read, vec![(b"a".as_slice(), 4)]);
+    }
 }
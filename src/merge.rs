@@ -0,0 +1,163 @@
+//! Merge operators, for read-modify-writes without the read. `DB::merge` writes an operand for a key
+//! rather than its new value, and the `MergeOperator` of `DBConfig::merge_operator` folds the operands
+//! into the value beneath them wherever they meet it: in the MemTable as they are written, on reads, and
+//! in compaction. Counters, sets or append-only lists are then updated at the speed of a `put`.
+
+use std::fmt;
+
+use crate::entry::Entry;
+
+/// A MergeOperator folds merge operands into the value they apply to. It has to be associative: when the
+/// value beneath some operands isn't known yet, e.g. because it is in a table no compaction has merged
+/// them with, the operands are folded into each other, the older one standing in for the value. A counter
+/// adding up its operands is, and so is a list appending them.
+pub trait MergeOperator: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Merges `operand` into `existing`, the value of `key` before it, `None` if it had none. `existing`
+    /// may also be an older operand, see above.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8>;
+}
+
+/// Folds `merges`, the `Entry::Merge` entries of `key` newest first, onto what lies beneath the oldest of
+/// them, keeping the `seq_no` and timestamp of the newest. A value made from one with a TTL expires with
+/// it, and one that had expired by `now` (milliseconds since the unix epoch) counts as no value at all.
+///
+/// With `beneath` known, i.e. `Some(entry)` and `entry` itself `None` when the key holds nothing there or
+/// is deleted, they make the value they turn it into. With `beneath` unknown they are folded into a
+/// single operand, still to be merged with whatever turns up further down.
+pub(crate) fn fold(
+    operator: &dyn MergeOperator,
+    key: &[u8],
+    merges: &[Entry],
+    beneath: Option<Option<&Entry>>,
+    now: u64,
+) -> Entry {
+    let (seq_no, timestamp) = merges
+        .first()
+        .map_or((0, None), |newest| (newest.seq_no(), newest.timestamp()));
+    let operands = merges.iter().rev().filter_map(|entry| match entry {
+        Entry::Merge { operand, .. } => Some(operand.as_slice()),
+        _ => None,
+    });
+
+    let Some(beneath) = beneath else {
+        let operand = operands
+            .fold(None, |acc: Option<Vec<u8>>, operand| match acc {
+                Some(acc) => Some(operator.merge(key, Some(&acc), operand)),
+                None => Some(operand.to_vec()),
+            })
+            .unwrap_or_default();
+        return Entry::Merge {
+            seq_no,
+            operand,
+            timestamp,
+        };
+    };
+
+    let (existing, expires_at) = match beneath {
+        Some(
+            entry @ Entry::Value {
+                val, expires_at, ..
+            },
+        ) if !entry.is_expired(now) => (Some(val.clone()), *expires_at),
+        _ => (None, None),
+    };
+    let val = operands
+        .fold(existing, |acc, operand| {
+            Some(operator.merge(key, acc.as_deref(), operand))
+        })
+        .unwrap_or_default();
+    Entry::Value {
+        seq_no,
+        val,
+        timestamp,
+        expires_at,
+    }
+}
+
+#[cfg(test)]
+mod merge_test {
+    use super::*;
+
+    /// Adds up `u64` operands.
+    #[derive(Debug)]
+    struct Counter;
+
+    impl MergeOperator for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+            let read = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+            (existing.map_or(0, read) + read(operand))
+                .to_le_bytes()
+                .to_vec()
+        }
+    }
+
+    fn merge(seq_no: u64, n: u64) -> Entry {
+        Entry::Merge {
+            seq_no,
+            operand: n.to_le_bytes().to_vec(),
+            timestamp: Some(seq_no * 10),
+        }
+    }
+
+    #[test]
+    fn operands_fold_onto_what_is_beneath_them() {
+        let merges = [merge(5, 3), merge(4, 2)];
+        let base = Entry::Value {
+            seq_no: 1,
+            val: 10u64.to_le_bytes().to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+        let value = |n: u64| Entry::Value {
+            seq_no: 5,
+            val: n.to_le_bytes().to_vec(),
+            timestamp: Some(50),
+            expires_at: None,
+        };
+
+        assert_eq!(
+            fold(&Counter, b"key", &merges, Some(Some(&base)), 0),
+            value(15)
+        );
+        let tombstone = Entry::Tombstone { seq_no: 1 };
+        assert_eq!(
+            fold(&Counter, b"key", &merges, Some(Some(&tombstone)), 0),
+            value(5)
+        );
+        assert_eq!(fold(&Counter, b"key", &merges, Some(None), 0), value(5));
+        // With nothing known beneath them they stay an operand
+        assert_eq!(fold(&Counter, b"key", &merges, None, 0), merge(5, 5));
+    }
+
+    #[test]
+    fn operands_onto_an_expired_value_fold_as_if_there_was_none() {
+        let merges = [merge(5, 3)];
+        let base = Entry::Value {
+            seq_no: 1,
+            val: 10u64.to_le_bytes().to_vec(),
+            timestamp: None,
+            expires_at: Some(1000),
+        };
+        let value = |n: u64, expires_at| Entry::Value {
+            seq_no: 5,
+            val: n.to_le_bytes().to_vec(),
+            timestamp: Some(50),
+            expires_at,
+        };
+
+        assert_eq!(
+            fold(&Counter, b"key", &merges, Some(Some(&base)), 999),
+            value(13, Some(1000))
+        );
+        assert_eq!(
+            fold(&Counter, b"key", &merges, Some(Some(&base)), 1000),
+            value(3, None)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! A minimal read-only memory map over a file, used by the `mmap` SSTable read mode. The mapping is
+//! made through the platform's `mmap(2)` directly, which limits the feature to unix targets.
+//!
+//! SSTables are immutable once written, which is what makes mapping them sound: nothing truncates or
+//! rewrites a live table underneath the mapping.
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+
+pub struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The mapping is read-only and private, so sharing it across threads is no different from sharing a `&[u8]`
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the first `len` bytes of `file`.
+    pub fn map(file: &File, len: usize) -> io::Result<Self> {
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty file",
+            ));
+        }
+
+        // SAFETY: a fresh private read-only mapping of a file descriptor we hold open
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { ptr, len })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` readable bytes for as long as the mapping lives
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: `ptr`/`len` describe a mapping created in `map` that has not been unmapped yet
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! A concurrent skiplist MemTable. Readers never take a lock, and inserts from any number of threads link
+//! their nodes in with compare-and-swap, so writers only ever contend on the handful of pointers around
+//! the key they insert.
+//!
+//! Nothing is freed before the list is dropped or cleared: a node once linked stays linked, and an entry
+//! replaced by a newer one stays reachable from it. That is what lets `get` hand out plain references
+//! while other threads keep inserting, at the cost of holding on to every overwritten entry until the
+//! MemTable is flushed. It is also what lets nodes, keys and entries come out of an `Arena`, which spares
+//! the allocator a handful of allocations per insert and frees them all at once.
+
+use std::mem::size_of;
+use std::ops::Bound;
+use std::ptr::{self, NonNull};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::arena::Arena;
+use crate::comparator::{self, BytewiseComparator, Comparator};
+use crate::entry::Entry;
+use crate::memtable::{MemTableIter, MemTableRep, val_len};
+
+// Enough levels for about 4^12 = 16M keys before searches start to slow down
+const MAX_HEIGHT: usize = 12;
+
+/// A SkipList maps keys to their latest `Entry`, like the BTreeMap `MemTable`. `insert_shared` takes
+/// `&self`, so the list can be shared between writer threads. Keys are kept in the order of its
+/// `Comparator`, byte order for `new`.
+pub struct SkipList {
+    comparator: Arc<dyn Comparator>,
+    // A sentinel with no key, linked at every level through `head_tower`
+    head: Node,
+    head_tower: Box<[AtomicPtr<Node>]>,
+    // Every node, key and version, values aside which stay in their entries
+    arena: Arena,
+    len: AtomicUsize,
+    // The bytes of the values held, replaced ones included as they are only freed along with the list
+    values_size: AtomicUsize,
+    // Seeds the height of new nodes
+    rng: AtomicU64,
+}
+
+struct Node {
+    key: NonNull<u8>,
+    key_len: usize,
+    // The newest entry of the key, never null
+    entry: AtomicPtr<Version>,
+    // The next node at every level the node is linked at, `height` of them
+    tower: NonNull<AtomicPtr<Node>>,
+    height: usize,
+}
+
+struct Version {
+    entry: Entry,
+    // The entry this one replaced, kept until the list is dropped
+    older: *mut Version,
+}
+
+// SAFETY: nodes and versions are only ever reached through the list, and hold keys and entries that are
+// Send and Sync themselves. Every shared pointer is published with release and read with acquire ordering
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        Self::with_comparator(Arc::new(BytewiseComparator))
+    }
+
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        let head_tower: Box<[AtomicPtr<Node>]> = (0..MAX_HEIGHT)
+            .map(|_| AtomicPtr::new(ptr::null_mut()))
+            .collect();
+        Self {
+            comparator,
+            head: Node {
+                key: NonNull::dangling(),
+                key_len: 0,
+                entry: AtomicPtr::new(ptr::null_mut()),
+                // Boxed, so the tower stays put when the list is moved
+                tower: NonNull::from(&head_tower[0]),
+                height: MAX_HEIGHT,
+            },
+            head_tower,
+            arena: Arena::new(),
+            len: AtomicUsize::new(0),
+            values_size: AtomicUsize::new(0),
+            rng: AtomicU64::new(0x853C_49E6_748F_EA9B),
+        }
+    }
+
+    /// `MemTableRep::insert` through a shared reference, safe to call from many threads at once.
+    pub fn insert_shared(&self, key: Vec<u8>, entry: Entry) {
+        let (mut preds, mut succs) = self.find(&key);
+        if let Some(node) = self.node(succs[0])
+            && self.same_key(node.key(), &key)
+        {
+            return self.replace(node, entry);
+        }
+
+        let val_len = val_len(&entry);
+        let node = self.new_node(&key, entry).as_ptr();
+        // SAFETY: `node` was just allocated, and is only shared once linked at level 0 below
+        let new = unsafe { &*node };
+
+        // Linking at level 0 puts the key in the list, a lost race means another node may have taken
+        // the key in the meantime
+        loop {
+            new.next(0).store(succs[0], Ordering::Relaxed);
+            let pred = self.node_or_head(preds[0]);
+            if pred
+                .next(0)
+                .compare_exchange(succs[0], node, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                break;
+            }
+
+            (preds, succs) = self.find(new.key());
+            if let Some(existing) = self.node(succs[0])
+                && self.same_key(existing.key(), new.key())
+            {
+                // SAFETY: `node` was never linked, so nothing else can have seen its version. The arena
+                // never drops it, so the entry is moved out rather than copied
+                let entry = unsafe { ptr::read(&(*new.entry.load(Ordering::Relaxed)).entry) };
+                return self.replace(existing, entry);
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.values_size.fetch_add(val_len, Ordering::Relaxed);
+
+        // The upper levels only speed up searches, the node is found through level 0 meanwhile
+        for level in 1..new.height {
+            loop {
+                new.next(level).store(succs[level], Ordering::Relaxed);
+                let pred = self.node_or_head(preds[level]);
+                if pred
+                    .next(level)
+                    .compare_exchange(succs[level], node, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    break;
+                }
+                (preds, succs) = self.find(new.key());
+            }
+        }
+    }
+
+    /// A node for `key` holding `entry`, of a random height and linked nowhere yet.
+    fn new_node(&self, key: &[u8], entry: Entry) -> NonNull<Node> {
+        let height = self.random_height();
+        let tower = self
+            .arena
+            .alloc(height * size_of::<AtomicPtr<Node>>())
+            .cast::<AtomicPtr<Node>>();
+        for level in 0..height {
+            // SAFETY: the tower was just allocated with room for `height` links
+            unsafe { tower.add(level).write(AtomicPtr::new(ptr::null_mut())) };
+        }
+
+        self.arena.alloc_val(Node {
+            key: self.arena.alloc_bytes(key),
+            key_len: key.len(),
+            entry: AtomicPtr::new(self.new_version(entry)),
+            tower,
+            height,
+        })
+    }
+
+    fn new_version(&self, entry: Entry) -> *mut Version {
+        self.arena
+            .alloc_val(Version {
+                entry,
+                older: ptr::null_mut(),
+            })
+            .as_ptr()
+    }
+
+    /// The last node before `key` and the first node at or after it, at every level. Null stands for the
+    /// head among the former, and for the end of the level among the latter.
+    fn find(&self, key: &[u8]) -> ([*mut Node; MAX_HEIGHT], [*mut Node; MAX_HEIGHT]) {
+        let mut preds = [ptr::null_mut(); MAX_HEIGHT];
+        let mut succs = [ptr::null_mut(); MAX_HEIGHT];
+        let mut pred: *mut Node = ptr::null_mut();
+        for level in (0..MAX_HEIGHT).rev() {
+            let mut next = self.node_or_head(pred).next(level).load(Ordering::Acquire);
+            while let Some(node) = self.node(next)
+                && self.comparator.compare(node.key(), key).is_lt()
+            {
+                pred = next;
+                next = node.next(level).load(Ordering::Acquire);
+            }
+            preds[level] = pred;
+            succs[level] = next;
+        }
+        (preds, succs)
+    }
+
+    /// The first node from `from` on at level 0.
+    fn seek(&self, from: Bound<&[u8]>) -> *mut Node {
+        match from {
+            Bound::Unbounded => self.head.next(0).load(Ordering::Acquire),
+            Bound::Included(key) => self.find(key).1[0],
+            Bound::Excluded(key) => {
+                let next = self.find(key).1[0];
+                match self.node(next) {
+                    Some(node) if self.same_key(node.key(), key) => {
+                        node.next(0).load(Ordering::Acquire)
+                    }
+                    _ => next,
+                }
+            }
+        }
+    }
+
+    /// The last node before `until` at level 0, null if there is none.
+    fn seek_before(&self, until: Bound<&[u8]>) -> *mut Node {
+        let before = |key: &[u8]| comparator::before(self.comparator.as_ref(), key, until);
+        let mut pred: *mut Node = ptr::null_mut();
+        for level in (0..MAX_HEIGHT).rev() {
+            let mut next = self.node_or_head(pred).next(level).load(Ordering::Acquire);
+            while let Some(node) = self.node(next)
+                && before(node.key())
+            {
+                pred = next;
+                next = node.next(level).load(Ordering::Acquire);
+            }
+        }
+        pred
+    }
+
+    fn same_key(&self, a: &[u8], b: &[u8]) -> bool {
+        self.comparator.compare(a, b).is_eq()
+    }
+
+    fn node(&self, node: *mut Node) -> Option<&Node> {
+        // SAFETY: every non-null pointer in the list is to a node that lives as long as the list
+        unsafe { node.as_ref() }
+    }
+
+    fn node_or_head(&self, node: *mut Node) -> &Node {
+        self.node(node).unwrap_or(&self.head)
+    }
+
+    /// A height of at least 1, each level above taken with a chance of 1 in 4.
+    fn random_height(&self) -> usize {
+        // splitmix64, stepped atomically so concurrent inserts draw different heights
+        let mut x = self.rng.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^= x >> 31;
+
+        let mut height = 1;
+        while height < MAX_HEIGHT && x & 3 == 0 {
+            height += 1;
+            x >>= 2;
+        }
+        height
+    }
+
+    /// Drops every entry and frees the arena, leaving the list empty.
+    fn free(&mut self) {
+        let mut next = self.head.next(0).load(Ordering::Acquire);
+        while let Some(node) = self.node(next) {
+            next = node.next(0).load(Ordering::Acquire);
+            let mut version = node.entry.load(Ordering::Acquire);
+            while !version.is_null() {
+                // SAFETY: `&mut self` rules out any reader, and every version is reachable from exactly
+                // one node, through exactly one link. The arena never drops what it holds
+                unsafe {
+                    let older = (*version).older;
+                    ptr::drop_in_place(version);
+                    version = older;
+                }
+            }
+        }
+        for link in self.head_tower.iter() {
+            link.store(ptr::null_mut(), Ordering::Relaxed);
+        }
+        self.arena.reset();
+        self.len.store(0, Ordering::Relaxed);
+        self.values_size.store(0, Ordering::Relaxed);
+    }
+
+    /// Makes `entry` the newest of `node`, unless it holds an entry with a seq_no at least as high.
+    fn replace(&self, node: &Node, entry: Entry) {
+        let mut current = node.entry.load(Ordering::Acquire);
+        // SAFETY: the node's versions live as long as the list
+        if unsafe { &(*current).entry }.seq_no() >= entry.seq_no() {
+            return;
+        }
+
+        let val_len = val_len(&entry);
+        let version = self.new_version(entry);
+        // SAFETY: `version` was just allocated, and is only shared once swapped in below
+        let new = unsafe { &mut *version };
+        loop {
+            new.older = current;
+            match node
+                .entry
+                .compare_exchange(current, version, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+
+            // SAFETY: as above
+            if unsafe { &(*current).entry }.seq_no() >= new.entry.seq_no() {
+                // SAFETY: `version` was never shared, and the arena never drops it
+                unsafe { ptr::drop_in_place(version) };
+                return;
+            }
+        }
+        self.values_size.fetch_add(val_len, Ordering::Relaxed);
+    }
+}
+
+impl Drop for SkipList {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
+
+impl Node {
+    fn key(&self) -> &[u8] {
+        // SAFETY: the key was copied into the arena along with the node, or is empty for the head
+        unsafe { std::slice::from_raw_parts(self.key.as_ptr(), self.key_len) }
+    }
+
+    fn next(&self, level: usize) -> &AtomicPtr<Node> {
+        assert!(level < self.height);
+        // SAFETY: the tower holds `height` links, initialized along with the node
+        unsafe { self.tower.add(level).as_ref() }
+    }
+
+    fn entry(&self) -> &Entry {
+        // SAFETY: `entry` is never null on a linked node, and versions live as long as the list
+        unsafe { &(*self.entry.load(Ordering::Acquire)).entry }
+    }
+}
+
+impl MemTableRep for SkipList {
+    fn get(&self, key: &[u8]) -> Option<&Entry> {
+        self.node(self.find(key).1[0])
+            .filter(|node| self.same_key(node.key(), key))
+            .map(Node::entry)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        self.insert_shared(key, entry)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn size(&self) -> usize {
+        self.arena.allocated() + self.values_size.load(Ordering::Relaxed)
+    }
+
+    fn iter_from(&self, from: Bound<&[u8]>) -> MemTableIter<'_> {
+        Box::new(Iter {
+            list: self,
+            next: self.seek(from),
+        })
+    }
+
+    fn iter_before(&self, until: Bound<&[u8]>) -> MemTableIter<'_> {
+        Box::new(RevIter {
+            list: self,
+            next: self.seek_before(until),
+        })
+    }
+
+    fn runs(&self) -> Vec<&dyn MemTableRep> {
+        vec![self]
+    }
+
+    fn clear(&mut self) {
+        self.free();
+    }
+
+    fn comparator(&self) -> &dyn Comparator {
+        self.comparator.as_ref()
+    }
+}
+
+/// Walks level 0. Nodes linked after the iterator passed their place are not seen.
+struct Iter<'a> {
+    list: &'a SkipList,
+    next: *mut Node,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], &'a Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.list.node(self.next)?;
+        self.next = node.next(0).load(Ordering::Acquire);
+        Some((node.key(), node.entry()))
+    }
+}
+
+/// Walks level 0 backwards. Nodes only link forwards, so every step searches the list anew for the node
+/// before the last one returned.
+struct RevIter<'a> {
+    list: &'a SkipList,
+    next: *mut Node,
+}
+
+impl<'a> Iterator for RevIter<'a> {
+    type Item = (&'a [u8], &'a Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.list.node(self.next)?;
+        self.next = self.list.seek_before(Bound::Excluded(node.key()));
+        Some((node.key(), node.entry()))
+    }
+}
+
+#[cfg(test)]
+mod skiplist_test {
+    use super::*;
+    use crate::memtable::MemTable;
+
+    fn value(seq_no: u64, val: &str) -> Entry {
+        Entry::Value {
+            seq_no,
+            val: val.as_bytes().to_vec(),
+            timestamp: None,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn behaves_like_the_btree_mem_table() {
+        let mut list = SkipList::new();
+        let mut map = MemTable::new();
+        for i in 0..2000u64 {
+            let key = format!("key{:04}", i * 7919 % 1000).into_bytes();
+            let entry = match i % 5 {
+                0 => Entry::Tombstone { seq_no: i },
+                // Older than what the key holds by now, which must be ignored
+                1 if i > 1000 => value(i - 1000, "stale"),
+                _ => value(i, &format!("val{i}")),
+            };
+            MemTableRep::insert(&mut list, key.clone(), entry.clone());
+            MemTableRep::insert(&mut map, key, entry);
+        }
+
+        assert_eq!(MemTableRep::len(&list), map.len());
+        assert!(list.iter().eq(MemTableRep::iter(&map)));
+        let forward: Vec<_> = MemTableRep::iter(&map).collect();
+        assert!(
+            list.iter_before(Bound::Unbounded)
+                .eq(forward.into_iter().rev())
+        );
+        for from in [b"key0500".as_slice(), b"key05000", b"a", b"z"] {
+            for bound in [Bound::Included(from), Bound::Excluded(from)] {
+                assert!(list.iter_from(bound).eq(map.iter_from(bound)), "{bound:?}");
+                assert!(
+                    list.iter_before(bound).eq(map.iter_before(bound)),
+                    "{bound:?}"
+                );
+            }
+        }
+        assert_eq!(
+            MemTableRep::get(&list, b"key0007"),
+            MemTableRep::get(&map, b"key0007")
+        );
+        assert_eq!(MemTableRep::get(&list, b"missing"), None);
+
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(list.iter().count(), 0);
+        MemTableRep::insert(&mut list, b"a".to_vec(), value(0, "a"));
+        assert_eq!(MemTableRep::get(&list, b"a"), Some(&value(0, "a")));
+    }
+
+    #[test]
+    fn concurrent_inserts_and_reads() {
+        let list = SkipList::new();
+        let list = &list;
+        std::thread::scope(|scope| {
+            for writer in 0..4u64 {
+                scope.spawn(move || {
+                    for i in 0..1000u64 {
+                        // Every writer overwrites the keys of the others
+                        let key = format!("key{:03}", i % 500).into_bytes();
+                        list.insert_shared(key, value(i * 4 + writer, "val"));
+                    }
+                });
+            }
+            scope.spawn(move || {
+                for _ in 0..100 {
+                    let keys: Vec<_> = list.iter().map(|(key, _)| key).collect();
+                    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                }
+            });
+        });
+
+        assert_eq!(MemTableRep::len(list), 500);
+        for i in 0..500u64 {
+            let key = format!("key{i:03}").into_bytes();
+            // The newest write of the key came from the last writer's second pass
+            let seq_no = (i + 500) * 4 + 3;
+            assert_eq!(MemTableRep::get(list, &key), Some(&value(seq_no, "val")));
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Point-in-time reads. A `Snapshot` pins the DB as it was when the snapshot was taken: reads through it
+//! only see the writes committed before, whatever is written, overwritten or deleted after. Snapshots are
+//! taken out with `DB::snapshot`.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::version::{self, VersionSet};
+
+/// A Snapshot sees every write with a `seq_no` below its own. It is registered with the DB for as long
+/// as it lives, so neither the MemTable nor compaction drop a version of a key the snapshot may still
+/// read, see `DB::snapshot`.
+/// Dropping it lets them go.
+pub struct Snapshot {
+    seq_no: u64,
+    versions: Arc<Mutex<VersionSet>>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(seq_no: u64, versions: Arc<Mutex<VersionSet>>) -> Self {
+        *version::lock(&versions)
+            .snapshots
+            .entry(seq_no)
+            .or_default() += 1;
+        Self { seq_no, versions }
+    }
+
+    /// The `seq_no` of the first write the snapshot doesn't see.
+    pub fn seq_no(&self) -> u64 {
+        self.seq_no
+    }
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("seq_no", &self.seq_no)
+            .finish()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut versions = version::lock(&self.versions);
+        if let Some(handles) = versions.snapshots.get_mut(&self.seq_no) {
+            *handles -= 1;
+            if *handles == 0 {
+                versions.snapshots.remove(&self.seq_no);
+            }
+        }
+    }
+}
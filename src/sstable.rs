@@ -1,13 +1,3008 @@
-use std::fs::File;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use crate::block::{Block, BlockBuilder, BlockEntry, BlockIter, DEFAULT_RESTART_INTERVAL};
+use crate::bloom::{self, BlockedBloomFilter, BloomFilter};
+use crate::checksum::{self, ChecksumType};
+use crate::clock::{Clock, SystemClock};
+use crate::comparator::{BYTEWISE_COMPARATOR_NAME, BytewiseComparator, Comparator};
+use crate::compression::{self, CompressionType};
+use crate::entry::{Entry, RangeTombstone};
+#[cfg(feature = "mmap")]
+use crate::mmap::Mmap;
+use crate::types::{DBError, read_exact_at, read_u32_le, read_u64_le, sync_parent_dir};
+
+/// Magic bytes trailing every SSTable file, used to tell an SSTable apart from any other file
+/// that may have ended up in the `ss_table_dir`.
+pub const SS_TABLE_MAGIC: u64 = 0x4C53_4D44_4253_5354; // "LSMDBSST"
+pub const SS_TABLE_FORMAT_VERSION: u32 = 3;
+
+/// [index_offset u64][index_len u32][props_offset u64][props_len u32][filter_offset u64][filter_len u32]
+/// [range_del_offset u64][range_del_len u32][checksum_type u8][file_checksum u32][version u32][magic u64]
+///
+/// The version and magic always trail the file so any future format can still be recognized and rejected.
+/// A `filter_len` of 0 means the table was written without a filter, and a `range_del_len` of 0 that it
+/// holds no range tombstones. `checksum_type` is the `ChecksumType` of every block trailer and of
+/// `file_checksum`, the checksum of every byte before the footer.
+pub const SS_TABLE_FOOTER_LEN: usize = 8 + 4 + 8 + 4 + 8 + 4 + 8 + 4 + 1 + 4 + 4 + 8;
+
+/// Format version 2 predates selectable checksums, its footer lacks the checksum type and everything in
+/// it is checksummed with crc32.
+pub const SS_TABLE_V2_FOOTER_LEN: usize = SS_TABLE_FOOTER_LEN - 1;
+
+/// Format version 1 predates range tombstones too, its footer also lacks the range deletion block handle.
+/// Such tables are still read.
+pub const SS_TABLE_V1_FOOTER_LEN: usize = SS_TABLE_V2_FOOTER_LEN - 8 - 4;
+
+/// Every block (data, filter, index and properties) is followed by a [compression u8][checksum u32] trailer.
+/// The checksum covers the (possibly compressed) contents and the compression byte.
+pub const BLOCK_TRAILER_LEN: usize = 1 + 4;
+
+pub const DEFAULT_BLOCK_SIZE: usize = 4 * 1024;
+
+/// Values larger than the block size are written to a chain of overflow blocks rather than into a data
+/// block, each overflow block being
+///
+/// [next_offset u64][next_len u32][value chunk]
+///
+/// where a `next_len` of 0 ends the chain. The data block entry holds a pointer to the first block:
+///
+/// [offset u64][len u32][value_len u64]
+const OVERFLOW_HEADER_LEN: usize = 8 + 4;
+const OVERFLOW_POINTER_LEN: usize = 8 + 4 + 8;
+pub const DEFAULT_BLOOM_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// A FilterPolicy decides how the filter block of a table is built and probed. Filters are built over the
+/// `bloom::hash` of every key in the table and must never answer `false` for one of those keys.
+///
+/// The policy's `name` is written into the filter block as [name_len u8][name][filter], and a reader only
+/// consults a filter whose name matches its own policy. Tables written under a different policy are still
+/// readable, their lookups just go straight to the data blocks.
+pub trait FilterPolicy: fmt::Debug + Send + Sync {
+    /// Identifies the filter format, changing how `build` lays out its output requires a new name.
+    fn name(&self) -> &'static str;
+
+    fn build(&self, key_hashes: &[u64]) -> Vec<u8>;
+
+    /// Whether the key behind `key_hash` may be in the table the `filter` was built for.
+    fn may_contain(&self, filter: &[u8], key_hash: u64) -> bool;
+}
+
+/// Stores a `BloomFilter` over the keys of the table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomFilterPolicy {
+    false_positive_rate: f64,
+}
+
+impl BloomFilterPolicy {
+    pub fn new(false_positive_rate: f64) -> Self {
+        Self {
+            false_positive_rate,
+        }
+    }
+}
+
+impl Default for BloomFilterPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOOM_FALSE_POSITIVE_RATE)
+    }
+}
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> &'static str {
+        "lsmdb.BloomFilter"
+    }
+
+    fn build(&self, key_hashes: &[u64]) -> Vec<u8> {
+        let mut filter = BloomFilter::new(key_hashes.len(), self.false_positive_rate);
+        for hash in key_hashes {
+            filter.insert_hash(*hash);
+        }
+        filter.encode()
+    }
+
+    fn may_contain(&self, filter: &[u8], key_hash: u64) -> bool {
+        BloomFilter::encoded_may_contain(filter, key_hash)
+    }
+}
+
+/// Stores a `BlockedBloomFilter` over the keys of the table, trading a little accuracy for lookups that
+/// touch a single cache line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockedBloomFilterPolicy {
+    false_positive_rate: f64,
+}
+
+impl BlockedBloomFilterPolicy {
+    pub fn new(false_positive_rate: f64) -> Self {
+        Self {
+            false_positive_rate,
+        }
+    }
+}
+
+impl Default for BlockedBloomFilterPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOOM_FALSE_POSITIVE_RATE)
+    }
+}
+
+impl FilterPolicy for BlockedBloomFilterPolicy {
+    fn name(&self) -> &'static str {
+        "lsmdb.BlockedBloomFilter"
+    }
+
+    fn build(&self, key_hashes: &[u64]) -> Vec<u8> {
+        let mut filter = BlockedBloomFilter::new(key_hashes.len(), self.false_positive_rate);
+        for hash in key_hashes {
+            filter.insert_hash(*hash);
+        }
+        filter.encode()
+    }
+
+    fn may_contain(&self, filter: &[u8], key_hash: u64) -> bool {
+        BlockedBloomFilter::encoded_may_contain(filter, key_hash)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SSTableMeta {
     file_no: u64,
     level: u32,
     path: String,
     smallest_key: Vec<u8>,
     largest_key: Vec<u8>,
+    min_seq_no: u64,
+    max_seq_no: u64,
+    creation_time: u64,
+    file_size: u64,
+    file_checksum: u32,
+}
+
+/// TableProperties are written into the properties block of every SSTable, so the table describes itself
+/// and its `SSTableMeta` can be rebuilt from the file alone.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TableProperties {
+    // The level the table was written to, it may since have been moved further down
+    pub level: u32,
+    // Every entry, tombstones included
+    pub entry_count: u64,
+    pub tombstone_count: u64,
+    // Range tombstones are not entries, see `SSTableWriter::add_range_tombstone`
+    pub range_deletion_count: u64,
+    pub raw_key_bytes: u64,
+    pub raw_value_bytes: u64,
+    // The number of data blocks and their size on disk i.e. after compression, overflow blocks included
+    pub data_block_count: u64,
+    pub data_bytes: u64,
+    // Range tombstones included
+    pub min_seq_no: u64,
+    pub max_seq_no: u64,
+    // Seconds since the unix epoch at which the table was written
+    pub creation_time: u64,
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    // The name of the `Comparator` the keys are sorted by
+    pub comparator_name: String,
+}
+
+/// The outcome of `SSTableReader::verify`. Failures are collected rather than returned so a single bad
+/// block does not hide the state of the rest of the table.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TableVerifyReport {
+    pub path: PathBuf,
+    pub blocks_checked: usize,
+    pub entries_checked: u64,
+    pub failures: Vec<BlockFailure>,
 }
 
+/// A data block that failed verification, identified by its offset in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockFailure {
+    pub offset: u64,
+    pub what: &'static str,
+}
+
+impl TableVerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The SSTableReader serves point lookups from a single SSTable. The footer and the index block are read
+/// once on `open` and kept in memory, so a lookup costs a binary search over the index plus a single
+/// data block read.
 pub struct SSTableReader {
-    file: File,
-}
\ No newline at end of file
+    source: TableSource,
+    path: PathBuf,
+    index: Vec<IndexEntry>,
+    // The filter block, kept only if it was built by the reader's own policy
+    filter: Option<(Arc<dyn FilterPolicy>, Vec<u8>)>,
+    filter_policy_name: Option<String>,
+    properties: TableProperties,
+    // Ordered by `start`, they are few enough to be kept in memory like the index
+    range_tombstones: Vec<RangeTombstone>,
+    comparator: Arc<dyn Comparator>,
+    footer_offset: u64,
+    file_size: u64,
+    checksum: ChecksumType,
+    file_checksum: u32,
+}
+
+impl SSTableMeta {
+    /// Builds the `SSTableMeta` for the table at `path` from the properties and checksum stored in its footer.
+    pub fn from_properties(
+        file_no: u64,
+        path: String,
+        props: &TableProperties,
+        file_size: u64,
+        file_checksum: u32,
+    ) -> Self {
+        Self {
+            file_no,
+            level: props.level,
+            path,
+            smallest_key: props.smallest_key.clone(),
+            largest_key: props.largest_key.clone(),
+            min_seq_no: props.min_seq_no,
+            max_seq_no: props.max_seq_no,
+            creation_time: props.creation_time,
+            file_size,
+            file_checksum,
+        }
+    }
+
+    pub fn file_no(&self) -> u64 {
+        self.file_no
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn smallest_key(&self) -> &[u8] {
+        &self.smallest_key
+    }
+
+    pub fn largest_key(&self) -> &[u8] {
+        &self.largest_key
+    }
+
+    /// The smallest and largest `seq_no` of any entry in the table. Both are 0 for an empty table.
+    pub fn min_seq_no(&self) -> u64 {
+        self.min_seq_no
+    }
+
+    pub fn max_seq_no(&self) -> u64 {
+        self.max_seq_no
+    }
+
+    /// When the table was written, in seconds since the unix epoch.
+    pub fn creation_time(&self) -> u64 {
+        self.creation_time
+    }
+
+    /// The size of the table file in bytes, footer included.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// The checksum of the table's contents before its footer, see `SS_TABLE_FOOTER_LEN`.
+    pub fn file_checksum(&self) -> u32 {
+        self.file_checksum
+    }
+
+    /// The same table, moved to `level`. A table's level lives in the manifest, its file is left as is.
+    pub(crate) fn with_level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Whether `key` falls within the table's key range, in the order of `comparator`, i.e. whether the
+    /// table could hold it at all. The key range spans the table's range tombstones too, so a table that
+    /// could delete `key` holds it.
+    pub fn may_contain_key(&self, comparator: &dyn Comparator, key: &[u8]) -> bool {
+        comparator.compare(&self.smallest_key, key).is_le()
+            && comparator.compare(key, &self.largest_key).is_le()
+    }
+
+    /// Whether the table's key range overlaps the inclusive range `[smallest, largest]`. Compaction uses this
+    /// to pull in every table a set of inputs overlaps with.
+    pub fn overlaps(&self, comparator: &dyn Comparator, smallest: &[u8], largest: &[u8]) -> bool {
+        comparator.compare(&self.smallest_key, largest).is_le()
+            && comparator.compare(smallest, &self.largest_key).is_le()
+    }
+}
+
+/// SSTableConfig holds the knobs used when writing a table.
+#[derive(Debug, Clone)]
+pub struct SSTableConfig {
+    pub block_size: usize,
+    /// How many entries share a prefix-compressed run before a full key is written, see `BlockBuilder`.
+    pub block_restart_interval: usize,
+    /// Compression applied to data blocks. Blocks that don't shrink by at least 1/8th are stored as-is.
+    pub compression: CompressionType,
+    /// When set, a filter built by this policy is stored alongside the table.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// The checksum guarding every block and the file as a whole, recorded in the footer.
+    pub checksum: ChecksumType,
+    /// The order keys are added in, its name is recorded in the properties block.
+    pub comparator: Arc<dyn Comparator>,
+    /// Tells the `creation_time` recorded in the properties block.
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for SSTableConfig {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            compression: CompressionType::None,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            checksum: ChecksumType::Crc32,
+            comparator: Arc::new(BytewiseComparator),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// How an `SSTableReader` gets at the bytes of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SSTableReadMode {
+    /// Every block read is a `pread` into a freshly allocated buffer.
+    #[default]
+    Pread,
+    /// The whole table is memory-mapped on open, and uncompressed blocks are served straight from the
+    /// mapping without a syscall or a copy.
+    #[cfg(feature = "mmap")]
+    Mmap,
+}
+
+/// SSTableReadOptions holds the knobs used when opening a table.
+#[derive(Debug, Clone)]
+pub struct SSTableReadOptions {
+    pub mode: SSTableReadMode,
+    /// The policy used to probe filter blocks. Filters written by any other policy, or all of them when
+    /// `None`, are ignored.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// The order the table's keys are expected in. Opening a table written under another fails.
+    pub comparator: Arc<dyn Comparator>,
+}
+
+impl Default for SSTableReadOptions {
+    fn default() -> Self {
+        Self {
+            mode: SSTableReadMode::default(),
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            comparator: Arc::new(BytewiseComparator),
+        }
+    }
+}
+
+enum TableSource {
+    File(File),
+    #[cfg(feature = "mmap")]
+    Mmap(Mmap),
+}
+
+impl TableSource {
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Cow<'_, [u8]>, DBError> {
+        match self {
+            TableSource::File(file) => {
+                let mut buf = vec![0u8; len];
+                read_exact_at(file, &mut buf, offset).map_err(|e| DBError::Io {
+                    op: "sstable: failed to read file",
+                    path: path.to_path_buf(),
+                    source: e,
+                })?;
+                Ok(Cow::Owned(buf))
+            }
+            #[cfg(feature = "mmap")]
+            TableSource::Mmap(map) => {
+                let start = offset as usize;
+                start
+                    .checked_add(len)
+                    .and_then(|end| map.as_slice().get(start..end))
+                    .map(Cow::Borrowed)
+                    .ok_or(DBError::Corruption {
+                        what: "sstable: read past the end of the mapped file",
+                        path: path.to_path_buf(),
+                        offset,
+                    })
+            }
+        }
+    }
+}
+
+/// Points at a single data block in the file. `last_key` is the largest key held by the block,
+/// which lets a reader binary-search the index for the first block that could contain a key.
+struct IndexEntry {
+    last_key: Vec<u8>,
+    offset: u64,
+    len: u32,
+}
+
+/// The SSTableWriter serializes an ordered stream of `Entry` values into an immutable on-disk file.
+/// Below is the layout of the file:
+///
+/// [data block 0]...[data block n][range deletion block][filter block][index block][properties block][footer]
+///
+/// Data blocks are cut once they grow past the block size, and every block gets a matching entry in the
+/// index block. Values larger than the block size are written to overflow blocks in between the data
+/// blocks, see `OVERFLOW_POINTER_LEN`. The optional filter block holds the filter built by the configured
+/// `FilterPolicy` over every key in the table and the properties block holds the `TableProperties`. The
+/// optional range deletion block holds the table's range tombstones, see `add_range_tombstone`. Data
+/// blocks may be compressed, and each block is followed by a trailer holding its compression type and
+/// checksum (see `BLOCK_TRAILER_LEN`) which readers verify before trusting the block. The footer is always
+/// the last `SS_TABLE_FOOTER_LEN` bytes and tells a reader where the other blocks live, which format
+/// version wrote the file, and carries the `SS_TABLE_MAGIC`.
+///
+/// Keys must be added in increasing order by the config's `Comparator`, which is exactly the order a
+/// `MemTable` under the same comparator iterates in. A key may be added more than once, newest version
+/// first, for the older versions a live snapshot still reads. The versions of a key are kept within a
+/// single data block.
+///
+/// The table is written to `<path>.tmp` and only renamed to `path` once `finish` has synced it, followed by
+/// a sync of the parent directory so the rename itself is durable. A crash mid-write therefore leaves at
+/// most a stray `.tmp` file behind, never a half-written table under its final name.
+///
+/// Long writes can be observed through `set_progress_callback`, and `preallocate` reserves the expected
+/// size of the file up front.
+pub struct SSTableWriter {
+    buf: BufWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file_no: u64,
+    config: SSTableConfig,
+    block: BlockBuilder,
+    key_hashes: Vec<u64>,
+    index: Vec<IndexEntry>,
+    offset: u64,
+    // Running checksum of everything written so far, becomes the footer's `file_checksum`
+    file_hasher: checksum::Hasher,
+    smallest_key: Option<Vec<u8>>,
+    last_key: Option<Vec<u8>>,
+    // The seq_no of the entry added last, older versions of `last_key` have to come in below it
+    last_seq_no: u64,
+    range_tombstones: Vec<RangeTombstone>,
+    props: TableProperties,
+    progress: Option<ProgressCallback>,
+    // Whether the file was grown by `preallocate`, in which case `finish` trims it back down
+    preallocated: bool,
+}
+
+/// Progress of an `SSTableWriter`, reported every time a data block is cut and once more on `finish`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriterProgress {
+    pub bytes_written: u64,
+    pub entries: u64,
+    // The number of data blocks cut so far, the block being built is the next one
+    pub blocks: u64,
+}
+
+pub type ProgressCallback = Box<dyn FnMut(&WriterProgress) + Send>;
+
+impl SSTableWriter {
+    pub fn new(path: PathBuf, file_no: u64, level: u32) -> Result<Self, DBError> {
+        Self::with_config(path, file_no, level, SSTableConfig::default())
+    }
+
+    pub fn with_config(
+        path: PathBuf,
+        file_no: u64,
+        level: u32,
+        config: SSTableConfig,
+    ) -> Result<Self, DBError> {
+        let tmp_path = tmp_path(&path);
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(|e| DBError::Io {
+                op: "sstable: failed to create file",
+                path: tmp_path.clone(),
+                source: e,
+            })?;
+
+        let props = TableProperties {
+            level,
+            min_seq_no: u64::MAX,
+            comparator_name: config.comparator.name().to_string(),
+            ..Default::default()
+        };
+        Ok(Self {
+            buf: BufWriter::new(file),
+            path,
+            tmp_path,
+            file_no,
+            block: BlockBuilder::new(config.block_restart_interval),
+            file_hasher: config.checksum.hasher(),
+            config,
+            key_hashes: Vec::new(),
+            index: Vec::new(),
+            offset: 0,
+            smallest_key: None,
+            last_key: None,
+            last_seq_no: 0,
+            range_tombstones: Vec::new(),
+            props,
+            progress: None,
+            preallocated: false,
+        })
+    }
+
+    pub fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
+    }
+
+    /// Reserves `size_hint` bytes of disk for the file up front so it is laid out in as few extents as
+    /// possible. Any space not used by the finished table is released again by `finish`.
+    pub fn preallocate(&mut self, size_hint: u64) -> Result<(), DBError> {
+        if size_hint == 0 {
+            return Ok(());
+        }
+
+        preallocate(self.buf.get_ref(), size_hint).map_err(|e| DBError::Io {
+            op: "sstable: failed to preallocate file",
+            path: self.tmp_path.clone(),
+            source: e,
+        })?;
+        self.preallocated = true;
+
+        Ok(())
+    }
+
+    /// Appends the `entry` for `key` to the current data block, cutting a new block once the current one
+    /// is full.
+    pub fn add(&mut self, key: &[u8], entry: &Entry) -> Result<(), DBError> {
+        if key.is_empty() {
+            return Err(DBError::Codec {
+                context: String::from("sstable: key cannot be empty"),
+                source: None,
+            });
+        }
+
+        let new_key = match &self.last_key {
+            Some(last_key) => match self.config.comparator.compare(key, last_key) {
+                std::cmp::Ordering::Greater => true,
+                std::cmp::Ordering::Equal if entry.seq_no() < self.last_seq_no => false,
+                _ => {
+                    return Err(DBError::Codec {
+                        context: String::from(
+                            "sstable: keys must be added in increasing order, the versions of a key newest first",
+                        ),
+                        source: None,
+                    });
+                }
+            },
+            None => true,
+        };
+
+        // A full block is only cut before a new key, so the versions of a key never straddle two blocks
+        if new_key && self.block.estimated_size() >= self.config.block_size {
+            self.flush_block()?;
+        }
+
+        match entry {
+            Entry::Value {
+                seq_no,
+                val,
+                timestamp,
+                expires_at,
+            } if val.len() > self.config.block_size => {
+                let pointer = self.write_overflow(val)?;
+                self.block
+                    .add_overflow(key, *seq_no, *timestamp, *expires_at, &pointer);
+            }
+            _ => self.block.add(key, entry),
+        }
+
+        self.props.entry_count += 1;
+        self.props.raw_key_bytes += key.len() as u64;
+        match entry {
+            Entry::Value { val, .. } | Entry::Merge { operand: val, .. } => {
+                self.props.raw_value_bytes += val.len() as u64
+            }
+            Entry::Tombstone { .. } => self.props.tombstone_count += 1,
+        }
+        self.props.min_seq_no = self.props.min_seq_no.min(entry.seq_no());
+        self.props.max_seq_no = self.props.max_seq_no.max(entry.seq_no());
+
+        if self.smallest_key.is_none() {
+            self.smallest_key = Some(key.to_vec());
+        }
+        if new_key {
+            self.last_key = Some(key.to_vec());
+            if self.config.filter_policy.is_some() {
+                self.key_hashes.push(bloom::hash(key));
+            }
+        }
+        self.last_seq_no = entry.seq_no();
+
+        Ok(())
+    }
+
+    /// Roughly how large the table would be if finished now: everything written so far plus the data block
+    /// being built. The filter, index and properties blocks are left out.
+    pub fn estimated_file_size(&self) -> u64 {
+        self.offset + self.block.estimated_size() as u64
+    }
+
+    /// Adds a tombstone deleting the range `[start, end)`, to be written to the range deletion block on
+    /// `finish`. Range tombstones are kept apart from the entries, they may be added in any order and may
+    /// overlap the entries and each other. The table's key range grows to span them.
+    pub fn add_range_tombstone(&mut self, tombstone: &RangeTombstone) -> Result<(), DBError> {
+        if tombstone.start.is_empty()
+            || self
+                .config
+                .comparator
+                .compare(&tombstone.start, &tombstone.end)
+                .is_ge()
+        {
+            return Err(DBError::Codec {
+                context: String::from(
+                    "sstable: range tombstone must start at a non-empty key before its end",
+                ),
+                source: None,
+            });
+        }
+
+        self.props.range_deletion_count += 1;
+        self.props.min_seq_no = self.props.min_seq_no.min(tombstone.seq_no);
+        self.props.max_seq_no = self.props.max_seq_no.max(tombstone.seq_no);
+        self.range_tombstones.push(tombstone.clone());
+
+        Ok(())
+    }
+
+    /// Writes out any pending data block, the range deletion block, the index block, the properties block
+    /// and the footer, then syncs the file and atomically moves it to its final path.
+    /// Returns the `SSTableMeta` describing the new table.
+    pub fn finish(mut self) -> Result<SSTableMeta, DBError> {
+        self.flush_block()?;
+        // Everything written so far is data and overflow blocks
+        self.props.data_bytes = self.offset;
+
+        let (range_del_offset, range_del_len) = if self.range_tombstones.is_empty() {
+            (0, 0)
+        } else {
+            let comparator = self.config.comparator.clone();
+            self.range_tombstones.sort_by(|a, b| {
+                comparator
+                    .compare(&a.start, &b.start)
+                    .then(b.seq_no.cmp(&a.seq_no))
+            });
+            let block = encode_range_tombstones(&self.range_tombstones);
+            self.write_block(&block, CompressionType::None)?
+        };
+
+        let (filter_offset, filter_len) = match self.config.filter_policy.clone() {
+            Some(policy) => {
+                let name = policy.name();
+                let name_len: u8 = name
+                    .len()
+                    .try_into()
+                    .expect("filter policy name is too long");
+                let mut filter_block = vec![name_len];
+                filter_block.extend_from_slice(name.as_bytes());
+                filter_block.extend_from_slice(&policy.build(&self.key_hashes));
+                self.write_block(&filter_block, CompressionType::None)?
+            }
+            None => (0, 0),
+        };
+
+        let mut index_block = Vec::new();
+        for entry in &self.index {
+            let key_len: u32 = entry.last_key.len().try_into().expect("key is too large");
+            index_block.extend_from_slice(&key_len.to_le_bytes());
+            index_block.extend_from_slice(&entry.last_key);
+            index_block.extend_from_slice(&entry.offset.to_le_bytes());
+            index_block.extend_from_slice(&entry.len.to_le_bytes());
+        }
+        let (index_offset, index_len) = self.write_block(&index_block, CompressionType::None)?;
+
+        if self.props.entry_count == 0 && self.props.range_deletion_count == 0 {
+            self.props.min_seq_no = 0;
+        }
+        self.props.creation_time = self.config.clock.now_millis() / 1000;
+        // The end of a range tombstone is exclusive, spanning it leaves the key range a little too wide at
+        // worst
+        let comparator = self.config.comparator.as_ref();
+        let smallest_key = self
+            .range_tombstones
+            .iter()
+            .map(|tombstone| &tombstone.start)
+            .chain(&self.smallest_key)
+            .min_by(|a, b| comparator.compare(a, b));
+        let largest_key = self
+            .range_tombstones
+            .iter()
+            .map(|tombstone| &tombstone.end)
+            .chain(&self.last_key)
+            .max_by(|a, b| comparator.compare(a, b));
+        self.props.smallest_key = smallest_key.cloned().unwrap_or_default();
+        self.props.largest_key = largest_key.cloned().unwrap_or_default();
+
+        let props_block = encode_properties(&self.props);
+        let (props_offset, props_len) = self.write_block(&props_block, CompressionType::None)?;
+
+        let mut footer = Vec::with_capacity(SS_TABLE_FOOTER_LEN);
+        footer.extend_from_slice(&index_offset.to_le_bytes());
+        footer.extend_from_slice(&index_len.to_le_bytes());
+        footer.extend_from_slice(&props_offset.to_le_bytes());
+        footer.extend_from_slice(&props_len.to_le_bytes());
+        footer.extend_from_slice(&filter_offset.to_le_bytes());
+        footer.extend_from_slice(&filter_len.to_le_bytes());
+        footer.extend_from_slice(&range_del_offset.to_le_bytes());
+        footer.extend_from_slice(&range_del_len.to_le_bytes());
+        footer.push(self.config.checksum as u8);
+        let file_checksum = self.file_hasher.clone().finalize();
+        footer.extend_from_slice(&file_checksum.to_le_bytes());
+        footer.extend_from_slice(&SS_TABLE_FORMAT_VERSION.to_le_bytes());
+        footer.extend_from_slice(&SS_TABLE_MAGIC.to_le_bytes());
+        self.write(&footer)?;
+
+        self.buf.flush().map_err(|e| DBError::Io {
+            op: "sstable: failed to flush buf",
+            path: self.tmp_path.clone(),
+            source: e,
+        })?;
+
+        if self.preallocated {
+            self.buf
+                .get_ref()
+                .set_len(self.offset)
+                .map_err(|e| DBError::Io {
+                    op: "sstable: failed to trim preallocated file",
+                    path: self.tmp_path.clone(),
+                    source: e,
+                })?;
+        }
+
+        self.report_progress();
+
+        self.buf.get_ref().sync_all().map_err(|e| DBError::Io {
+            op: "sstable: failed to sync_all",
+            path: self.tmp_path.clone(),
+            source: e,
+        })?;
+
+        std::fs::rename(&self.tmp_path, &self.path).map_err(|e| DBError::Io {
+            op: "sstable: failed to rename tmp file",
+            path: self.tmp_path.clone(),
+            source: e,
+        })?;
+        sync_parent_dir(&self.path)?;
+
+        Ok(SSTableMeta::from_properties(
+            self.file_no,
+            self.path.to_string_lossy().into_owned(),
+            &self.props,
+            self.offset,
+            file_checksum,
+        ))
+    }
+
+    /// Writes `val` out as a chain of overflow blocks of at most `block_size` bytes each and returns the
+    /// pointer to store in the data block, see `OVERFLOW_POINTER_LEN`. Chunks are written last to first so
+    /// that each one can point at the already written chunk following it.
+    fn write_overflow(&mut self, val: &[u8]) -> Result<Vec<u8>, DBError> {
+        let chunk_len = self.config.block_size.max(1);
+        let (mut next_offset, mut next_len) = (0u64, 0u32);
+
+        let chunks: Vec<&[u8]> = val.chunks(chunk_len).collect();
+        for chunk in chunks.into_iter().rev() {
+            let mut block = Vec::with_capacity(OVERFLOW_HEADER_LEN + chunk.len());
+            block.extend_from_slice(&next_offset.to_le_bytes());
+            block.extend_from_slice(&next_len.to_le_bytes());
+            block.extend_from_slice(chunk);
+            (next_offset, next_len) = self.write_block(&block, self.config.compression)?;
+        }
+
+        let mut pointer = Vec::with_capacity(OVERFLOW_POINTER_LEN);
+        pointer.extend_from_slice(&next_offset.to_le_bytes());
+        pointer.extend_from_slice(&next_len.to_le_bytes());
+        pointer.extend_from_slice(&(val.len() as u64).to_le_bytes());
+        Ok(pointer)
+    }
+
+    fn flush_block(&mut self) -> Result<(), DBError> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+
+        let block = self.block.finish();
+        let (offset, len) = self.write_block(&block, self.config.compression)?;
+        self.props.data_block_count += 1;
+
+        self.index.push(IndexEntry {
+            // A non-empty block always has a last key
+            last_key: self.last_key.clone().unwrap_or_default(),
+            offset,
+            len,
+        });
+        self.report_progress();
+
+        Ok(())
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            progress(&WriterProgress {
+                bytes_written: self.offset,
+                entries: self.props.entry_count,
+                blocks: self.props.data_block_count,
+            });
+        }
+    }
+
+    /// Writes `contents` followed by its crc trailer, returning the offset and length of `contents`.
+    fn write_block(
+        &mut self,
+        contents: &[u8],
+        compression: CompressionType,
+    ) -> Result<(u64, u32), DBError> {
+        let compressed = match compression {
+            CompressionType::None => None,
+            _ => {
+                let compressed = compression::compress(compression, contents)
+                    .map_err(|what| DBError::InvalidConfig { what })?;
+                (compressed.len() < contents.len() - contents.len() / 8).then_some(compressed)
+            }
+        };
+
+        let (payload, compression) = match &compressed {
+            Some(compressed) => (compressed.as_slice(), compression),
+            None => (contents, CompressionType::None),
+        };
+
+        let offset = self.offset;
+        let len: u32 = payload.len().try_into().expect("block too large");
+
+        let mut hasher = self.config.checksum.hasher();
+        hasher.update(payload);
+        hasher.update(&[compression as u8]);
+        let crc = hasher.finalize();
+
+        self.write(payload)?;
+        self.write(&[compression as u8])?;
+        self.write(&crc.to_le_bytes())?;
+
+        Ok((offset, len))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), DBError> {
+        self.buf.write_all(bytes).map_err(|e| DBError::Io {
+            op: "sstable: failed to write buf",
+            path: self.tmp_path.clone(),
+            source: e,
+        })?;
+        self.file_hasher.update(bytes);
+        self.offset += bytes.len() as u64;
+
+        Ok(())
+    }
+}
+
+impl SSTableReader {
+    pub fn open(path: PathBuf) -> Result<Self, DBError> {
+        Self::open_with_options(path, &SSTableReadOptions::default())
+    }
+
+    pub fn open_with_mode(path: PathBuf, mode: SSTableReadMode) -> Result<Self, DBError> {
+        let options = SSTableReadOptions {
+            mode,
+            ..Default::default()
+        };
+        Self::open_with_options(path, &options)
+    }
+
+    pub fn open_with_options(path: PathBuf, options: &SSTableReadOptions) -> Result<Self, DBError> {
+        let file = File::open(path.clone()).map_err(|e| DBError::Io {
+            op: "sstable: failed to open file",
+            path: path.clone(),
+            source: e,
+        })?;
+
+        let file_len = file
+            .metadata()
+            .map_err(|e| DBError::Io {
+                op: "sstable: failed to read file metadata",
+                path: path.clone(),
+                source: e,
+            })?
+            .len();
+
+        if file_len < SS_TABLE_V1_FOOTER_LEN as u64 {
+            return Err(DBError::Corruption {
+                what: "sstable: file too short to hold a footer",
+                path,
+                offset: 0,
+            });
+        }
+
+        let source = match options.mode {
+            SSTableReadMode::Pread => TableSource::File(file),
+            #[cfg(feature = "mmap")]
+            SSTableReadMode::Mmap => TableSource::Mmap(
+                Mmap::map(&file, file_len as usize).map_err(|e| DBError::Io {
+                    op: "sstable: failed to mmap file",
+                    path: path.clone(),
+                    source: e,
+                })?,
+            ),
+        };
+
+        // The version and magic come first, they decide how long the footer is
+        let trailer_offset = file_len - 4 - 8;
+        let trailer = source.read_at(&path, trailer_offset, 4 + 8)?.into_owned();
+        let trailer_corruption = |what: &'static str| DBError::Corruption {
+            what,
+            path: path.clone(),
+            offset: trailer_offset,
+        };
+        let version =
+            read_u32_le(&trailer).ok_or_else(|| trailer_corruption("sstable: bad version"))?;
+        let magic =
+            read_u64_le(&trailer[4..]).ok_or_else(|| trailer_corruption("sstable: bad magic"))?;
+
+        if magic != SS_TABLE_MAGIC {
+            return Err(trailer_corruption("sstable: magic mismatch"));
+        }
+
+        let footer_len = match version {
+            1 => SS_TABLE_V1_FOOTER_LEN,
+            2 => SS_TABLE_V2_FOOTER_LEN,
+            SS_TABLE_FORMAT_VERSION => SS_TABLE_FOOTER_LEN,
+            _ => {
+                return Err(DBError::UnsupportedVersion {
+                    what: "sstable: unsupported format version",
+                    path,
+                    version,
+                });
+            }
+        };
+        if file_len < footer_len as u64 {
+            return Err(trailer_corruption(
+                "sstable: file too short to hold a footer",
+            ));
+        }
+
+        let footer_offset = file_len - footer_len as u64;
+        let footer = source
+            .read_at(&path, footer_offset, footer_len)?
+            .into_owned();
+
+        let corruption = |what: &'static str| DBError::Corruption {
+            what,
+            path: path.clone(),
+            offset: footer_offset,
+        };
+
+        let index_offset =
+            read_u64_le(&footer[0..]).ok_or_else(|| corruption("sstable: bad index offset"))?;
+        let index_len =
+            read_u32_le(&footer[8..]).ok_or_else(|| corruption("sstable: bad index len"))?;
+        let props_offset = read_u64_le(&footer[12..])
+            .ok_or_else(|| corruption("sstable: bad properties offset"))?;
+        let props_len =
+            read_u32_le(&footer[20..]).ok_or_else(|| corruption("sstable: bad properties len"))?;
+        let filter_offset =
+            read_u64_le(&footer[24..]).ok_or_else(|| corruption("sstable: bad filter offset"))?;
+        let filter_len =
+            read_u32_le(&footer[32..]).ok_or_else(|| corruption("sstable: bad filter len"))?;
+        let (range_del_offset, range_del_len) = if version == 1 {
+            (0, 0)
+        } else {
+            (
+                read_u64_le(&footer[36..])
+                    .ok_or_else(|| corruption("sstable: bad range deletion offset"))?,
+                read_u32_le(&footer[44..])
+                    .ok_or_else(|| corruption("sstable: bad range deletion len"))?,
+            )
+        };
+        let checksum = if version < 3 {
+            ChecksumType::Crc32
+        } else {
+            ChecksumType::try_from(footer[48]).map_err(corruption)?
+        };
+        let file_checksum = read_u32_le(&footer[footer_len - 4 - 4 - 8..])
+            .ok_or_else(|| corruption("sstable: bad file checksum"))?;
+
+        let trailer_len = BLOCK_TRAILER_LEN as u64;
+        let after_range_del = if filter_len > 0 {
+            filter_offset
+        } else {
+            index_offset
+        };
+        if index_offset + index_len as u64 + trailer_len != props_offset
+            || props_offset + props_len as u64 + trailer_len != footer_offset
+            || (filter_len > 0 && filter_offset + filter_len as u64 + trailer_len != index_offset)
+            || (range_del_len > 0
+                && range_del_offset + range_del_len as u64 + trailer_len != after_range_del)
+        {
+            return Err(corruption(
+                "sstable: block handles in footer are inconsistent",
+            ));
+        }
+
+        let (filter, filter_policy_name) = if filter_len > 0 {
+            let filter_block =
+                read_block(&source, &path, Some(checksum), filter_offset, filter_len)?;
+            let (name, filter) = decode_filter_block(&filter_block).ok_or(DBError::Corruption {
+                what: "sstable: malformed filter block",
+                path: path.clone(),
+                offset: filter_offset,
+            })?;
+
+            let filter = match &options.filter_policy {
+                Some(policy) if policy.name() == name => Some((policy.clone(), filter.to_vec())),
+                _ => None,
+            };
+            (filter, Some(name.to_string()))
+        } else {
+            (None, None)
+        };
+
+        let props_block = read_block(&source, &path, Some(checksum), props_offset, props_len)?;
+        let properties = decode_properties(&props_block).ok_or(DBError::Corruption {
+            what: "sstable: malformed properties block",
+            path: path.clone(),
+            offset: props_offset,
+        })?;
+        if properties.comparator_name != options.comparator.name() {
+            return Err(DBError::InvalidConfig {
+                what: "sstable: table was written under a different comparator",
+            });
+        }
+
+        let index_block = read_block(&source, &path, Some(checksum), index_offset, index_len)?;
+        let index = decode_index(&index_block).ok_or(DBError::Corruption {
+            what: "sstable: malformed index block",
+            path: path.clone(),
+            offset: index_offset,
+        })?;
+
+        let range_tombstones = if range_del_len > 0 {
+            let range_del_block = read_block(
+                &source,
+                &path,
+                Some(checksum),
+                range_del_offset,
+                range_del_len,
+            )?;
+            decode_range_tombstones(&range_del_block).ok_or(DBError::Corruption {
+                what: "sstable: malformed range deletion block",
+                path: path.clone(),
+                offset: range_del_offset,
+            })?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            source,
+            path,
+            index,
+            filter,
+            filter_policy_name,
+            properties,
+            range_tombstones,
+            comparator: options.comparator.clone(),
+            footer_offset,
+            file_size: file_len,
+            checksum,
+            file_checksum,
+        })
+    }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// The name of the `FilterPolicy` the table's filter was built with, if it has one.
+    pub fn filter_policy_name(&self) -> Option<&str> {
+        self.filter_policy_name.as_deref()
+    }
+
+    /// Whether lookups consult the table's filter, i.e. it has one and it was built by the reader's policy.
+    pub fn uses_filter(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// The table's range tombstones, ordered by `start`. Lookups through `get` and `iter` do not apply
+    /// them, see `entry::covering_seq_no`.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// The whole-file checksum recorded in the footer, see `SS_TABLE_FOOTER_LEN`.
+    pub fn file_checksum(&self) -> u32 {
+        self.file_checksum
+    }
+
+    /// Re-reads every byte before the footer and checks it against the footer's `file_checksum`. Unlike
+    /// `verify` this does not say where the damage is, but it also catches damage outside of any block.
+    pub fn verify_file_checksum(&self) -> Result<(), DBError> {
+        const CHUNK_LEN: u64 = 64 * 1024;
+
+        let mut hasher = self.checksum.hasher();
+        let mut offset = 0;
+        while offset < self.footer_offset {
+            let len = CHUNK_LEN.min(self.footer_offset - offset);
+            hasher.update(&self.source.read_at(&self.path, offset, len as usize)?);
+            offset += len;
+        }
+
+        if hasher.finalize() != self.file_checksum {
+            return Err(DBError::Corruption {
+                what: "sstable: file checksum mismatch",
+                path: self.path.clone(),
+                offset: 0,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the newest version of `key` in the table. A `Some(Entry::Tombstone { .. })` means the key was
+    /// deleted as of this table and callers must not fall through to older tables.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>, DBError> {
+        self.get_with_checksums(key, true)
+    }
+
+    /// `get`, checking the checksum of every block read only if `verify_checksums`. Skipping the check
+    /// saves hashing the block, at the price of handing out whatever a damaged block decodes to.
+    pub fn get_with_checksums(
+        &self,
+        key: &[u8],
+        verify_checksums: bool,
+    ) -> Result<Option<Entry>, DBError> {
+        self.get_at(key, u64::MAX, verify_checksums)
+    }
+
+    /// `get_with_checksums` as a snapshot at `read_seq_no` reads the table: the newest version of `key`
+    /// written before it, skipping the newer versions the table holds.
+    pub fn get_at(
+        &self,
+        key: &[u8],
+        read_seq_no: u64,
+        verify_checksums: bool,
+    ) -> Result<Option<Entry>, DBError> {
+        if !self.may_contain(key) {
+            return Ok(None);
+        }
+
+        // The first block whose last key is >= `key` is the only one that could contain it
+        let block_idx = self
+            .index
+            .partition_point(|e| self.comparator.compare(&e.last_key, key).is_lt());
+        let Some(block_handle) = self.index.get(block_idx) else {
+            return Ok(None);
+        };
+
+        let block = read_block(
+            &self.source,
+            &self.path,
+            self.block_checksum(verify_checksums),
+            block_handle.offset,
+            block_handle.len,
+        )?;
+
+        let entry = Block::decode(block)
+            .and_then(|block| block.get_before(key, read_seq_no, self.comparator.as_ref()))
+            .ok_or(DBError::Corruption {
+                what: "sstable: malformed data block",
+                path: self.path.clone(),
+                offset: block_handle.offset,
+            })?;
+
+        entry
+            .map(|entry| self.resolve(entry, verify_checksums))
+            .transpose()
+    }
+
+    /// Whether the table may hold an entry for `key`, as far as its filter and index tell without reading a
+    /// data block. False positives are possible, a `false` is certain.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        if let Some((policy, filter)) = &self.filter
+            && !policy.may_contain(filter, bloom::hash(key))
+        {
+            return false;
+        }
+        self.index
+            .last()
+            .is_some_and(|last| self.comparator.compare(key, &last.last_key).is_le())
+    }
+
+    /// `get` for every key of `keys`, which must be in ascending order by the table's `Comparator`,
+    /// returning the results in the same order. Keys that fall into the same data block share a single read
+    /// of it, and keys the filter rules out are dropped before the index is even searched.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Vec<Result<Option<Entry>, DBError>> {
+        self.multi_get_with_checksums(keys, true)
+    }
+
+    /// `multi_get`, checking the checksum of every block read only if `verify_checksums`, see
+    /// `get_with_checksums`.
+    pub fn multi_get_with_checksums(
+        &self,
+        keys: &[&[u8]],
+        verify_checksums: bool,
+    ) -> Vec<Result<Option<Entry>, DBError>> {
+        let mut results: Vec<_> = keys.iter().map(|_| Ok(None)).collect();
+        let mut wanted = (0..keys.len())
+            .filter(|&i| match &self.filter {
+                Some((policy, filter)) => policy.may_contain(filter, bloom::hash(keys[i])),
+                None => true,
+            })
+            .peekable();
+
+        while let Some(first) = wanted.next() {
+            let block_idx = self
+                .index
+                .partition_point(|e| self.comparator.compare(&e.last_key, keys[first]).is_lt());
+            // The keys are sorted, the ones after `first` are past the last block too
+            let Some(block_handle) = self.index.get(block_idx) else {
+                break;
+            };
+            let mut in_block = vec![first];
+            while let Some(i) = wanted.next_if(|&i| {
+                self.comparator
+                    .compare(keys[i], &block_handle.last_key)
+                    .is_le()
+            }) {
+                in_block.push(i);
+            }
+
+            match self.decode_data_block(block_idx, verify_checksums) {
+                Ok(block) => {
+                    for i in in_block {
+                        results[i] = block
+                            .get(keys[i], self.comparator.as_ref())
+                            .ok_or_else(|| self.malformed_block(block_idx))
+                            .and_then(|entry| {
+                                entry
+                                    .map(|entry| self.resolve(entry, verify_checksums))
+                                    .transpose()
+                            });
+                    }
+                }
+                // Errors can't be cloned, every other key gets its own from looking it up alone
+                Err(e) => {
+                    results[first] = Err(e);
+                    for i in in_block.into_iter().skip(1) {
+                        results[i] = self.get_with_checksums(keys[i], verify_checksums);
+                    }
+                }
+            }
+        }
+
+        results
+    }
+
+    /// The checksum to verify blocks with, none if `verify_checksums` is off.
+    fn block_checksum(&self, verify_checksums: bool) -> Option<ChecksumType> {
+        verify_checksums.then_some(self.checksum)
+    }
+
+    /// Turns a `BlockEntry` into the `Entry` it stands for, reading the value out of its overflow blocks
+    /// if need be.
+    fn resolve(&self, entry: BlockEntry, verify_checksums: bool) -> Result<Entry, DBError> {
+        match entry {
+            BlockEntry::Entry(entry) => Ok(entry),
+            BlockEntry::Overflow {
+                seq_no,
+                pointer,
+                timestamp,
+                expires_at,
+            } => Ok(Entry::Value {
+                seq_no,
+                val: self.read_overflow(&pointer, verify_checksums)?,
+                timestamp,
+                expires_at,
+            }),
+        }
+    }
+
+    fn read_overflow(&self, pointer: &[u8], verify_checksums: bool) -> Result<Vec<u8>, DBError> {
+        let corruption = |what: &'static str, offset: u64| DBError::Corruption {
+            what,
+            path: self.path.clone(),
+            offset,
+        };
+
+        let (Some(mut offset), Some(mut len), Some(value_len)) = (
+            read_u64_le(pointer),
+            pointer.get(8..).and_then(read_u32_le),
+            pointer.get(12..).and_then(read_u64_le),
+        ) else {
+            return Err(corruption("sstable: malformed overflow pointer", 0));
+        };
+
+        // A value can never be larger than the file holding it, don't trust a corrupt length any further
+        let mut val = Vec::with_capacity(value_len.min(self.footer_offset) as usize);
+        while len > 0 {
+            let checksum = self.block_checksum(verify_checksums);
+            let block = read_block(&self.source, &self.path, checksum, offset, len)?;
+            let (Some(next_offset), Some(next_len)) =
+                (read_u64_le(&block), block.get(8..).and_then(read_u32_le))
+            else {
+                return Err(corruption("sstable: malformed overflow block", offset));
+            };
+
+            // Chunks are written back to front, so a chain that does not move backwards is a loop
+            if next_len > 0 && next_offset >= offset {
+                return Err(corruption(
+                    "sstable: overflow chain does not terminate",
+                    offset,
+                ));
+            }
+
+            val.extend_from_slice(&block[OVERFLOW_HEADER_LEN..]);
+            (offset, len) = (next_offset, next_len);
+        }
+
+        if val.len() as u64 != value_len {
+            return Err(corruption(
+                "sstable: overflow value has the wrong length",
+                offset,
+            ));
+        }
+
+        Ok(val)
+    }
+
+    /// Walks every data block of the table, checking its crc, that it decodes, that its keys are strictly
+    /// increasing (across blocks too), that its last key matches its index entry and that the overflow
+    /// blocks of its large values are intact. The filter, index and
+    /// properties blocks were already checked by `open`. Only I/O errors are returned as `Err`, corruption
+    /// is reported per block in the `TableVerifyReport`.
+    pub fn verify(&self) -> Result<TableVerifyReport, DBError> {
+        let mut report = TableVerifyReport {
+            path: self.path.clone(),
+            ..Default::default()
+        };
+        let mut prev_key: Option<Vec<u8>> = None;
+        let mut overflow_failures = Vec::new();
+
+        for (block_idx, handle) in self.index.iter().enumerate() {
+            report.blocks_checked += 1;
+            let mut fail = |what| {
+                report.failures.push(BlockFailure {
+                    offset: handle.offset,
+                    what,
+                })
+            };
+
+            let mut block = match self.read_data_block(block_idx, true) {
+                Ok(block) => block,
+                Err(DBError::Corruption { what, .. }) => {
+                    fail(what);
+                    // Keys after a block we could not read cannot be compared against it
+                    prev_key = None;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let mut entries = 0;
+            let mut status = block.seek_to_first();
+            while status.is_some() && block.valid() {
+                if prev_key
+                    .as_deref()
+                    .is_some_and(|prev| self.comparator.compare(prev, block.key()).is_ge())
+                {
+                    break;
+                }
+                if let Some(BlockEntry::Overflow { pointer, .. }) = block.entry()
+                    && let Err(DBError::Corruption { what, offset, .. }) =
+                        self.read_overflow(pointer, true)
+                {
+                    overflow_failures.push(BlockFailure { offset, what });
+                }
+                prev_key = Some(block.key().to_vec());
+                entries += 1;
+                status = block.next();
+            }
+
+            if status.is_none() {
+                fail("sstable: malformed data block");
+                prev_key = None;
+            } else if block.valid() {
+                fail("sstable: keys out of order");
+                prev_key = None;
+            } else if prev_key.as_deref() != Some(handle.last_key.as_slice()) {
+                fail("sstable: last key of block does not match the index");
+            }
+            report.entries_checked += entries;
+        }
+        report.failures.append(&mut overflow_failures);
+
+        if let Err(DBError::Corruption { what, offset, .. }) = self.verify_file_checksum() {
+            report.failures.push(BlockFailure { offset, what });
+        }
+
+        if report.is_ok() && report.entries_checked != self.properties.entry_count {
+            report.failures.push(BlockFailure {
+                offset: self.index.first().map_or(0, |handle| handle.offset),
+                what: "sstable: entry count does not match the properties block",
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Returns a cursor over every entry in the table, tombstones included.
+    pub fn iter(&self) -> SSTableIterator<'_> {
+        self.iter_with_checksums(true)
+    }
+
+    /// `iter`, checking the checksum of every block read only if `verify_checksums`, see
+    /// `get_with_checksums`.
+    pub fn iter_with_checksums(&self, verify_checksums: bool) -> SSTableIterator<'_> {
+        SSTableIterator {
+            reader: self,
+            verify_checksums,
+            block_idx: 0,
+            block: None,
+            entry: None,
+        }
+    }
+
+    fn read_data_block(
+        &self,
+        block_idx: usize,
+        verify_checksums: bool,
+    ) -> Result<BlockIter<'_>, DBError> {
+        self.decode_data_block(block_idx, verify_checksums)
+            .map(Block::iter)
+    }
+
+    fn decode_data_block(
+        &self,
+        block_idx: usize,
+        verify_checksums: bool,
+    ) -> Result<Block<'_>, DBError> {
+        let handle = &self.index[block_idx];
+        let block = read_block(
+            &self.source,
+            &self.path,
+            self.block_checksum(verify_checksums),
+            handle.offset,
+            handle.len,
+        )?;
+        Block::decode(block).ok_or_else(|| self.malformed_block(block_idx))
+    }
+
+    fn malformed_block(&self, block_idx: usize) -> DBError {
+        DBError::Corruption {
+            what: "sstable: malformed data block",
+            path: self.path.clone(),
+            offset: self.index[block_idx].offset,
+        }
+    }
+}
+
+/// A cursor walking an `SSTableReader` in key order, one data block at a time. It starts out invalid and
+/// has to be positioned with `seek_to_first`, `seek_to_last` or `seek` before `key`/`entry` mean anything.
+/// Running off either end of the table leaves the cursor invalid.
+pub struct SSTableIterator<'a> {
+    reader: &'a SSTableReader,
+    verify_checksums: bool,
+    block_idx: usize,
+    block: Option<BlockIter<'a>>,
+    // The current entry with any overflow value already read in
+    entry: Option<Entry>,
+}
+
+impl<'a> SSTableIterator<'a> {
+    pub fn valid(&self) -> bool {
+        self.block.as_ref().is_some_and(|block| block.valid())
+    }
+
+    /// The key of the current entry. Only meaningful while `valid()`.
+    pub fn key(&self) -> &[u8] {
+        self.block.as_ref().map_or(&[], |block| block.key())
+    }
+
+    /// The current entry, or `None` when the cursor is not `valid()`.
+    pub fn entry(&self) -> Option<&Entry> {
+        self.entry.as_ref()
+    }
+
+    pub fn seek_to_first(&mut self) -> Result<(), DBError> {
+        if self.reader.index.is_empty() {
+            self.invalidate();
+            return Ok(());
+        }
+        self.load_block(0)?;
+        self.step(BlockIter::seek_to_first)
+    }
+
+    pub fn seek_to_last(&mut self) -> Result<(), DBError> {
+        let Some(last) = self.reader.index.len().checked_sub(1) else {
+            self.invalidate();
+            return Ok(());
+        };
+        self.load_block(last)?;
+        self.step(BlockIter::seek_to_last)
+    }
+
+    /// Positions the cursor at the first entry whose key is >= `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        let block_idx = self
+            .reader
+            .index
+            .partition_point(|e| self.reader.comparator.compare(&e.last_key, key).is_lt());
+        if block_idx == self.reader.index.len() {
+            self.invalidate();
+            return Ok(());
+        }
+        self.load_block(block_idx)?;
+        let comparator = self.reader.comparator.as_ref();
+        self.step(|block| block.seek(key, comparator))
+    }
+
+    /// Positions the cursor at the last entry whose key is <= `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.seek(key)?;
+        if !self.valid() {
+            return self.seek_to_last();
+        }
+        if self.reader.comparator.compare(self.key(), key).is_gt() {
+            return self.prev();
+        }
+
+        // On the newest version of `key`, the last entry is its oldest
+        let found = self.key().to_vec();
+        while self.valid() && self.key() == found.as_slice() {
+            self.next()?;
+        }
+        match self.valid() {
+            true => self.prev(),
+            false => self.seek_to_last(),
+        }
+    }
+
+    // A cursor step rather than `Iterator::next`, the cursor also has to move backwards
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<(), DBError> {
+        if !self.valid() {
+            return Ok(());
+        }
+        self.step(BlockIter::next)?;
+        if !self.valid() && self.block_idx + 1 < self.reader.index.len() {
+            self.load_block(self.block_idx + 1)?;
+            self.step(BlockIter::seek_to_first)?;
+        }
+        Ok(())
+    }
+
+    pub fn prev(&mut self) -> Result<(), DBError> {
+        if !self.valid() {
+            return Ok(());
+        }
+        self.step(BlockIter::prev)?;
+        if !self.valid() && self.block_idx > 0 {
+            self.load_block(self.block_idx - 1)?;
+            self.step(BlockIter::seek_to_last)?;
+        }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.block = None;
+        self.entry = None;
+    }
+
+    fn load_block(&mut self, block_idx: usize) -> Result<(), DBError> {
+        self.invalidate();
+        self.block = Some(
+            self.reader
+                .read_data_block(block_idx, self.verify_checksums)?,
+        );
+        self.block_idx = block_idx;
+        Ok(())
+    }
+
+    /// Runs `op` against the current block, turning a malformed block into a corruption error, and loads
+    /// the entry it lands on.
+    fn step(&mut self, op: impl FnOnce(&mut BlockIter<'a>) -> Option<()>) -> Result<(), DBError> {
+        let Some(block) = self.block.as_mut() else {
+            return Ok(());
+        };
+        self.entry = None;
+        op(block).ok_or_else(|| self.reader.malformed_block(self.block_idx))?;
+
+        if let Some(entry) = block.entry() {
+            self.entry = Some(self.reader.resolve(entry.clone(), self.verify_checksums)?);
+        }
+        Ok(())
+    }
+}
+
+/// Allocates the first `len` bytes of `file`.
+#[cfg(target_os = "linux")]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let len = libc::off_t::try_from(len).unwrap_or(libc::off_t::MAX);
+    // SAFETY: plain syscall wrapper on a descriptor we own, no memory is handed over
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        err => Err(std::io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Grows `file` to at least `len` bytes. Without `posix_fallocate` the space is not reserved up front, but
+/// the file ends in the same zeros as a preallocated one and is read back the same way.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    if file.metadata()?.len() < len {
+        file.set_len(len)?;
+    }
+    Ok(())
+}
+
+/// The name of the SSTable numbered `file_no` within the `ss_table_dir`, e.g. `000042.sst`.
+pub fn table_file_name(file_no: u64) -> String {
+    format!("{file_no:06}.sst")
+}
+
+/// Parses the `file_no` back out of an SSTable path, the inverse of `table_file_name`.
+pub fn parse_table_file_name(path: &Path) -> Option<u64> {
+    if path.extension()? != "sst" {
+        return None;
+    }
+
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// The path an SSTable is written to before being renamed into place, i.e. `<path>.tmp`.
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Reads the block of `len` bytes at `offset`, verifies it against its `checksum` trailer unless no
+/// `checksum` is given, and decompresses it.
+fn read_block<'a>(
+    source: &'a TableSource,
+    path: &Path,
+    checksum: Option<ChecksumType>,
+    offset: u64,
+    len: u32,
+) -> Result<Cow<'a, [u8]>, DBError> {
+    let len = len as usize;
+    let block = source.read_at(path, offset, len + BLOCK_TRAILER_LEN)?;
+
+    let corruption = |what: &'static str| DBError::Corruption {
+        what,
+        path: path.to_path_buf(),
+        offset,
+    };
+
+    let crc_expected =
+        read_u32_le(&block[len + 1..]).ok_or_else(|| corruption("sstable: missing block crc"))?;
+    if let Some(checksum) = checksum
+        && checksum.checksum(&block[..len + 1]) != crc_expected
+    {
+        return Err(corruption("sstable: block crc mismatch"));
+    }
+
+    let compression = CompressionType::try_from(block[len]).map_err(corruption)?;
+
+    match (compression, block) {
+        (CompressionType::None, Cow::Borrowed(block)) => Ok(Cow::Borrowed(&block[..len])),
+        (CompressionType::None, Cow::Owned(mut block)) => {
+            block.truncate(len);
+            Ok(Cow::Owned(block))
+        }
+        (_, block) => compression::decompress(compression, &block[..len])
+            .map(Cow::Owned)
+            .map_err(corruption),
+    }
+}
+
+fn decode_index(buf: &[u8]) -> Option<Vec<IndexEntry>> {
+    let mut index = Vec::new();
+    let mut offset = 0;
+
+    while offset < buf.len() {
+        let key_len = read_u32_le(buf.get(offset..)?)? as usize;
+        offset += 4;
+        let last_key = buf.get(offset..offset + key_len)?.to_vec();
+        offset += key_len;
+        let block_offset = read_u64_le(buf.get(offset..)?)?;
+        offset += 8;
+        let len = read_u32_le(buf.get(offset..)?)?;
+        offset += 4;
+
+        index.push(IndexEntry {
+            last_key,
+            offset: block_offset,
+            len,
+        });
+    }
+
+    Some(index)
+}
+
+/// Splits a filter block into the name of the policy that built it and the filter itself, see
+/// `FilterPolicy`.
+fn decode_filter_block(buf: &[u8]) -> Option<(&str, &[u8])> {
+    let (name_len, rest) = buf.split_first()?;
+    let name_len = *name_len as usize;
+    let name = std::str::from_utf8(rest.get(..name_len)?).ok()?;
+    Some((name, &rest[name_len..]))
+}
+
+/// Encodes the range tombstones as a run of
+///
+/// [start_len u32][start bytes][end_len u32][end bytes][seq u64]
+fn encode_range_tombstones(tombstones: &[RangeTombstone]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for tombstone in tombstones {
+        let start_len: u32 = tombstone.start.len().try_into().expect("key is too large");
+        let end_len: u32 = tombstone.end.len().try_into().expect("key is too large");
+        block.extend_from_slice(&start_len.to_le_bytes());
+        block.extend_from_slice(&tombstone.start);
+        block.extend_from_slice(&end_len.to_le_bytes());
+        block.extend_from_slice(&tombstone.end);
+        block.extend_from_slice(&tombstone.seq_no.to_le_bytes());
+    }
+    block
+}
+
+fn decode_range_tombstones(buf: &[u8]) -> Option<Vec<RangeTombstone>> {
+    let mut tombstones = Vec::new();
+    let mut offset = 0;
+
+    while offset < buf.len() {
+        let start_len = read_u32_le(buf.get(offset..)?)? as usize;
+        offset += 4;
+        let start = buf.get(offset..offset + start_len)?.to_vec();
+        offset += start_len;
+        let end_len = read_u32_le(buf.get(offset..)?)? as usize;
+        offset += 4;
+        let end = buf.get(offset..offset + end_len)?.to_vec();
+        offset += end_len;
+        let seq_no = read_u64_le(buf.get(offset..)?)?;
+        offset += 8;
+
+        tombstones.push(RangeTombstone { start, end, seq_no });
+    }
+
+    Some(tombstones)
+}
+
+/// Encodes the `TableProperties` as
+///
+/// [level u32][entry_count u64][tombstone_count u64][raw_key_bytes u64][raw_value_bytes u64]
+/// [data_block_count u64][data_bytes u64][min_seq u64][max_seq u64][creation_time u64]
+/// [smallest_key_len u32][smallest_key bytes][largest_key_len u32][largest_key bytes]
+/// [range_deletion_count u64][comparator_name_len u32][comparator_name]
+///
+/// The `range_deletion_count` was added in format version 2 and is read as 0 when missing. The comparator
+/// name came later still, a table without one was written in byte order.
+fn encode_properties(props: &TableProperties) -> Vec<u8> {
+    let mut block = Vec::with_capacity(
+        4 + 8 * 9
+            + 4
+            + props.smallest_key.len()
+            + 4
+            + props.largest_key.len()
+            + 8
+            + 4
+            + props.comparator_name.len(),
+    );
+
+    let smallest_len: u32 = props
+        .smallest_key
+        .len()
+        .try_into()
+        .expect("key is too large");
+    let largest_len: u32 = props
+        .largest_key
+        .len()
+        .try_into()
+        .expect("key is too large");
+
+    block.extend_from_slice(&props.level.to_le_bytes());
+    block.extend_from_slice(&props.entry_count.to_le_bytes());
+    block.extend_from_slice(&props.tombstone_count.to_le_bytes());
+    block.extend_from_slice(&props.raw_key_bytes.to_le_bytes());
+    block.extend_from_slice(&props.raw_value_bytes.to_le_bytes());
+    block.extend_from_slice(&props.data_block_count.to_le_bytes());
+    block.extend_from_slice(&props.data_bytes.to_le_bytes());
+    block.extend_from_slice(&props.min_seq_no.to_le_bytes());
+    block.extend_from_slice(&props.max_seq_no.to_le_bytes());
+    block.extend_from_slice(&props.creation_time.to_le_bytes());
+    block.extend_from_slice(&smallest_len.to_le_bytes());
+    block.extend_from_slice(&props.smallest_key);
+    block.extend_from_slice(&largest_len.to_le_bytes());
+    block.extend_from_slice(&props.largest_key);
+    block.extend_from_slice(&props.range_deletion_count.to_le_bytes());
+    let comparator_name_len: u32 = props
+        .comparator_name
+        .len()
+        .try_into()
+        .expect("comparator name is too long");
+    block.extend_from_slice(&comparator_name_len.to_le_bytes());
+    block.extend_from_slice(props.comparator_name.as_bytes());
+
+    block
+}
+
+fn decode_properties(buf: &[u8]) -> Option<TableProperties> {
+    let level = read_u32_le(buf)?;
+    let entry_count = read_u64_le(buf.get(4..)?)?;
+    let tombstone_count = read_u64_le(buf.get(12..)?)?;
+    let raw_key_bytes = read_u64_le(buf.get(20..)?)?;
+    let raw_value_bytes = read_u64_le(buf.get(28..)?)?;
+    let data_block_count = read_u64_le(buf.get(36..)?)?;
+    let data_bytes = read_u64_le(buf.get(44..)?)?;
+    let min_seq_no = read_u64_le(buf.get(52..)?)?;
+    let max_seq_no = read_u64_le(buf.get(60..)?)?;
+    let creation_time = read_u64_le(buf.get(68..)?)?;
+
+    let mut offset = 76;
+    let smallest_len = read_u32_le(buf.get(offset..)?)? as usize;
+    offset += 4;
+    let smallest_key = buf.get(offset..offset + smallest_len)?.to_vec();
+    offset += smallest_len;
+    let largest_len = read_u32_le(buf.get(offset..)?)? as usize;
+    offset += 4;
+    let largest_key = buf.get(offset..offset + largest_len)?.to_vec();
+    offset += largest_len;
+    let range_deletion_count = match buf.get(offset..)? {
+        [] => 0,
+        rest => read_u64_le(rest)?,
+    };
+    offset += 8;
+    let comparator_name = match buf.get(offset..) {
+        None | Some([]) => BYTEWISE_COMPARATOR_NAME.to_string(),
+        Some(rest) => {
+            let name_len = read_u32_le(rest)? as usize;
+            let name = rest.get(4..4 + name_len)?;
+            String::from_utf8(name.to_vec()).ok()?
+        }
+    };
+
+    Some(TableProperties {
+        level,
+        entry_count,
+        tombstone_count,
+        range_deletion_count,
+        raw_key_bytes,
+        raw_value_bytes,
+        data_block_count,
+        data_bytes,
+        min_seq_no,
+        max_seq_no,
+        creation_time,
+        smallest_key,
+        largest_key,
+        comparator_name,
+    })
+}
+
+#[cfg(test)]
+mod sstable_test {
+    use super::*;
+    use std::fs;
+
+    use crate::comparator::ReverseBytewiseComparator;
+
+    const TEST_DATA_DIR: &str = "test_data/sstable";
+
+    fn test_path(name: &str) -> PathBuf {
+        fs::create_dir_all(TEST_DATA_DIR).unwrap();
+        let mut path = PathBuf::from(TEST_DATA_DIR);
+        path.push(format!("{name}.sst"));
+        path
+    }
+
+    #[test]
+    fn write_blocks_index_and_footer() {
+        let path = test_path("write_blocks_index_and_footer");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+
+        for i in 0..1000u32 {
+            let key = format!("key-{i:05}").into_bytes();
+            let entry = if i % 10 == 0 {
+                Entry::Tombstone { seq_no: i as u64 }
+            } else {
+                Entry::Value {
+                    seq_no: i as u64,
+                    val: format!("val-{i}").into_bytes(),
+                    timestamp: None,
+                    expires_at: None,
+                }
+            };
+            writer.add(&key, &entry).unwrap();
+        }
+
+        let meta = writer.finish().unwrap();
+        assert_eq!(meta.file_no, 1);
+        assert_eq!(meta.level, 0);
+        assert_eq!(meta.smallest_key, b"key-00000".to_vec());
+        assert_eq!(meta.largest_key, b"key-00999".to_vec());
+        assert_eq!(meta.min_seq_no(), 0);
+        assert_eq!(meta.max_seq_no(), 999);
+
+        let bytes = fs::read(&path).unwrap();
+        let footer = &bytes[bytes.len() - SS_TABLE_FOOTER_LEN..];
+        let index_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+        let index_len = u32::from_le_bytes(footer[8..12].try_into().unwrap());
+        let props_offset = u64::from_le_bytes(footer[12..20].try_into().unwrap());
+        let props_len = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+        let filter_len = u32::from_le_bytes(footer[32..36].try_into().unwrap());
+        let range_del_len = u32::from_le_bytes(footer[44..48].try_into().unwrap());
+        let checksum_type = footer[48];
+        let file_checksum = u32::from_le_bytes(footer[49..53].try_into().unwrap());
+        let version = u32::from_le_bytes(footer[53..57].try_into().unwrap());
+        let magic = u64::from_le_bytes(footer[57..65].try_into().unwrap());
+
+        assert_eq!(magic, SS_TABLE_MAGIC);
+        assert_eq!(version, SS_TABLE_FORMAT_VERSION);
+        assert!(filter_len > 0);
+        assert_eq!(range_del_len, 0);
+        assert_eq!(checksum_type, ChecksumType::Crc32 as u8);
+        assert_eq!(
+            file_checksum,
+            crc32fast::hash(&bytes[..bytes.len() - SS_TABLE_FOOTER_LEN])
+        );
+        assert_eq!(meta.file_checksum(), file_checksum);
+        assert_eq!(
+            index_offset + (index_len as usize + BLOCK_TRAILER_LEN) as u64,
+            props_offset
+        );
+        assert_eq!(
+            props_offset as usize + props_len as usize + BLOCK_TRAILER_LEN,
+            bytes.len() - SS_TABLE_FOOTER_LEN
+        );
+        // 1000 entries cannot fit in a single 4KiB block
+        assert!(index_offset as usize > DEFAULT_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn meta_key_range_checks() {
+        let props = TableProperties {
+            smallest_key: b"c".to_vec(),
+            largest_key: b"f".to_vec(),
+            ..Default::default()
+        };
+        let meta = SSTableMeta::from_properties(1, String::new(), &props, 0, 0);
+
+        assert!(!meta.may_contain_key(&BytewiseComparator, b"b"));
+        assert!(meta.may_contain_key(&BytewiseComparator, b"c"));
+        assert!(meta.may_contain_key(&BytewiseComparator, b"d"));
+        assert!(meta.may_contain_key(&BytewiseComparator, b"f"));
+        assert!(!meta.may_contain_key(&BytewiseComparator, b"fa"));
+
+        assert!(meta.overlaps(&BytewiseComparator, b"a", b"c"));
+        assert!(meta.overlaps(&BytewiseComparator, b"d", b"e"));
+        assert!(meta.overlaps(&BytewiseComparator, b"a", b"z"));
+        assert!(meta.overlaps(&BytewiseComparator, b"f", b"g"));
+        assert!(!meta.overlaps(&BytewiseComparator, b"a", b"b"));
+        assert!(!meta.overlaps(&BytewiseComparator, b"g", b"z"));
+    }
+
+    #[test]
+    fn written_to_tmp_file_until_finished() {
+        let path = test_path("written_to_tmp_file_until_finished");
+        let _ = fs::remove_file(&path);
+        let tmp = tmp_path(&path);
+
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        writer.add(b"key", &Entry::Tombstone { seq_no: 1 }).unwrap();
+        assert!(tmp.exists());
+        assert!(!path.exists());
+
+        writer.finish().unwrap();
+        assert!(!tmp.exists());
+        assert!(
+            SSTableReader::open(path)
+                .unwrap()
+                .get(b"key")
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn progress_callback_and_preallocation() {
+        use std::sync::{Arc, Mutex};
+
+        let path = test_path("progress_callback_and_preallocation");
+        let config = SSTableConfig {
+            block_size: 512,
+            ..Default::default()
+        };
+        let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        writer.set_progress_callback(Box::new(move |progress| {
+            sink.lock().unwrap().push(*progress);
+        }));
+        writer.preallocate(1024 * 1024).unwrap();
+        assert_eq!(fs::metadata(tmp_path(&path)).unwrap().len(), 1024 * 1024);
+
+        for i in 0..500u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: format!("val-{i}").into_bytes(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reports = reports.lock().unwrap();
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        // One report per data block plus the final one
+        assert_eq!(reports.len(), reader.index.len() + 1);
+        assert!(
+            reports
+                .windows(2)
+                .all(|w| w[0].bytes_written < w[1].bytes_written
+                    && w[0].entries <= w[1].entries
+                    && w[0].blocks <= w[1].blocks)
+        );
+
+        let last = reports.last().unwrap();
+        assert_eq!(last.entries, 500);
+        assert_eq!(last.blocks, reader.index.len() as u64);
+        // The unused preallocated space is trimmed off again
+        assert_eq!(last.bytes_written, fs::metadata(&path).unwrap().len());
+        assert!(reader.get(b"key-00042").unwrap().is_some());
+    }
+
+    #[test]
+    fn point_lookups() {
+        let path = test_path("point_lookups");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+
+        for i in (0..2000u32).step_by(2) {
+            let key = format!("key-{i:05}").into_bytes();
+            let entry = if i % 10 == 0 {
+                Entry::Tombstone { seq_no: i as u64 }
+            } else {
+                Entry::Value {
+                    seq_no: i as u64,
+                    val: format!("val-{i}").into_bytes(),
+                    timestamp: None,
+                    expires_at: None,
+                }
+            };
+            writer.add(&key, &entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SSTableReader::open(path).unwrap();
+        assert!(reader.index.len() > 1);
+
+        let props = reader.properties();
+        assert_eq!(props.entry_count, 1000);
+        assert_eq!(props.tombstone_count, 200);
+        assert_eq!(props.raw_key_bytes, 1000 * 9);
+        assert_eq!(props.data_block_count, reader.index.len() as u64);
+        assert_eq!(
+            props.data_bytes,
+            reader
+                .index
+                .iter()
+                .map(|h| (h.len as usize + BLOCK_TRAILER_LEN) as u64)
+                .sum::<u64>()
+        );
+        assert!(props.creation_time > 0);
+        assert_eq!(props.min_seq_no, 0);
+        assert_eq!(props.max_seq_no, 1998);
+        assert_eq!(props.smallest_key, b"key-00000".to_vec());
+        assert_eq!(props.largest_key, b"key-01998".to_vec());
+
+        assert_eq!(
+            reader.get(b"key-00002").unwrap(),
+            Some(Entry::Value {
+                seq_no: 2,
+                val: b"val-2".to_vec(),
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+        assert_eq!(
+            reader.get(b"key-01998").unwrap(),
+            Some(Entry::Value {
+                seq_no: 1998,
+                val: b"val-1998".to_vec(),
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+        assert_eq!(
+            reader.get(b"key-00010").unwrap(),
+            Some(Entry::Tombstone { seq_no: 10 })
+        );
+
+        // Gaps between keys, and keys on either side of the table's range
+        assert_eq!(reader.get(b"key-00003").unwrap(), None);
+        assert_eq!(reader.get(b"a").unwrap(), None);
+        assert_eq!(reader.get(b"z").unwrap(), None);
+    }
+
+    #[test]
+    fn iterate_across_blocks() {
+        let path = test_path("iterate_across_blocks");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        let keys: Vec<Vec<u8>> = (0..2000u32)
+            .step_by(2)
+            .map(|i| format!("key-{i:05}").into_bytes())
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: vec![b'v'; 16],
+                timestamp: None,
+                expires_at: None,
+            };
+            writer.add(key, &entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SSTableReader::open(path).unwrap();
+        assert!(reader.index.len() > 1);
+        let mut iter = reader.iter();
+        assert!(!iter.valid());
+
+        let mut forwards = Vec::new();
+        iter.seek_to_first().unwrap();
+        while iter.valid() {
+            forwards.push(iter.key().to_vec());
+            iter.next().unwrap();
+        }
+        assert_eq!(forwards, keys);
+
+        let mut backwards = Vec::new();
+        iter.seek_to_last().unwrap();
+        while iter.valid() {
+            backwards.push(iter.key().to_vec());
+            iter.prev().unwrap();
+        }
+        backwards.reverse();
+        assert_eq!(backwards, keys);
+
+        // Seeking between keys lands on the next one, including across a block boundary
+        let first_block_last_key = reader.index[0].last_key.clone();
+        let mut between = first_block_last_key.clone();
+        between.push(b'~');
+        iter.seek(&between).unwrap();
+        let next_idx = keys
+            .iter()
+            .position(|k| *k == first_block_last_key)
+            .unwrap()
+            + 1;
+        assert_eq!(iter.key(), keys[next_idx].as_slice());
+        assert_eq!(iter.entry().unwrap().seq_no(), next_idx as u64);
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), first_block_last_key.as_slice());
+
+        iter.seek(b"key-00001").unwrap();
+        assert_eq!(iter.key(), b"key-00002");
+        iter.seek(b"zzz").unwrap();
+        assert!(!iter.valid());
+
+        // Seeking for prev lands on the key before, again across the block boundary
+        iter.seek_for_prev(&between).unwrap();
+        assert_eq!(iter.key(), first_block_last_key.as_slice());
+        iter.seek_for_prev(b"key-00002").unwrap();
+        assert_eq!(iter.key(), b"key-00002");
+        iter.seek_for_prev(b"zzz").unwrap();
+        assert_eq!(iter.key(), keys.last().unwrap().as_slice());
+        iter.seek_for_prev(b"a").unwrap();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn open_rejects_bad_magic() {
+        let path = test_path("open_rejects_bad_magic");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        writer
+            .add(
+                b"k",
+                &Entry::Value {
+                    seq_no: 0,
+                    val: b"v".to_vec(),
+                    timestamp: None,
+                    expires_at: None,
+                },
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(
+            SSTableReader::open(path),
+            Err(DBError::Corruption { .. })
+        ));
+    }
+
+    #[test]
+    fn get_detects_corrupt_data_block() {
+        let path = test_path("get_detects_corrupt_data_block");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        for i in 0..1000u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: format!("val-{i}").into_bytes(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Flip a byte inside the second data block
+        let second_block = {
+            let reader = SSTableReader::open(path.clone()).unwrap();
+            reader.index[1].offset
+        };
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[second_block as usize + 10] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        assert!(reader.get(b"key-00000").unwrap().is_some());
+
+        let last_key_of_second_block = reader.index[1].last_key.clone();
+        match reader.get(&last_key_of_second_block) {
+            Err(DBError::Corruption {
+                path: err_path,
+                offset,
+                ..
+            }) => {
+                assert_eq!(err_path, path);
+                assert_eq!(offset, second_block);
+            }
+            other => panic!("expected corruption, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn checksums_are_only_verified_when_asked_to() {
+        let path = test_path("checksums_are_only_verified_when_asked_to");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        for i in 0..1000u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: format!("val-{i}").into_bytes(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Flip a byte of the checksum of the first data block, leaving its contents intact
+        let crc_offset = {
+            let reader = SSTableReader::open(path.clone()).unwrap();
+            reader.index[0].offset as usize + reader.index[0].len as usize + 1
+        };
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[crc_offset] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        assert!(matches!(
+            reader.get(b"key-00000"),
+            Err(DBError::Corruption { .. })
+        ));
+        assert!(
+            reader
+                .get_with_checksums(b"key-00000", false)
+                .unwrap()
+                .is_some()
+        );
+
+        let mut iter = reader.iter();
+        assert!(matches!(
+            iter.seek_to_first(),
+            Err(DBError::Corruption { .. })
+        ));
+        let mut iter = reader.iter_with_checksums(false);
+        iter.seek_to_first().unwrap();
+        assert_eq!(iter.key(), b"key-00000");
+    }
+
+    #[test]
+    fn multi_get_matches_get_and_only_fails_the_keys_of_a_corrupt_block() {
+        let path = test_path("multi_get_matches_get_and_only_fails_the_keys_of_a_corrupt_block");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        for i in 0..1000u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: format!("val-{i}").into_bytes(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let keys: [&[u8]; 6] = [
+            b"key-00000",
+            b"key-00001",
+            b"key-00500",
+            b"key-00500-missing",
+            b"key-00999",
+            b"zzz",
+        ];
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        let entries: Vec<_> = reader
+            .multi_get(&keys)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let expected: Vec<_> = keys.iter().map(|key| reader.get(key).unwrap()).collect();
+        assert_eq!(entries, expected);
+        assert!(entries[3].is_none() && entries[5].is_none());
+        drop(reader);
+
+        // Flip a byte of the checksum of the first data block
+        let crc_offset = {
+            let reader = SSTableReader::open(path.clone()).unwrap();
+            reader.index[0].offset as usize + reader.index[0].len as usize + 1
+        };
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[crc_offset] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        let results = reader.multi_get(&keys);
+        for result in &results[..2] {
+            assert!(matches!(result, Err(DBError::Corruption { .. })));
+        }
+        let rest: Vec<_> = results.into_iter().skip(2).map(Result::unwrap).collect();
+        assert_eq!(rest, expected[2..]);
+        assert!(
+            reader
+                .multi_get_with_checksums(&keys, false)
+                .into_iter()
+                .all(|result| result.is_ok())
+        );
+    }
+
+    #[test]
+    fn verify_reports_corrupt_blocks() {
+        let path = test_path("verify_reports_corrupt_blocks");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        for i in 0..1000u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: format!("val-{i}").into_bytes(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.blocks_checked, reader.index.len());
+        assert_eq!(report.entries_checked, 1000);
+
+        let second_block = reader.index[1].offset;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[second_block as usize + 10] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        let report = reader.verify().unwrap();
+        assert_eq!(report.path, path);
+        assert_eq!(report.blocks_checked, reader.index.len());
+        assert_eq!(
+            report.failures,
+            vec![
+                BlockFailure {
+                    offset: second_block,
+                    what: "sstable: block crc mismatch",
+                },
+                BlockFailure {
+                    offset: 0,
+                    what: "sstable: file checksum mismatch",
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_skips_data_blocks_for_missing_keys() {
+        let path = test_path("filter_skips_data_blocks_for_missing_keys");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        for i in 0..100u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: vec![0; 100],
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Corrupt every data block, any lookup that reaches one would error out
+        let data_end = SSTableReader::open(path.clone())
+            .unwrap()
+            .index
+            .last()
+            .unwrap()
+            .offset;
+        let mut bytes = fs::read(&path).unwrap();
+        for byte in &mut bytes[..data_end as usize] {
+            *byte ^= 0xFF;
+        }
+        fs::write(&path, bytes).unwrap();
+
+        let reader = SSTableReader::open(path).unwrap();
+        let (policy, filter) = reader.filter.as_ref().unwrap();
+        let misses = (0..1000)
+            .map(|i| format!("key-{i:05}x"))
+            .filter(|key| !policy.may_contain(filter, bloom::hash(key.as_bytes())))
+            .collect::<Vec<_>>();
+        assert!(misses.len() > 900);
+
+        for key in misses {
+            assert!(!reader.may_contain(key.as_bytes()));
+            assert_eq!(reader.get(key.as_bytes()).unwrap(), None);
+        }
+        assert!(reader.may_contain(b"key-00000"));
+        assert!(reader.get(b"key-00000").is_err());
+    }
+
+    #[test]
+    fn filter_from_another_policy_is_ignored() {
+        let path = test_path("filter_from_another_policy_is_ignored");
+        let config = SSTableConfig {
+            filter_policy: Some(Arc::new(BlockedBloomFilterPolicy::default())),
+            ..Default::default()
+        };
+        let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+        for i in 0..100u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: i.to_le_bytes().to_vec(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let check = |reader: &SSTableReader| {
+            for i in 0..100u32 {
+                let entry = reader.get(format!("key-{i:05}").as_bytes()).unwrap();
+                assert_eq!(
+                    entry,
+                    Some(Entry::Value {
+                        seq_no: i as u64,
+                        val: i.to_le_bytes().to_vec(),
+                        timestamp: None,
+                        expires_at: None,
+                    })
+                );
+            }
+            assert_eq!(reader.get(b"key-00100").unwrap(), None);
+        };
+
+        // The default reader probes with a plain bloom filter policy
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        assert_eq!(
+            reader.filter_policy_name(),
+            Some("lsmdb.BlockedBloomFilter")
+        );
+        assert!(!reader.uses_filter());
+        check(&reader);
+
+        let options = SSTableReadOptions {
+            filter_policy: Some(Arc::new(BlockedBloomFilterPolicy::default())),
+            ..Default::default()
+        };
+        let reader = SSTableReader::open_with_options(path.clone(), &options).unwrap();
+        assert!(reader.uses_filter());
+        check(&reader);
+
+        let options = SSTableReadOptions {
+            filter_policy: None,
+            ..Default::default()
+        };
+        let reader = SSTableReader::open_with_options(path, &options).unwrap();
+        assert!(!reader.uses_filter());
+        check(&reader);
+    }
+
+    #[test]
+    fn tables_are_read_under_the_comparator_they_were_written_with() {
+        let path = test_path("tables_are_read_under_the_comparator_they_were_written_with");
+        let reverse: Arc<dyn Comparator> = Arc::new(ReverseBytewiseComparator);
+        let config = SSTableConfig {
+            comparator: reverse.clone(),
+            ..Default::default()
+        };
+        let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+        for i in (0..100u32).rev() {
+            let key = format!("key-{i:05}").into_bytes();
+            writer
+                .add(&key, &Entry::Tombstone { seq_no: i as u64 })
+                .unwrap();
+        }
+        // Out of order under the reverse comparator
+        assert!(
+            writer
+                .add(b"key-00200", &Entry::Tombstone { seq_no: 200 })
+                .is_err()
+        );
+        let meta = writer.finish().unwrap();
+        assert_eq!(meta.smallest_key, b"key-00099");
+        assert_eq!(meta.largest_key, b"key-00000");
+
+        assert!(matches!(
+            SSTableReader::open(path.clone()),
+            Err(DBError::InvalidConfig { .. })
+        ));
+        let options = SSTableReadOptions {
+            comparator: reverse,
+            ..Default::default()
+        };
+        let reader = SSTableReader::open_with_options(path, &options).unwrap();
+        assert_eq!(
+            reader.properties().comparator_name,
+            "lsmdb.ReverseBytewiseComparator"
+        );
+        assert_eq!(
+            reader.get(b"key-00042").unwrap(),
+            Some(Entry::Tombstone { seq_no: 42 })
+        );
+        let mut iter = reader.iter();
+        iter.seek(b"key-00050").unwrap();
+        assert_eq!(iter.key(), b"key-00050");
+        iter.next().unwrap();
+        assert_eq!(iter.key(), b"key-00049");
+    }
+
+    #[test]
+    fn block_size_controls_where_blocks_are_cut() {
+        let write = |name: &str, block_size: usize| {
+            let path = test_path(name);
+            let config = SSTableConfig {
+                block_size,
+                ..Default::default()
+            };
+            let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+            for i in 0..500u32 {
+                let entry = Entry::Value {
+                    seq_no: i as u64,
+                    val: vec![7; 32],
+                    timestamp: None,
+                    expires_at: None,
+                };
+                writer
+                    .add(format!("key-{i:05}").as_bytes(), &entry)
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+            SSTableReader::open(path).unwrap()
+        };
+
+        let small = write("block_size_small", 256);
+        let large = write("block_size_large", 16 * 1024);
+        assert!(small.index.len() > 4 * large.index.len());
+
+        // A block is only cut once it crosses the block size, so it can overshoot by at most one entry
+        for handle in &small.index {
+            assert!(handle.len as usize <= 256 + 64);
+        }
+        assert!(small.get(b"key-00321").unwrap().is_some());
+    }
+
+    #[cfg(any(feature = "lz4", feature = "snappy", feature = "zstd"))]
+    #[test]
+    fn compressed_data_blocks_round_trip() {
+        let kinds = [
+            #[cfg(feature = "lz4")]
+            CompressionType::Lz4,
+            #[cfg(feature = "snappy")]
+            CompressionType::Snappy,
+            #[cfg(feature = "zstd")]
+            CompressionType::Zstd,
+        ];
+
+        for kind in kinds {
+            let path = test_path(&format!("compressed_data_blocks_round_trip_{kind:?}"));
+            let config = SSTableConfig {
+                compression: kind,
+                ..Default::default()
+            };
+            let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+            for i in 0..1000u32 {
+                let entry = Entry::Value {
+                    seq_no: i as u64,
+                    val: format!("a fairly repetitive value {}", i % 3).into_bytes(),
+                    timestamp: None,
+                    expires_at: None,
+                };
+                writer
+                    .add(format!("key-{i:05}").as_bytes(), &entry)
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+
+            let reader = SSTableReader::open(path.clone()).unwrap();
+            let on_disk: u64 = reader.index.iter().map(|h| h.len as u64).sum();
+            assert!(on_disk < reader.properties().raw_value_bytes);
+
+            assert_eq!(
+                reader.get(b"key-00500").unwrap(),
+                Some(Entry::Value {
+                    seq_no: 500,
+                    val: b"a fairly repetitive value 2".to_vec(),
+                    timestamp: None,
+                    expires_at: None,
+                })
+            );
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_reads_match_pread() {
+        let path = test_path("mmap_reads_match_pread");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        for i in 0..1000u32 {
+            let entry = Entry::Value {
+                seq_no: i as u64,
+                val: format!("val-{i}").into_bytes(),
+                timestamp: None,
+                expires_at: None,
+            };
+            writer
+                .add(format!("key-{i:05}").as_bytes(), &entry)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let pread = SSTableReader::open_with_mode(path.clone(), SSTableReadMode::Pread).unwrap();
+        let mmap = SSTableReader::open_with_mode(path, SSTableReadMode::Mmap).unwrap();
+        assert_eq!(pread.properties(), mmap.properties());
+
+        for i in (0..1000u32).step_by(7) {
+            let key = format!("key-{i:05}");
+            assert_eq!(
+                pread.get(key.as_bytes()).unwrap(),
+                mmap.get(key.as_bytes()).unwrap()
+            );
+        }
+        assert_eq!(mmap.get(b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn large_values_go_to_overflow_blocks() {
+        let path = test_path("large_values_go_to_overflow_blocks");
+        let config = SSTableConfig {
+            block_size: 1024,
+            ..Default::default()
+        };
+        let big = |i: u32| -> Vec<u8> { (0..10_000 + i).map(|b| (b % 251) as u8).collect() };
+
+        let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+        for i in 0..20u32 {
+            let val = if i % 2 == 0 {
+                big(i)
+            } else {
+                b"small".to_vec()
+            };
+            writer
+                .add(
+                    format!("key-{i:02}").as_bytes(),
+                    &Entry::Value {
+                        seq_no: i as u64,
+                        val,
+                        timestamp: None,
+                        expires_at: None,
+                    },
+                )
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        assert_eq!(
+            reader.properties().raw_value_bytes,
+            (0..20u32)
+                .map(|i| if i % 2 == 0 { 10_000 + i as u64 } else { 5 })
+                .sum::<u64>()
+        );
+        // Ten values of ~10 KiB out of line leave only a handful of small data blocks
+        assert!(reader.index.len() < 4, "{}", reader.index.len());
+
+        assert_eq!(
+            reader.get(b"key-04").unwrap(),
+            Some(Entry::Value {
+                seq_no: 4,
+                val: big(4),
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+        assert_eq!(
+            reader.get(b"key-05").unwrap(),
+            Some(Entry::Value {
+                seq_no: 5,
+                val: b"small".to_vec(),
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+
+        let mut iter = reader.iter();
+        iter.seek(b"key-10").unwrap();
+        assert_eq!(
+            iter.entry(),
+            Some(&Entry::Value {
+                seq_no: 10,
+                val: big(10),
+                timestamp: None,
+                expires_at: None,
+            })
+        );
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"key-09");
+        assert!(reader.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn open_rejects_unknown_version() {
+        let path = test_path("open_rejects_unknown_version");
+        let writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        writer.finish().unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        let version_at = bytes.len() - 12;
+        bytes[version_at..version_at + 4].copy_from_slice(&99u32.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(
+            SSTableReader::open(path),
+            Err(DBError::UnsupportedVersion { version: 99, .. })
+        ));
+    }
+
+    #[test]
+    fn range_tombstones_round_trip() {
+        let path = test_path("range_tombstones_round_trip");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        writer
+            .add(
+                b"m",
+                &Entry::Value {
+                    seq_no: 1,
+                    val: b"v".to_vec(),
+                    timestamp: None,
+                    expires_at: None,
+                },
+            )
+            .unwrap();
+        let tombstones = [
+            RangeTombstone {
+                start: b"p".to_vec(),
+                end: b"z".to_vec(),
+                seq_no: 7,
+            },
+            RangeTombstone {
+                start: b"c".to_vec(),
+                end: b"f".to_vec(),
+                seq_no: 3,
+            },
+        ];
+        for tombstone in &tombstones {
+            writer.add_range_tombstone(tombstone).unwrap();
+        }
+        assert!(matches!(
+            writer.add_range_tombstone(&RangeTombstone {
+                start: b"f".to_vec(),
+                end: b"c".to_vec(),
+                seq_no: 8,
+            }),
+            Err(DBError::Codec { .. })
+        ));
+
+        // The key range spans the tombstones, not just the entries
+        let meta = writer.finish().unwrap();
+        assert_eq!(meta.smallest_key(), b"c");
+        assert_eq!(meta.largest_key(), b"z");
+        assert_eq!(meta.max_seq_no(), 7);
+
+        let reader = SSTableReader::open(path).unwrap();
+        assert_eq!(
+            reader.range_tombstones(),
+            [tombstones[1].clone(), tombstones[0].clone()]
+        );
+        assert_eq!(reader.properties().range_deletion_count, 2);
+        assert_eq!(reader.properties().entry_count, 1);
+        assert!(reader.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn checksum_type_is_read_from_the_footer() {
+        let path = test_path("checksum_type_is_read_from_the_footer");
+        let config = SSTableConfig {
+            checksum: ChecksumType::XxHash64,
+            ..Default::default()
+        };
+        let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+        let entry = Entry::Value {
+            seq_no: 0,
+            val: b"v".to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+        writer.add(b"k", &entry).unwrap();
+        let meta = writer.finish().unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        let footer_at = bytes.len() - SS_TABLE_FOOTER_LEN;
+        assert_eq!(bytes[footer_at + 48], ChecksumType::XxHash64 as u8);
+        assert_eq!(
+            meta.file_checksum(),
+            ChecksumType::XxHash64.checksum(&bytes[..footer_at])
+        );
+
+        // Reading it needs nothing but the path, the footer says how it was checksummed
+        let reader = SSTableReader::open(path.clone()).unwrap();
+        assert_eq!(reader.get(b"k").unwrap(), Some(entry.clone()));
+        reader.verify_file_checksum().unwrap();
+
+        // A version 2 footer lacks the checksum type, everything in the table is crc32
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        writer.add(b"k", &entry).unwrap();
+        writer.finish().unwrap();
+        bytes = fs::read(&path).unwrap();
+        let footer_at = bytes.len() - SS_TABLE_FOOTER_LEN;
+        bytes.remove(footer_at + 48);
+        let version_at = bytes.len() - 12;
+        bytes[version_at..version_at + 4].copy_from_slice(&2u32.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+
+        let reader = SSTableReader::open(path).unwrap();
+        assert_eq!(reader.get(b"k").unwrap(), Some(entry));
+        reader.verify_file_checksum().unwrap();
+    }
+
+    #[test]
+    fn reads_version_1_tables() {
+        let path = test_path("reads_version_1_tables");
+        let mut writer = SSTableWriter::new(path.clone(), 1, 0).unwrap();
+        let entry = Entry::Value {
+            seq_no: 0,
+            val: b"v".to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+        writer.add(b"k", &entry).unwrap();
+        writer.finish().unwrap();
+
+        // A version 1 footer is the same, minus the range deletion block handle and the checksum type
+        let mut bytes = fs::read(&path).unwrap();
+        let footer_at = bytes.len() - SS_TABLE_FOOTER_LEN;
+        bytes.drain(footer_at + 36..footer_at + 49);
+        let version_at = bytes.len() - 12;
+        bytes[version_at..version_at + 4].copy_from_slice(&1u32.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+
+        let reader = SSTableReader::open(path).unwrap();
+        assert_eq!(reader.get(b"k").unwrap(), Some(entry));
+        assert!(reader.range_tombstones().is_empty());
+        assert_eq!(reader.file_size(), bytes.len() as u64);
+        reader.verify_file_checksum().unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_order_keys() {
+        let path = test_path("rejects_out_of_order_keys");
+        let mut writer = SSTableWriter::new(path, 1, 0).unwrap();
+
+        let entry = Entry::Value {
+            seq_no: 0,
+            val: b"v".to_vec(),
+            timestamp: None,
+            expires_at: None,
+        };
+        writer.add(b"b", &entry).unwrap();
+
+        assert!(matches!(
+            writer.add(b"a", &entry),
+            Err(DBError::Codec { .. })
+        ));
+        assert!(matches!(
+            writer.add(b"b", &entry),
+            Err(DBError::Codec { .. })
+        ));
+    }
+
+    #[test]
+    fn versions_of_a_key_are_read_as_of_a_seq_no() {
+        let path = test_path("versions_of_a_key_are_read_as_of_a_seq_no");
+        let config = SSTableConfig {
+            block_size: 64,
+            ..Default::default()
+        };
+        let mut writer = SSTableWriter::with_config(path.clone(), 1, 0, config).unwrap();
+        let value = |seq_no: u64| Entry::Value {
+            seq_no,
+            val: vec![7; 32],
+            timestamp: None,
+            expires_at: None,
+        };
+        writer.add(b"a", &value(50)).unwrap();
+        for seq_no in (21..=40).rev() {
+            writer.add(b"k", &value(seq_no)).unwrap();
+        }
+        writer.add(b"z", &value(0)).unwrap();
+        assert!(matches!(
+            writer.add(b"z", &value(0)),
+            Err(DBError::Codec { .. })
+        ));
+        writer.finish().unwrap();
+
+        // The versions of a key are never split across blocks, the first is only cut before "z"
+        let reader = SSTableReader::open(path).unwrap();
+        assert_eq!(reader.index.len(), 2);
+        let seq_no = |entry: Option<Entry>| entry.map(|entry| entry.seq_no());
+        assert_eq!(seq_no(reader.get(b"k").unwrap()), Some(40));
+        assert_eq!(seq_no(reader.get_at(b"k", 30, true).unwrap()), Some(29));
+        assert_eq!(seq_no(reader.get_at(b"k", 21, true).unwrap()), None);
+
+        let mut iter = reader.iter();
+        iter.seek_for_prev(b"k").unwrap();
+        assert_eq!(seq_no(iter.entry().cloned()), Some(21));
+        iter.seek_for_prev(b"m").unwrap();
+        assert_eq!(seq_no(iter.entry().cloned()), Some(21));
+        iter.seek(b"b").unwrap();
+        assert_eq!(seq_no(iter.entry().cloned()), Some(40));
+    }
+}
@@ -0,0 +1,63 @@
+//! A live feed of the writes committed to the DB, for building replication, cache invalidation or
+//! indexing on top of it. Subscriptions are taken out with `DB::subscribe`.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::wal::WALRecord;
+
+/// A Subscription receives every write committed after it was taken out, as the `WALRecord` logged for
+/// it, in `seq_no` order. Writes are handed over as soon as they are committed: once logged to the WAL,
+/// or straight away for those skipping it, so a change may be seen before it can be read back from the DB.
+///
+/// Writes queue up until received, so a subscriber that stops reading should be dropped.
+///
+/// Iterating blocks until the next write, and ends once the DB has been dropped.
+pub struct Subscription {
+    receiver: Receiver<WALRecord>,
+}
+
+impl Subscription {
+    /// The next write if one is waiting, without blocking.
+    pub fn try_next(&self) -> Option<WALRecord> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next write.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<WALRecord> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = WALRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// The live subscriptions of a DB.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: Mutex<Vec<Sender<WALRecord>>>,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        self.lock().push(sender);
+        Subscription { receiver }
+    }
+
+    /// Hands `record` to every subscription, letting go of the ones that were dropped.
+    pub(crate) fn publish(&self, record: &WALRecord) {
+        let mut senders = self.lock();
+        senders.retain(|sender| sender.send(record.clone()).is_ok());
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Sender<WALRecord>>> {
+        self.senders.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
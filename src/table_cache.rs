@@ -0,0 +1,190 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::sstable::{SSTableMeta, SSTableReadOptions, SSTableReader};
+use crate::types::DBError;
+
+pub const DEFAULT_MAX_OPEN_FILES: usize = 1000;
+
+/// The TableCache keeps the readers of recently used SSTables open so repeated reads skip re-opening the
+/// file and re-reading its footer, index and filter. At most `max_open_files` readers are kept, once full
+/// the least recently used one is closed to make room.
+///
+/// Readers are handed out as `Arc`s so an evicted reader stays usable by whoever is still holding it, the
+/// file is closed once the last user drops it.
+pub struct TableCache {
+    max_open_files: usize,
+    read_options: SSTableReadOptions,
+    state: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    readers: HashMap<u64, (Arc<SSTableReader>, u64)>,
+    // Last use tick -> `file_no`, the first entry is the least recently used reader
+    lru: BTreeMap<u64, u64>,
+    tick: u64,
+}
+
+impl TableCache {
+    pub fn new(max_open_files: usize, read_options: SSTableReadOptions) -> Self {
+        Self {
+            max_open_files: max_open_files.max(1),
+            read_options,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    /// Returns the reader for the table described by `meta`, opening it if it is not cached.
+    pub fn get(&self, meta: &SSTableMeta) -> Result<Arc<SSTableReader>, DBError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(reader) = state.touch(meta.file_no()) {
+            return Ok(reader);
+        }
+
+        let reader = Arc::new(SSTableReader::open_with_options(
+            PathBuf::from(meta.path()),
+            &self.read_options,
+        )?);
+        state.insert(meta.file_no(), reader.clone(), self.max_open_files);
+
+        Ok(reader)
+    }
+
+    /// `get` for one-off reads, e.g. a bulk scan: a cached reader is handed out as usual, but one that is
+    /// not is opened for the caller alone and never cached. The least recently used order is left as is
+    /// either way, so the scan doesn't push the readers of the tables in use out of the cache.
+    pub fn get_without_caching(&self, meta: &SSTableMeta) -> Result<Arc<SSTableReader>, DBError> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((reader, _)) = state.readers.get(&meta.file_no()) {
+            return Ok(reader.clone());
+        }
+        drop(state);
+
+        let reader =
+            SSTableReader::open_with_options(PathBuf::from(meta.path()), &self.read_options)?;
+        Ok(Arc::new(reader))
+    }
+
+    /// Caches an already opened reader, e.g. one opened during recovery.
+    pub fn insert(&self, file_no: u64, reader: SSTableReader) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.insert(file_no, Arc::new(reader), self.max_open_files);
+    }
+
+    /// Drops the cached reader of `file_no`, for tables that are no longer live.
+    pub fn evict(&self, file_no: u64) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, tick)) = state.readers.remove(&file_no) {
+            state.lru.remove(&tick);
+        }
+    }
+
+    pub fn contains(&self, file_no: u64) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.readers.contains_key(&file_no)
+    }
+
+    /// The number of readers currently held open.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.readers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl LruState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Marks `file_no` as the most recently used reader and returns it, if cached.
+    fn touch(&mut self, file_no: u64) -> Option<Arc<SSTableReader>> {
+        let tick = self.next_tick();
+        let (reader, last_used) = self.readers.get_mut(&file_no)?;
+        self.lru.remove(last_used);
+        *last_used = tick;
+        self.lru.insert(tick, file_no);
+        Some(reader.clone())
+    }
+
+    fn insert(&mut self, file_no: u64, reader: Arc<SSTableReader>, max_open_files: usize) {
+        let tick = self.next_tick();
+        if let Some((_, last_used)) = self.readers.insert(file_no, (reader, tick)) {
+            self.lru.remove(&last_used);
+        }
+        self.lru.insert(tick, file_no);
+
+        while self.readers.len() > max_open_files {
+            let Some((_, evicted)) = self.lru.pop_first() else {
+                break;
+            };
+            self.readers.remove(&evicted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod table_cache_test {
+    use super::*;
+    use crate::entry::Entry;
+    use crate::sstable::SSTableWriter;
+
+    const TEST_DATA_DIR: &str = "test_data/table_cache";
+
+    fn write_table(name: &str, file_no: u64) -> SSTableMeta {
+        std::fs::create_dir_all(TEST_DATA_DIR).unwrap();
+        let path = PathBuf::from(TEST_DATA_DIR).join(format!("{name}_{file_no}.sst"));
+        let mut writer = SSTableWriter::new(path, file_no, 0).unwrap();
+        writer
+            .add(
+                format!("key-{file_no}").as_bytes(),
+                &Entry::Tombstone { seq_no: file_no },
+            )
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_reader() {
+        let metas: Vec<SSTableMeta> = (1..=3)
+            .map(|file_no| write_table("evicts_least_recently_used_reader", file_no))
+            .collect();
+        let cache = TableCache::new(2, SSTableReadOptions::default());
+
+        cache.get(&metas[0]).unwrap();
+        cache.get(&metas[1]).unwrap();
+        // Touch 1 so that 2 becomes the least recently used
+        cache.get(&metas[0]).unwrap();
+        let reader = cache.get(&metas[2]).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(1));
+        assert!(!cache.contains(2));
+        assert!(cache.contains(3));
+        assert!(reader.get(b"key-3").unwrap().is_some());
+
+        cache.evict(3);
+        assert!(!cache.contains(3));
+        // An evicted reader stays usable by whoever still holds it
+        assert!(reader.get(b"key-3").unwrap().is_some());
+
+        cache.get(&metas[1]).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(1) && cache.contains(2));
+
+        // One-off reads neither fill the cache nor change which reader goes next
+        let one_off = cache.get_without_caching(&metas[2]).unwrap();
+        assert!(one_off.get(b"key-3").unwrap().is_some());
+        assert!(!cache.contains(3));
+        cache.get_without_caching(&metas[0]).unwrap();
+        cache.get(&metas[2]).unwrap();
+        assert!(!cache.contains(1));
+        assert!(cache.contains(2) && cache.contains(3));
+    }
+}
@@ -0,0 +1,133 @@
+//! Optimistic transactions. A `Transaction` reads the DB as of the snapshot it was begun at and buffers
+//! its writes, nothing is locked meanwhile. At commit the keys it read are checked against the DB: if
+//! any was written since the snapshot the commit fails with `DBError::Conflict`, otherwise the writes are
+//! committed as a single `WriteBatch`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::batch::WriteBatch;
+use crate::comparator::Comparator;
+use crate::snapshot::Snapshot;
+use crate::types::{DBError, Decode, Encode};
+use crate::wal::Op;
+use crate::{DB, ReadOptions, WriteOptions, entry_value};
+
+/// A Transaction is begun with `DB::begin_transaction` and handed the DB back for its reads and its
+/// commit, it doesn't borrow it in between, so other writes go on while it runs. It must only be used with
+/// the DB it was begun on.
+///
+/// Reads see the transaction's own writes first. Keys read from the DB are remembered, it is those
+/// `commit` checks: a write the transaction never read, a blind write, never conflicts. The snapshot keeps
+/// the versions it reads around for as long as the transaction lives, see `DB::snapshot`.
+pub struct Transaction {
+    snapshot: Snapshot,
+    batch: WriteBatch,
+    // Encoded keys read from the DB rather than from the transaction's own writes
+    read_keys: HashSet<Vec<u8>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot,
+            batch: WriteBatch::new(),
+            read_keys: HashSet::new(),
+        }
+    }
+
+    /// The value of `key` as the transaction sees it: as its own writes left it if they touched it, as
+    /// of the snapshot otherwise.
+    pub fn get_raw<K: Encode>(&mut self, db: &DB, key: &K) -> Result<Option<Vec<u8>>, DBError> {
+        let key = key.encode();
+        if let Some(val) = self.own_write(db.comparator(), &key) {
+            return Ok(val);
+        }
+
+        let read_opts = ReadOptions {
+            snapshot: Some(&self.snapshot),
+            ..ReadOptions::default()
+        };
+        let val = db.get_entry(&key, &read_opts)?.and_then(entry_value);
+        self.read_keys.insert(key);
+        Ok(val)
+    }
+
+    pub fn get_typed<K: Encode, V: Decode>(
+        &mut self,
+        db: &DB,
+        key: &K,
+    ) -> Result<Option<V>, DBError> {
+        self.get_raw(db, key)?
+            .map(|val| V::decode(&val))
+            .transpose()
+    }
+
+    pub fn put<K: Encode, V: Encode>(&mut self, key: &K, val: &V) -> &mut Self {
+        self.batch.put(key, val);
+        self
+    }
+
+    pub fn delete<K: Encode>(&mut self, key: &K) -> &mut Self {
+        self.batch.delete(key);
+        self
+    }
+
+    /// Deletes every key in the half-open range `[start, end)`, see `DB::delete_range`.
+    pub fn delete_range<K: Encode>(&mut self, start: &K, end: &K) -> &mut Self {
+        self.batch.delete_range(start, end);
+        self
+    }
+
+    /// The seq_no of the snapshot the transaction reads at, see `Snapshot::seq_no`.
+    pub fn seq_no(&self) -> u64 {
+        self.snapshot.seq_no()
+    }
+
+    /// Commits the transaction's writes to `db` atomically, as `DB::write` does, unless a key it read
+    /// was written since its snapshot. The first such key is returned in a `DBError::Conflict` and
+    /// nothing is written, it is up to the caller to begin a new transaction and redo its work.
+    pub fn commit(self, db: &mut DB) -> Result<(), DBError> {
+        self.commit_opt(db, &WriteOptions::default())
+    }
+
+    /// `commit` with per-write options, see `WriteOptions`.
+    pub fn commit_opt(self, db: &mut DB, write_opts: &WriteOptions) -> Result<(), DBError> {
+        // Nothing else writes while `db` is borrowed, the keys can't change between the check and the write
+        for key in &self.read_keys {
+            if db
+                .last_write_seq_no(key)?
+                .is_some_and(|seq_no| seq_no >= self.snapshot.seq_no())
+            {
+                return Err(DBError::Conflict { key: key.clone() });
+            }
+        }
+        db.write(self.batch, write_opts)
+    }
+
+    /// What the transaction's own writes left of `key`, the value of its last put or None if it was
+    /// deleted since. None at all if they never touched it.
+    fn own_write(&self, comparator: &dyn Comparator, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.batch.ops().iter().rev().find_map(|op| match op.op {
+            Op::Put if op.key == key => Some(Some(op.val.clone())),
+            Op::Delete if op.key == key => Some(None),
+            Op::DeleteRange
+                if comparator.compare(&op.key, key).is_le()
+                    && comparator.compare(key, &op.val).is_lt() =>
+            {
+                Some(None)
+            }
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("seq_no", &self.snapshot.seq_no())
+            .field("writes", &self.batch.len())
+            .field("reads", &self.read_keys.len())
+            .finish()
+    }
+}
@@ -0,0 +1,106 @@
+//! A typed view over a `DB`. Applications with one key type and one value type wrap their DB in a
+//! `TypedDB`, which encodes and decodes on the way in and out, so they never handle raw bytes.
+
+use std::fmt;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+
+use crate::DB;
+use crate::iterator::{DBIterator, KeyValue};
+use crate::types::{DBError, Decode, Encode};
+
+/// A TypedDB holds keys of type `K` and values of type `V`. Keys still compare by their encoding, ranges
+/// and iteration follow the order of the encoded keys, which is only the order of `K` if its `Encode`
+/// preserves it.
+pub struct TypedDB<K, V> {
+    db: DB,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: Encode + Decode, V: Encode + Decode> TypedDB<K, V> {
+    pub fn new(db: DB) -> Self {
+        Self {
+            db,
+            types: PhantomData,
+        }
+    }
+
+    pub fn put(&mut self, key: &K, val: &V) -> Result<(), DBError> {
+        self.db.put(key, val)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, DBError> {
+        self.db.get_typed(key)
+    }
+
+    pub fn delete(&mut self, key: &K) -> Result<(), DBError> {
+        self.db.delete(key)
+    }
+
+    /// Iterates over every live key in key order, along with its value.
+    pub fn iter(&self) -> TypedIterator<'_, K, V> {
+        TypedIterator::new(self.db.iter())
+    }
+
+    /// Iterates over the live keys within `range` in key order, along with their values, see `DB::range`.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> TypedIterator<'_, K, V> {
+        TypedIterator::new(self.db.range(range))
+    }
+
+    /// The wrapped DB, for everything the typed view doesn't cover.
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn db_mut(&mut self) -> &mut DB {
+        &mut self.db
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+}
+
+impl<K, V> fmt::Debug for TypedDB<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedDB").finish_non_exhaustive()
+    }
+}
+
+/// A `DBIterator` decoding its keys and values. A key or value that fails to decode is yielded as the
+/// error, the iteration goes on past it.
+pub struct TypedIterator<'a, K, V> {
+    inner: DBIterator<'a>,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'a, K: Decode, V: Decode> TypedIterator<'a, K, V> {
+    pub fn new(inner: DBIterator<'a>) -> Self {
+        Self {
+            inner,
+            types: PhantomData,
+        }
+    }
+
+    fn decode(item: Result<KeyValue, DBError>) -> Result<(K, V), DBError> {
+        let (key, val) = item?;
+        Ok((K::decode(&key)?, V::decode(&val)?))
+    }
+}
+
+impl<K: Decode, V: Decode> Iterator for TypedIterator<'_, K, V> {
+    type Item = Result<(K, V), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(Self::decode)
+    }
+}
+
+impl<K: Decode, V: Decode> DoubleEndedIterator for TypedIterator<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(Self::decode)
+    }
+}
+
+impl<K: Decode, V: Decode> FusedIterator for TypedIterator<'_, K, V> {}
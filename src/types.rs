@@ -1,6 +1,8 @@
 use std::{
     fmt::{self, Display, Formatter},
-    path::PathBuf,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
 };
 
 pub trait Encode {
@@ -11,7 +13,205 @@ pub trait Decode: Sized {
     fn decode(bytes: &[u8]) -> Result<Self, DBError>;
 }
 
-pub const ERR_CONFIG_EMPTY_KEY: &'static str = "empty key";
+/// Strings are their UTF-8 bytes, so they compare the way `str` does.
+impl Encode for str {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl Encode for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl Decode for String {
+    fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| DBError::Codec {
+            context: String::from("string is not UTF-8"),
+            source: Some(Box::new(e)),
+        })
+    }
+}
+
+/// A reference encodes as what it refers to, e.g. `db.put(&"key", &"val")`.
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self) -> Vec<u8> {
+        (**self).encode()
+    }
+}
+
+/// Bytes are stored as they are.
+impl Encode for [u8] {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl Decode for Vec<u8> {
+    fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+        bytes.try_into().map_err(|_| wrong_len(N, bytes.len()))
+    }
+}
+
+fn wrong_len(expected: usize, len: usize) -> DBError {
+    DBError::Codec {
+        context: format!("expected {expected} bytes, got {len}"),
+        source: None,
+    }
+}
+
+/// Integers are big-endian, with the sign bit flipped for the signed ones, so their encodings compare the
+/// way the numbers do and a range of integer keys scans in numeric order, see `keys` for composite keys.
+macro_rules! integer_codec {
+    ($($unsigned:ty, $signed:ty);+) => {$(
+        impl Encode for $unsigned {
+            fn encode(&self) -> Vec<u8> {
+                self.to_be_bytes().to_vec()
+            }
+        }
+
+        impl Decode for $unsigned {
+            fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+                let bytes = bytes
+                    .try_into()
+                    .map_err(|_| wrong_len(size_of::<Self>(), bytes.len()))?;
+                Ok(<$unsigned>::from_be_bytes(bytes))
+            }
+        }
+
+        impl Encode for $signed {
+            fn encode(&self) -> Vec<u8> {
+                ((*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1))).encode()
+            }
+        }
+
+        impl Decode for $signed {
+            fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+                let bits = <$unsigned>::decode(bytes)?;
+                Ok((bits ^ (1 << (<$unsigned>::BITS - 1))) as $signed)
+            }
+        }
+    )+};
+}
+
+integer_codec!(u8, i8; u16, i16; u32, i32; u64, i64; u128, i128);
+
+/// A UUID is its 16 bytes, so time-ordered ones, e.g. version 7, scan in the order they were made.
+#[cfg(feature = "uuid")]
+impl Encode for uuid::Uuid {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl Decode for uuid::Uuid {
+    fn decode(bytes: &[u8]) -> Result<Self, DBError> {
+        uuid::Uuid::from_slice(bytes).map_err(|e| DBError::Codec {
+            context: String::from("malformed uuid"),
+            source: Some(Box::new(e)),
+        })
+    }
+}
+
+pub(crate) fn read_u32_le(input: &[u8]) -> Option<u32> {
+    let bitfield: [u8; 4] = input.get(0..4)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bitfield))
+}
+
+pub(crate) fn read_u64_le(input: &[u8]) -> Option<u64> {
+    let bitfield: [u8; 8] = input.get(0..8)?.try_into().ok()?;
+    Some(u64::from_le_bytes(bitfield))
+}
+
+/// Syncs the directory holding `path`, making a file created or renamed within it durable.
+#[cfg(unix)]
+pub(crate) fn sync_parent_dir(path: &Path) -> Result<(), DBError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| DBError::Io {
+            op: "failed to sync parent dir",
+            path: dir.to_path_buf(),
+            source: e,
+        })
+}
+
+/// Directories cannot be opened as files, let alone synced, outside of unix, where renames and creations
+/// are made durable by the file system's own journal.
+#[cfg(not(unix))]
+pub(crate) fn sync_parent_dir(_path: &Path) -> Result<(), DBError> {
+    Ok(())
+}
+
+/// Reads from `file` at `offset` into `buf` without moving a cursor other readers of the file rely on,
+/// returning how many bytes were read like `Read::read`.
+#[cfg(unix)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+/// `seek_read` moves the cursor of `file` as well, but nothing else reads through it.
+#[cfg(windows)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Without positional reads the cursor is seeked and read, which is only sound while no other thread does
+/// the same in between, so every such read is made under one lock.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::Mutex;
+
+    static CURSOR: Mutex<()> = Mutex::new(());
+    let _guard = CURSOR.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// Fills `buf` from `file` at `offset`, see `read_at`, failing with `UnexpectedEof` if the file ends first.
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+pub const ERR_CONFIG_EMPTY_KEY: &str = "empty key";
 
 #[derive(Debug)]
 pub enum DBError {
@@ -27,7 +227,7 @@ pub enum DBError {
     },
     WAL {
         what: &'static str,
-        err: Option<Box <dyn std::error::Error + Send + Sync + 'static>>,
+        err: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
     },
     Codec {
         context: String,
@@ -36,6 +236,19 @@ pub enum DBError {
     InvalidConfig {
         what: &'static str,
     },
+    UnsupportedVersion {
+        what: &'static str,
+        path: PathBuf,
+        version: u32,
+    },
+    // The write was turned away for now and may be retried, see `DBConfig::l0_stop_writes_trigger`
+    Busy {
+        what: &'static str,
+    },
+    // A transaction read `key` and someone else wrote it before the commit, see `Transaction::commit`
+    Conflict {
+        key: Vec<u8>,
+    },
 }
 
 impl std::error::Error for DBError {
@@ -62,6 +275,69 @@ impl Display for DBError {
             DBError::WAL { what, err } => {
                 write!(f, "what: {what:?} - err: {err:?}")
             }
+            DBError::UnsupportedVersion {
+                what,
+                path,
+                version,
+            } => {
+                write!(f, "what: {what:?} - path: {path:?} - version: {version:?}")
+            }
+            DBError::Busy { what } => {
+                write!(f, "what: {what:?}")
+            }
+            DBError::Conflict { key } => {
+                write!(f, "key: {key:?}")
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod types_test {
+    use super::*;
+
+    fn round_trip<T: Encode + Decode + PartialEq + fmt::Debug>(val: T) {
+        assert_eq!(T::decode(&val.encode()).unwrap(), val);
+    }
+
+    #[test]
+    fn std_types_round_trip() {
+        round_trip(String::from("héllo"));
+        round_trip(vec![0u8, 1, 255]);
+        round_trip([7u8; 4]);
+        round_trip(u8::MAX);
+        round_trip(i16::MIN);
+        round_trip(-1i32);
+        round_trip(u64::MAX);
+        round_trip(i128::MAX);
+        assert_eq!("key".encode(), b"key");
+        assert_eq!((&&"key").encode(), b"key");
+        assert_eq!(b"key"[..].encode(), b"key");
+    }
+
+    #[test]
+    fn integers_compare_by_value() {
+        let unsigned = [0u32, 1, 255, 256, u32::MAX].map(|n| n.encode());
+        assert!(unsigned.is_sorted());
+        let signed = [i64::MIN, -256, -1, 0, 1, 256, i64::MAX].map(|n| n.encode());
+        assert!(signed.is_sorted());
+        assert_eq!(258u16.encode(), [1, 2]);
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuids_are_their_bytes() {
+        let id = uuid::Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        assert_eq!(id.encode(), id.as_bytes());
+        round_trip(id);
+        assert!(uuid::Uuid::decode(&[0; 15]).is_err());
+    }
+
+    #[test]
+    fn malformed_bytes_fail_to_decode() {
+        assert!(u64::decode(&[0; 7]).is_err());
+        assert!(i8::decode(&[]).is_err());
+        assert!(<[u8; 4]>::decode(&[0; 5]).is_err());
+        assert!(String::decode(&[0xff]).is_err());
+    }
+}
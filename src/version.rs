@@ -0,0 +1,104 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::compaction::CompactionStats;
+use crate::manifest::{Manifest, VersionEdit};
+use crate::sstable::SSTableMeta;
+use crate::table_cache::TableCache;
+use crate::types::DBError;
+
+/// The VersionSet is the set of live SSTables together with the manifest recording it. It is shared between
+/// the DB and its background worker behind a `Mutex`: reads hold the lock for as long as they consult the
+/// tables, which is what keeps a compaction from deleting a table out from under them.
+pub(crate) struct VersionSet {
+    // Kept ordered newest-to-oldest, see `newest_first`
+    pub(crate) ss_meta: Vec<SSTableMeta>,
+    pub(crate) manifest: Manifest,
+    // The `seq_no` of every live snapshot, with how many handles share it
+    pub(crate) snapshots: BTreeMap<u64, usize>,
+    // What flushes and compactions have written since the DB was opened
+    pub(crate) stats: CompactionStats,
+}
+
+impl VersionSet {
+    pub(crate) fn new(manifest: Manifest) -> Self {
+        Self {
+            ss_meta: Vec::new(),
+            manifest,
+            snapshots: BTreeMap::new(),
+            stats: CompactionStats::default(),
+        }
+    }
+
+    /// The `seq_no` of every live snapshot, oldest first. Compaction must keep whatever they can still see.
+    pub(crate) fn live_snapshots(&self) -> Vec<u64> {
+        self.snapshots.keys().copied().collect()
+    }
+
+    /// Whether a live snapshot sees some but not all of the writes with a `seq_no` within
+    /// `[min_seq_no, max_seq_no]`. Overwriting the version of a key written at `min_seq_no` with the one
+    /// written at `max_seq_no` then drops the version the snapshot reads.
+    pub(crate) fn snapshot_within(&self, min_seq_no: u64, max_seq_no: u64) -> bool {
+        min_seq_no < max_seq_no
+            && self
+                .snapshots
+                .range(min_seq_no + 1..=max_seq_no)
+                .next()
+                .is_some()
+    }
+
+    /// Registers a live SSTable, keeping `ss_meta` ordered newest-to-oldest.
+    pub(crate) fn install(&mut self, meta: SSTableMeta) {
+        self.ss_meta.push(meta);
+        self.ss_meta.sort_by_key(newest_first);
+    }
+
+    /// Logs `edit` to the manifest and then applies it to `ss_meta`. The files of removed tables are left
+    /// alone, see `remove_ss_table_files`.
+    pub(crate) fn apply(&mut self, edit: VersionEdit) -> Result<(), DBError> {
+        self.manifest.log_edit(edit.clone())?;
+
+        self.ss_meta
+            .retain(|meta| !edit.removed.contains(&meta.file_no()));
+        for meta in edit.added {
+            self.install(meta);
+        }
+
+        Ok(())
+    }
+}
+
+/// Orders tables newest-to-oldest: by level, then within a level by descending `max_seq_no`. An L0 table
+/// holding newer writes may well have the lower `file_no`: a flush takes its number before it writes the
+/// table, and an intra-L0 compaction that starts meanwhile writes its older data under a higher one.
+pub(crate) fn newest_first(meta: &SSTableMeta) -> (u32, Reverse<u64>, Reverse<u64>) {
+    (
+        meta.level(),
+        Reverse(meta.max_seq_no()),
+        Reverse(meta.file_no()),
+    )
+}
+
+pub(crate) fn lock(versions: &Mutex<VersionSet>) -> MutexGuard<'_, VersionSet> {
+    versions.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Deletes the files of tables that are no longer live. Must only be called once the edit removing them has
+/// been applied, a crash before then leaves orphans that the next open cleans up.
+pub(crate) fn remove_ss_table_files(
+    removed: &[SSTableMeta],
+    table_cache: &TableCache,
+) -> Result<(), DBError> {
+    for meta in removed {
+        table_cache.evict(meta.file_no());
+        std::fs::remove_file(meta.path()).map_err(|e| DBError::Io {
+            op: "failed to remove compacted ss_table",
+            path: PathBuf::from(meta.path()),
+            source: e,
+        })?;
+    }
+
+    Ok(())
+}
@@ -1,157 +1,919 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::memtable::{MemTable, put};
-use crate::types::DBError;
+use crate::checksum::ChecksumType;
+use crate::compression::{CompressionType, compress, decompress};
+use crate::encryption::{Encryptor, NONCE_LEN, new_nonce};
+use crate::entry::RangeTombstone;
+use crate::memtable::{self, MemTableRep, delete, put_expiring, put_with_timestamp};
+use crate::merge::MergeOperator;
+use crate::sstable::preallocate;
+use crate::types::{DBError, read_at, read_u32_le, read_u64_le, sync_parent_dir};
 
+pub const DEFAULT_WAL_SEGMENT_SIZE: u64 = 64 * 1024 * 1024; // 64MiB
+pub const DEFAULT_MAX_RECORD_LEN: u32 = 1024 * 1000; // 1MiB
+// How many bytes `WAL::replay_into` gets through between two progress reports
+pub const REPLAY_PROGRESS_INTERVAL: u64 = 4 * 1024 * 1024; // 4MiB
+// Where `WALConfig::archive` moves segments, within the WAL's directory
+pub const ARCHIVE_DIR: &str = "archive";
+/// Magic bytes every segment starts with, a segment without them predates the segment header and is read
+/// as one written with `ChecksumType::Crc32`.
+pub const WAL_MAGIC: u64 = 0x4C53_4D44_4257_414C; // "LSMDBWAL"
+/// The format of the segments written. Version 1 segments hold records encoded with `encode_record`,
+/// later versions the varint encoding of `encode_varint_record`. Every version is still read.
+pub const WAL_FORMAT_VERSION: u32 = 3;
+/// [magic u64][version u32][checksum_type u8][segment_no u64][created_at u64], followed by the records or
+/// the encryption header, see `SegmentHeader`.
+pub const SEGMENT_HEADER_LEN: u64 = 8 + 4 + 1 + 8 + 8;
+// A version 2 header lacks the creation time, and a version 1 header the segment number too
+const V2_SEGMENT_HEADER_LEN: u64 = SEGMENT_HEADER_LEN - 8;
+const V1_SEGMENT_HEADER_LEN: u64 = V2_SEGMENT_HEADER_LEN - 8;
+// The longest a varint encoded u32 and u64 get
+const MAX_VARINT32_LEN: usize = 5;
+const MAX_VARINT64_LEN: usize = 10;
+/// The most a record adds to the `Op::Batch` record holding it besides its key and val, see `encode_batch`:
+/// [len u32][op u8][seq_no u64][key_len u32][val_len u32][timestamp u64] and the [crc u32] after.
+pub(crate) const BATCHED_RECORD_OVERHEAD: usize = 4 + 1 + 8 + 4 + 4 + 8 + 4;
+// Follows the nonce of an encrypted segment, encrypted, so a segment read with the wrong key is told
+// apart from a damaged one
+const KEY_CHECK: &[u8; 8] = b"lsmdbwal";
+// The nonce and the key check
+const ENCRYPTION_HEADER_LEN: u64 = (NONCE_LEN + KEY_CHECK.len()) as u64;
+
+/// When appended records are fsynced. Records that haven't been synced yet are lost if the machine goes
+/// down, policies other than `Always` trade that window for write throughput.
 #[derive(Debug, Clone, Copy)]
 pub enum SyncPolicy {
     Always,
+    // Syncs once every this many records, must be greater than 0
+    EveryN(u32),
+    // Syncs in the background at this interval, whatever was appended since the last sync. Must be greater
+    // than zero
+    Interval(Duration),
     Never,
 }
 
+/// How a `WAL` writes its segments.
+#[derive(Debug, Clone)]
+pub struct WALConfig {
+    pub sync: SyncPolicy,
+    // Records longer than this are turned away by `append`, and taken for corruption on replay
+    pub max_record_len: u32,
+    // The WAL moves on to a new segment once the current one would grow past this many bytes
+    pub segment_size: u64,
+    // Reserves `segment_size` bytes of disk for every new segment up front, so appends never grow the file
+    // and a sync doesn't have to persist a new file size along with the records
+    pub preallocate: bool,
+    // Keeps up to this many of the segments `truncate` drops to be written over by new segments, which
+    // saves allocating their blocks all over again. See `WAL::replay_into` for what it costs
+    pub recycle_files: usize,
+    // Starts writing the records back to disk every this many bytes appended, without waiting for them,
+    // whatever the `SyncPolicy`. 0 leaves the write-back to the OS
+    pub bytes_per_sync: u64,
+    // Moves the segments `truncate` drops into `ARCHIVE_DIR` rather than deleting them. Archived segments
+    // are never recycled
+    pub archive: Option<WALArchiveConfig>,
+    // Compresses the val of every record, worth it for large values. The codec must be compiled in
+    pub compression: CompressionType,
+    // Encrypts every record but its length, the segment header is then followed by the segment's nonce and
+    // a check of the key. Compression is applied first, encrypted bytes don't compress
+    pub encryptor: Option<Arc<dyn Encryptor>>,
+    // The checksum guarding every record. It is recorded in the segment header, so segments written
+    // before a change are still read back
+    pub checksum: ChecksumType,
+}
+
+/// How long archived segments are kept, the oldest ones go first once either limit is hit. With neither
+/// set the archive grows forever, and it is up to the user to clear it out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WALArchiveConfig {
+    // The combined size of every archived segment
+    pub max_size: Option<u64>,
+    // How long a segment is kept once it was last written to
+    pub max_age: Option<Duration>,
+}
+
+impl Default for WALConfig {
+    fn default() -> Self {
+        Self {
+            sync: SyncPolicy::Always,
+            max_record_len: DEFAULT_MAX_RECORD_LEN,
+            segment_size: DEFAULT_WAL_SEGMENT_SIZE,
+            preallocate: false,
+            recycle_files: 0,
+            bytes_per_sync: 0,
+            archive: None,
+            compression: CompressionType::None,
+            encryptor: None,
+            checksum: ChecksumType::Crc32,
+        }
+    }
+}
+
 /// The WAL (Write-Ahead-Log) acts as a persistent store for incoming changes for the MemTable. It acts as a durability layer that accepts append-only writes
 /// that go to file, and only then are then added to the MemTable
+///
+/// The WAL is a directory of segments `000001.wal`, `000002.wal`, ... numbered in the order they were
+/// written, each starting with a `SegmentHeader` naming its format version and `ChecksumType`. Records are
+/// appended to the newest segment until it would grow past `segment_size`, at which point the next one is
+/// started, so replay goes through the segments in order. A new segment is also started on open, so
+/// nothing is ever appended to a segment a crash may have left a torn record in.
+/// Segments kept for recycling are renamed `000001.recycle`, ... until they are reused, and archived
+/// segments are moved to `archive/000001.wal`, ... where a `WalReader` can still get at them.
+///
+/// Appends from concurrent writers are group committed: a writer queues its record, and the first writer
+/// to find no write in progress becomes the leader, writing every queued record and syncing them with a
+/// single fsync while the others wait for it. Writers arriving in the meantime form the next group, so
+/// under `SyncPolicy::Always` the fsyncs paid for stay about one per concurrent writer rather than one per
+/// record.
 pub struct WAL {
+    dir: PathBuf,
+    config: WALConfig,
+    group: Mutex<GroupCommit>,
+    // Signalled whenever a leader is done with its group
+    committed: Condvar,
+    // Locked by the leader, the interval flusher, and the calls that sync, rotate or truncate the WAL
+    segment: Arc<Mutex<Segment>>,
+    // Dropped segments waiting to be reused, oldest first. Only locked with `segment` held
+    recycled: Mutex<Vec<PathBuf>>,
+    // Runs the syncs of `SyncPolicy::Interval`, stopped by hanging up
+    flusher: Option<(Sender<()>, JoinHandle<()>)>,
+    // The segments holding writes not flushed yet, by who wrote them, see `hold`. Only locked on its own
+    // or with `segment` held
+    holds: Mutex<HashMap<u32, Hold>>,
+}
+
+/// The oldest segments an owner of writes appended to the WAL still needs, see `WAL::hold`.
+#[derive(Default)]
+struct Hold {
+    // Held for the MemTable being written to
+    active: Option<u64>,
+    // Held for the MemTable being flushed
+    frozen: Option<u64>,
+}
+
+/// The records waiting for a leader to write them. Every append takes a ticket, a record is written once
+/// the leader left its position under its ticket in `written`.
+#[derive(Default)]
+struct GroupCommit {
+    pending: Vec<PendingRecord>,
+    // Whether any of the pending records has to be synced whatever the `SyncPolicy`
+    pending_sync: bool,
+    last_ticket: u64,
+    // Where the records written for other writers ended, until they come to collect it
+    written: HashMap<u64, WalPosition>,
+    leading: bool,
+    // Set once a group failed to be written, the segment may then end in a torn record and nothing is
+    // appended to it any more
+    failed: bool,
+    // The number of groups written, for the tests
+    groups: u64,
+}
+
+/// The segment being appended to.
+struct Segment {
     buf: BufWriter<File>,
-    pathBuf: PathBuf,
-    sync: SyncPolicy,
-    max_record_len: u32,
+    segment_no: u64,
+    path: PathBuf,
+    // How many bytes of the file are taken, the headers and the records. A preallocated or recycled file
+    // is larger
+    len: u64,
+    // Set for an encrypted segment, whose records start `ENCRYPTION_HEADER_LEN` bytes after the header
+    nonce: Option<[u8; NONCE_LEN]>,
+    // The seq_no of the last record written, the next one is encoded relative to it
+    last_seq_no: u64,
+    // How many records were written since the last sync
+    unsynced: u64,
+    // How many of the `len` bytes have been synced, or at least had their write-back started
+    synced_len: u64,
+    // How many of the `len` bytes have been synced, and so survive a crash
+    durable_len: u64,
+    // A failed background sync, returned by the next append
+    flusher_error: Option<DBError>,
+}
+
+impl Segment {
+    fn sync(&mut self) -> Result<(), DBError> {
+        // `flush()` only moves the writes from the buffer to the kernel
+        // i.e user_space -> kernel_space.
+        // If something happens to the kernel e.g. power outage, the writes might not have been
+        // synced to the file system, so we need to call `sync_all` for that.
+        self.buf.flush().map_err(|e| DBError::Io {
+            op: "wal: failed to flush wal buf",
+            path: self.path.clone(),
+            source: e,
+        })?;
+
+        self.buf.get_ref().sync_all().map_err(|e| DBError::Io {
+            op: "wal: failed to sync_all",
+            path: self.path.clone(),
+            source: e,
+        })?;
+        self.unsynced = 0;
+        self.synced_len = self.len;
+        self.durable_len = self.len;
+
+        Ok(())
+    }
+
+    fn durable_position(&self) -> WalPosition {
+        WalPosition {
+            segment_no: self.segment_no,
+            offset: self.durable_len,
+        }
+    }
+
+    /// Starts writing the bytes appended since the last sync back to disk, without waiting for them. It
+    /// makes no promise about durability, but leaves the OS little to write back on its own, so it never
+    /// gets to stall appends with one huge write-back and a crash loses at most the bytes since.
+    fn sync_range(&mut self) -> Result<(), DBError> {
+        self.buf.flush().map_err(|e| DBError::Io {
+            op: "wal: failed to flush wal buf",
+            path: self.path.clone(),
+            source: e,
+        })?;
+
+        start_write_back(
+            self.buf.get_ref(),
+            self.synced_len,
+            self.len - self.synced_len,
+        )
+        .map_err(|e| DBError::Io {
+            op: "wal: failed to sync range",
+            path: self.path.clone(),
+            source: e,
+        })?;
+        self.synced_len = self.len;
+
+        Ok(())
+    }
+}
+
+/// Where a replay stands, carried from one segment to the next.
+struct Replay<'a, F> {
+    mem_table: &'a mut dyn MemTableRep,
+    range_tombstones: &'a mut Vec<RangeTombstone>,
+    merge_operator: Option<&'a dyn MergeOperator>,
+    // Merge operands are folded into the MemTable as of this time, see `memtable::merge`
+    now: u64,
+    // Takes the records of the column families sharing the WAL, see `WAL::replay_routing_into`
+    route: &'a mut dyn FnMut(u32, Vec<WALRecord>) -> Result<(), DBError>,
+    // Records below this `seq_no` were flushed already
+    flushed_seq_no: u64,
+    last_seq_no: Option<u64>,
+    progress: ReplayProgress,
+    on_progress: F,
 }
 
 impl WAL {
-    pub fn new(file_path: PathBuf, sync: SyncPolicy, max_record_len: u32) -> Result<Self, DBError> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .read(true)
-            .open(file_path.clone())
-            .map_err(|e| DBError::Io {
-                op: "wal: failed to open file from path_buf",
-                path: file_path.clone(),
+    /// Opens the WAL in `dir`, creating the directory if need be, and starts a new segment to append to.
+    pub fn new(dir: PathBuf, config: WALConfig) -> Result<Self, DBError> {
+        std::fs::create_dir_all(&dir).map_err(|e| DBError::Io {
+            op: "wal: failed to create dir",
+            path: dir.clone(),
+            source: e,
+        })?;
+
+        if config.archive.is_some() {
+            let archive_dir = dir.join(ARCHIVE_DIR);
+            std::fs::create_dir_all(&archive_dir).map_err(|e| DBError::Io {
+                op: "wal: failed to create archive dir",
+                path: archive_dir,
                 source: e,
             })?;
+        }
+
+        // Files beyond what may be recycled now, e.g. after `recycle_files` was lowered, are let go
+        let mut recycled = recycled_files(&dir)?;
+        let recycle_files = if config.archive.is_some() {
+            0
+        } else {
+            config.recycle_files
+        };
+        let excess = recycled.len().saturating_sub(recycle_files);
+        for path in recycled.drain(..excess) {
+            remove_file(path)?;
+        }
+
+        let segment_no = segments(&dir)?
+            .last()
+            .map_or(1, |(segment_no, _)| segment_no + 1);
+        let segment = open_segment(&dir, segment_no, &config, &mut recycled)?;
+        let segment = Arc::new(Mutex::new(segment));
+
+        let flusher = match config.sync {
+            SyncPolicy::Interval(interval) => Some(spawn_flusher(segment.clone(), interval)?),
+            _ => None,
+        };
 
         Ok(Self {
-            buf: BufWriter::new(file),
-            pathBuf: file_path,
-            sync: sync,
-            max_record_len,
+            dir,
+            config,
+            group: Mutex::new(GroupCommit::default()),
+            committed: Condvar::new(),
+            segment,
+            recycled: Mutex::new(recycled),
+            flusher,
+            holds: Mutex::new(HashMap::new()),
         })
     }
 
-    pub fn append(&mut self, rec: &WALRecord) -> Result<(), DBError> {
-        let encode = encode_record(rec);
+    /// Appends `rec`, returning once it has been written, and synced if `sync` is set or the `SyncPolicy`
+    /// calls for it. The record may be written by another writer's call as part of its group, see `WAL`.
+    ///
+    /// Returns where the record was written, the `WalPosition` a `WalReader` hands it out with. It is
+    /// durable once `durable_position` has moved past it.
+    ///
+    /// Fails with `DBError::Codec` without writing anything if the record could encode to more than
+    /// `max_record_len` bytes, which replay would take for corruption.
+    pub fn append(&self, rec: &WALRecord, sync: bool) -> Result<WalPosition, DBError> {
+        let record = PendingRecord::new(rec, self.config.compression);
+        record.check_len(self.config.max_record_len)?;
+        self.commit(record, sync)
+    }
 
-        self.buf
-            .write_all(encode.as_ref())
-            .map_err(|e| DBError::Io {
-                op: "wal: failed to write wal buf",
-                path: self.pathBuf.clone(),
-                source: e,
-            })?;
+    /// Appends `records` as a single `Op::Batch` record, so replay applies either all of them or, when the
+    /// batch was torn by a crash, none of them. Otherwise the same as `append`.
+    pub fn append_batch(&self, records: &[WALRecord], sync: bool) -> Result<WalPosition, DBError> {
+        let batch = PendingRecord::new(&batch_record(records)?, self.config.compression);
+        batch.check_len(self.config.max_record_len)?;
+        self.commit(batch, sync)
+    }
 
-        match self.sync {
-            SyncPolicy::Always => {
-                // `flush()` only moves the writes from the buffer to the kernel
-                // i.e user_space -> kernel_space.
-                self.buf.flush().map_err(|e| DBError::Io {
-                    op: "wal: failed to flush wal buf",
-                    path: self.pathBuf.clone(),
+    fn commit(&self, record: PendingRecord, sync: bool) -> Result<WalPosition, DBError> {
+        let mut group = self.lock_group();
+        group.pending.push(record);
+        group.pending_sync |= sync;
+        group.last_ticket += 1;
+        let ticket = group.last_ticket;
+
+        loop {
+            // Written by an earlier group, even if a group after it failed since
+            if let Some(position) = group.written.remove(&ticket) {
+                return Ok(position);
+            }
+            if group.failed {
+                return Err(DBError::WAL {
+                    what: "wal: an earlier write failed, the wal no longer accepts appends",
+                    err: None,
+                });
+            }
+            if !group.leading {
+                break;
+            }
+            group = self
+                .committed
+                .wait(group)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        // Lead the group: everything queued so far, our own record included. Records queued while we write
+        // are left to the next leader
+        group.leading = true;
+        let records = std::mem::take(&mut group.pending);
+        let sync = std::mem::take(&mut group.pending_sync);
+        let last_ticket = group.last_ticket;
+        drop(group);
+
+        let result = self.write_group(&records, sync);
+
+        let mut group = self.lock_group();
+        group.leading = false;
+        group.groups += 1;
+        let result = match result {
+            Ok(positions) => {
+                // The group took every ticket from the one after the previous group's last
+                let first_ticket = last_ticket + 1 - positions.len() as u64;
+                let position = positions[(ticket - first_ticket) as usize];
+                let others = (first_ticket..)
+                    .zip(positions)
+                    .filter(|&(other, _)| other != ticket);
+                group.written.extend(others);
+                Ok(position)
+            }
+            Err(e) => {
+                group.failed = true;
+                Err(e)
+            }
+        };
+        self.committed.notify_all();
+
+        result
+    }
+
+    /// Writes `records`, returning where each of them starts.
+    fn write_group(
+        &self,
+        records: &[PendingRecord],
+        sync: bool,
+    ) -> Result<Vec<WalPosition>, DBError> {
+        let mut segment = self.lock_segment();
+        if let Some(e) = segment.flusher_error.take() {
+            return Err(e);
+        }
+
+        let checksum = self.config.checksum;
+        let mut positions = Vec::with_capacity(records.len());
+        for record in records {
+            // A record is never split, one larger than a whole segment gets a segment of its own
+            let header_len =
+                SEGMENT_HEADER_LEN + segment.nonce.map_or(0, |_| ENCRYPTION_HEADER_LEN);
+            let mut encoded = record.encode(segment.last_seq_no, segment.segment_no, checksum);
+            if segment.len > header_len
+                && segment.len + encoded.0.len() as u64 > self.config.segment_size
+            {
+                self.rotate(&mut segment)?;
+                encoded = record.encode(segment.last_seq_no, segment.segment_no, checksum);
+            }
+            let (mut encode, len_prefix) = encoded;
+            segment.last_seq_no = record.seq_no;
+
+            // The len is left readable, a reader needs it to find where the record ends
+            if let (Some(encryptor), Some(nonce)) = (&self.config.encryptor, &segment.nonce) {
+                encryptor.encrypt(
+                    nonce,
+                    segment.len + len_prefix as u64,
+                    &mut encode[len_prefix..],
+                );
+            }
+
+            segment
+                .buf
+                .write_all(encode.as_ref())
+                .map_err(|e| DBError::Io {
+                    op: "wal: failed to write wal buf",
+                    path: segment.path.clone(),
                     source: e,
                 })?;
+            positions.push(WalPosition {
+                segment_no: segment.segment_no,
+                offset: segment.len,
+            });
+            segment.len += encode.len() as u64;
+            segment.unsynced += 1;
+        }
+
+        match self.config.sync {
+            _ if sync => segment.sync()?,
+            SyncPolicy::Always => segment.sync()?,
+            SyncPolicy::EveryN(n) if segment.unsynced >= u64::from(n) => segment.sync()?,
+            SyncPolicy::EveryN(_) | SyncPolicy::Interval(_) | SyncPolicy::Never => {}
+        };
+        let bytes_per_sync = self.config.bytes_per_sync;
+        if bytes_per_sync > 0 && segment.len - segment.synced_len >= bytes_per_sync {
+            segment.sync_range()?;
+        }
+
+        Ok(positions)
+    }
+
+    /// Syncs every record appended so far whatever the `SyncPolicy`, returning the `durable_position` it
+    /// got the WAL to.
+    pub fn sync(&self) -> Result<WalPosition, DBError> {
+        let mut segment = self.lock_segment();
+        if let Some(e) = segment.flusher_error.take() {
+            return Err(e);
+        }
+        segment.sync()?;
+        Ok(segment.durable_position())
+    }
 
-                // If something happens to the kernel e.g. power outage, the writes might not have been
-                // synced to the file system, so we need to call `sync_all` for that.
-                self.buf.get_ref().sync_all().map_err(|e| DBError::Io {
-                    op: "wal: failed to sync_all",
-                    path: self.pathBuf.clone(),
+    /// How far the WAL is known to survive a crash: every record before this position has been synced.
+    /// Syncs always cover whole records, and a segment is synced in full before the next one is started.
+    pub fn durable_position(&self) -> WalPosition {
+        self.lock_segment().durable_position()
+    }
+
+    /// Drops every record in the WAL. Only to be called once they have all been persisted elsewhere, i.e.
+    /// the MemTable they were replayed into has been flushed and the flush recorded in the manifest, so the
+    /// WAL doesn't grow forever and replay only has the writes since the last flush to go through.
+    ///
+    /// A new segment is started and every older one archived, or else removed or kept for recycling while
+    /// there is room.
+    pub fn truncate(&self) -> Result<(), DBError> {
+        let segment_no = self.start_segment()?;
+        self.truncate_before(segment_no)
+    }
+
+    /// Syncs the segment being appended to and starts the next one, returning its number. Every record
+    /// appended so far is in the segments before it.
+    pub fn start_segment(&self) -> Result<u64, DBError> {
+        let mut segment = self.lock_segment();
+        self.rotate(&mut segment)?;
+        Ok(segment.segment_no)
+    }
+
+    /// `truncate`, but only for the segments numbered below `segment_no`, e.g. once the MemTable frozen when
+    /// `segment_no` was started has been flushed while appends went on in the newer segments. The segment
+    /// being appended to is always kept, and so are the segments still held for writes not flushed yet,
+    /// see `hold`.
+    pub fn truncate_before(&self, segment_no: u64) -> Result<(), DBError> {
+        let segment = self.lock_segment();
+        let segment_no = segment_no
+            .min(segment.segment_no)
+            .min(self.held_from().unwrap_or(u64::MAX));
+
+        if let Some(archive) = &self.config.archive {
+            let archive_dir = self.dir.join(ARCHIVE_DIR);
+            let mut archived = None;
+            for (old_segment_no, path) in segments(&self.dir)? {
+                if old_segment_no >= segment_no {
+                    continue;
+                }
+                let archived_path = archive_dir.join(segment_file_name(old_segment_no));
+                std::fs::rename(&path, &archived_path).map_err(|e| DBError::Io {
+                    op: "wal: failed to archive segment",
+                    path,
                     source: e,
                 })?;
+                archived = Some(archived_path);
             }
-            SyncPolicy::Never => {}
-        };
+            // Both directories changed
+            sync_parent_dir(&segment.path)?;
+            if let Some(archived_path) = archived {
+                sync_parent_dir(&archived_path)?;
+            }
+            return prune_archive(&archive_dir, archive);
+        }
+
+        let mut recycled = self.lock_recycled();
+        for (old_segment_no, path) in segments(&self.dir)? {
+            if old_segment_no >= segment_no {
+                continue;
+            }
+            if recycled.len() >= self.config.recycle_files {
+                remove_file(path)?;
+                continue;
+            }
+
+            let recycled_path = self.dir.join(recycled_file_name(old_segment_no));
+            std::fs::rename(&path, &recycled_path).map_err(|e| DBError::Io {
+                op: "wal: failed to rename segment for recycling",
+                path,
+                source: e,
+            })?;
+            recycled.push(recycled_path);
+        }
+        sync_parent_dir(&segment.path)?;
 
         Ok(())
     }
-    
-    /// Loads all the contents of the WAL file into the `mem_table`. Prefer this over `read_all`
-    /// during DB reload as it will load files at best effort, if it encounters corruption, at least
-    /// the memtable will contain the requisite records.
-    pub fn replay_into(&mut self, wal_file: File, mem_table: &mut MemTable) -> Result<(), DBError> {
-        let mut buf = Vec::new();
-        let mut buf_reader = BufReader::new(wal_file);
-        
-        let num_bytes  = buf_reader.read_to_end(&mut buf)
-            .map_err(|e| {
-                DBError::Io { op: "failed to read_to_end", path: PathBuf::new(),  source: e }
-            })?;
-        
+
+    /// The number of the segment being appended to.
+    pub fn segment_no(&self) -> u64 {
+        self.lock_segment().segment_no
+    }
+
+    /// Keeps the segment being appended to and every later one from being truncated, for the writes
+    /// `owner` is about to append, until the MemTable they go to is flushed, see `freeze_hold` and
+    /// `release_frozen`. Only the first write to a MemTable takes the hold, the others find it taken.
+    ///
+    /// A DB holds its own writes as owner 0, and a column family sharing the WAL of its DB holds its
+    /// writes with the id its records are tagged with, see `column_family_record`. A segment is then only
+    /// truncated once every column family flushed its writes in it.
+    pub(crate) fn hold(&self, owner: u32) {
+        let held = |holds: &HashMap<u32, Hold>| {
+            holds.get(&owner).is_some_and(|hold| hold.active.is_some())
+        };
+        if held(&self.lock_holds()) {
+            return;
+        }
+        // No truncation goes on until the hold is taken, and the write lands in this segment or a later one
+        let segment = self.lock_segment();
+        self.hold_from(owner, segment.segment_no);
+    }
+
+    /// `hold` for writes in the segments from `segment_no` on already, e.g. those replayed into a MemTable.
+    pub(crate) fn hold_from(&self, owner: u32, segment_no: u64) {
+        let mut holds = self.lock_holds();
+        let active = &mut holds.entry(owner).or_default().active;
+        *active = Some(active.map_or(segment_no, |held| held.min(segment_no)));
+    }
+
+    /// Moves the hold `owner` took for its MemTable over to it once frozen for flushing, writes to the
+    /// next MemTable take a hold of their own.
+    pub(crate) fn freeze_hold(&self, owner: u32) {
+        let mut holds = self.lock_holds();
+        let hold = holds.entry(owner).or_default();
+        hold.frozen = hold.active.take();
+    }
+
+    /// Releases the hold `owner` took for its MemTable, e.g. after a replay that brought nothing back.
+    pub(crate) fn release_hold(&self, owner: u32) {
+        if let Some(hold) = self.lock_holds().get_mut(&owner) {
+            hold.active = None;
+        }
+    }
+
+    /// Releases the hold of the MemTable `owner` froze, once it has been flushed.
+    pub(crate) fn release_frozen(&self, owner: u32) {
+        if let Some(hold) = self.lock_holds().get_mut(&owner) {
+            hold.frozen = None;
+        }
+    }
+
+    /// The oldest segment any owner holds, if any.
+    fn held_from(&self) -> Option<u64> {
+        self.lock_holds()
+            .values()
+            .flat_map(|hold| hold.active.into_iter().chain(hold.frozen))
+            .min()
+    }
+
+    /// Loads all the contents of the WAL segments into the `mem_table`, `Op::Delete` records as tombstones,
+    /// and the range tombstones of `Op::DeleteRange` records into `range_tombstones`, oldest segment first.
+    /// Prefer this over `read_all` during DB reload as it will load files at best effort, if it encounters
+    /// corruption, at least the memtable will contain the requisite records.
+    ///
+    /// Records below `flushed_seq_no` are skipped, they made it into a table before the WAL could be
+    /// truncated. Segments are read through a `RecordReader`, so only one record is held in memory at a
+    /// time however large the WAL has grown. `on_progress` is called every `REPLAY_PROGRESS_INTERVAL` bytes
+    /// and once every segment has been replayed.
+    ///
+    /// A segment may end in a record torn by a crash: cut short, or failing its crc with nothing but zeros
+    /// after it. The segment is truncated back to its last whole record before anything else is done with
+    /// the WAL, so the torn bytes can't be mistaken for damage later on. A bad record anywhere else is
+    /// corruption, and fails the replay.
+    ///
+    /// With `recycle_files` set, a segment may go on with the records of its previous use after its own.
+    /// Those are told apart by their `seq_no` being lower than the records before them, but the bytes
+    /// where the old records were cut are as good as garbage. So the first bad record ends a segment
+    /// there, which means damage in the middle of a segment goes unnoticed and the records after it are
+    /// lost.
+    ///
+    /// Returns a `ReplayReport` with the highest `seq_no` replayed and what it took to get there. A WAL
+    /// holding `Op::Merge` records fails the replay, see `replay_merging_into`.
+    pub fn replay_into(
+        &self,
+        flushed_seq_no: u64,
+        mem_table: &mut dyn MemTableRep,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        on_progress: impl FnMut(&ReplayProgress),
+    ) -> Result<ReplayReport, DBError> {
+        self.replay_merging_into(
+            flushed_seq_no,
+            mem_table,
+            range_tombstones,
+            None,
+            0,
+            on_progress,
+        )
+    }
+
+    /// `replay_into`, folding the operands of `Op::Merge` records into the MemTable with `merge_operator`
+    /// as `DB::merge` does, as of `now` (milliseconds since the unix epoch). A WAL holding
+    /// `Op::ColumnFamily` records fails the replay, those are for the DB to route, see `DB::write`.
+    pub fn replay_merging_into(
+        &self,
+        flushed_seq_no: u64,
+        mem_table: &mut dyn MemTableRep,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        merge_operator: Option<&dyn MergeOperator>,
+        now: u64,
+        on_progress: impl FnMut(&ReplayProgress),
+    ) -> Result<ReplayReport, DBError> {
+        self.replay_routing_into(
+            flushed_seq_no,
+            mem_table,
+            range_tombstones,
+            (merge_operator, now),
+            &mut |_, _| {
+                Err(DBError::WAL {
+                    what: "wal: column family records can only be replayed by their DB",
+                    err: None,
+                })
+            },
+            on_progress,
+        )
+    }
+
+    /// `replay_merging_into`, handing the records of every `Op::ColumnFamily` record to `route` along
+    /// with the id of their column family. Whether they were flushed already is up to `route` to tell, they
+    /// count in the column family's seq_nos. They are counted in the `ReplayProgress::records` but not in
+    /// the `ReplayReport::last_seq_no`.
+    pub(crate) fn replay_routing_into(
+        &self,
+        flushed_seq_no: u64,
+        mem_table: &mut dyn MemTableRep,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        (merge_operator, now): (Option<&dyn MergeOperator>, u64),
+        route: &mut dyn FnMut(u32, Vec<WALRecord>) -> Result<(), DBError>,
+        on_progress: impl FnMut(&ReplayProgress),
+    ) -> Result<ReplayReport, DBError> {
+        let segments = segments(&self.dir)?
+            .into_iter()
+            .map(|(segment_no, path)| {
+                let len = std::fs::metadata(&path)
+                    .map_err(|e| DBError::Io {
+                        op: "wal: failed to stat segment",
+                        path: path.clone(),
+                        source: e,
+                    })?
+                    .len();
+                Ok((segment_no, path, len))
+            })
+            .collect::<Result<Vec<_>, DBError>>()?;
+        let mut replay = Replay {
+            mem_table,
+            range_tombstones,
+            merge_operator,
+            now,
+            route,
+            flushed_seq_no,
+            last_seq_no: None,
+            progress: ReplayProgress {
+                segments: segments.len() as u64,
+                bytes: segments.iter().map(|(_, _, len)| len).sum(),
+                ..Default::default()
+            },
+            on_progress,
+        };
+
+        // The segment being appended to was started by this WAL, so it can't hold a torn record. Whatever
+        // follows its records is preallocated space or left from its previous use, and is kept for appends
+        let active = self.segment_no();
+        for (segment_no, path, len) in &segments {
+            let segments_before = replay.progress.bytes_replayed;
+            let truncate = *segment_no < active;
+            self.replay_segment(*segment_no, path, *len, truncate, &mut replay)?;
+
+            // A torn tail is skipped, the segment still counts as replayed in full
+            replay.progress.segments_replayed += 1;
+            replay.progress.bytes_replayed = segments_before + len;
+            (replay.on_progress)(&replay.progress);
+        }
+
+        Ok(ReplayReport {
+            progress: replay.progress,
+            last_seq_no: replay.last_seq_no,
+        })
+    }
+
+    fn replay_segment<F: FnMut(&ReplayProgress)>(
+        &self,
+        segment_no: u64,
+        path: &Path,
+        len: u64,
+        truncate: bool,
+        replay: &mut Replay<'_, F>,
+    ) -> Result<(), DBError> {
+        let wal_file = File::open(path).map_err(|e| DBError::Io {
+            op: "wal: failed to open segment",
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+
+        let recycling = self.config.recycle_files > 0;
+        let mut reader = open_reader(
+            wal_file,
+            self.config.max_record_len,
+            self.config.encryptor.clone(),
+        )
+        .and_then(|reader| reader.header.check_segment_no(segment_no).map(|()| reader))
+        .map_err(|e| DBError::WAL {
+            what: "failed reading segment header",
+            err: Some(Box::new(e)),
+        })?;
+        let (start, mut reported) = (replay.progress.bytes_replayed, 0);
+        // Where the segment's own records end
+        let mut end = reader.offset();
+
         // decode data and load into mem_table
-        let mut offset = 0;
-        while offset < num_bytes {
-            match decode_record(&buf, offset, self.max_record_len) {
-                Ok((record, new_offset)) => {
-                    put(mem_table, record.key, record.val, record.seq_no)?;
-                    offset = new_offset
-                },
+        loop {
+            let record = match reader.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
                 Err(e) => {
-                    match  e {
-                        WalDecodeError::CleanEOF => {
-                            break
-                        },
-                        _ => {
-                            return Err(DBError::WAL { what: "failed decoding record", err: Some(Box::new(e)) })
-                        }
+                    let torn = recycling
+                        || matches!(e, WalDecodeError::Corruption { .. })
+                            && reader.at_torn_tail().unwrap_or(false);
+                    if torn {
+                        break;
                     }
+                    return Err(DBError::WAL {
+                        what: "failed decoding record",
+                        err: Some(Box::new(e)),
+                    });
                 }
+            };
+
+            // A record older than the ones before it is left over from the segment's previous use
+            let next_seq_no = replay.last_seq_no.map_or(replay.flushed_seq_no, |seq_no| {
+                replay.flushed_seq_no.max(seq_no + 1)
+            });
+            if recycling && record.seq_no < next_seq_no {
+                break;
             }
-            
-        };
-        
+            end = reader.offset();
+
+            let records = match record.op {
+                Op::Batch => decode_batch(&record).map_err(|e| DBError::WAL {
+                    what: "failed decoding batch",
+                    err: Some(Box::new(e)),
+                })?,
+                _ => vec![record],
+            };
+            for record in records {
+                if record.op == Op::ColumnFamily {
+                    let (id, records) =
+                        decode_column_family(&record).map_err(|e| DBError::WAL {
+                            what: "failed decoding column family record",
+                            err: Some(Box::new(e)),
+                        })?;
+                    replay.progress.records += records.len() as u64;
+                    (replay.route)(id, records)?;
+                    continue;
+                }
+                if record.seq_no < replay.flushed_seq_no {
+                    replay.progress.flushed_records += 1;
+                    continue;
+                }
+                replay.last_seq_no = replay.last_seq_no.max(Some(record.seq_no));
+                apply_record(
+                    record,
+                    replay.mem_table,
+                    replay.range_tombstones,
+                    replay.merge_operator,
+                    replay.now,
+                )?;
+                replay.progress.records += 1;
+            }
+
+            replay.progress.bytes_replayed = start + end;
+            if end - reported >= REPLAY_PROGRESS_INTERVAL {
+                reported = end;
+                (replay.on_progress)(&replay.progress);
+            }
+        }
+
+        replay.progress.bytes_skipped += len - end;
+        if truncate && end < len {
+            truncate_segment(path, end)?;
+        }
+
         Ok(())
     }
 
-    pub fn read_all(&mut self, wal_file: File) -> Result<Vec<WALRecord>, WalDecodeError> {
-        let mut reader: BufReader<File> = BufReader::new(wal_file);
+    /// Syncs the segment being appended to and starts the next one.
+    fn rotate(&self, segment: &mut Segment) -> Result<(), DBError> {
+        segment.sync()?;
+        let flusher_error = segment.flusher_error.take();
+        let mut recycled = self.lock_recycled();
+        *segment = open_segment(
+            &self.dir,
+            segment.segment_no + 1,
+            &self.config,
+            &mut recycled,
+        )?;
+        segment.flusher_error = flusher_error;
+        Ok(())
+    }
 
-        // TODO: Perf - use try_reserve
-        let mut buf: Vec<u8>  = Vec::new();
+    fn lock_group(&self) -> MutexGuard<'_, GroupCommit> {
+        self.group.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        let num_bytes = reader.read_to_end(&mut buf)
-            .map_err(|e| {
-                WalDecodeError::Io { op: "failed to read_all", source: Some(e) }
-            })?;
+    fn lock_segment(&self) -> MutexGuard<'_, Segment> {
+        self.segment.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        if num_bytes == 0 {
-            return Ok(vec![])
-        }
+    fn lock_recycled(&self) -> MutexGuard<'_, Vec<PathBuf>> {
+        self.recycled.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_holds(&self) -> MutexGuard<'_, HashMap<u32, Hold>> {
+        self.holds.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn read_all(&self, wal_file: File) -> Result<Vec<WALRecord>, WalDecodeError> {
+        let mut reader = open_reader(
+            wal_file,
+            self.config.max_record_len,
+            self.config.encryptor.clone(),
+        )?;
 
-        // TODO: Perf - can we be smarter with the alloc here?
         let mut records: Vec<WALRecord> = Vec::new();
-        let mut offset = 0;
-        
-        while offset < num_bytes {
-            match decode_record(buf.as_ref(), offset, self.max_record_len) {
-                Ok((rec, new_offset)) =>  {
-                    if new_offset <= offset {
-                        return Err(WalDecodeError::Corruption { what: "decoder made no progress", offset: Some(offset as u32) })
-                    }
-                    
-                    offset = new_offset;
-                    records.push(rec)
-                }
-                Err(WalDecodeError::CleanEOF) => {
-                    // Expected after crash
-                    break;
-                }
-                Err(WalDecodeError::Corruption { what: _, offset }) => {
+        loop {
+            match reader.next_record() {
+                Ok(Some(rec)) if rec.op == Op::Batch => match decode_batch(&rec) {
+                    Ok(batch) => records.extend(batch),
+                    Err(_) => break,
+                },
+                Ok(Some(rec)) => records.push(rec),
+                // Expected after crash
+                Ok(None) => break,
+                Err(WalDecodeError::Corruption { .. }) => {
                     // Stop at last good record
                     break;
                 }
-                Err(e @ WalDecodeError::Io { .. }) => return Err(e)
+                Err(e) => return Err(e),
             }
         }
 
@@ -159,12 +921,442 @@ impl WAL {
     }
 }
 
+impl Drop for WAL {
+    fn drop(&mut self) {
+        // Hanging up has the flusher sync what is left and exit
+        if let Some((sender, handle)) = self.flusher.take() {
+            drop(sender);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The file name of WAL segment `segment_no`, e.g. `000042.wal`.
+pub fn segment_file_name(segment_no: u64) -> String {
+    format!("{segment_no:06}.wal")
+}
+
+/// The segment number of a file named by `segment_file_name`, `None` for any other file.
+pub fn parse_segment_file_name(path: &Path) -> Option<u64> {
+    parse_file_name(path, ".wal")
+}
+
+/// The file name segment `segment_no` is kept under while waiting to be recycled, e.g. `000042.recycle`.
+pub fn recycled_file_name(segment_no: u64) -> String {
+    format!("{segment_no:06}.recycle")
+}
+
+fn parse_file_name(path: &Path, suffix: &str) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    let segment_no = name.strip_suffix(suffix)?;
+    if segment_no.is_empty() || !segment_no.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    segment_no.parse().ok()
+}
+
+/// Every segment in `dir` waiting to be recycled, oldest first.
+fn recycled_files(dir: &Path) -> Result<Vec<PathBuf>, DBError> {
+    let mut recycled = files(dir, ".recycle")?;
+    recycled.sort_unstable();
+    Ok(recycled.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Every segment in `dir`, ordered by segment number.
+fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>, DBError> {
+    let mut segments = files(dir, ".wal")?;
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// The files in `dir` named after a segment number followed by `suffix`.
+fn files(dir: &Path, suffix: &str) -> Result<Vec<(u64, PathBuf)>, DBError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| DBError::Io {
+        op: "wal: failed to read dir",
+        path: dir.to_path_buf(),
+        source: e,
+    })?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| DBError::Io {
+                op: "wal: failed to read dir entry",
+                path: dir.to_path_buf(),
+                source: e,
+            })?
+            .path();
+        if let Some(segment_no) = parse_file_name(&path, suffix) {
+            files.push((segment_no, path));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Starts segment `segment_no` in `dir`, reusing the oldest of the `recycled` files if there is one. The
+/// segment's directory entry is made durable before any record relies on it.
+fn open_segment(
+    dir: &Path,
+    segment_no: u64,
+    config: &WALConfig,
+    recycled: &mut Vec<PathBuf>,
+) -> Result<Segment, DBError> {
+    let path = dir.join(segment_file_name(segment_no));
+
+    let recycle = (!recycled.is_empty()).then(|| recycled.remove(0));
+    if let Some(recycled_path) = &recycle {
+        std::fs::rename(recycled_path, &path).map_err(|e| DBError::Io {
+            op: "wal: failed to recycle segment",
+            path: recycled_path.clone(),
+            source: e,
+        })?;
+    }
+
+    // Records are written from the start of the file, over whatever a recycled file or preallocation left
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| DBError::Io {
+            op: "wal: failed to create segment",
+            path: path.clone(),
+            source: e,
+        })?;
+
+    if recycle.is_none() && config.preallocate {
+        preallocate(&file, config.segment_size).map_err(|e| DBError::Io {
+            op: "wal: failed to preallocate segment",
+            path: path.clone(),
+            source: e,
+        })?;
+    }
+
+    // The headers have to be on disk before any record relying on them
+    let mut header = Vec::with_capacity((SEGMENT_HEADER_LEN + ENCRYPTION_HEADER_LEN) as usize);
+    header.extend_from_slice(&WAL_MAGIC.to_le_bytes());
+    header.extend_from_slice(&WAL_FORMAT_VERSION.to_le_bytes());
+    header.push(config.checksum as u8);
+    header.extend_from_slice(&segment_no.to_le_bytes());
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    header.extend_from_slice(&created_at.to_le_bytes());
+    let nonce = config.encryptor.as_ref().map(|_| new_nonce(segment_no));
+    if let (Some(encryptor), Some(nonce)) = (&config.encryptor, &nonce) {
+        let mut key_check = *KEY_CHECK;
+        encryptor.encrypt(nonce, SEGMENT_HEADER_LEN + NONCE_LEN as u64, &mut key_check);
+        header.extend_from_slice(nonce);
+        header.extend_from_slice(&key_check);
+    }
+    (&file)
+        .write_all(&header)
+        .and_then(|()| file.sync_data())
+        .map_err(|e| DBError::Io {
+            op: "wal: failed to write segment header",
+            path: path.clone(),
+            source: e,
+        })?;
+    sync_parent_dir(&path)?;
+
+    let len = header.len() as u64;
+    Ok(Segment {
+        buf: BufWriter::new(file),
+        segment_no,
+        path,
+        len,
+        nonce,
+        last_seq_no: 0,
+        unsynced: 0,
+        synced_len: len,
+        durable_len: len,
+        flusher_error: None,
+    })
+}
+
+/// A `RecordReader` over a segment file past its header, decrypting its records with `encryptor` if set.
+fn open_reader(
+    mut file: File,
+    max_record_len: u32,
+    encryptor: Option<Arc<dyn Encryptor>>,
+) -> Result<RecordReader<BufReader<File>>, WalDecodeError> {
+    let (offset, header) = read_segment_header(&file)?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| WalDecodeError::Io {
+            op: "failed to seek past segment header",
+            source: Some(e),
+        })?;
+
+    let mut reader = RecordReader {
+        offset,
+        header: header.unwrap_or(HEADERLESS),
+        ..RecordReader::new(BufReader::new(file), max_record_len)
+    };
+    if let Some(encryptor) = encryptor {
+        reader.read_key_check(encryptor)?;
+    }
+    Ok(reader)
+}
+
+/// Where the records of the segment `file` start and its header, see `SEGMENT_HEADER_LEN`. A segment
+/// without a header is read from its start as `HEADERLESS`, and one cut short before its header made it
+/// to disk is read as holding no records.
+fn read_segment_header(file: &File) -> Result<(u64, Option<SegmentHeader>), WalDecodeError> {
+    let mut header = [0; SEGMENT_HEADER_LEN as usize];
+    let mut filled = 0;
+    while filled < header.len() {
+        match read_at(file, &mut header[filled..], filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                return Err(WalDecodeError::Io {
+                    op: "failed to read segment header",
+                    source: Some(e),
+                });
+            }
+        }
+    }
+
+    if read_u64_le(&header[..filled]) != Some(WAL_MAGIC) {
+        return Ok((0, None));
+    }
+
+    let corruption = |what| WalDecodeError::Corruption {
+        what,
+        offset: Some(8),
+    };
+    let version = read_u32_le(&header[8..filled]);
+    let header_len = match version {
+        Some(1) => V1_SEGMENT_HEADER_LEN,
+        Some(2) => V2_SEGMENT_HEADER_LEN,
+        Some(WAL_FORMAT_VERSION) => SEGMENT_HEADER_LEN,
+        Some(_) => return Err(corruption("unsupported segment format version")),
+        None => return Ok((filled as u64, None)),
+    };
+    if (filled as u64) < header_len {
+        return Ok((filled as u64, None));
+    }
+
+    let field = |at: u64| {
+        if at < header_len {
+            read_u64_le(&header[at as usize..])
+        } else {
+            None
+        }
+    };
+    let header = SegmentHeader {
+        version: version.unwrap_or_default(),
+        checksum: ChecksumType::try_from(header[12]).map_err(corruption)?,
+        segment_no: field(V1_SEGMENT_HEADER_LEN).unwrap_or_default(),
+        created_at: field(V2_SEGMENT_HEADER_LEN).unwrap_or_default(),
+    };
+    Ok((header_len, Some(header)))
+}
+
+/// Removes the oldest segments in `archive_dir` until what is left is within the `archive` limits.
+fn prune_archive(archive_dir: &Path, archive: &WALArchiveConfig) -> Result<(), DBError> {
+    let mut archived = Vec::new();
+    for (_, path) in segments(archive_dir)? {
+        let meta = std::fs::metadata(&path).map_err(|e| DBError::Io {
+            op: "wal: failed to stat archived segment",
+            path: path.clone(),
+            source: e,
+        })?;
+        archived.push((path, meta.len(), meta.modified().ok()));
+    }
+
+    let now = SystemTime::now();
+    let mut size: u64 = archived.iter().map(|(_, len, _)| len).sum();
+    let mut removed = None;
+    for (path, len, modified) in archived {
+        let too_large = archive.max_size.is_some_and(|max_size| size > max_size);
+        let too_old = archive.max_age.is_some_and(|max_age| {
+            modified
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > max_age)
+        });
+        // Segments are archived in order, so the ones after this one are newer still
+        if !too_large && !too_old {
+            break;
+        }
+        remove_file(path.clone())?;
+        size -= len;
+        removed = Some(path);
+    }
+
+    match removed {
+        Some(path) => sync_parent_dir(&path),
+        None => Ok(()),
+    }
+}
+
+/// Starts the write-back of `len` bytes of `file` from `offset`.
+#[cfg(target_os = "linux")]
+fn start_write_back(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // `sync_file_range` takes 64-bit offsets on every Linux target, `off64_t` or a 64-bit `off_t` on musl
+    let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let flags = libc::SYNC_FILE_RANGE_WRITE;
+    // SAFETY: plain syscall wrapper on a descriptor we own, no memory is handed over
+    match unsafe { libc::sync_file_range(file.as_raw_fd(), offset, len, flags) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Elsewhere there is no way to start a write-back without waiting for it, so the bytes are left to the OS
+/// until the next sync.
+#[cfg(not(target_os = "linux"))]
+fn start_write_back(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+fn remove_file(path: PathBuf) -> Result<(), DBError> {
+    std::fs::remove_file(&path).map_err(|e| DBError::Io {
+        op: "wal: failed to remove obsolete segment",
+        path,
+        source: e,
+    })
+}
+
+/// Applies a replayed `record` to the MemTable, or to the range tombstones written alongside it. An
+/// `Op::Merge` record can only be applied with the `merge_operator` it was written for, and is folded in as
+/// of `now`, see `memtable::merge`.
+pub(crate) fn apply_record(
+    record: WALRecord,
+    mem_table: &mut dyn MemTableRep,
+    range_tombstones: &mut Vec<RangeTombstone>,
+    merge_operator: Option<&dyn MergeOperator>,
+    now: u64,
+) -> Result<(), DBError> {
+    match record.op {
+        Op::Put => put_with_timestamp(
+            mem_table,
+            record.key,
+            record.val,
+            record.seq_no,
+            record.timestamp,
+        )?,
+        Op::ExpiringPut => {
+            let expires_at = read_u64_le(&record.val).ok_or(DBError::WAL {
+                what: "wal: expiring put without an expiry",
+                err: None,
+            })?;
+            let val = record.val[8..].to_vec();
+            put_expiring(
+                mem_table,
+                record.key,
+                val,
+                record.seq_no,
+                record.timestamp,
+                expires_at,
+            )?;
+        }
+        Op::Delete => delete(mem_table, record.key, record.seq_no)?,
+        Op::DeleteRange => range_tombstones.push(RangeTombstone {
+            start: record.key,
+            end: record.val,
+            seq_no: record.seq_no,
+        }),
+        Op::Merge => {
+            let Some(merge_operator) = merge_operator else {
+                return Err(DBError::InvalidConfig {
+                    what: "merge records need a merge operator, see DBConfig::merge_operator",
+                });
+            };
+            memtable::merge(
+                mem_table,
+                range_tombstones,
+                (merge_operator, now),
+                record.key,
+                record.val,
+                record.seq_no,
+                record.timestamp,
+            )?;
+        }
+        Op::Batch => {
+            return Err(DBError::WAL {
+                what: "wal: batches cannot be nested",
+                err: None,
+            });
+        }
+        Op::ColumnFamily => {
+            return Err(DBError::WAL {
+                what: "wal: column family records can only be replayed by their DB",
+                err: None,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Cuts the segment at `path` back to its first `len` bytes, dropping a torn tail.
+fn truncate_segment(path: &Path, len: u64) -> Result<(), DBError> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| {
+            file.set_len(len)?;
+            file.sync_all()
+        })
+        .map_err(|e| DBError::Io {
+            op: "wal: failed to truncate torn segment",
+            path: path.to_path_buf(),
+            source: e,
+        })
+}
+
+/// Spawns the thread syncing `segment` every `interval` under `SyncPolicy::Interval`, and once more when
+/// hung up on. A failed sync is handed to the next append.
+fn spawn_flusher(
+    segment: Arc<Mutex<Segment>>,
+    interval: Duration,
+) -> Result<(Sender<()>, JoinHandle<()>), DBError> {
+    let (sender, receiver) = mpsc::channel();
+    let handle = std::thread::Builder::new()
+        .name(String::from("lsmdb-wal-flusher"))
+        .spawn(move || {
+            loop {
+                let hung_up = match receiver.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                };
+
+                let mut segment = segment.lock().unwrap_or_else(PoisonError::into_inner);
+                if segment.unsynced > 0
+                    && segment.flusher_error.is_none()
+                    && let Err(e) = segment.sync()
+                {
+                    segment.flusher_error = Some(e);
+                }
+                drop(segment);
+
+                if hung_up {
+                    break;
+                }
+            }
+        })
+        .map_err(|e| DBError::Io {
+            op: "wal: failed to spawn flusher",
+            path: Default::default(),
+            source: e,
+        })?;
+
+    Ok((sender, handle))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WALRecord {
     op: Op,
     seq_no: u64,
     key: Vec<u8>,
     val: Vec<u8>,
+    timestamp: Option<u64>,
 }
 
 impl WALRecord {
@@ -174,15 +1366,421 @@ impl WALRecord {
             seq_no,
             key,
             val,
+            timestamp: None,
+        }
+    }
+
+    /// The record of a value written at `timestamp`, in milliseconds since the UNIX epoch. Only kept for
+    /// an `Op::Put`, an `Op::ExpiringPut` or an `Op::Merge`, replay ignores it on any other record. See
+    /// `Entry::Value`.
+    pub fn with_timestamp(mut self, timestamp: Option<u64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn op(&self) -> &Op {
+        &self.op
+    }
+
+    pub fn seq_no(&self) -> u64 {
+        self.seq_no
+    }
+
+    /// The key written, or the start of the range for `Op::DeleteRange`.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The value written, empty for `Op::Delete` and the exclusive end of the range for `Op::DeleteRange`.
+    pub fn val(&self) -> &[u8] {
+        &self.val
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+}
+
+/// Progress of `WAL::replay_into`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReplayProgress {
+    pub segments_replayed: u64,
+    pub segments: u64,
+    pub bytes_replayed: u64,
+    // The combined size of every segment
+    pub bytes: u64,
+    // The records applied, those of a batch counted one by one, along with those handed to their column
+    // family, see `WAL::replay_routing_into`
+    pub records: u64,
+    // Records skipped for being below the flushed seq_no, they are in a table already
+    pub flushed_records: u64,
+    // The bytes past the last record of each segment: a torn record, preallocated space, or what is left
+    // of a recycled file's previous use. See `WAL::replay_into`
+    pub bytes_skipped: u64,
+}
+
+/// The outcome of `WAL::replay_into`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReplayReport {
+    // As last reported, once every segment was replayed
+    pub progress: ReplayProgress,
+    // The highest seq_no replayed, `None` for an empty WAL
+    pub last_seq_no: Option<u64>,
+}
+
+/// Decodes the records of a segment one at a time as they are read, so only the record being decoded is
+/// held in memory. A truncated final record ends the segment like a clean end of file.
+pub struct RecordReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    // The offset of the next record
+    offset: u64,
+    max_record_len: u32,
+    header: SegmentHeader,
+    // The seq_no of the last record decoded, which a varint encoded record's seq_no is relative to
+    last_seq_no: u64,
+    // Decrypts the records of an encrypted segment, keyed by the segment's nonce
+    cipher: Option<(Arc<dyn Encryptor>, [u8; NONCE_LEN])>,
+}
+
+impl<R: Read> RecordReader<R> {
+    /// A reader over records encoded with `encode_record` one after the other, as in a segment without a
+    /// header.
+    pub fn new(reader: R, max_record_len: u32) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            offset: 0,
+            max_record_len,
+            header: HEADERLESS,
+            last_seq_no: 0,
+            cipher: None,
+        }
+    }
+
+    /// A reader over records encrypted with `encryptor`, `WALConfig::encryptor`, reading their nonce first.
+    /// Fails if they were encrypted with another key.
+    pub fn encrypted(
+        reader: R,
+        max_record_len: u32,
+        encryptor: Arc<dyn Encryptor>,
+    ) -> Result<Self, WalDecodeError> {
+        let mut reader = Self::new(reader, max_record_len);
+        reader.read_key_check(encryptor)?;
+        Ok(reader)
+    }
+
+    /// Reads the nonce and the key check following the segment header.
+    fn read_key_check(&mut self, encryptor: Arc<dyn Encryptor>) -> Result<(), WalDecodeError> {
+        let (mut nonce, mut key_check) = ([0; NONCE_LEN], *KEY_CHECK);
+        // A segment cut short before its header made it to disk holds no records, and is read to its end
+        if read_full(&mut self.reader, &mut nonce)? == NONCE_LEN
+            && read_full(&mut self.reader, &mut key_check)? == KEY_CHECK.len()
+        {
+            encryptor.decrypt(&nonce, self.offset + NONCE_LEN as u64, &mut key_check);
+            if &key_check != KEY_CHECK {
+                return Err(WalDecodeError::Corruption {
+                    what: "segment was encrypted with another key",
+                    offset: Some((self.offset + NONCE_LEN as u64) as u32),
+                });
+            }
+            self.offset += ENCRYPTION_HEADER_LEN;
+        }
+
+        self.cipher = Some((encryptor, nonce));
+        Ok(())
+    }
+
+    /// The offset in the segment of the next record, i.e. the number of bytes taken up by the headers of
+    /// the segment and the records decoded so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether nothing but zeros follows the record `next_record` just failed to decode, i.e. it is the
+    /// final record and was torn by a crash rather than damaged. Reads the rest of the segment.
+    pub fn at_torn_tail(&mut self) -> Result<bool, WalDecodeError> {
+        let mut chunk = [0; 4096];
+        loop {
+            let n = read_full(&mut self.reader, &mut chunk)?;
+            if chunk[..n].iter().any(|&b| b != 0) {
+                return Ok(false);
+            }
+            if n < chunk.len() {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Decodes the next record, `None` once the segment has run out, see `decode_record`.
+    pub fn next_record(&mut self) -> Result<Option<WALRecord>, WalDecodeError> {
+        let offset = self.offset;
+        let corruption = move |what| WalDecodeError::Corruption {
+            what,
+            offset: Some(offset as u32),
+        };
+
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        if len == 0 || len > self.max_record_len {
+            return Err(corruption("invalid len"));
+        }
+
+        let prefix = self.buf.len();
+        self.buf.resize(prefix + len as usize, 0);
+        if read_full(&mut self.reader, &mut self.buf[prefix..])? < len as usize {
+            // the tail has likely been truncated
+            return Ok(None);
+        }
+        if let Some((encryptor, nonce)) = &self.cipher {
+            encryptor.decrypt(nonce, self.offset + prefix as u64, &mut self.buf[prefix..]);
+        }
+
+        let decoded = match self.header.version {
+            1 => decode_record_with(&self.buf, 0, self.max_record_len, self.header.checksum)
+                .map(|(record, _)| record),
+            _ => decode_varint_record(&self.buf[prefix..], self.last_seq_no, &self.header),
+        };
+        let record = decoded.map_err(|e| match e {
+            WalDecodeError::Corruption { what, .. } => corruption(what),
+            e => e,
+        })?;
+        self.offset += self.buf.len() as u64;
+        self.last_seq_no = record.seq_no;
+
+        Ok(Some(record))
+    }
+
+    /// Reads the len prefix of the next record into `buf`, a `[len u32]` or in a version 2 segment a
+    /// `[len varint]`. `None` if the segment ends before it does.
+    fn read_len(&mut self) -> Result<Option<u32>, WalDecodeError> {
+        if self.header.version == 1 {
+            self.buf.resize(4, 0);
+            if read_full(&mut self.reader, &mut self.buf)? < 4 {
+                return Ok(None);
+            }
+            return Ok(read_u32_le(&self.buf));
+        }
+
+        self.buf.clear();
+        loop {
+            let mut byte = [0];
+            if read_full(&mut self.reader, &mut byte)? == 0 {
+                return Ok(None);
+            }
+            self.buf.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            if self.buf.len() == MAX_VARINT32_LEN {
+                return Err(WalDecodeError::Corruption {
+                    what: "invalid len",
+                    offset: Some(self.offset as u32),
+                });
+            }
+        }
+        // Anything above u32::MAX is over any max_record_len
+        Ok(decode_varint(&self.buf).map(|(len, _)| len.try_into().unwrap_or(u32::MAX)))
+    }
+}
+
+/// The header every segment starts with, telling how its records are encoded and where the segment came
+/// from. A header naming a segment other than the file it is found in fails replay, as the file was not
+/// written as the segment it is named as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentHeader {
+    pub version: u32,
+    pub checksum: ChecksumType,
+    // Also mixed into the checksum of every varint encoded record, see `encode_varint_record`. 0 in a
+    // version 1 header
+    pub segment_no: u64,
+    // When the segment was started, in seconds since the UNIX epoch. 0 before version 3
+    pub created_at: u64,
+}
+
+// What a segment without a header, written before there were any, is read as
+const HEADERLESS: SegmentHeader = SegmentHeader {
+    version: 1,
+    checksum: ChecksumType::Crc32,
+    segment_no: 0,
+    created_at: 0,
+};
+
+impl SegmentHeader {
+    /// Reads the header of the segment file at `path`, `None` for a segment without one.
+    pub fn read(path: impl AsRef<Path>) -> Result<Option<Self>, DBError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| DBError::Io {
+            op: "wal: failed to open segment",
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        let (_, header) = read_segment_header(&file).map_err(|e| DBError::WAL {
+            what: "wal: failed reading segment header",
+            err: Some(Box::new(e)),
+        })?;
+        Ok(header)
+    }
+
+    /// Fails if the header doesn't belong to segment `segment_no`. Version 1 headers lack the segment
+    /// number and belong anywhere.
+    fn check_segment_no(&self, segment_no: u64) -> Result<(), WalDecodeError> {
+        if self.version > 1 && self.segment_no != segment_no {
+            return Err(WalDecodeError::Corruption {
+                what: "segment header names another segment",
+                offset: Some(V1_SEGMENT_HEADER_LEN as u32),
+            });
         }
+        Ok(())
     }
 }
 
+/// Where a record sits in the WAL: the segment holding it, and the offset of its first byte within the
+/// segment. Also how far the WAL has been synced, see `WAL::durable_position`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WalPosition {
+    // 0 for a file not named by `segment_file_name`
+    pub segment_no: u64,
+    pub offset: u64,
+}
+
+/// Reads the records of a WAL in the order they were appended, each with its `WalPosition`, for tools
+/// consuming the log outside the DB such as replication shippers or debuggers. `Op::Batch` records are
+/// handed out as they are, see `decode_batch`.
+///
+/// A segment ends at its first torn record just like on replay, see `WAL::replay_into`. Any other bad
+/// record is returned as an error, after which the reader is done.
+pub struct WalReader {
+    // The segments not started on yet, oldest last
+    segments: Vec<PathBuf>,
+    current: Option<(u64, RecordReader<BufReader<File>>)>,
+    max_record_len: u32,
+    encryptor: Option<Arc<dyn Encryptor>>,
+    done: bool,
+}
+
+impl WalReader {
+    /// Opens the WAL at `path`, either a directory of segments or a single segment file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DBError> {
+        let path = path.as_ref();
+        let mut segments: Vec<_> = if path.is_dir() {
+            segments(path)?.into_iter().map(|(_, path)| path).collect()
+        } else {
+            vec![path.to_path_buf()]
+        };
+        segments.reverse();
+
+        Ok(Self {
+            segments,
+            current: None,
+            max_record_len: DEFAULT_MAX_RECORD_LEN,
+            encryptor: None,
+            done: false,
+        })
+    }
+
+    /// Records longer than `max_record_len` are taken for corruption, it should match the
+    /// `WALConfig::max_record_len` the WAL was written with.
+    pub fn max_record_len(mut self, max_record_len: u32) -> Self {
+        self.max_record_len = max_record_len;
+        self
+    }
+
+    /// Decrypts the records with `encryptor`, for a WAL written with `WALConfig::encryptor` set.
+    pub fn encryptor(mut self, encryptor: Arc<dyn Encryptor>) -> Self {
+        self.encryptor = Some(encryptor);
+        self
+    }
+
+    fn next_record(&mut self) -> Result<Option<(WalPosition, WALRecord)>, WalDecodeError> {
+        loop {
+            let (segment_no, mut reader) = match self.current.take() {
+                Some(current) => current,
+                None => {
+                    let Some(path) = self.segments.pop() else {
+                        return Ok(None);
+                    };
+                    let file = File::open(&path).map_err(|e| WalDecodeError::Io {
+                        op: "failed to open segment",
+                        source: Some(e),
+                    })?;
+                    let reader = open_reader(file, self.max_record_len, self.encryptor.clone())?;
+                    // A file named as no segment in particular could be any of them
+                    let segment_no = parse_segment_file_name(&path);
+                    if let Some(segment_no) = segment_no {
+                        reader.header.check_segment_no(segment_no)?;
+                    }
+                    (segment_no.unwrap_or(0), reader)
+                }
+            };
+
+            let position = WalPosition {
+                segment_no,
+                offset: reader.offset(),
+            };
+            match reader.next_record() {
+                Ok(Some(record)) => {
+                    self.current = Some((segment_no, reader));
+                    return Ok(Some((position, record)));
+                }
+                // On to the next segment
+                Ok(None) => {}
+                Err(e @ WalDecodeError::Corruption { .. }) => {
+                    if !reader.at_torn_tail()? {
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Iterator for WalReader {
+    type Item = Result<(WalPosition, WALRecord), WalDecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let next = self.next_record().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+/// Fills `buf` from `reader`, returning how many bytes it got before the end of the file.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, WalDecodeError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                return Err(WalDecodeError::Io {
+                    op: "failed to read record",
+                    source: Some(e),
+                });
+            }
+        }
+    }
+    Ok(filled)
+}
+
 #[derive(Debug)]
 pub enum WalDecodeError {
     CleanEOF,
-    Io {op: &'static str, source: Option<io::Error>},
-    Corruption {what: &'static str, offset: Option<u32> },
+    Io {
+        op: &'static str,
+        source: Option<io::Error>,
+    },
+    Corruption {
+        what: &'static str,
+        offset: Option<u32>,
+    },
 }
 
 impl std::error::Error for WalDecodeError {}
@@ -192,49 +1790,222 @@ impl Display for WalDecodeError {
         match self {
             WalDecodeError::CleanEOF => write!(f, "CleanEOF"),
             WalDecodeError::Io { op, source } => write!(f, "op: {op:?} - source: {source:?}"),
-            WalDecodeError::Corruption {what, offset} => write!(f, "{what:?} - offset: {offset:?}")
+            WalDecodeError::Corruption { what, offset } => {
+                write!(f, "{what:?} - offset: {offset:?}")
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[repr(u8)]
-pub enum Op {
-    Put = 1,
-    Delete = 2,
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    Put = 1,
+    Delete = 2,
+    // The key is the start of the range, the val its exclusive end
+    DeleteRange = 3,
+    // Several records applied as one, see `encode_batch`
+    Batch = 4,
+    // The val is a merge operand, see `DB::merge`
+    Merge = 5,
+    // A put whose val starts with the `u64` time it expires at, see `DB::put_with_ttl`
+    ExpiringPut = 6,
+    // The records of a column family sharing the WAL of its DB, see `column_family_record`
+    ColumnFamily = 7,
+}
+
+// Set in the `Op` bits of a record followed by the timestamp it was written at, see `encode_record`
+const RECORD_TIMESTAMP_FLAG: u8 = 0x08;
+
+/// The `op` byte of `rec` with its val compressed with `compression`.
+fn op_byte(rec: &WALRecord, compression: CompressionType) -> u8 {
+    let timestamp = if rec.timestamp.is_some() {
+        RECORD_TIMESTAMP_FLAG
+    } else {
+        0
+    };
+    ((compression as u8) << 4) | timestamp | rec.op.clone() as u8
+}
+
+impl TryFrom<u8> for Op {
+    type Error = WalDecodeError;
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        match val {
+            0x1 => Ok(Op::Put),
+            0x2 => Ok(Self::Delete),
+            0x3 => Ok(Self::DeleteRange),
+            0x4 => Ok(Self::Batch),
+            0x5 => Ok(Self::Merge),
+            0x6 => Ok(Self::ExpiringPut),
+            0x7 => Ok(Self::ColumnFamily),
+            _ => Err(WalDecodeError::Corruption {
+                what: "invalid op code found",
+                offset: None,
+            }),
+        }
+    }
+}
+
+/// Encodes `records` into a single `Op::Batch` record. Its key holds the number of records as a `u32`, and
+/// its val every record encoded with `encode_record` one after the other, so the batch's checksum covers
+/// them all and a torn batch is dropped as a whole. The batch takes the `seq_no` of its first record.
+///
+/// The records within a batch are always checksummed with crc32 whatever the segment's `ChecksumType`, so
+/// a batch decodes the same wherever it was read from.
+pub fn encode_batch(records: &[WALRecord]) -> Result<Vec<u8>, DBError> {
+    Ok(encode_record(&batch_record(records)?))
+}
+
+fn batch_record(records: &[WALRecord]) -> Result<WALRecord, DBError> {
+    if records.iter().any(|rec| rec.op == Op::Batch) {
+        return Err(DBError::WAL {
+            what: "wal: batches cannot be nested",
+            err: None,
+        });
+    }
+    group_record(Op::Batch, Vec::new(), records)
+}
+
+/// Encodes the `records` of the column family `id` into a single `Op::ColumnFamily` record, for a WAL the
+/// column families of a DB share. Its key holds `id` then the number of records, both as a `u32`, and its
+/// val the records as in `encode_batch`. It takes the `seq_no` of its first record, which counts in the
+/// column family's own seq_nos rather than those of the WAL's DB.
+///
+/// It may be part of a batch, so a batch holding the records of several column families commits them all
+/// or none.
+pub fn column_family_record(id: u32, records: &[WALRecord]) -> Result<WALRecord, DBError> {
+    if records
+        .iter()
+        .any(|rec| matches!(rec.op, Op::Batch | Op::ColumnFamily))
+    {
+        return Err(DBError::WAL {
+            what: "wal: column family records only hold writes",
+            err: None,
+        });
+    }
+    group_record(Op::ColumnFamily, id.to_le_bytes().to_vec(), records)
+}
+
+/// A record of `op` holding `records`, whose key is `prefix` followed by their number.
+fn group_record(op: Op, mut prefix: Vec<u8>, records: &[WALRecord]) -> Result<WALRecord, DBError> {
+    let Some(first) = records.first() else {
+        return Err(DBError::WAL {
+            what: "wal: a batch needs at least one record",
+            err: None,
+        });
+    };
+
+    let count: u32 = records.len().try_into().expect("batch too large");
+    prefix.extend_from_slice(&count.to_le_bytes());
+    let val = records.iter().flat_map(encode_record).collect();
+
+    Ok(WALRecord::new(op, first.seq_no, prefix, val))
+}
+
+/// Decodes the records of an `Op::Batch` record, see `encode_batch`.
+pub fn decode_batch(batch: &WALRecord) -> Result<Vec<WALRecord>, WalDecodeError> {
+    decode_group(&batch.key, &batch.val, |op| *op == Op::Batch)
 }
 
-impl TryFrom<u8> for Op {
-    type Error = WalDecodeError;
-    fn try_from(val: u8) -> Result<Self, Self::Error> {
-        match val {
-            0x1 => Ok(Op::Put),
-            0x2 => Ok(Self::Delete),
-            _ => Err(WalDecodeError::Corruption{what: "invalid op code found", offset: None}),
+/// Decodes the id of the column family and the records of an `Op::ColumnFamily` record, see
+/// `column_family_record`.
+pub fn decode_column_family(record: &WALRecord) -> Result<(u32, Vec<WALRecord>), WalDecodeError> {
+    let id = read_u32_le(&record.key).ok_or(WalDecodeError::Corruption {
+        what: "column family id missing",
+        offset: None,
+    })?;
+    let records = decode_group(&record.key[4..], &record.val, |op| {
+        matches!(op, Op::Batch | Op::ColumnFamily)
+    })?;
+    Ok((id, records))
+}
+
+/// Decodes the records of a record made by `group_record`, from its key past the prefix and its val.
+/// `nested` tells the ops it can't hold.
+fn decode_group(
+    count: &[u8],
+    val: &[u8],
+    nested: impl Fn(&Op) -> bool,
+) -> Result<Vec<WALRecord>, WalDecodeError> {
+    let corruption = |what| WalDecodeError::Corruption { what, offset: None };
+    let count = read_u32_le(count).ok_or(corruption("batch count missing"))? as usize;
+
+    let mut records = Vec::with_capacity(count);
+    let mut offset = 0;
+    while offset < val.len() {
+        let (rec, next) = decode_record(val, offset, u32::MAX)?;
+        if nested(&rec.op) {
+            return Err(corruption("nested batch"));
         }
+        records.push(rec);
+        offset = next;
+    }
+
+    if records.len() != count {
+        return Err(corruption("batch count mismatch"));
     }
+    Ok(records)
 }
 
 /// Attempts to encode to a `Vec<u8>` from the WAL record with some extra information e.g. key and val lengths.
 /// Below is a map of the encoding:
 ///
-/// [op u8][seq u64][key_len u32][val_len u32][key bytes][val bytes]
+/// [op u8][seq u64][key_len u32][val_len u32][timestamp u64]?[key bytes][val bytes]
 ///
 /// The above structure is maintained regardless of whether the `op` i.e operation is a `DEL` or
+/// `PUT`. The low 3 bits of the `op` byte hold the `Op`, the high 4 bits the `CompressionType` of the val
+/// bytes, see `encode_record_with`. The bit in between is set on a record with a timestamp, which is then
+/// stored right after the header. The record is checksummed with crc32.
 pub fn encode_record(rec: &WALRecord) -> Vec<u8> {
+    encode(rec, ChecksumType::Crc32, CompressionType::None, &rec.val)
+}
+
+/// `encode_record` checksummed with `checksum`, and with the val compressed with `compression`. Vals that
+/// compression doesn't shrink are stored as they are, the record reads back with `decode_record_with`
+/// given the same `checksum` either way.
+pub fn encode_record_with(
+    rec: &WALRecord,
+    checksum: ChecksumType,
+    compression: CompressionType,
+) -> Vec<u8> {
+    match compress_val(&rec.val, compression) {
+        Some(compressed) => encode(rec, checksum, compression, &compressed),
+        None => encode(rec, checksum, CompressionType::None, &rec.val),
+    }
+}
+
+/// `val` compressed with `compression`, `None` if it should be stored as it is.
+fn compress_val(val: &[u8], compression: CompressionType) -> Option<Vec<u8>> {
+    if compression == CompressionType::None {
+        return None;
+    }
+    compress(compression, val)
+        .ok()
+        .filter(|compressed| compressed.len() < val.len())
+}
+
+fn encode(
+    rec: &WALRecord,
+    checksum: ChecksumType,
+    compression: CompressionType,
+    val: &[u8],
+) -> Vec<u8> {
     let key_len_u32: u32 = rec.key.len().try_into().expect("key is too large");
-    let val_len_u32: u32 = rec.val.len().try_into().expect("val too large");
+    let val_len_u32: u32 = val.len().try_into().expect("val too large");
 
-    let mut body = Vec::with_capacity(1 + 8 + 4 + 4 + rec.key.len() + rec.val.len());
+    let mut body = Vec::with_capacity(1 + 8 + 4 + 4 + 8 + rec.key.len() + val.len());
 
-    body.push(rec.op.clone() as u8);
+    body.push(op_byte(rec, compression));
     body.extend_from_slice(&rec.seq_no.to_le_bytes());
     body.extend_from_slice(&key_len_u32.to_le_bytes());
     body.extend_from_slice(&val_len_u32.to_le_bytes());
+    if let Some(timestamp) = rec.timestamp {
+        body.extend_from_slice(&timestamp.to_le_bytes());
+    }
     body.extend_from_slice(&rec.key);
-    body.extend_from_slice(&rec.val);
+    body.extend_from_slice(val);
 
-    let crc: u32 = crc32fast::hash(&body);
+    let crc: u32 = checksum.checksum(&body);
 
     // len = [body]+[crc]
     let len: u32 = (body.len() + 4).try_into().expect("record too large");
@@ -248,11 +2019,22 @@ pub fn encode_record(rec: &WALRecord) -> Vec<u8> {
     out
 }
 
-///
+/// Attempts to decode a single `WALRecord` from `buf` starting at `offset`, verifying the record's crc.
+/// On success the decoded record is returned alongside the offset of the next record.
 pub fn decode_record(
     buf: &[u8],
     offset: usize,
     max_record_len: u32,
+) -> Result<(WALRecord, usize), WalDecodeError> {
+    decode_record_with(buf, offset, max_record_len, ChecksumType::Crc32)
+}
+
+/// `decode_record` for a record checksummed with `checksum`, see `encode_record_with`.
+pub fn decode_record_with(
+    buf: &[u8],
+    offset: usize,
+    max_record_len: u32,
+    checksum: ChecksumType,
 ) -> Result<(WALRecord, usize), WalDecodeError> {
     //  start by obtaining the len
     if buf.len().saturating_sub(offset) < 4 {
@@ -262,10 +2044,13 @@ pub fn decode_record(
     // [len u32][op u8][seq u64][key_len u32][val_len u32][key bytes][val bytes]
     let len = read_u32_le(&buf[offset..]).ok_or(WalDecodeError::CleanEOF)?;
     if len == 0 || len > max_record_len {
-        return Err(WalDecodeError::Corruption{what: "invalid len", offset: Some(offset as u32) });
+        return Err(WalDecodeError::Corruption {
+            what: "invalid len",
+            offset: Some(offset as u32),
+        });
     }
 
-    let total = (4 as usize) + len as usize;
+    let total = 4 + len as usize;
     if buf.len().saturating_sub(offset) < total {
         // the tail has likely been truncated
         return Err(WalDecodeError::CleanEOF);
@@ -279,41 +2064,78 @@ pub fn decode_record(
 
     // If the start->end is less than at least the `rest_of_body+crc`, our record is too short;
     if rest_of_buf.len() < 4 {
-        return Err(WalDecodeError::Corruption{what: "record too short", offset: Some(offset as u32) });
+        return Err(WalDecodeError::Corruption {
+            what: "record too short",
+            offset: Some(offset as u32),
+        });
     }
 
     let body_len = rest_of_buf.len() - 4;
     let body = &rest_of_buf[..body_len];
     let crc_expecteed =
-        read_u32_le(&rest_of_buf[body_len..]).ok_or(WalDecodeError::Corruption{what: "missing crc", offset: Some(offset as u32) })?;
-    let crc_actual = crc32fast::hash(body);
+        read_u32_le(&rest_of_buf[body_len..]).ok_or(WalDecodeError::Corruption {
+            what: "missing crc",
+            offset: Some(offset as u32),
+        })?;
+    let crc_actual = checksum.checksum(body);
     if crc_actual != crc_expecteed {
-        return Err(WalDecodeError::Corruption{what: "crc mismatch", offset: Some(offset as u32) });
+        return Err(WalDecodeError::Corruption {
+            what: "crc mismatch",
+            offset: Some(offset as u32),
+        });
     }
 
     //  compute the crchash first to see bits are still valid
     //
     if body.len() < 1 + 8 + 4 + 4 {
-        return Err(WalDecodeError::Corruption{what: "body too short", offset: Some(offset as u32) });
+        return Err(WalDecodeError::Corruption {
+            what: "body too short",
+            offset: Some(offset as u32),
+        });
     }
 
     let op = body[0];
-    let seq_no = read_u64_le(&body[1..]).ok_or(WalDecodeError::Corruption{what: "bad seq", offset: Some(offset as u32) })?;
-    let key_len =
-        read_u32_le(&body[1 + 8..]).ok_or(WalDecodeError::Corruption{what: "bad seq", offset: Some(offset as u32) })? as usize;
-    let val_len =
-        read_u32_le(&body[1 + 8 + 4..]).ok_or(WalDecodeError::Corruption{what: "bad seq", offset: Some(offset as u32) })? as usize;
+    let seq_no = read_u64_le(&body[1..]).ok_or(WalDecodeError::Corruption {
+        what: "bad seq",
+        offset: Some(offset as u32),
+    })?;
+    let key_len = read_u32_le(&body[1 + 8..]).ok_or(WalDecodeError::Corruption {
+        what: "bad seq",
+        offset: Some(offset as u32),
+    })? as usize;
+    let val_len = read_u32_le(&body[1 + 8 + 4..]).ok_or(WalDecodeError::Corruption {
+        what: "bad seq",
+        offset: Some(offset as u32),
+    })? as usize;
 
     if key_len == 0 {
-        return Err(WalDecodeError::Corruption{what: "key_len is 0", offset: Some(offset as u32) })
+        return Err(WalDecodeError::Corruption {
+            what: "key_len is 0",
+            offset: Some(offset as u32),
+        });
     }
 
+    // A timestamp sits between the header and the key
+    let mut payload_offset = 1 + 8 + 4 + 4;
+    let timestamp = if op & RECORD_TIMESTAMP_FLAG != 0 {
+        let timestamp = read_u64_le(&body[payload_offset..]).ok_or(WalDecodeError::Corruption {
+            what: "bad timestamp",
+            offset: Some(offset as u32),
+        })?;
+        payload_offset += 8;
+        Some(timestamp)
+    } else {
+        None
+    };
+
     // Now we grab the [key:?][body:?]
-    let payload_offset = 1 + 8 + 4 + 4;
     let expected_body_size = payload_offset + key_len + val_len;
 
     if expected_body_size != body.len() {
-        return Err(WalDecodeError::Corruption{what: "length mismatch - body len doesnt match what is described in payload metadata", offset: Some(offset as u32) });
+        return Err(WalDecodeError::Corruption {
+            what: "length mismatch - body len doesnt match what is described in payload metadata",
+            offset: Some(offset as u32),
+        });
     }
 
     // half-open i.e body[0..n)
@@ -323,31 +2145,256 @@ pub fn decode_record(
     let val_end = val_start + val_len;
 
     let key = body[key_start..key_end].to_vec();
-    let val = body[val_start..val_end].to_vec();
+    let compression =
+        CompressionType::try_from(op >> 4).map_err(|what| WalDecodeError::Corruption {
+            what,
+            offset: Some(offset as u32),
+        })?;
+    let val = decompress(compression, &body[val_start..val_end]).map_err(|what| {
+        WalDecodeError::Corruption {
+            what,
+            offset: Some(offset as u32),
+        }
+    })?;
 
     let rec = WALRecord {
-        op: Op::try_from(op)?,
+        op: Op::try_from(op & 0x0f & !RECORD_TIMESTAMP_FLAG)?,
         seq_no,
         key,
         val,
+        timestamp,
     };
 
     Ok((rec, end))
 }
 
-fn read_u32_le(input: &[u8]) -> Option<u32> {
-    let bitfield: [u8; 4] = input.get(0..4)?.try_into().ok()?;
-    Some(u32::from_le_bytes(bitfield))
+/// A record waiting for the leader of its group commit to write it, see `WAL`. Its val is compressed up
+/// front, the record is only encoded by the leader, which knows the record written before it.
+struct PendingRecord {
+    // The `Op`, the `CompressionType` of `val` and whether there is a timestamp, see `op_byte`
+    op: u8,
+    seq_no: u64,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    timestamp: Option<u64>,
+}
+
+impl PendingRecord {
+    fn new(rec: &WALRecord, compression: CompressionType) -> Self {
+        let (compression, val) = match compress_val(&rec.val, compression) {
+            Some(compressed) => (compression, compressed),
+            None => (CompressionType::None, rec.val.clone()),
+        };
+        Self {
+            op: op_byte(rec, compression),
+            seq_no: rec.seq_no,
+            key: rec.key.clone(),
+            val,
+            timestamp: rec.timestamp,
+        }
+    }
+
+    /// The longest `len` of the record once encoded, see `encode_varint_record`.
+    fn max_len(&self) -> usize {
+        1 + 2 * MAX_VARINT64_LEN + 2 * MAX_VARINT32_LEN + self.key.len() + self.val.len() + 4
+    }
+
+    /// Fails if the record could be longer than `max_record_len` once encoded. Its seq_no delta is yet to
+    /// be known, so a record within a few bytes of the limit may be turned away.
+    fn check_len(&self, max_record_len: u32) -> Result<(), DBError> {
+        let len = self.max_len();
+        if len > max_record_len as usize {
+            return Err(DBError::Codec {
+                context: format!(
+                    "wal: record of up to {len} bytes, max_record_len is {max_record_len}"
+                ),
+                source: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn encode(
+        &self,
+        last_seq_no: u64,
+        segment_no: u64,
+        checksum: ChecksumType,
+    ) -> (Vec<u8>, usize) {
+        encode_varint_record(self, last_seq_no, segment_no, checksum)
+    }
+}
+
+/// Encodes `rec` for a version 2 segment:
+///
+/// [len varint][op u8][seq_no_delta varint][key_len varint][val_len varint][timestamp varint]?[key bytes]
+/// [val bytes][checksum u32]
+///
+/// `len` counts the bytes after it, and the seq_no is stored as the zigzag encoded difference to
+/// `last_seq_no`, the seq_no of the record before it in the segment or 0 for the first one. A small record
+/// in a WAL appended to in seq_no order thus takes 9 bytes on top of its key and val rather than the 25 of
+/// `encode_record`. The timestamp is only there when flagged in `op`, as in `encode_record`.
+///
+/// The checksum covers the segment number followed by everything from `op` on: the records a recycled file
+/// holds from its previous use would otherwise decode as perfectly good records, their seq_nos relative to
+/// the new records before them.
+///
+/// Returns the record alongside the length of its len prefix.
+fn encode_varint_record(
+    rec: &PendingRecord,
+    last_seq_no: u64,
+    segment_no: u64,
+    checksum: ChecksumType,
+) -> (Vec<u8>, usize) {
+    let key_len: u32 = rec.key.len().try_into().expect("key is too large");
+    let val_len: u32 = rec.val.len().try_into().expect("val too large");
+    let delta = rec.seq_no.wrapping_sub(last_seq_no) as i64;
+
+    let mut body = Vec::with_capacity(rec.max_len());
+    body.push(rec.op);
+    encode_varint(&mut body, ((delta << 1) ^ (delta >> 63)) as u64);
+    encode_varint(&mut body, u64::from(key_len));
+    encode_varint(&mut body, u64::from(val_len));
+    if let Some(timestamp) = rec.timestamp {
+        encode_varint(&mut body, timestamp);
+    }
+    body.extend_from_slice(&rec.key);
+    body.extend_from_slice(&rec.val);
+
+    let mut hasher = checksum.hasher();
+    hasher.update(&segment_no.to_le_bytes());
+    hasher.update(&body);
+    let crc = hasher.finalize();
+
+    let len: u32 = (body.len() + 4).try_into().expect("record too large");
+    let mut out = Vec::with_capacity(MAX_VARINT32_LEN + body.len() + 4);
+    encode_varint(&mut out, u64::from(len));
+    let len_prefix = out.len();
+    out.extend_from_slice(&body);
+    out.extend_from_slice(&crc.to_le_bytes());
+
+    (out, len_prefix)
+}
+
+/// Decodes a record encoded with `encode_varint_record` from `buf`, the `len` bytes following its len
+/// prefix.
+fn decode_varint_record(
+    buf: &[u8],
+    last_seq_no: u64,
+    header: &SegmentHeader,
+) -> Result<WALRecord, WalDecodeError> {
+    let corruption = |what| WalDecodeError::Corruption { what, offset: None };
+
+    let Some(body_len) = buf.len().checked_sub(4) else {
+        return Err(corruption("record too short"));
+    };
+    let (body, crc) = buf.split_at(body_len);
+    let mut hasher = header.checksum.hasher();
+    hasher.update(&header.segment_no.to_le_bytes());
+    hasher.update(body);
+    if Some(hasher.finalize()) != read_u32_le(crc) {
+        return Err(corruption("crc mismatch"));
+    }
+
+    let (&op, mut rest) = body.split_first().ok_or(corruption("body too short"))?;
+    let mut next_varint = || {
+        let (val, len) = decode_varint(rest).ok_or(corruption("bad varint"))?;
+        rest = &rest[len..];
+        Ok(val)
+    };
+    let delta = next_varint()?;
+    let key_len = next_varint()?;
+    let val_len = next_varint()?;
+    let timestamp = if op & RECORD_TIMESTAMP_FLAG != 0 {
+        Some(next_varint()?)
+    } else {
+        None
+    };
+
+    if key_len == 0 {
+        return Err(corruption("key_len is 0"));
+    }
+    if key_len.checked_add(val_len) != Some(rest.len() as u64) {
+        return Err(corruption(
+            "length mismatch - body len doesnt match what is described in payload metadata",
+        ));
+    }
+    let (key, val) = rest.split_at(key_len as usize);
+
+    let compression = CompressionType::try_from(op >> 4).map_err(corruption)?;
+    let delta = ((delta >> 1) as i64) ^ -((delta & 1) as i64);
+    Ok(WALRecord {
+        op: Op::try_from(op & 0x0f & !RECORD_TIMESTAMP_FLAG)?,
+        seq_no: last_seq_no.wrapping_add(delta as u64),
+        key: key.to_vec(),
+        val: decompress(compression, val).map_err(corruption)?,
+        timestamp,
+    })
+}
+
+/// Appends `val` as a LEB128 varint, 7 bits per byte with the high bit set on every byte but the last.
+fn encode_varint(buf: &mut Vec<u8>, mut val: u64) {
+    while val >= 0x80 {
+        buf.push(val as u8 | 0x80);
+        val >>= 7;
+    }
+    buf.push(val as u8);
 }
 
-fn read_u64_le(input: &[u8]) -> Option<u64> {
-    let bitfield: [u8; 8] = input.get(0..8)?.try_into().ok()?;
-    Some(u64::from_le_bytes(bitfield))
+/// Decodes the varint `buf` starts with, returning it alongside its length.
+fn decode_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut val = 0u64;
+    for (i, &byte) in buf.iter().take(MAX_VARINT64_LEN).enumerate() {
+        val |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((val, i + 1));
+        }
+    }
+    None
 }
 
 #[cfg(test)]
 mod wal_test {
-    use crate::wal::{Op, WALRecord, decode_record, encode_record};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use crate::checksum::ChecksumType;
+    use crate::compression::CompressionType;
+    use crate::encryption::{Encryptor, NONCE_LEN};
+    use crate::entry::Entry;
+    use crate::memtable::{MemTable, MemTableRep};
+    use crate::types::DBError;
+    use crate::wal::{
+        ARCHIVE_DIR, DEFAULT_WAL_SEGMENT_SIZE, ENCRYPTION_HEADER_LEN, Op, PendingRecord,
+        ReplayProgress, SEGMENT_HEADER_LEN, SegmentHeader, SyncPolicy, WAL, WAL_FORMAT_VERSION,
+        WAL_MAGIC, WALArchiveConfig, WALConfig, WALRecord, WalDecodeError, WalReader,
+        column_family_record, decode_batch, decode_record, encode_record, recycled_file_name,
+        segment_file_name,
+    };
+
+    fn config(sync: SyncPolicy, max_record_len: u32, segment_size: u64) -> WALConfig {
+        WALConfig {
+            sync,
+            max_record_len,
+            segment_size,
+            ..Default::default()
+        }
+    }
+
+    fn record(seq_no: u64) -> WALRecord {
+        WALRecord::new(
+            Op::Put,
+            seq_no,
+            format!("key{seq_no}").into_bytes(),
+            b"val".to_vec(),
+        )
+    }
+
+    // The bytes a `record` takes in a segment, any of the first 64 appended in seq_no order
+    fn record_len() -> u64 {
+        let pending = PendingRecord::new(&record(0), CompressionType::None);
+        pending.encode(0, 1, ChecksumType::Crc32).0.len() as u64
+    }
 
     #[test]
     fn test_enc_dec() {
@@ -356,6 +2403,7 @@ mod wal_test {
             seq_no: 42,
             key: vec![0, 1],
             val: vec![0, 1, 2, 3, 4, 5],
+            timestamp: None,
         };
 
         let enc = encode_record(&record);
@@ -367,4 +2415,924 @@ mod wal_test {
         assert_eq!(dec.val, record.val);
         assert_eq!(next, enc.len());
     }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compressed_vals_are_flagged_in_the_op_byte() {
+        use crate::wal::encode_record_with;
+
+        let large = WALRecord::new(Op::Put, 7, b"key".to_vec(), vec![b'v'; 1000]);
+        let enc = encode_record_with(&large, ChecksumType::Crc32, CompressionType::Lz4);
+        assert!(enc.len() < encode_record(&large).len() / 4);
+        assert_eq!(enc[4] >> 4, CompressionType::Lz4 as u8);
+        assert_eq!(decode_record(&enc, 0, 1024 * 1024).unwrap().0, large);
+
+        // Not worth compressing, so stored as is
+        let small = record(8);
+        let enc = encode_record_with(&small, ChecksumType::Crc32, CompressionType::Lz4);
+        assert_eq!(enc, encode_record(&small));
+
+        let dir = PathBuf::from("test_data/wal/compressed_vals_are_flagged_in_the_op_byte");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal_config = WALConfig {
+            sync: SyncPolicy::Never,
+            compression: CompressionType::Lz4,
+            ..Default::default()
+        };
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        wal.append(&large, false).unwrap();
+        wal.append_batch(
+            &[
+                small.clone(),
+                WALRecord::new(Op::Put, 9, b"b".to_vec(), vec![0; 500]),
+            ],
+            false,
+        )
+        .unwrap();
+        drop(wal);
+
+        let wal = WAL::new(dir, wal_config).unwrap();
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(mem_table.len(), 3);
+        let entry = Entry::Value {
+            seq_no: 9,
+            val: vec![0; 500],
+            timestamp: None,
+            expires_at: None,
+        };
+        assert_eq!(mem_table.get(b"b".as_slice()), Some(&entry));
+    }
+
+    /// A toy stream cipher, xoring every byte with a mix of the key, the nonce and the byte's offset.
+    #[derive(Debug)]
+    struct XorEncryptor(u8);
+
+    impl Encryptor for XorEncryptor {
+        fn encrypt(&self, nonce: &[u8; NONCE_LEN], offset: u64, data: &mut [u8]) {
+            let seed = nonce.iter().fold(self.0, |acc, b| acc.wrapping_mul(31) ^ b);
+            for (i, b) in data.iter_mut().enumerate() {
+                let pos = offset + i as u64;
+                *b ^= seed ^ (pos as u8).wrapping_mul(167) ^ (pos >> 8) as u8;
+            }
+        }
+
+        fn decrypt(&self, nonce: &[u8; NONCE_LEN], offset: u64, data: &mut [u8]) {
+            self.encrypt(nonce, offset, data);
+        }
+    }
+
+    #[test]
+    fn encrypted_segments_only_read_back_with_their_key() {
+        let dir = PathBuf::from("test_data/wal/encrypted_segments_only_read_back_with_their_key");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal_config = |key| WALConfig {
+            sync: SyncPolicy::Never,
+            encryptor: Some(Arc::new(XorEncryptor(key)) as Arc<dyn Encryptor>),
+            ..Default::default()
+        };
+        let secret = WALRecord::new(Op::Put, 0, b"key".to_vec(), b"a secret value".to_vec());
+        let wal = WAL::new(dir.clone(), wal_config(1)).unwrap();
+        wal.append(&secret, false).unwrap();
+        wal.append(&record(1), false).unwrap();
+        drop(wal);
+
+        let segment = dir.join(segment_file_name(1));
+        let bytes = std::fs::read(&segment).unwrap();
+        assert!(
+            !bytes
+                .windows(secret.val.len())
+                .any(|window| window == secret.val)
+        );
+
+        let encryptor: Arc<dyn Encryptor> = Arc::new(XorEncryptor(1));
+        let read: Vec<_> = WalReader::open(&segment)
+            .unwrap()
+            .encryptor(encryptor)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read[0].0.offset, SEGMENT_HEADER_LEN + ENCRYPTION_HEADER_LEN);
+        assert_eq!(read[0].1, secret);
+
+        let wal = WAL::new(dir.clone(), wal_config(1)).unwrap();
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(mem_table.len(), 2);
+        drop(wal);
+
+        // The wrong key is not mistaken for a torn tail to cut off
+        let wal = WAL::new(dir, wal_config(2)).unwrap();
+        assert!(matches!(
+            wal.replay_into(0, &mut MemTable::new(), &mut Vec::new(), |_| {}),
+            Err(DBError::WAL { .. })
+        ));
+        assert_eq!(std::fs::read(&segment).unwrap(), bytes);
+    }
+
+    #[test]
+    fn concurrent_appends_are_group_committed() {
+        let dir = PathBuf::from("test_data/wal/concurrent_appends_are_group_committed");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Always, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        let wal = &wal;
+
+        let mut positions = std::thread::scope(|scope| {
+            // Holding the segment stalls the first leader, so every other writer queues up behind it
+            let segment = wal.lock_segment();
+            let mut writers = vec![scope.spawn(|| (0, wal.append(&record(0), false).unwrap()))];
+            while !wal.lock_group().leading {
+                std::thread::yield_now();
+            }
+            for seq_no in 1..8 {
+                writers.push(
+                    scope.spawn(move || (seq_no, wal.append(&record(seq_no), false).unwrap())),
+                );
+            }
+            while wal.lock_group().pending.len() < 7 {
+                std::thread::yield_now();
+            }
+            drop(segment);
+            writers
+                .into_iter()
+                .map(|writer| writer.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        // The first record on its own, then everyone who queued behind it
+        assert_eq!(wal.lock_group().groups, 2);
+        // Every writer was told where its own record went, whoever led its group
+        let read: Vec<_> = WalReader::open(&dir)
+            .unwrap()
+            .map(|read| read.map(|(position, rec)| (rec.seq_no, position)).unwrap())
+            .collect();
+        positions.sort_by_key(|&(_, position)| position);
+        assert_eq!(positions, read);
+        assert!(wal.lock_group().written.is_empty());
+
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(mem_table.len(), 8);
+    }
+
+    #[test]
+    fn sync_moves_the_durable_position_past_every_append() {
+        let dir = PathBuf::from("test_data/wal/sync_moves_the_durable_position_past_every_append");
+        let _ = std::fs::remove_dir_all(&dir);
+        let record_len = record_len();
+        let segment_size = SEGMENT_HEADER_LEN + 2 * record_len;
+        let wal = WAL::new(dir, config(SyncPolicy::Never, 1024, segment_size)).unwrap();
+        let start = wal.durable_position();
+        assert_eq!(start.offset, SEGMENT_HEADER_LEN);
+
+        let first = wal.append(&record(0), false).unwrap();
+        assert_eq!(first, start);
+        let second = wal.append(&record(1), false).unwrap();
+        assert_eq!(second.offset, SEGMENT_HEADER_LEN + record_len);
+        assert_eq!(wal.durable_position(), start);
+
+        // A record asking to be synced takes every record before it along
+        let third = wal.append(&record(2), true).unwrap();
+        assert_eq!(third.segment_no, start.segment_no + 1);
+        assert!(wal.durable_position() > third);
+        assert!(wal.durable_position() > second);
+
+        let fourth = wal.append(&record(3), false).unwrap();
+        assert!(wal.durable_position() <= fourth);
+        let durable = wal.sync().unwrap();
+        assert_eq!(durable, wal.durable_position());
+        assert_eq!(durable.offset, SEGMENT_HEADER_LEN + 2 * record_len);
+        assert!(durable > fourth);
+    }
+
+    #[test]
+    fn replay_skips_a_torn_tail_and_reports_progress() {
+        let dir = PathBuf::from("test_data/wal/replay_skips_a_torn_tail_and_reports_progress");
+        let _ = std::fs::remove_dir_all(&dir);
+        let record_len = record_len();
+        // Two records per segment
+        let segment_size = SEGMENT_HEADER_LEN + 2 * record_len;
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 1024 * 1024, segment_size),
+        )
+        .unwrap();
+        for seq_no in 0..5 {
+            wal.append(&record(seq_no), false).unwrap();
+        }
+        drop(wal);
+
+        // Crashed halfway through the last record
+        let last_segment = dir.join(segment_file_name(3));
+        let len = std::fs::metadata(&last_segment).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&last_segment)
+            .unwrap()
+            .set_len(len - record_len / 2)
+            .unwrap();
+
+        let wal = WAL::new(dir, config(SyncPolicy::Never, 1024 * 1024, segment_size)).unwrap();
+        let mut mem_table = MemTable::new();
+        let mut reports = Vec::new();
+        let report = wal
+            .replay_into(0, &mut mem_table, &mut Vec::new(), |progress| {
+                reports.push(*progress)
+            })
+            .unwrap();
+
+        assert_eq!(report.last_seq_no, Some(3));
+        assert_eq!(mem_table.len(), 4);
+        assert_eq!(
+            std::fs::metadata(&last_segment).unwrap().len(),
+            SEGMENT_HEADER_LEN
+        );
+        // One report per segment, the new empty one included
+        let bytes = 4 * SEGMENT_HEADER_LEN + 5 * record_len - record_len / 2;
+        assert_eq!(reports.len(), 4);
+        let progress = ReplayProgress {
+            segments_replayed: 4,
+            segments: 4,
+            bytes_replayed: bytes,
+            bytes,
+            records: 4,
+            flushed_records: 0,
+            bytes_skipped: record_len - record_len / 2,
+        };
+        assert_eq!(reports.last(), Some(&progress));
+        assert_eq!(report.progress, progress);
+
+        // Replayed again past a flush of the first three records
+        let mut mem_table = MemTable::new();
+        let report = wal
+            .replay_into(3, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(report.last_seq_no, Some(3));
+        assert_eq!(
+            (report.progress.records, report.progress.flushed_records),
+            (1, 3)
+        );
+        assert_eq!(report.progress.bytes_skipped, 0);
+    }
+
+    #[test]
+    fn torn_tail_is_truncated_but_corruption_before_it_fails_replay() {
+        let dir = PathBuf::from("test_data/wal/torn_tail_is_truncated");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        for seq_no in 0..3 {
+            wal.append(&record(seq_no), false).unwrap();
+        }
+        drop(wal);
+
+        // The last record made it to disk only in part, the rest of its block reads back as zeros
+        let segment = dir.join(segment_file_name(1));
+        let mut buf = std::fs::read(&segment).unwrap();
+        let good_len = buf.len() - record_len() as usize;
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        buf.extend_from_slice(&[0; 100]);
+        std::fs::write(&segment, &buf).unwrap();
+
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(mem_table.len(), 2);
+        assert_eq!(std::fs::metadata(&segment).unwrap().len(), good_len as u64);
+
+        // Replaying again finds nothing wrong
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(mem_table.len(), 2);
+        drop(wal);
+
+        // Damage to a record with others after it is not a torn tail
+        let mut buf = std::fs::read(&segment).unwrap();
+        buf[5] ^= 0xff;
+        std::fs::write(&segment, &buf).unwrap();
+        let wal = WAL::new(
+            dir,
+            config(SyncPolicy::Never, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        assert!(matches!(
+            wal.replay_into(0, &mut MemTable::new(), &mut Vec::new(), |_| {}),
+            Err(DBError::WAL { .. })
+        ));
+    }
+
+    #[test]
+    fn torn_batch_is_rolled_back_as_a_whole() {
+        let dir = PathBuf::from("test_data/wal/torn_batch_is_rolled_back_as_a_whole");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        let delete = WALRecord::new(Op::Delete, 2, b"key0".to_vec(), Vec::new());
+        wal.append_batch(&[record(0), record(1), delete], false)
+            .unwrap();
+        wal.append_batch(&[record(3), record(4)], false).unwrap();
+
+        let too_large: Vec<_> = (5..100).map(record).collect();
+        assert!(matches!(
+            wal.append_batch(&too_large, false),
+            Err(DBError::Codec { .. })
+        ));
+        drop(wal);
+
+        // The second batch only made it to disk in part
+        let segment = dir.join(segment_file_name(1));
+        let len = std::fs::metadata(&segment).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+
+        let wal = WAL::new(
+            dir,
+            config(SyncPolicy::Never, 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        let mut mem_table = MemTable::new();
+        let last_seq_no = wal
+            .replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap()
+            .last_seq_no;
+        assert_eq!(last_seq_no, Some(2));
+        assert_eq!(
+            mem_table.get(b"key0".as_slice()),
+            Some(&Entry::Tombstone { seq_no: 2 })
+        );
+        assert!(mem_table.get(b"key1".as_slice()).is_some());
+        assert_eq!(mem_table.len(), 2);
+    }
+
+    #[test]
+    fn column_family_records_are_routed_and_held_until_flushed() {
+        let dir =
+            PathBuf::from("test_data/wal/column_family_records_are_routed_and_held_until_flushed");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        // The column family's seq_nos run apart from the DB's
+        let cf_records = [
+            record(0),
+            WALRecord::new(Op::Delete, 1, b"key0".to_vec(), Vec::new()),
+        ];
+        wal.hold(0);
+        wal.hold(1);
+        let cf_record = column_family_record(1, &cf_records).unwrap();
+        wal.append_batch(&[record(5), cf_record], true).unwrap();
+
+        let mut routed = Vec::new();
+        let mut mem_table = MemTable::new();
+        let report = wal
+            .replay_routing_into(
+                0,
+                &mut mem_table,
+                &mut Vec::new(),
+                (None, 0),
+                &mut |id, records| {
+                    routed.push((id, records));
+                    Ok(())
+                },
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(report.last_seq_no, Some(5));
+        assert_eq!(report.progress.records, 3);
+        assert_eq!(mem_table.len(), 1);
+        assert_eq!(routed, [(1, cf_records.to_vec())]);
+        assert!(matches!(
+            wal.replay_into(0, &mut MemTable::new(), &mut Vec::new(), |_| {}),
+            Err(DBError::WAL { .. })
+        ));
+
+        // The DB flushed its writes, but the column family still holds the segment
+        let segment_no = wal.start_segment().unwrap();
+        wal.freeze_hold(0);
+        wal.release_frozen(0);
+        wal.truncate_before(segment_no).unwrap();
+        assert!(dir.join(segment_file_name(1)).exists());
+
+        wal.freeze_hold(1);
+        wal.release_frozen(1);
+        wal.truncate_before(segment_no).unwrap();
+        assert!(!dir.join(segment_file_name(1)).exists());
+    }
+
+    #[test]
+    fn records_over_max_record_len_are_turned_away_on_append() {
+        let dir =
+            PathBuf::from("test_data/wal/records_over_max_record_len_are_turned_away_on_append");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 64, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+
+        wal.append(&record(0), false).unwrap();
+        let large_val = WALRecord::new(Op::Put, 1, b"key1".to_vec(), vec![0; 64]);
+        assert!(matches!(
+            wal.append(&large_val, false),
+            Err(DBError::Codec { .. })
+        ));
+        let large_key = WALRecord::new(Op::Delete, 1, vec![0; 64], Vec::new());
+        assert!(matches!(
+            wal.append(&large_key, true),
+            Err(DBError::Codec { .. })
+        ));
+        // Nothing was written, so the WAL carries on
+        wal.append(&record(1), false).unwrap();
+        assert_eq!(
+            wal.lock_segment().len,
+            SEGMENT_HEADER_LEN + 2 * record_len()
+        );
+        drop(wal);
+
+        let wal = WAL::new(dir, config(SyncPolicy::Never, 64, DEFAULT_WAL_SEGMENT_SIZE)).unwrap();
+        let mut mem_table = MemTable::new();
+        let last_seq_no = wal
+            .replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap()
+            .last_seq_no;
+        assert_eq!(last_seq_no, Some(1));
+        assert_eq!(mem_table.len(), 2);
+    }
+
+    #[test]
+    fn preallocated_segments_replay_up_to_their_last_record() {
+        let dir =
+            PathBuf::from("test_data/wal/preallocated_segments_replay_up_to_their_last_record");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal_config = WALConfig {
+            sync: SyncPolicy::Never,
+            segment_size: 4096,
+            preallocate: true,
+            ..Default::default()
+        };
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        for seq_no in 0..3 {
+            wal.append(&record(seq_no), false).unwrap();
+        }
+        let segment = dir.join(segment_file_name(1));
+        assert_eq!(std::fs::metadata(&segment).unwrap().len(), 4096);
+        drop(wal);
+
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        let mut mem_table = MemTable::new();
+        let last_seq_no = wal
+            .replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap()
+            .last_seq_no;
+        assert_eq!(last_seq_no, Some(2));
+        assert_eq!(mem_table.len(), 3);
+
+        // The zeros after the last record are cut off, but the segment now appended to keeps its space
+        let record_len = record_len();
+        assert_eq!(
+            std::fs::metadata(&segment).unwrap().len(),
+            SEGMENT_HEADER_LEN + 3 * record_len
+        );
+        let active = dir.join(segment_file_name(2));
+        assert_eq!(std::fs::metadata(&active).unwrap().len(), 4096);
+    }
+
+    #[test]
+    fn recycled_segments_are_reused_without_replaying_their_old_records() {
+        let dir = PathBuf::from("test_data/wal/recycled_segments_are_reused");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal_config = WALConfig {
+            sync: SyncPolicy::Never,
+            recycle_files: 1,
+            ..Default::default()
+        };
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        for seq_no in 0..3 {
+            wal.append(&record(seq_no), false).unwrap();
+        }
+        wal.truncate().unwrap();
+        assert!(dir.join(recycled_file_name(1)).exists());
+
+        // Segment 3 reuses segment 1's file, the new record only overwrites the first of its old ones
+        wal.append(&record(3), false).unwrap();
+        wal.truncate().unwrap();
+        wal.append(&record(4), false).unwrap();
+        drop(wal);
+        assert!(!dir.join(recycled_file_name(1)).exists());
+        assert!(dir.join(recycled_file_name(2)).exists());
+
+        // Every record before seq_no 4 was flushed before segments 1 and 2 were dropped
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        assert_eq!(wal.segment_no(), 4);
+        let mut mem_table = MemTable::new();
+        let last_seq_no = wal
+            .replay_into(4, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap()
+            .last_seq_no;
+        assert_eq!(last_seq_no, Some(4));
+        assert_eq!(
+            mem_table.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec![b"key4"]
+        );
+
+        let record_len = record_len();
+        let segment = dir.join(segment_file_name(3));
+        assert_eq!(
+            std::fs::metadata(&segment).unwrap().len(),
+            SEGMENT_HEADER_LEN + record_len
+        );
+    }
+
+    #[test]
+    fn wal_reader_yields_every_record_with_its_position() {
+        let dir = PathBuf::from("test_data/wal/wal_reader_yields_every_record_with_its_position");
+        let _ = std::fs::remove_dir_all(&dir);
+        let record_len = record_len();
+        let segment_size = SEGMENT_HEADER_LEN + 2 * record_len;
+        let wal = WAL::new(
+            dir.clone(),
+            config(SyncPolicy::Never, 1024 * 1024, segment_size),
+        )
+        .unwrap();
+        for seq_no in 0..3 {
+            wal.append(&record(seq_no), false).unwrap();
+        }
+        drop(wal);
+
+        let read: Vec<_> = WalReader::open(&dir).unwrap().map(Result::unwrap).collect();
+        let positions: Vec<_> = read
+            .iter()
+            .map(|(position, _)| {
+                (
+                    position.segment_no,
+                    (position.offset - SEGMENT_HEADER_LEN) / record_len,
+                )
+            })
+            .collect();
+        assert_eq!(positions, vec![(1, 0), (1, 1), (2, 0)]);
+        assert_eq!(read[2].1, record(2));
+
+        // A single segment can be read on its own, and damage ends the reader with an error
+        let segment = dir.join(segment_file_name(1));
+        let mut buf = std::fs::read(&segment).unwrap();
+        buf[SEGMENT_HEADER_LEN as usize + 5] ^= 0xff;
+        std::fs::write(&segment, &buf).unwrap();
+        let mut reader = WalReader::open(&segment).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(WalDecodeError::Corruption { .. }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn segments_are_read_back_with_the_checksum_they_were_written_with() {
+        let dir = PathBuf::from("test_data/wal/segments_are_read_back_with_their_checksum");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A segment from before segment headers, records only
+        let legacy: Vec<u8> = (0..2)
+            .flat_map(|seq_no| encode_record(&record(seq_no)))
+            .collect();
+        std::fs::write(dir.join(segment_file_name(1)), legacy).unwrap();
+
+        let wal_config = WALConfig {
+            sync: SyncPolicy::Never,
+            checksum: ChecksumType::XxHash64,
+            ..Default::default()
+        };
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        assert_eq!(wal.segment_no(), 2);
+        wal.append(&record(2), false).unwrap();
+        wal.append_batch(&[record(3), record(4)], false).unwrap();
+        drop(wal);
+
+        let segment = std::fs::read(dir.join(segment_file_name(2))).unwrap();
+        assert_eq!(segment[12], ChecksumType::XxHash64 as u8);
+        let pending = PendingRecord::new(&record(2), CompressionType::None);
+        let (encoded, _) = pending.encode(0, 2, ChecksumType::XxHash64);
+        assert_eq!(
+            &segment[SEGMENT_HEADER_LEN as usize..][..encoded.len()],
+            encoded
+        );
+        assert_ne!(pending.encode(0, 2, ChecksumType::Crc32).0, encoded);
+
+        // Switching back to crc32 applies to new segments only
+        let wal = WAL::new(dir.clone(), WALConfig::default()).unwrap();
+        let mut mem_table = MemTable::new();
+        let last_seq_no = wal
+            .replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap()
+            .last_seq_no;
+        assert_eq!(last_seq_no, Some(4));
+        assert_eq!(mem_table.len(), 5);
+        drop(wal);
+
+        let seq_nos: Vec<_> = WalReader::open(&dir)
+            .unwrap()
+            .map(|read| read.unwrap().1.seq_no)
+            .collect();
+        assert_eq!(seq_nos, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn varint_records_are_smaller_and_version_1_segments_still_read() {
+        let dir = PathBuf::from("test_data/wal/varint_records_are_smaller");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut v1 = Vec::new();
+        v1.extend_from_slice(&WAL_MAGIC.to_le_bytes());
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        v1.push(ChecksumType::Crc32 as u8);
+        v1.extend((0..2).flat_map(|seq_no| encode_record(&record(seq_no))));
+        std::fs::write(dir.join(segment_file_name(1)), v1).unwrap();
+
+        let wal_config = config(SyncPolicy::Never, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE);
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        // seq_nos going backwards and far apart are deltas all the same
+        let seq_nos = [2, 3, u64::MAX - 1, 4, 1 << 40];
+        for seq_no in seq_nos {
+            wal.append(&record(seq_no), false).unwrap();
+        }
+        drop(wal);
+
+        let segment = std::fs::metadata(dir.join(segment_file_name(2))).unwrap();
+        let fixed_len: usize = seq_nos
+            .iter()
+            .map(|&seq_no| encode_record(&record(seq_no)).len())
+            .sum();
+        assert!(segment.len() - SEGMENT_HEADER_LEN < fixed_len as u64 * 2 / 3);
+        // 9 bytes on top of the key and val rather than 25
+        assert_eq!(record_len(), 9 + 4 + 3);
+        assert_eq!(encode_record(&record(0)).len(), 25 + 4 + 3);
+
+        let read: Vec<_> = WalReader::open(&dir)
+            .unwrap()
+            .map(|read| read.unwrap().1)
+            .collect();
+        let expected: Vec<_> = [0, 1].into_iter().chain(seq_nos).map(record).collect();
+        assert_eq!(read, expected);
+
+        let wal = WAL::new(dir, wal_config).unwrap();
+        let mut mem_table = MemTable::new();
+        let last_seq_no = wal
+            .replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap()
+            .last_seq_no;
+        assert_eq!(last_seq_no, Some(u64::MAX - 1));
+        assert_eq!(mem_table.len(), 7);
+    }
+
+    #[test]
+    fn timestamps_are_kept_in_both_encodings() {
+        let timed = |seq_no| record(seq_no).with_timestamp(Some(1_700_000_000_000 + seq_no));
+
+        let enc = encode_record(&timed(1));
+        assert_eq!(enc.len(), encode_record(&record(1)).len() + 8);
+        assert_eq!(decode_record(&enc, 0, 1024 * 1024).unwrap().0, timed(1));
+
+        let dir = PathBuf::from("test_data/wal/timestamps_are_kept_in_both_encodings");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal_config = config(SyncPolicy::Never, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE);
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        wal.append(&timed(2), false).unwrap();
+        wal.append(&record(3), false).unwrap();
+        wal.append_batch(&[timed(4), record(5)], false).unwrap();
+        drop(wal);
+
+        let read: Vec<_> = WalReader::open(&dir)
+            .unwrap()
+            .map(|read| read.unwrap().1)
+            .flat_map(|rec| match rec.op() {
+                Op::Batch => decode_batch(&rec).unwrap(),
+                _ => vec![rec],
+            })
+            .collect();
+        assert_eq!(read, [timed(2), record(3), timed(4), record(5)]);
+
+        let wal = WAL::new(dir, wal_config).unwrap();
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        let timestamps: Vec<_> = mem_table
+            .iter()
+            .map(|(_, entry)| entry.timestamp())
+            .collect();
+        assert_eq!(
+            timestamps,
+            [Some(1_700_000_000_002), None, Some(1_700_000_000_004), None]
+        );
+    }
+
+    #[test]
+    fn segment_headers_are_validated_on_replay() {
+        let dir = PathBuf::from("test_data/wal/segment_headers_are_validated_on_replay");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A version 2 header lacks the creation time
+        let mut v2 = Vec::new();
+        v2.extend_from_slice(&WAL_MAGIC.to_le_bytes());
+        v2.extend_from_slice(&2u32.to_le_bytes());
+        v2.push(ChecksumType::Crc32 as u8);
+        v2.extend_from_slice(&1u64.to_le_bytes());
+        let pending = PendingRecord::new(&record(0), CompressionType::None);
+        v2.extend(pending.encode(0, 1, ChecksumType::Crc32).0);
+        std::fs::write(dir.join(segment_file_name(1)), v2).unwrap();
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let wal = WAL::new(dir.clone(), WALConfig::default()).unwrap();
+        wal.append(&record(1), false).unwrap();
+        drop(wal);
+
+        let header = SegmentHeader::read(dir.join(segment_file_name(1)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (header.version, header.segment_no, header.created_at),
+            (2, 1, 0)
+        );
+        let header = SegmentHeader::read(dir.join(segment_file_name(2)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.version, WAL_FORMAT_VERSION);
+        assert_eq!(header.checksum, ChecksumType::Crc32);
+        assert_eq!(header.segment_no, 2);
+        assert!(header.created_at >= started);
+
+        let wal = WAL::new(dir.clone(), WALConfig::default()).unwrap();
+        let mut mem_table = MemTable::new();
+        assert_eq!(
+            wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+                .unwrap()
+                .last_seq_no,
+            Some(1)
+        );
+        drop(wal);
+
+        // A segment copied over another is told apart by its header
+        std::fs::copy(
+            dir.join(segment_file_name(2)),
+            dir.join(segment_file_name(1)),
+        )
+        .unwrap();
+        let wal = WAL::new(dir.clone(), WALConfig::default()).unwrap();
+        let replayed = wal.replay_into(0, &mut MemTable::new(), &mut Vec::new(), |_| {});
+        assert!(matches!(replayed, Err(DBError::WAL { .. })));
+        assert!(WalReader::open(&dir).unwrap().any(|read| read.is_err()));
+
+        // Anything but a segment is taken for corruption rather than an empty segment
+        std::fs::write(dir.join(segment_file_name(1)), b"not a wal segment at all").unwrap();
+        let replayed = wal.replay_into(0, &mut MemTable::new(), &mut Vec::new(), |_| {});
+        assert!(matches!(replayed, Err(DBError::WAL { .. })));
+    }
+
+    #[test]
+    fn truncated_segments_are_archived_within_the_retention_size() {
+        let dir = PathBuf::from("test_data/wal/truncated_segments_are_archived");
+        let _ = std::fs::remove_dir_all(&dir);
+        let record_len = record_len();
+        let wal_config = WALConfig {
+            sync: SyncPolicy::Never,
+            archive: Some(WALArchiveConfig {
+                max_size: Some(2 * (SEGMENT_HEADER_LEN + record_len)),
+                max_age: None,
+            }),
+            ..Default::default()
+        };
+        let wal = WAL::new(dir.clone(), wal_config.clone()).unwrap();
+        wal.append(&record(0), false).unwrap();
+        wal.append(&record(1), false).unwrap();
+        wal.truncate().unwrap();
+        assert!(!dir.join(segment_file_name(1)).exists());
+        assert!(dir.join(ARCHIVE_DIR).join(segment_file_name(1)).exists());
+
+        // Archiving segment 2 takes the archive over its size, so segment 1 goes
+        wal.append(&record(2), false).unwrap();
+        wal.truncate().unwrap();
+        let archived: Vec<_> = WalReader::open(dir.join(ARCHIVE_DIR))
+            .unwrap()
+            .map(|read| read.map(|(position, record)| (position.segment_no, record.seq_no)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(archived, vec![(2, 2)]);
+
+        // Replay never looks at the archive
+        let mut mem_table = MemTable::new();
+        assert_eq!(
+            wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+                .unwrap()
+                .last_seq_no,
+            None
+        );
+    }
+
+    #[test]
+    fn every_n_syncs_once_every_n_records() {
+        let dir = PathBuf::from("test_data/wal/every_n_syncs_once_every_n_records");
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WAL::new(
+            dir,
+            config(SyncPolicy::EveryN(3), 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+
+        let mut unsynced = Vec::new();
+        for seq_no in 0..7 {
+            wal.append(&record(seq_no), false).unwrap();
+            unsynced.push(wal.lock_segment().unsynced);
+        }
+        assert_eq!(unsynced, vec![1, 2, 0, 1, 2, 0, 1]);
+
+        // A record asking to be synced is, whatever the policy
+        wal.append(&record(7), true).unwrap();
+        assert_eq!(wal.lock_segment().unsynced, 0);
+    }
+
+    #[test]
+    fn bytes_per_sync_starts_the_write_back_under_any_policy() {
+        let dir = PathBuf::from("test_data/wal/bytes_per_sync_starts_the_write_back");
+        let _ = std::fs::remove_dir_all(&dir);
+        let record_len = record_len();
+        let wal_config = WALConfig {
+            sync: SyncPolicy::Never,
+            bytes_per_sync: 2 * record_len,
+            ..Default::default()
+        };
+        let wal = WAL::new(dir, wal_config).unwrap();
+
+        let mut synced_len = Vec::new();
+        for seq_no in 0..4 {
+            wal.append(&record(seq_no), false).unwrap();
+            synced_len.push((wal.lock_segment().synced_len - SEGMENT_HEADER_LEN) / record_len);
+        }
+        assert_eq!(synced_len, vec![0, 2, 2, 4]);
+        // The records were handed to the OS, but not synced
+        assert!(wal.lock_segment().buf.buffer().is_empty());
+        assert_eq!(wal.lock_segment().unsynced, 4);
+    }
+
+    #[test]
+    fn interval_syncs_in_the_background() {
+        let dir = PathBuf::from("test_data/wal/interval_syncs_in_the_background");
+        let _ = std::fs::remove_dir_all(&dir);
+        let policy = SyncPolicy::Interval(Duration::from_millis(5));
+        let wal = WAL::new(
+            dir.clone(),
+            config(policy, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+
+        wal.append(&record(0), false).unwrap();
+        wal.append(&record(1), false).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while wal.lock_segment().unsynced > 0 {
+            assert!(Instant::now() < deadline, "the flusher never synced");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(wal.lock_segment().buf.buffer().is_empty());
+
+        // Closing syncs whatever the flusher hasn't got to yet
+        wal.append(&record(2), false).unwrap();
+        drop(wal);
+        let wal = WAL::new(
+            dir,
+            config(SyncPolicy::Never, 1024 * 1024, DEFAULT_WAL_SEGMENT_SIZE),
+        )
+        .unwrap();
+        let mut mem_table = MemTable::new();
+        wal.replay_into(0, &mut mem_table, &mut Vec::new(), |_| {})
+            .unwrap();
+        assert_eq!(mem_table.len(), 3);
+    }
 }